@@ -0,0 +1,318 @@
+use crate::document::is_text;
+use crate::network::exchange::{self, Exchange};
+use crate::network::idn;
+use protocol::gemini_protocol::response::{OkResponse, Response};
+use protocol::gemtext::gemtext_body::Line;
+use protocol::robots::{self, Robots};
+use rustls::ClientConfig;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// Pages saved at most, so a capsule that generates pages can't fill the disk.
+const MAX_PAGES: usize = 500;
+
+/// Time between two requests, and after a `44 SLOW DOWN` without a number.
+const REQUEST_DELAY: Duration = Duration::from_secs(1);
+
+/// The longest `44 SLOW DOWN` waited for, the page is skipped when the server wants more.
+const MAX_SLOW_DOWN: Duration = Duration::from_secs(60);
+
+/// The virtual agent of the robots.txt companion spec that saving a capsule is, see
+/// [protocol::robots].
+const USER_AGENT: &str = "archiver";
+
+/// What [save_capsule] did.
+#[derive(Debug, Clone)]
+pub struct Saved {
+    /// The local copy of the page the capsule was saved from.
+    pub start: PathBuf,
+    pub pages: usize,
+    /// Pages robots.txt disallowed, that failed to load or that weren't text.
+    pub skipped: usize,
+}
+
+enum Page {
+    Text(OkResponse),
+    /// A redirect to another page of the capsule.
+    Moved(Url),
+}
+
+/// Saves the pages of the capsule of `start` that can be reached from it by following at
+/// most `depth` links, into a directory named after the host in `dir`. Links between saved
+/// pages are made relative, so the copy can be read through `file://` and moved around.
+///
+/// Pages with a query are left out, they answer input rather than being part of the
+/// capsule.
+pub async fn save_capsule(
+    tls_config: Arc<ClientConfig>,
+    start: Url,
+    dir: PathBuf,
+    depth: usize,
+) -> Result<Saved, String> {
+    let start = idn::to_ascii(&start).map_err(|e| format!("Invalid host: {}", e))?;
+    let host = start.host_str().ok_or("No host found")?.to_string();
+
+    let robots = match robots::url(&start) {
+        Some(url) => match fetch(&tls_config, &url).await {
+            Ok(response) => Robots::from_response(&response),
+            Err(_) => Robots::default(),
+        },
+        None => Robots::default(),
+    };
+
+    let mut queue = VecDeque::from([(start.clone(), 0)]);
+    let mut seen = HashSet::from([start.clone()]);
+    let mut pages = vec![];
+    let mut skipped = 0;
+
+    while let Some((url, level)) = queue.pop_front() {
+        if pages.len() == MAX_PAGES {
+            log::warn!("Stopped saving {} after {} pages", host, MAX_PAGES);
+            break;
+        }
+        if !robots.is_allowed(url.path(), USER_AGENT) {
+            log::info!("Not saving {}, robots.txt disallows it", url);
+            skipped += 1;
+            continue;
+        }
+
+        async_std::task::sleep(REQUEST_DELAY).await;
+        let mut response = fetch(&tls_config, &url).await;
+        if let Ok(Response::SlowDown(meta)) = &response {
+            let wait = meta
+                .as_deref()
+                .and_then(|seconds| seconds.trim().parse().ok())
+                .map_or(REQUEST_DELAY, Duration::from_secs);
+
+            if wait <= MAX_SLOW_DOWN {
+                async_std::task::sleep(wait).await;
+                response = fetch(&tls_config, &url).await;
+            }
+        }
+
+        let mut enqueue = |link: &Url, level| {
+            let mut link = link.clone();
+            link.set_fragment(None);
+
+            if same_capsule(&start, &link) && seen.insert(link.clone()) {
+                queue.push_back((link, level));
+            }
+        };
+
+        match response {
+            Ok(Response::Success(ok)) => {
+                if level < depth && ok.mime.matches("text/gemini") {
+                    for line in ok.gemtext().map(|body| &body[..]).unwrap_or_default() {
+                        if let Line::Link { url: link, .. } = line {
+                            enqueue(link, level + 1);
+                        }
+                    }
+                }
+                pages.push((url, Page::Text(ok)));
+            }
+            Ok(Response::TemporaryRedirect(target) | Response::PermanentRedirect(target)) => {
+                match url.join(&target) {
+                    Ok(target) if same_capsule(&start, &target) => {
+                        enqueue(&target, level);
+                        pages.push((url, Page::Moved(target)));
+                    }
+                    _ => {
+                        log::info!("Not saving {}, it redirects off the capsule", url);
+                        skipped += 1;
+                    }
+                }
+            }
+            Ok(response) => {
+                log::info!("Not saving {}: {}", url, response);
+                skipped += 1;
+            }
+            Err(e) => {
+                log::warn!("Not saving {}: {}", url, e);
+                skipped += 1;
+            }
+        }
+    }
+
+    let root = dir.join(&host);
+    let local = pages
+        .iter()
+        .map(|(url, page)| (url.clone(), root.join(local_path(url, page))))
+        .collect::<HashMap<_, _>>();
+
+    for (url, page) in &pages {
+        let path = &local[url];
+        let content = match page {
+            Page::Text(ok) if ok.mime.matches("text/gemini") => render(ok, path, &local),
+            Page::Text(ok) => ok.to_bytes(),
+            Page::Moved(target) => render_moved(target, path, &local).into_bytes(),
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to save {}: {}", url, e))?;
+        }
+        std::fs::write(path, content).map_err(|e| format!("Failed to save {}: {}", url, e))?;
+    }
+
+    let start = local
+        .get(&start)
+        .cloned()
+        .ok_or_else(|| format!("Failed to load {}", start))?;
+
+    Ok(Saved {
+        start,
+        pages: pages.len(),
+        skipped,
+    })
+}
+
+/// Requests `url`, reading the body of text responses only.
+async fn fetch(tls_config: &Arc<ClientConfig>, url: &Url) -> Result<Response, String> {
+    let Exchange {
+        header,
+        body: stream,
+        ..
+    } = Exchange::send(url, tls_config.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if header.is_success() && !is_text(&header.meta) {
+        return Err(format!("{} isn't text", header.meta));
+    }
+
+    let mut body = vec![];
+    if header.is_success() {
+        exchange::read_body(stream, &mut body)
+            .map_err(|e| format!("Failed to read response: {}", e))?;
+    }
+
+    header
+        .response(url, &body)
+        .map_err(|e| format!("Invalid response: {}", e))
+}
+
+fn same_capsule(start: &Url, url: &Url) -> bool {
+    url.scheme() == "gemini"
+        && url.host_str() == start.host_str()
+        && url.port() == start.port()
+        && url.query().is_none()
+}
+
+/// Where a page is saved, relative to the directory of its capsule. Directories get an
+/// `index.gmi` and gemtext a `.gmi` extension, so the client reads them as gemtext.
+fn local_path(url: &Url, page: &Page) -> PathBuf {
+    let gemtext = match page {
+        Page::Text(ok) => ok.mime.matches("text/gemini"),
+        Page::Moved(_) => true,
+    };
+
+    let mut path = url
+        .path()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<PathBuf>();
+
+    if url.path().ends_with('/') || path.as_os_str().is_empty() {
+        path.push("index.gmi");
+    } else if gemtext
+        && !path
+            .extension()
+            .is_some_and(|ext| ext == "gmi" || ext == "gemini")
+    {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".gmi");
+        path.set_file_name(name);
+    }
+
+    path
+}
+
+/// The link from the page saved at `from` to the saved copy of `to`, `to` itself when it
+/// wasn't saved.
+fn link(to: &Url, from: &Path, local: &HashMap<Url, PathBuf>) -> String {
+    let mut page = to.clone();
+    page.set_fragment(None);
+
+    let relative = local.get(&page).and_then(|target| {
+        let from = Url::from_file_path(from).ok()?;
+        let mut target = Url::from_file_path(target).ok()?;
+        target.set_fragment(to.fragment());
+
+        from.make_relative(&target)
+    });
+
+    relative.unwrap_or_else(|| to.to_string())
+}
+
+fn render(ok: &OkResponse, path: &Path, local: &HashMap<Url, PathBuf>) -> Vec<u8> {
+    let mut out = String::new();
+
+    for line in ok.gemtext().map(|body| &body[..]).unwrap_or_default() {
+        match line {
+            Line::Link { url, description } => {
+                let description = description.clone().unwrap_or_else(|| url.to_string());
+                out.push_str(&format!("=> {} {}", link(url, path, local), description));
+            }
+            line => out.push_str(&line.to_string()),
+        }
+        out.push('\n');
+    }
+
+    out.into_bytes()
+}
+
+fn render_moved(target: &Url, path: &Path, local: &HashMap<Url, PathBuf>) -> String {
+    format!("=> {} Moved to {}\n", link(target, path, local), target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::gemini_protocol::parse_response;
+
+    #[test]
+    fn test_render() {
+        let root = std::env::temp_dir().join("example.org");
+        let url = Url::parse("gemini://example.org/").unwrap();
+        let page = parse_response(
+            &url,
+            "20 text/gemini\r\n# Capsule\n=> /log/ Log\n=> log/first Entry\n=> gemini://example.net/\n=> /old\n",
+        )
+        .unwrap();
+        let Response::Success(ok) = page else {
+            panic!("{:?}", page)
+        };
+
+        let log = Url::parse("gemini://example.org/log/").unwrap();
+        let first = Url::parse("gemini://example.org/log/first").unwrap();
+        let old = Url::parse("gemini://example.org/old").unwrap();
+        let moved = Page::Moved(first.clone());
+        let pages = [
+            (&url, Page::Text(ok.clone())),
+            (&log, moved),
+            (&old, Page::Moved(log.clone())),
+        ];
+
+        let local = pages
+            .iter()
+            .map(|(url, page)| ((*url).clone(), root.join(local_path(url, page))))
+            .chain([(first.clone(), root.join("log/first.gmi"))])
+            .collect::<HashMap<_, _>>();
+        assert_eq!(local[&url], root.join("index.gmi"));
+        assert_eq!(local[&log], root.join("log/index.gmi"));
+        assert_eq!(local[&old], root.join("old.gmi"));
+
+        assert_eq!(
+            String::from_utf8(render(&ok, &local[&url], &local)).unwrap(),
+            "# Capsule\n=> log/index.gmi Log\n=> log/first.gmi Entry\n\
+             => gemini://example.net/ gemini://example.net/\n=> old.gmi gemini://example.org/old\n"
+        );
+        assert_eq!(
+            render_moved(&log, &local[&old], &local),
+            "=> log/index.gmi Moved to gemini://example.org/log/\n"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use protocol::gemtext::gemtext_body::Line;
+use protocol::gemtext::parse_gemtext;
+use std::io;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Bookmark {
+    pub url: Url,
+    pub title: String,
+}
+
+/// The bookmarked pages, kept as the links of a gemtext file so the file can be opened
+/// as a page as well.
+#[derive(Debug, Default)]
+pub struct Bookmarks {
+    /// Where the bookmarks are saved, `None` keeps them in memory only.
+    path: Option<PathBuf>,
+    entries: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    /// `bookmarks.gmi` in the `gemini` directory of the user's config directory.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("gemini").join("bookmarks.gmi"))
+    }
+
+    /// Starts out empty when the file doesn't exist yet.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(content) => parse_bookmarks(&path, content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e),
+        };
+
+        Ok(Bookmarks {
+            path: Some(path),
+            entries,
+        })
+    }
+
+    pub fn contains(&self, url: &Url) -> bool {
+        self.entries.iter().any(|b| &b.url == url)
+    }
+
+    /// Bookmarks the page, or removes its bookmark, and returns whether it is bookmarked now.
+    pub fn toggle(&mut self, url: &Url, title: &str) -> io::Result<bool> {
+        let bookmarked = if self.contains(url) {
+            self.entries.retain(|b| &b.url != url);
+            false
+        } else {
+            self.entries.push(Bookmark {
+                url: url.clone(),
+                title: title.to_string(),
+            });
+            true
+        };
+
+        self.save()?;
+
+        Ok(bookmarked)
+    }
+
+    /// Adds the bookmarks of pages that aren't bookmarked yet and returns how many were.
+    pub fn import(&mut self, bookmarks: Vec<Bookmark>) -> io::Result<usize> {
+        let before = self.entries.len();
+        for bookmark in bookmarks {
+            if !self.contains(&bookmark.url) {
+                self.entries.push(bookmark);
+            }
+        }
+
+        let added = self.entries.len() - before;
+        if added > 0 {
+            self.save()?;
+        }
+
+        Ok(added)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let mut content = String::from("# Bookmarks\n\n");
+        for bookmark in &self.entries {
+            content.push_str(&format!("=> {} {}\n", bookmark.url, bookmark.title));
+        }
+
+        std::fs::write(path, content)
+    }
+}
+
+fn parse_bookmarks(path: &Path, content: String) -> Vec<Bookmark> {
+    let base = Url::from_file_path(path).unwrap_or_else(|_| Url::parse("about:blank").unwrap());
+
+    match parse_gemtext(&base, content) {
+        Ok(body) => body
+            .0
+            .into_iter()
+            .filter_map(|line| match line {
+                Line::Link { url, description } => Some(Bookmark {
+                    title: description.unwrap_or_else(|| url.to_string()),
+                    url,
+                }),
+                _ => None,
+            })
+            .collect(),
+        Err(e) => {
+            log::error!("Failed to parse the bookmarks in {:?}: {}", path, e);
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bookmarks() {
+        let path = std::env::temp_dir()
+            .join(format!("gemini-bookmarks-{}", std::process::id()))
+            .join("bookmarks.gmi");
+        let url = Url::parse("gemini://geminiprotocol.net/").unwrap();
+        let other = Url::parse("gemini://example.org/page.gmi").unwrap();
+
+        let mut bookmarks = Bookmarks::load(path.clone()).unwrap();
+        assert!(!bookmarks.contains(&url));
+
+        assert!(bookmarks.toggle(&url, "Project Gemini").unwrap());
+        assert!(bookmarks.toggle(&other, "Example").unwrap());
+        assert!(bookmarks.contains(&url));
+
+        let loaded = Bookmarks::load(path.clone()).unwrap();
+        assert_eq!(loaded.entries, bookmarks.entries);
+        assert_eq!(loaded.entries[0].title, "Project Gemini");
+
+        let imported = vec![
+            Bookmark {
+                url: other.clone(),
+                title: "Duplicate".to_string(),
+            },
+            Bookmark {
+                url: Url::parse("gemini://example.net/").unwrap(),
+                title: "Imported".to_string(),
+            },
+        ];
+        assert_eq!(bookmarks.import(imported).unwrap(), 1);
+        assert_eq!(Bookmarks::load(path.clone()).unwrap().entries.len(), 3);
+
+        assert!(!bookmarks.toggle(&url, "Project Gemini").unwrap());
+        let loaded = Bookmarks::load(path.clone()).unwrap();
+        assert!(!loaded.contains(&url));
+        assert!(loaded.contains(&other));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -1,20 +1,41 @@
-use crate::network::tls_client::TlsClient;
+use crate::downloads::{self, Download, DownloadState};
+use crate::events::NavigationEvent;
+use crate::handlers::Handlers;
+use crate::identity::IdentityStore;
+use crate::network::exchange::{self, Exchange, ExchangeError};
+use crate::network::idn;
+use crate::network::known_hosts::{CertificateChanged, HostTrust, KnownHosts, Pin};
+use crate::network::tls_client::{SessionInfo, Termination};
+use crate::network::tls_config;
+use crate::network::traffic;
+use crate::network::NetworkError;
+use crate::player::{self, Player};
+use crate::read_aloud::{self, Section};
+use async_std::net::TcpStream;
 use iced::advanced::text::Shaping;
 use iced::advanced::widget::Text;
-use iced::futures::AsyncReadExt;
+use iced::futures::io::BufReader;
+use iced::futures::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use iced::widget::button::{Status, Style};
-use iced::widget::{button, tooltip, Column, Tooltip};
-use iced::{widget::text, Background, Border, Color, Shadow, Task, Theme};
-use protocol::gemini_protocol::parse_response;
-use protocol::gemini_protocol::response::{OkResponse, Response};
-use protocol::gemtext::gemtext_body::Line;
+use iced::widget::{button, column, mouse_area, row, slider, tooltip, Column, Tooltip};
+use iced::{widget::text, Background, Border, Center, Color, Font, Shadow, Task, Theme};
+use protocol::capture::Capture;
+use protocol::error::FetchError;
+use protocol::gemini_protocol::client;
+use protocol::gemini_protocol::response::{Body, OkResponse, Response};
+use protocol::gemtext::gemtext_body::{Line, MimeType, TocEntry};
 use protocol::gemtext::parse_gemtext;
+use protocol::gopher;
+use protocol::spartan;
 use rustls::ClientConfig;
-use std::collections::LinkedList;
-use std::io::{Read, Write};
+use std::collections::{HashMap, HashSet, LinkedList};
 use std::sync::Arc;
+use std::time::SystemTime;
+use time::OffsetDateTime;
 use url::Url;
 
+pub const DEFAULT_PORT: u16 = 1965;
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum ShouldSaveHistory {
     Yes,
@@ -25,20 +46,56 @@ pub enum ShouldSaveHistory {
 pub enum LoadStatus {
     Success(DocumentData),
     Error(Response),
+    /// The response wasn't text and was saved to disk.
+    Download(Download),
+    /// The host presented a certificate other than its pinned one, nothing was requested.
+    CertificateChanged(CertificateChanged),
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum DocumentMessage {
-    LoadComplete((Url, Result<LoadStatus, String>)),
+    LoadComplete((Url, Result<LoadStatus, String>, Option<Capture>)),
     LinkPressed(Url),
+    LinkHovered(Url),
     NavigateBack,
     NavigateUrl(Url),
+    RetryDownload,
+    /// Pins the certificate the host presented instead of its pinned one.
+    TrustCertificate,
+    /// Creates an identity for the page that asked for a client certificate, or uses the
+    /// identity of its host there as well.
+    CreateIdentity,
+    PlayerPaused(bool),
+    /// Seconds to seek, backwards when negative.
+    PlayerSeek(i32),
+    PlayerVolume(u8),
 }
 
 #[derive(Debug, Clone)]
 pub struct DocumentData {
     url: Url,
     content: OkResponse,
+    /// The server didn't end the response with a close_notify, see [Termination].
+    truncated: bool,
+    /// The TLS session the document was loaded over, `None` for local files.
+    session: Option<SessionInfo>,
+    /// The certificate of the host, `None` for local files.
+    certificate: Option<Pin>,
+    /// When a local file was last modified, to reload it once it changes.
+    modified: Option<SystemTime>,
+    /// The URLs that redirected to this document, in the order they were visited.
+    redirects: Vec<Url>,
+}
+
+impl DocumentData {
+    /// The lines of the page, none for a body that isn't text.
+    fn lines(&self) -> &[Line] {
+        match self.content.gemtext() {
+            Some(body) => &body[..],
+            None => &[],
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -46,6 +103,44 @@ pub struct Document {
     tls_config: Arc<ClientConfig>,
     pub history: LinkedList<Url>,
     pub state: DocumentState,
+    /// Connect to the capsule of a hovered link ahead of time, so following the link
+    /// resumes the TLS session instead of doing a full handshake.
+    pub preconnect: bool,
+    preconnected: HashSet<(String, u16)>,
+    /// Record the bytes sent and received for the developer panel.
+    pub capture: bool,
+    raw_capture: Option<Capture>,
+    /// Published on the event bus of the window after every update.
+    events: Vec<NavigationEvent>,
+    /// The programs that open responses that aren't text.
+    handlers: Arc<Handlers>,
+    /// Plays an audio response, stopped when the document navigates.
+    player: Option<Player>,
+    /// The certificates trusted on first use, the same as the verifier of `tls_config`.
+    known_hosts: Arc<KnownHosts>,
+    /// The client certificates sent to the pages below their prefixes.
+    identities: Arc<IdentityStore>,
+    /// `tls_config` with the certificate of an identity, by the name of the identity.
+    identity_configs: HashMap<String, Arc<ClientConfig>>,
+    /// Loaded with the private TLS config and pins of the window, see
+    /// [crate::window::GeminiRootWindow].
+    private: bool,
+    /// The load in progress was started by [Document::reload_if_changed].
+    background_reload: bool,
+    /// A background reload changed the page since the tab was last viewed.
+    updated: bool,
+}
+
+/// What the tab of a document shows next to its title.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TabStatus {
+    Idle,
+    Loading,
+    Failed,
+    /// An audio response is playing.
+    Playing,
+    /// Reloaded in the background since the tab was last viewed.
+    Updated,
 }
 
 #[derive(Debug)]
@@ -53,14 +148,39 @@ pub enum DocumentState {
     Loading,
     Error(Url, Response),
     Loaded(DocumentData),
+    Downloaded(Download),
+    CertificateChanged(Url, CertificateChanged),
 }
 
 impl Document {
-    pub fn new(tls_client: Arc<ClientConfig>, url: Url) -> (Self, Task<DocumentMessage>) {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        tls_client: Arc<ClientConfig>,
+        url: Url,
+        preconnect: bool,
+        capture: bool,
+        handlers: Arc<Handlers>,
+        known_hosts: Arc<KnownHosts>,
+        identities: Arc<IdentityStore>,
+        private: bool,
+    ) -> (Self, Task<DocumentMessage>) {
         let mut doc = Self {
             tls_config: tls_client.clone(),
             history: LinkedList::new(),
             state: DocumentState::Loading,
+            preconnect,
+            preconnected: HashSet::new(),
+            capture,
+            raw_capture: None,
+            events: vec![],
+            handlers,
+            player: None,
+            known_hosts,
+            identities,
+            identity_configs: HashMap::new(),
+            private,
+            background_reload: false,
+            updated: false,
         };
         let task = doc.load_new_page(url.clone(), ShouldSaveHistory::Yes);
 
@@ -70,16 +190,148 @@ impl Document {
     pub fn title(&self) -> String {
         match &self.state {
             DocumentState::Loading => "Loading...".to_string(),
-            DocumentState::Error(url, ..) => format!("Error {}", url),
-            DocumentState::Loaded(data) => data.url.to_string(),
+            DocumentState::Error(url, response) => {
+                let (code, title) = response.short_code_and_title();
+                format!("{} {} {}", code, title, url)
+            }
+            DocumentState::CertificateChanged(url, ..) => format!("Certificate changed {}", url),
+            DocumentState::Loaded(data) => idn::to_unicode(&data.url),
+            DocumentState::Downloaded(download) => match download.path.file_name() {
+                Some(name) => format!("Download {}", name.to_string_lossy()),
+                None => format!("Download {}", download.url),
+            },
+        }
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.private
+    }
+
+    pub fn tab_status(&self) -> TabStatus {
+        match &self.state {
+            DocumentState::Loading => TabStatus::Loading,
+            DocumentState::Error(..) | DocumentState::CertificateChanged(..) => TabStatus::Failed,
+            DocumentState::Downloaded(download)
+                if matches!(download.state, DownloadState::Failed(_)) =>
+            {
+                TabStatus::Failed
+            }
+            _ if self.player.as_ref().is_some_and(|p| !p.paused) => TabStatus::Playing,
+            _ if self.updated => TabStatus::Updated,
+            _ => TabStatus::Idle,
         }
     }
 
+    /// The trust in the host of a page loaded over Gemini, or of one whose certificate changed.
+    pub fn host_trust(&self) -> Option<HostTrust> {
+        match &self.state {
+            DocumentState::CertificateChanged(..) => Some(HostTrust::Changed),
+            DocumentState::Loaded(data) if data.certificate.is_some() => {
+                self.known_hosts.trust(data.url.host_str()?)
+            }
+            _ => None,
+        }
+    }
+
+    /// Clears [TabStatus::Updated], the tab is being viewed.
+    pub fn mark_seen(&mut self) {
+        self.updated = false;
+    }
+
     pub fn url(&self) -> Url {
         match &self.state {
             DocumentState::Loading => Url::parse("about:blank").unwrap(),
-            DocumentState::Error(url, ..) => url.clone(),
+            DocumentState::Error(url, ..) | DocumentState::CertificateChanged(url, ..) => {
+                url.clone()
+            }
             DocumentState::Loaded(data) => data.url.clone(),
+            DocumentState::Downloaded(download) => download.url.clone(),
+        }
+    }
+
+    pub fn session_info(&self) -> Option<&SessionInfo> {
+        match &self.state {
+            DocumentState::Loaded(data) => data.session.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The lines of the loaded document that contain `query`, ignoring case.
+    pub fn find(&self, query: &str) -> Vec<FindMatch> {
+        match &self.state {
+            DocumentState::Loaded(data) => find_lines(data.lines(), query),
+            _ => vec![],
+        }
+    }
+
+    pub fn table_of_contents(&self) -> Vec<TocEntry> {
+        match &self.state {
+            DocumentState::Loaded(data) => data
+                .content
+                .gemtext()
+                .map(|body| body.table_of_contents())
+                .unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+
+    /// The text of the loaded document, split at its headings.
+    pub fn read_aloud_sections(&self) -> Vec<Section> {
+        match &self.state {
+            DocumentState::Loaded(data) => read_aloud::sections(data.lines()),
+            _ => vec![],
+        }
+    }
+
+    pub fn line_count(&self) -> usize {
+        match &self.state {
+            DocumentState::Loaded(data) => data.lines().len(),
+            _ => 0,
+        }
+    }
+
+    /// What happened since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<NavigationEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// The bytes of the last load, when it was captured.
+    pub fn raw_capture(&self) -> Option<&Capture> {
+        self.raw_capture.as_ref()
+    }
+
+    /// Whether the document is a local file that is reloaded when it changes.
+    pub fn is_watched(&self) -> bool {
+        matches!(&self.state, DocumentState::Loaded(data) if data.modified.is_some())
+    }
+
+    /// Reloads a local file that was modified since it was loaded, keeping the history.
+    pub fn reload_if_changed(&mut self) -> Task<DocumentMessage> {
+        let DocumentState::Loaded(data) = &self.state else {
+            return Task::none();
+        };
+        let (Some(loaded), Ok(path)) = (data.modified, data.url.to_file_path()) else {
+            return Task::none();
+        };
+
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+        if modified.is_none_or(|modified| modified == loaded) {
+            return Task::none();
+        }
+
+        log::info!("{:?} changed, reloading", path);
+        let url = data.url.clone();
+        let task = self.load_new_page(url, ShouldSaveHistory::No);
+        self.background_reload = true;
+
+        task
+    }
+
+    /// The URLs the current document was redirected from, first to last.
+    pub fn redirects(&self) -> &[Url] {
+        match &self.state {
+            DocumentState::Loaded(data) => &data.redirects,
+            _ => &[],
         }
     }
 
@@ -90,17 +342,83 @@ impl Document {
     pub fn update(&mut self, message: DocumentMessage) -> Task<DocumentMessage> {
         match &self.state {
             DocumentState::Loading => {
+                if let DocumentMessage::LoadComplete((_, result, raw)) = &message {
+                    self.raw_capture = raw.clone();
+                    self.updated = std::mem::take(&mut self.background_reload)
+                        && matches!(result, Ok(LoadStatus::Success(_)));
+                }
+
                 match message {
-                    DocumentMessage::LoadComplete((url, Ok(data))) => match data {
+                    DocumentMessage::LoadComplete((url, Ok(data), _)) => match data {
                         LoadStatus::Success(data) => {
+                            // Going back shouldn't go through the redirects again.
+                            if !data.redirects.is_empty()
+                                && let Some(last) = self.history.back_mut()
+                                && *last == url
+                            {
+                                *last = data.url.clone();
+                            }
+
+                            self.events.push(NavigationEvent::ResponseReceived {
+                                url: url.clone(),
+                                response: format!("Success ({})", data.content.mime),
+                            });
+                            self.events.push(NavigationEvent::Finished(url));
+
                             self.state = DocumentState::Loaded(data);
                         }
                         LoadStatus::Error(response) => {
+                            self.events.push(NavigationEvent::ResponseReceived {
+                                url: url.clone(),
+                                response: response.to_string(),
+                            });
+                            self.events.push(NavigationEvent::Failed {
+                                url: url.clone(),
+                                error: response.to_string(),
+                            });
+
                             self.state = DocumentState::Error(url, response);
                         }
+                        LoadStatus::Download(download) => {
+                            self.events.push(NavigationEvent::ResponseReceived {
+                                url: url.clone(),
+                                response: format!("Download to {:?}", download.path),
+                            });
+                            self.events.push(match &download.state {
+                                DownloadState::Finished { .. } => NavigationEvent::Finished(url),
+                                DownloadState::Failed(error) => NavigationEvent::Failed {
+                                    url,
+                                    error: error.clone(),
+                                },
+                            });
+
+                            let finished = matches!(download.state, DownloadState::Finished { .. });
+                            if finished
+                                && download.opened_with.is_none()
+                                && player::is_inline_audio(&download.mime)
+                            {
+                                match Player::start(&download.path) {
+                                    Ok(p) => self.player = Some(p),
+                                    Err(e) => log::error!("Failed to play {}: {}", download.url, e),
+                                }
+                            }
+
+                            self.state = DocumentState::Downloaded(download);
+                        }
+                        LoadStatus::CertificateChanged(changed) => {
+                            self.events.push(NavigationEvent::Failed {
+                                url: url.clone(),
+                                error: changed.to_string(),
+                            });
+
+                            self.state = DocumentState::CertificateChanged(url, changed);
+                        }
                     },
-                    DocumentMessage::LoadComplete((url, Err(error))) => {
-                        log::error!("Failed to load document: {}", error);
+                    DocumentMessage::LoadComplete((url, Err(error), _)) => {
+                        self.events.push(NavigationEvent::Failed {
+                            url: url.clone(),
+                            error: error.clone(),
+                        });
 
                         self.state =
                             DocumentState::Error(url, Response::PermanentFailure(Some(error)));
@@ -112,6 +430,27 @@ impl Document {
             }
             // TODO: Somehow share logic in NavigateBack/NavigateUrl for Error and Loaded states.
             DocumentState::Error(url, r) => match message {
+                DocumentMessage::CreateIdentity
+                    if matches!(r, Response::CertificateRequired(_)) =>
+                {
+                    let url = url.clone();
+                    let name = url.host_str().unwrap_or_default().to_string();
+
+                    let result = match self.identities.get(&name) {
+                        Some(_) => self.identities.associate(&name, &url),
+                        None => self.identities.create(&name, &url).map(|_| ()),
+                    };
+                    match result {
+                        Ok(()) => {
+                            log::info!("Using the identity {} for {}", name, url);
+                            self.load_new_page(url, ShouldSaveHistory::No)
+                        }
+                        Err(e) => {
+                            log::error!("Failed to create an identity for {}: {}", url, e);
+                            Task::none()
+                        }
+                    }
+                }
                 DocumentMessage::NavigateBack => self.try_go_back(),
                 DocumentMessage::NavigateUrl(url) => {
                     self.load_new_page(url, ShouldSaveHistory::Yes)
@@ -128,6 +467,61 @@ impl Document {
 
                     self.load_new_page(url, ShouldSaveHistory::Yes)
                 }
+                DocumentMessage::LinkHovered(url) => self.preconnect_to(url),
+                DocumentMessage::NavigateBack => self.try_go_back(),
+                DocumentMessage::NavigateUrl(url) => {
+                    self.load_new_page(url, ShouldSaveHistory::Yes)
+                }
+                _ => Task::none(),
+            },
+            DocumentState::CertificateChanged(url, changed) => match message {
+                DocumentMessage::TrustCertificate => {
+                    log::info!("Trusting the new certificate of {}", changed.host);
+                    self.known_hosts
+                        .pin(&changed.host, changed.presented.clone());
+
+                    let url = url.clone();
+                    self.load_new_page(url, ShouldSaveHistory::No)
+                }
+                DocumentMessage::NavigateBack => self.try_go_back(),
+                DocumentMessage::NavigateUrl(url) => {
+                    self.load_new_page(url, ShouldSaveHistory::Yes)
+                }
+                _ => Task::none(),
+            },
+            DocumentState::Downloaded(download) => match message {
+                DocumentMessage::RetryDownload => {
+                    let url = download.url.clone();
+
+                    self.load_new_page(url, ShouldSaveHistory::No)
+                }
+                DocumentMessage::PlayerPaused(paused) => {
+                    if let Some(player) = &mut self.player
+                        && let Err(e) = player.set_paused(paused)
+                    {
+                        log::error!("Failed to pause {}: {}", download.url, e);
+                    }
+
+                    Task::none()
+                }
+                DocumentMessage::PlayerSeek(seconds) => {
+                    if let Some(player) = &mut self.player
+                        && let Err(e) = player.seek(seconds)
+                    {
+                        log::error!("Failed to seek {}: {}", download.url, e);
+                    }
+
+                    Task::none()
+                }
+                DocumentMessage::PlayerVolume(volume) => {
+                    if let Some(player) = &mut self.player
+                        && let Err(e) = player.set_volume(volume)
+                    {
+                        log::error!("Failed to change the volume of {}: {}", download.url, e);
+                    }
+
+                    Task::none()
+                }
                 DocumentMessage::NavigateBack => self.try_go_back(),
                 DocumentMessage::NavigateUrl(url) => {
                     self.load_new_page(url, ShouldSaveHistory::Yes)
@@ -137,14 +531,93 @@ impl Document {
         }
     }
 
-    pub fn view(&self) -> iced::Element<DocumentMessage> {
+    pub fn view(&self) -> iced::Element<'_, DocumentMessage> {
         match &self.state {
             DocumentState::Loading => text("Loading...").into(),
+            DocumentState::Error(url, response @ Response::CertificateRequired(_)) => column![
+                text(format!("{}: {}", url, response)),
+                button("Create an identity").on_press(DocumentMessage::CreateIdentity),
+            ]
+            .spacing(10)
+            .into(),
             DocumentState::Error(url, response) => text(format!("{}: {}", url, response)).into(),
+            DocumentState::CertificateChanged(_, changed) => column![
+                text(changed.to_string()),
+                text(changed.explanation(OffsetDateTime::now_utc())),
+                text(format!(
+                    "Pinned: {} (until {})",
+                    changed.pinned.fingerprint,
+                    changed.pinned.not_after.date()
+                )),
+                text(format!(
+                    "Presented: {} (until {})",
+                    changed.presented.fingerprint,
+                    changed.presented.not_after.date()
+                )),
+                row![
+                    button("Trust the new certificate").on_press(DocumentMessage::TrustCertificate),
+                    button("Go back").on_press(DocumentMessage::NavigateBack),
+                ]
+                .spacing(10),
+            ]
+            .spacing(10)
+            .into(),
+            DocumentState::Downloaded(download) if let Some(player) = &self.player => {
+                let label = if player.paused { "Play" } else { "Pause" };
+
+                column![
+                    text(format!("Playing {}", download.url)),
+                    row![
+                        button(label).on_press(DocumentMessage::PlayerPaused(!player.paused)),
+                        button("-10s").on_press(DocumentMessage::PlayerSeek(-10)),
+                        button("+10s").on_press(DocumentMessage::PlayerSeek(10)),
+                        text("Volume"),
+                        slider(0..=100, player.volume, DocumentMessage::PlayerVolume).width(150),
+                    ]
+                    .spacing(10)
+                    .align_y(Center),
+                ]
+                .spacing(10)
+                .into()
+            }
+            DocumentState::Downloaded(download) => match &download.state {
+                DownloadState::Finished { size } if let Some(program) = &download.opened_with => {
+                    text(format!(
+                        "Opened {} with {} ({} bytes)",
+                        download.url, program, size
+                    ))
+                    .into()
+                }
+                DownloadState::Finished { size } => text(format!(
+                    "Saved {} to {} ({} bytes)",
+                    download.url,
+                    download.path.display(),
+                    size
+                ))
+                .into(),
+                DownloadState::Failed(error) => column![
+                    text(format!("Failed to save {}: {}", download.url, error)),
+                    button("Retry").on_press(DocumentMessage::RetryDownload),
+                ]
+                .spacing(10)
+                .into(),
+            },
             DocumentState::Loaded(data) => {
                 let mut columns = Column::new();
 
-                for line in &data.content.body.0 {
+                if data.truncated {
+                    columns = columns.push(
+                        text("The connection ended without a TLS close_notify, this page may be incomplete.")
+                            .color(Color::from_rgb8(0xd0, 0x40, 0x20)),
+                    );
+                }
+                if let (Some(pin), Some(host)) = (&data.certificate, data.url.host_str())
+                    && let Some(warning) = pin.warning(host, OffsetDateTime::now_utc())
+                {
+                    columns = columns.push(text(warning).color(Color::from_rgb8(0xd0, 0x40, 0x20)));
+                }
+
+                for line in data.lines() {
                     columns = match line {
                         Line::Link { url, description } => {
                             let description = match description {
@@ -165,7 +638,14 @@ impl Document {
                                 .on_press(DocumentMessage::LinkPressed(url.clone()))
                                 .style(link_style);
 
-                            columns.push(b)
+                            if self.preconnect {
+                                columns.push(
+                                    mouse_area(b)
+                                        .on_enter(DocumentMessage::LinkHovered(url.clone())),
+                                )
+                            } else {
+                                columns.push(b)
+                            }
                         }
                         Line::Heading { text: t, depth } => {
                             let head = Text::new(t)
@@ -174,10 +654,24 @@ impl Document {
 
                             columns.push(head)
                         }
-                        Line::Text(value)
-                        | Line::Quote(value)
-                        | Line::Raw(value)
-                        | Line::ListItem(value) => {
+                        Line::Preformatted { alt, lines } => {
+                            let block = Text::new(lines.join("\n"))
+                                .shaping(Shaping::Advanced)
+                                .font(Font::with_name("DejaVu Sans Mono"));
+
+                            match alt {
+                                Some(alt) => columns.push(
+                                    Tooltip::new(
+                                        block,
+                                        Text::new(alt).shaping(Shaping::Advanced),
+                                        tooltip::Position::Bottom,
+                                    )
+                                    .snap_within_viewport(true),
+                                ),
+                                None => columns.push(block),
+                            }
+                        }
+                        Line::Text(value) | Line::Quote(value) | Line::ListItem(value) => {
                             columns.push(Text::new(value).shaping(Shaping::Advanced))
                         }
                     };
@@ -193,19 +687,69 @@ impl Document {
         url: Url,
         should_save_history: ShouldSaveHistory,
     ) -> Task<DocumentMessage> {
-        log::info!("Loading new page: {}", url);
+        self.events.push(NavigationEvent::Started(url.clone()));
 
+        self.player = None;
         self.state = DocumentState::Loading;
+        self.background_reload = false;
         if should_save_history == ShouldSaveHistory::Yes {
             self.history.push_back(url.clone());
         }
 
         Task::perform(
-            Self::load_document(self.tls_config.clone(), url.clone()),
+            Self::load_document(
+                self.tls_config_for(&url),
+                url.clone(),
+                self.capture,
+                self.handlers.clone(),
+            ),
             DocumentMessage::LoadComplete,
         )
     }
 
+    /// The TLS config that sends the certificate of the identity used for `url`, if any.
+    fn tls_config_for(&mut self, url: &Url) -> Arc<ClientConfig> {
+        let Some(identity) = self.identities.for_url(url) else {
+            return self.tls_config.clone();
+        };
+        if let Some(config) = self.identity_configs.get(&identity.name) {
+            return config.clone();
+        }
+
+        match tls_config::with_identity(&self.tls_config, &identity) {
+            Ok(config) => {
+                self.identity_configs.insert(identity.name, config.clone());
+                config
+            }
+            Err(e) => {
+                log::error!("Failed to use the identity {}: {}", identity.name, e);
+                self.tls_config.clone()
+            }
+        }
+    }
+
+    /// Connects to the capsule of a link once per document, unless the document was loaded
+    /// from there already.
+    fn preconnect_to(&mut self, url: Url) -> Task<DocumentMessage> {
+        let Some(key) = capsule(&url) else {
+            return Task::none();
+        };
+        if capsule(&self.url()).as_ref() == Some(&key) || !self.preconnected.insert(key.clone()) {
+            return Task::none();
+        }
+
+        let tls_config = self.tls_config_for(&url);
+        Task::future(async move {
+            let (host, port) = key;
+            log::debug!("Pre-connecting to {}:{}", host, port);
+
+            if let Err(e) = exchange::connect(&host, port, tls_config).await {
+                log::debug!("Pre-connect to {} failed: {}", host, e);
+            }
+        })
+        .discard()
+    }
+
     fn try_go_back(&mut self) -> Task<DocumentMessage> {
         if !self.can_go_back() {
             return Task::none();
@@ -221,57 +765,388 @@ impl Document {
         }
     }
 
-    async fn load_document(tls: Arc<ClientConfig>, url: Url) -> (Url, Result<LoadStatus, String>) {
+    async fn load_document(
+        tls: Arc<ClientConfig>,
+        url: Url,
+        capture: bool,
+        handlers: Arc<Handlers>,
+    ) -> (Url, Result<LoadStatus, String>, Option<Capture>) {
+        let mut raw = capture.then(|| Capture {
+            sent: SystemTime::now(),
+            ..Capture::default()
+        });
+
         let r = match url.scheme() {
-            "gemini" => Self::load_gemini(tls, &url).await,
+            "gemini" | "spartan" => {
+                Self::follow_redirects(tls, &url, raw.as_mut(), &handlers).await
+            }
+            "gopher" => Self::load_gopher(&url, raw.as_mut(), &handlers).await,
             "file" => Self::load_file(&url).await,
+            "about" => Self::load_about(&url),
             _ => Err(format!("Unsupported scheme: {}", url.scheme())),
         };
 
-        (url, r)
+        // Only Gemini, Spartan and Gopher requests have bytes on the wire.
+        let raw = raw
+            .filter(|raw| !raw.request.is_empty())
+            .map(|raw| Capture {
+                received: SystemTime::now(),
+                ..raw
+            });
+        (url, r, raw)
+    }
+
+    /// Loads `url` and the URLs of the same protocol it redirects to, see
+    /// [client::follow_redirects].
+    async fn follow_redirects(
+        tls_config: Arc<ClientConfig>,
+        url: &Url,
+        raw: Option<&mut Capture>,
+        handlers: &Handlers,
+    ) -> Result<LoadStatus, String> {
+        // Every load is captured on its own, the capture of the last one is kept.
+        let capture = raw.is_some();
+        let load = |url: Url| {
+            let tls_config = tls_config.clone();
+            async move {
+                let mut raw = capture.then(Capture::default);
+                let status = match url.scheme() {
+                    "spartan" => Self::load_spartan(&url, raw.as_mut(), handlers).await,
+                    _ => Self::load_gemini(tls_config, &url, raw.as_mut(), handlers).await,
+                };
+
+                Ok((status, raw))
+            }
+        };
+        let redirected = client::follow_redirects(
+            url,
+            client::MAX_REDIRECTS,
+            load,
+            |(status, _)| match status {
+                Ok(LoadStatus::Error(response)) => response.redirect_target(),
+                _ => None,
+            },
+        )
+        .await
+        .map_err(|e| match e {
+            FetchError::TooManyRedirects(max) => format!("Stopped after {} redirects", max),
+            e => format!("Not following the redirect: {}", e),
+        })?;
+        let targets = redirected.redirects.iter().skip(1).chain([&redirected.url]);
+        for (from, to) in redirected.redirects.iter().zip(targets) {
+            log::info!("{} redirected to {}", from, to);
+        }
+
+        let (status, last_raw) = redirected.response;
+        if let (Some(raw), Some(last_raw)) = (raw, last_raw) {
+            raw.request = last_raw.request;
+            raw.response = last_raw.response;
+        }
+
+        match status? {
+            LoadStatus::Success(mut data) => {
+                data.redirects = redirected.redirects;
+                Ok(LoadStatus::Success(data))
+            }
+            // Redirects to another protocol are left to the user.
+            LoadStatus::Error(
+                Response::TemporaryRedirect(target) | Response::PermanentRedirect(target),
+            ) => {
+                let target = redirected
+                    .url
+                    .join(&target)
+                    .map(String::from)
+                    .unwrap_or(target);
+                Err(format!("Not following the redirect to {}", target))
+            }
+            status => Ok(status),
+        }
     }
 
-    async fn load_gemini(tls_config: Arc<ClientConfig>, url: &Url) -> Result<LoadStatus, String> {
-        const DEFAULT_PORT: u16 = 1965;
+    async fn load_gemini(
+        tls_config: Arc<ClientConfig>,
+        url: &Url,
+        raw: Option<&mut Capture>,
+        handlers: &Handlers,
+    ) -> Result<LoadStatus, String> {
+        let url = &idn::to_ascii(url).map_err(|e| format!("Invalid host: {}", e))?;
+
+        let Exchange {
+            request,
+            header,
+            session,
+            body: stream,
+        } = match Exchange::send(url, tls_config).await {
+            Ok(exchange) => exchange,
+            Err(ExchangeError::Connect(NetworkError::HandshakeError(ref e, _)))
+                if let Some(changed) = CertificateChanged::from_error(e) =>
+            {
+                return Ok(LoadStatus::CertificateChanged(changed.clone()));
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        let certificate = session
+            .peer_certificates
+            .first()
+            .and_then(|cert| Pin::from_certificate(cert).ok());
+        let mut head = format!("{} {}\r\n", header.status, header.meta).into_bytes();
 
-        let host = url.host_str().ok_or("No host found")?;
-        let port = url.port().unwrap_or(DEFAULT_PORT);
+        if !header.is_success() || !is_text(&header.meta) {
+            if let Some(raw) = raw {
+                raw.request = request;
+                raw.response = head;
+            }
+
+            if !header.is_success() {
+                let r = header
+                    .response(url, b"")
+                    .map_err(|e| format!("Invalid response: {}", e))?;
+                return Ok(LoadStatus::Error(r));
+            }
+
+            return Ok(Self::download(url, header.meta, stream, handlers).await);
+        }
+
+        let mut body = vec![];
+        let termination = exchange::read_body(stream, &mut body);
+        if let Some(raw) = raw {
+            head.extend_from_slice(&body);
+            raw.request = request;
+            raw.response = head;
+        }
+        let termination = termination.map_err(|e| format!("Failed to read response: {}", e))?;
+        if termination == Termination::Truncated {
+            log::warn!("{} ended without a close_notify, it may be truncated", url);
+        }
 
-        let mut conn = TlsClient::new_from_host((host, port), tls_config.clone(), None)
+        let r = header
+            .response(url, &body)
+            .map_err(|e| format!("Invalid response: {}", e))?;
+
+        if let Response::Success(r) = r {
+            Ok(LoadStatus::Success(DocumentData {
+                url: url.clone(),
+                content: r,
+                truncated: termination == Termination::Truncated,
+                session: Some(session),
+                certificate,
+                modified: None,
+                redirects: vec![],
+            }))
+        } else {
+            Ok(LoadStatus::Error(r))
+        }
+    }
+
+    /// Like [Document::load_gemini], over plain TCP. The query of the URL is uploaded as the
+    /// content of the request.
+    async fn load_spartan(
+        url: &Url,
+        raw: Option<&mut Capture>,
+        handlers: &Handlers,
+    ) -> Result<LoadStatus, String> {
+        let url = &idn::to_ascii(url).map_err(|e| format!("Invalid host: {}", e))?;
+        let request = spartan::request::Request::from_url(url)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        let port = url.port().unwrap_or(spartan::DEFAULT_PORT);
+
+        let mut conn = TcpStream::connect((request.host.as_str(), port))
+            .await
             .map_err(|e| format!("Failed to connect: {}", e))?;
+        traffic::record_request(&request.host);
+        let request = request.to_bytes();
+        conn.write_all(&request)
+            .await
+            .map_err(|e| format!("Failed to send request: {}", e))?;
 
-        write!(conn, "{}\r\n", url.to_string()).unwrap();
+        let mut reader = BufReader::new(conn);
+        let header = spartan::response::Header::read(&mut reader)
+            .await
+            .map_err(|e| format!("Invalid response: {}", e))?;
+        let mut head = format!("{} {}\r\n", header.status, header.meta).into_bytes();
 
-        let mut pt = vec![];
-        conn.read_to_end(&mut pt).unwrap();
-        let pt = String::from_utf8_lossy(&pt).to_string();
+        if header.is_success() && !is_text(&header.meta) {
+            if let Some(raw) = raw {
+                raw.request = request;
+                raw.response = head;
+            }
 
-        let r = parse_response(&url, &pt).unwrap();
+            return Ok(Self::download(url, header.meta, reader, handlers).await);
+        }
+
+        let mut body = vec![];
+        reader
+            .read_to_end(&mut body)
+            .await
+            .map_err(|e| format!("Failed to read response: {}", e))?;
+        if let Some(raw) = raw {
+            head.extend_from_slice(&body);
+            raw.request = request;
+            raw.response = head;
+        }
+
+        let r = header
+            .response(url, &body)
+            .map_err(|e| format!("Invalid response: {}", e))?;
+
+        if let spartan::response::Response::Success(r) = r {
+            Ok(LoadStatus::Success(DocumentData {
+                url: url.clone(),
+                content: r,
+                truncated: false,
+                session: None,
+                certificate: None,
+                modified: None,
+                redirects: vec![],
+            }))
+        } else {
+            Ok(LoadStatus::Error(r.into()))
+        }
+    }
+
+    /// Like [Document::load_spartan], without a header. Menus are shown as gemtext, the
+    /// item type in the URL says whether the item is shown or downloaded.
+    async fn load_gopher(
+        url: &Url,
+        raw: Option<&mut Capture>,
+        handlers: &Handlers,
+    ) -> Result<LoadStatus, String> {
+        let url = &idn::to_ascii(url).map_err(|e| format!("Invalid host: {}", e))?;
+        let request = gopher::request::Request::from_url(url)
+            .map_err(|e| format!("Invalid request: {}", e))?;
+        if request.needs_search() {
+            return Ok(LoadStatus::Error(Response::MustPromptForInput(
+                "Search".to_string(),
+            )));
+        }
+        let port = url.port().unwrap_or(gopher::DEFAULT_PORT);
+
+        let mut conn = TcpStream::connect((request.host.as_str(), port))
+            .await
+            .map_err(|e| format!("Failed to connect: {}", e))?;
+        traffic::record_request(&request.host);
+        conn.write_all(&request.to_bytes())
+            .await
+            .map_err(|e| format!("Failed to send request: {}", e))?;
+
+        if !is_text(request.mime()) {
+            if let Some(raw) = raw {
+                raw.request = request.to_bytes();
+            }
+
+            return Ok(Self::download(url, request.mime().to_string(), conn, handlers).await);
+        }
+
+        let mut body = vec![];
+        conn.read_to_end(&mut body)
+            .await
+            .map_err(|e| format!("Failed to read response: {}", e))?;
+        if let Some(raw) = raw {
+            raw.request = request.to_bytes();
+            raw.response = body.clone();
+        }
+
+        let r = request
+            .response(url, &body)
+            .map_err(|e| format!("Invalid response: {}", e))?;
 
         if let Response::Success(r) = r {
             Ok(LoadStatus::Success(DocumentData {
                 url: url.clone(),
                 content: r,
+                truncated: false,
+                session: None,
+                certificate: None,
+                modified: None,
+                redirects: vec![],
             }))
         } else {
             Ok(LoadStatus::Error(r))
         }
     }
 
+    /// Saves a response that isn't text, then opens it with its handler.
+    async fn download(
+        url: &Url,
+        mime: String,
+        body: impl AsyncRead + Unpin,
+        handlers: &Handlers,
+    ) -> LoadStatus {
+        // Files that are opened right away go to a temporary directory, not the downloads.
+        let handler = handlers.find(&mime);
+        let play = handler.is_none() && player::is_inline_audio(&mime);
+        let dir = if handler.is_some() || play {
+            downloads::handler_dir()
+        } else {
+            downloads::download_dir()
+        };
+        let path = downloads::target_path(&dir, url);
+        log::info!("Saving {} ({}) to {:?}", url, mime, path);
+
+        let mut state = match downloads::save(body, &path).await {
+            Ok(size) => DownloadState::Finished { size },
+            Err(e) => DownloadState::Failed(e.to_string()),
+        };
+
+        let mut opened_with = None;
+        if let (Some(handler), DownloadState::Finished { .. }) = (handler, &state) {
+            let program = handler.command[0].clone();
+            match handler.launch(&path) {
+                Ok(()) => opened_with = Some(program),
+                Err(e) => {
+                    state = DownloadState::Failed(format!("Failed to start {}: {}", program, e))
+                }
+            }
+        }
+
+        LoadStatus::Download(Download {
+            url: url.clone(),
+            mime,
+            path,
+            state,
+            opened_with,
+        })
+    }
+
+    /// Pages of the browser itself.
+    fn load_about(url: &Url) -> Result<LoadStatus, String> {
+        let page = match url.path() {
+            "stats" => traffic::snapshot().to_gemtext(),
+            _ => return Err(format!("Unknown page: {}", url)),
+        };
+        let body = parse_gemtext(url, page).map_err(|e| format!("Invalid page: {}", e))?;
+
+        Ok(LoadStatus::Success(DocumentData {
+            url: url.clone(),
+            content: OkResponse {
+                mime: Default::default(),
+                body: Body::GemText(body),
+            },
+            truncated: false,
+            session: None,
+            certificate: None,
+            modified: None,
+            redirects: vec![],
+        }))
+    }
+
     async fn load_file(url: &Url) -> Result<LoadStatus, String> {
         use async_std::fs::File;
 
-        let path = url.path().strip_prefix("/").unwrap();
+        let path = url
+            .to_file_path()
+            .map_err(|_| format!("Not a local path: {}", url))?;
 
-        let mut file = File::open(path)
+        let mut file = File::open(&path)
             .await
             .map_err(|e| format!("Failed to open file: {}", e))?;
+        let modified = file.metadata().await.and_then(|m| m.modified()).ok();
 
         let mut content = String::new();
         file.read_to_string(&mut content)
             .await
             .map_err(|e| format!("Failed to read file: {}", e))?;
-        let r = match parse_gemtext(&url, content) {
+        let r = match parse_gemtext(url, content) {
             Ok(r) => r,
             Err(e) => return Err(format!("Failed to parse gemtext: {}", e)),
         };
@@ -280,12 +1155,77 @@ impl Document {
             url: url.clone(),
             content: OkResponse {
                 mime: Default::default(),
-                body: r,
+                body: Body::GemText(r),
             },
+            truncated: false,
+            session: None,
+            certificate: None,
+            modified,
+            redirects: vec![],
         }))
     }
 }
 
+/// Whether a response with `meta` is shown rather than downloaded, a missing MIME type
+/// means `text/gemini`.
+pub fn is_text(meta: &str) -> bool {
+    match MimeType::parse(meta) {
+        Some(mime) => mime.matches("text/*"),
+        None => meta.split(';').next().unwrap_or_default().trim().is_empty(),
+    }
+}
+
+/// The host and port a `gemini://` URL connects to.
+fn capsule(url: &Url) -> Option<(String, u16)> {
+    let url = idn::to_ascii(url)
+        .ok()
+        .filter(|url| url.scheme() == "gemini")?;
+
+    Some((
+        url.host_str()?.to_string(),
+        url.port().unwrap_or(DEFAULT_PORT),
+    ))
+}
+
+/// A line of a document that contains the query of a find.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FindMatch {
+    /// The index of the line in the document.
+    pub line: usize,
+    pub text: String,
+}
+
+/// The text a line shows, links by their description.
+pub fn line_text(line: &Line) -> String {
+    match line {
+        Line::Link {
+            description: Some(d),
+            ..
+        } => d.clone(),
+        Line::Link { url, .. } => url.to_string(),
+        Line::Heading { text, .. }
+        | Line::Text(text)
+        | Line::Quote(text)
+        | Line::ListItem(text) => text.clone(),
+        Line::Preformatted { lines, .. } => lines.join("\n"),
+    }
+}
+
+fn find_lines(lines: &[Line], query: &str) -> Vec<FindMatch> {
+    let query = query.to_lowercase();
+    if query.is_empty() {
+        return vec![];
+    }
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(line, l)| (line, line_text(l)))
+        .filter(|(_, text)| text.to_lowercase().contains(&query))
+        .map(|(line, text)| FindMatch { line, text })
+        .collect()
+}
+
 fn link_style(theme: &Theme, status: Status) -> Style {
     let text = theme.palette().primary;
 
@@ -298,24 +1238,24 @@ fn link_style(theme: &Theme, status: Status) -> Style {
 
     match status {
         Status::Pressed => {
-            let mut text = text.clone();
-            text.r = text.r * 0.9;
-            text.g = text.g * 0.9;
-            text.b = text.b * 0.9;
+            let mut text = text;
+            text.r *= 0.9;
+            text.g *= 0.9;
+            text.b *= 0.9;
 
-            let mut style = style.clone();
+            let mut style = style;
 
             style.text_color = text;
 
             style
         }
         Status::Hovered => {
-            let mut text = text.clone();
-            text.r = text.r * 1.1;
-            text.g = text.g * 1.1;
-            text.b = text.b * 1.1;
+            let mut text = text;
+            text.r *= 1.1;
+            text.g *= 1.1;
+            text.b *= 1.1;
 
-            let mut style = style.clone();
+            let mut style = style;
 
             style.text_color = text;
 
@@ -324,3 +1264,81 @@ fn link_style(theme: &Theme, status: Status) -> Style {
         _ => style,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_if_changed() {
+        let path = std::env::temp_dir().join(format!("gemini-reload-{}.gmi", std::process::id()));
+        std::fs::write(&path, "# Draft\n").unwrap();
+        let url = Url::from_file_path(&path).unwrap();
+
+        let Ok(LoadStatus::Success(data)) = async_std::task::block_on(Document::load_file(&url))
+        else {
+            panic!("{:?} didn't load", path);
+        };
+        assert_eq!(data.lines().len(), 1);
+
+        let mut document = Document {
+            tls_config: crate::network::tls_config::make_tls_config(false, Arc::default()).unwrap(),
+            history: LinkedList::from([url.clone()]),
+            state: DocumentState::Loaded(data),
+            preconnect: false,
+            preconnected: HashSet::new(),
+            capture: false,
+            raw_capture: None,
+            events: vec![],
+            handlers: Arc::default(),
+            player: None,
+            known_hosts: Arc::default(),
+            identities: Arc::default(),
+            identity_configs: HashMap::new(),
+            private: false,
+            background_reload: false,
+            updated: false,
+        };
+        assert!(document.is_watched());
+        assert_eq!(document.tab_status(), TabStatus::Idle);
+
+        let _ = document.reload_if_changed();
+        assert!(matches!(document.state, DocumentState::Loaded(_)));
+
+        let later = SystemTime::now() + std::time::Duration::from_secs(10);
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(later).unwrap();
+
+        let _ = document.reload_if_changed();
+        assert!(matches!(document.state, DocumentState::Loading));
+        assert_eq!(document.tab_status(), TabStatus::Loading);
+        assert_eq!(document.history.len(), 1);
+
+        let loaded = async_std::task::block_on(Document::load_file(&url));
+        let _ = document.update(DocumentMessage::LoadComplete((url.clone(), loaded, None)));
+        assert_eq!(document.tab_status(), TabStatus::Updated);
+
+        document.mark_seen();
+        assert_eq!(document.tab_status(), TabStatus::Idle);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_find_lines() {
+        let url = Url::parse("gemini://example.org/").unwrap();
+        let page = "# Gemini\nSome text\n=> /spec The GEMINI specification\n=> gemini://gemini.example/\n* a list";
+        let lines = parse_gemtext(&url, page.to_string()).unwrap().0;
+
+        let found = find_lines(&lines, "gemini");
+        assert_eq!(
+            found.iter().map(|m| m.line).collect::<Vec<_>>(),
+            vec![0, 2, 3]
+        );
+        assert_eq!(found[1].text, "The GEMINI specification");
+        assert_eq!(found[2].text, "gemini://gemini.example/");
+
+        assert!(find_lines(&lines, "").is_empty());
+        assert!(find_lines(&lines, "gopher").is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+use iced::futures::io::{self, AllowStdIo, AsyncRead};
+use percent_encoding::percent_decode_str;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Appended to the name of a download until all of it has been written.
+const PART_SUFFIX: &str = ".part";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum DownloadState {
+    Finished {
+        size: u64,
+    },
+    /// Nothing is left on disk, retrying starts over.
+    Failed(String),
+}
+
+/// A response that isn't text, saved to disk instead of shown.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Download {
+    pub url: Url,
+    /// The meta of the response.
+    pub mime: String,
+    pub path: PathBuf,
+    pub state: DownloadState,
+    /// The program of the [crate::handlers::Handler] the file was opened with.
+    pub opened_with: Option<String>,
+}
+
+/// The user's download directory, or the temporary directory when there is none.
+pub fn download_dir() -> PathBuf {
+    dirs::download_dir().unwrap_or_else(std::env::temp_dir)
+}
+
+/// Where files are kept while an external program has them open.
+pub fn handler_dir() -> PathBuf {
+    std::env::temp_dir().join("gemini")
+}
+
+/// Where `url` is saved in `dir`, named after the last segment of its path. Files that
+/// exist already aren't overwritten, the name gets a number instead.
+pub fn target_path(dir: &Path, url: &Url) -> PathBuf {
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().to_string())
+        .filter(|name| !matches!(name.as_str(), "" | "." | "..") && !name.contains(['/', '\\']))
+        .unwrap_or_else(|| url.host_str().unwrap_or("download").to_string());
+
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name.as_str(), String::new()),
+    };
+
+    (1..)
+        .map(|n| match n {
+            1 => dir.join(&name),
+            n => dir.join(format!("{} ({}){}", stem, n, extension)),
+        })
+        .find(|path| !path.exists() && !part_path(path).exists())
+        .unwrap()
+}
+
+/// `path` with [PART_SUFFIX] appended.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(PART_SUFFIX);
+
+    PathBuf::from(part)
+}
+
+/// Streams `body` into the `.part` file of `path` and moves it to `path` once it has been
+/// written and synced. The `.part` file is removed when that fails.
+pub async fn save(body: impl AsyncRead + Unpin, path: &Path) -> std::io::Result<u64> {
+    let part = part_path(path);
+
+    let result = async {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = AllowStdIo::new(File::create(&part)?);
+        let size = io::copy(body, &mut file).await?;
+        file.into_inner().sync_all()?;
+        std::fs::rename(&part, path)?;
+
+        Ok(size)
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&part);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iced::futures::io::Cursor;
+
+    /// Fails after the first few bytes, like a dropped connection.
+    struct Broken(usize);
+
+    impl AsyncRead for Broken {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut [u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            if self.0 == 0 {
+                return std::task::Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+
+            let n = self.0.min(buf.len());
+            buf[..n].fill(b'x');
+            self.0 -= n;
+            std::task::Poll::Ready(Ok(n))
+        }
+    }
+
+    #[test]
+    fn test_save() {
+        let dir = std::env::temp_dir().join(format!("gemini-downloads-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let url = Url::parse("gemini://example.org/music/song%20one.ogg").unwrap();
+
+        let path = target_path(&dir, &url);
+        assert_eq!(path, dir.join("song one.ogg"));
+
+        let body = vec![0xff; 100_000];
+        let size = async_std::task::block_on(save(Cursor::new(body.clone()), &path)).unwrap();
+        assert_eq!(size, 100_000);
+        assert_eq!(std::fs::read(&path).unwrap(), body);
+        assert!(!part_path(&path).exists());
+
+        let second = target_path(&dir, &url);
+        assert_eq!(second, dir.join("song one (2).ogg"));
+        assert!(async_std::task::block_on(save(Broken(10), &second)).is_err());
+        assert!(!second.exists());
+        assert!(!part_path(&second).exists());
+
+        let root = Url::parse("gemini://example.org/").unwrap();
+        assert_eq!(target_path(&dir, &root), dir.join("example.org"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt::{Debug, Formatter};
+use url::Url;
+
+/// What happened while a document navigated.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum NavigationEvent {
+    Started(Url),
+    /// The header of the response, before the document shows it.
+    ResponseReceived {
+        url: Url,
+        response: String,
+    },
+    Finished(Url),
+    /// The page couldn't be loaded or the server answered with an error.
+    Failed {
+        url: Url,
+        error: String,
+    },
+}
+
+/// A navigation event and the tab it happened in.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Event {
+    pub tab: usize,
+    pub navigation: NavigationEvent,
+}
+
+/// Reacts to what the documents do, without the documents knowing about it.
+pub trait EventListener {
+    fn on_event(&mut self, event: &Event);
+}
+
+/// Passes every event to the listeners, in the order they subscribed.
+#[derive(Default)]
+pub struct EventBus {
+    listeners: Vec<Box<dyn EventListener>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&mut self, listener: impl EventListener + 'static) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    pub fn publish(&mut self, event: Event) {
+        for listener in &mut self.listeners {
+            listener.on_event(&event);
+        }
+    }
+}
+
+impl Debug for EventBus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventBus")
+            .field("listeners", &self.listeners.len())
+            .finish()
+    }
+}
+
+/// Logs every navigation.
+pub struct NavigationLog;
+
+impl EventListener for NavigationLog {
+    fn on_event(&mut self, event: &Event) {
+        match &event.navigation {
+            NavigationEvent::Started(url) => log::info!("[{}] Loading {}", event.tab, url),
+            NavigationEvent::ResponseReceived { url, response } => {
+                log::debug!("[{}] {}: {}", event.tab, url, response)
+            }
+            NavigationEvent::Finished(url) => log::info!("[{}] Loaded {}", event.tab, url),
+            NavigationEvent::Failed { url, error } => {
+                log::error!("[{}] Failed to load {}: {}", event.tab, url, error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct Record(Arc<Mutex<Vec<Event>>>);
+
+    impl EventListener for Record {
+        fn on_event(&mut self, event: &Event) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn test_event_bus() {
+        let first = Arc::new(Mutex::new(vec![]));
+        let second = Arc::new(Mutex::new(vec![]));
+
+        let mut bus = EventBus::default();
+        bus.subscribe(Record(first.clone()));
+        bus.subscribe(Record(second.clone()));
+
+        let url = Url::parse("gemini://geminiprotocol.net/").unwrap();
+        let events = [
+            NavigationEvent::Started(url.clone()),
+            NavigationEvent::Finished(url.clone()),
+        ]
+        .map(|navigation| Event { tab: 1, navigation });
+        for event in events.clone() {
+            bus.publish(event);
+        }
+
+        assert_eq!(*first.lock().unwrap(), events);
+        assert_eq!(*second.lock().unwrap(), events);
+    }
+}
@@ -0,0 +1,112 @@
+use protocol::gemtext::gemtext_body::MimeType;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A program that opens responses of the MIME types matching `pattern`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Handler {
+    /// `image/png`, `image/*` or `*/*`.
+    pub pattern: String,
+    /// The program and its arguments, the file is passed after them.
+    pub command: Vec<String>,
+}
+
+impl Handler {
+    fn matches(&self, mime: &str) -> bool {
+        MimeType::parse(mime).is_some_and(|mime| mime.matches(&self.pattern))
+    }
+
+    /// Starts the program on `path` without waiting for it.
+    pub fn launch(&self, path: &Path) -> io::Result<()> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or(io::ErrorKind::InvalidInput)?;
+
+        let mut child = Command::new(program).args(args).arg(path).spawn()?;
+        std::thread::spawn(move || child.wait());
+
+        Ok(())
+    }
+}
+
+/// The external programs responses that aren't text are opened with, instead of being
+/// saved to the download directory.
+///
+/// Each line of the file maps a MIME type to a command, the first match wins:
+///
+/// ```text
+/// image/* feh
+/// audio/* mpv --no-video
+/// ```
+#[derive(Debug, Default)]
+pub struct Handlers(Vec<Handler>);
+
+impl Handlers {
+    /// `handlers` in the `gemini` directory of the user's config directory.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("gemini").join("handlers"))
+    }
+
+    /// No handlers when the file doesn't exist.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Handlers::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Blank lines, comments starting with `#` and lines without a command are skipped.
+    pub fn parse(content: &str) -> Self {
+        let handlers = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut words = line.split_whitespace().map(str::to_string);
+                let pattern = words.next()?;
+                let command = words.collect::<Vec<_>>();
+
+                if command.is_empty() {
+                    log::warn!("The handler of {} has no command", pattern);
+                    return None;
+                }
+
+                Some(Handler { pattern, command })
+            })
+            .collect();
+
+        Handlers(handlers)
+    }
+
+    pub fn find(&self, mime: &str) -> Option<&Handler> {
+        self.0.iter().find(|handler| handler.matches(mime))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let handlers = Handlers::parse(
+            "# Viewers\nimage/svg+xml inkview\nimage/* feh --scale-down\n\naudio/*\nAUDIO/* mpv --no-video\n*/* xdg-open\n",
+        );
+        let program = |mime| handlers.find(mime).map(|h| h.command.join(" "));
+
+        assert_eq!(program("image/svg+xml").as_deref(), Some("inkview"));
+        assert_eq!(program("image/png").as_deref(), Some("feh --scale-down"));
+        assert_eq!(
+            program("audio/ogg; charset=binary").as_deref(),
+            Some("mpv --no-video")
+        );
+        assert_eq!(program("application/pdf").as_deref(), Some("xdg-open"));
+
+        let handlers = Handlers::parse("video/mp4 mpv");
+        assert!(handlers.find("video/webm").is_none());
+        assert!(handlers.find("VIDEO/MP4").is_some());
+    }
+}
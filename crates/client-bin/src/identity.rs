@@ -0,0 +1,451 @@
+use crypto::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use crypto::pbkdf2;
+use crypto::rand::{SecureRandom, SystemRandom};
+use rcgen::{CertificateParams, DnType, KeyPair};
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+use std::io::{self, Write};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use time::{Duration, OffsetDateTime};
+use url::Url;
+
+// Identities are sealed with the crypto library of the TLS provider the client is built with.
+#[cfg(feature = "aws-lc-rs")]
+use aws_lc_rs as crypto;
+#[cfg(all(feature = "ring", not(feature = "aws-lc-rs")))]
+use ring as crypto;
+
+/// The environment variable with the passphrase of the identities file. Without it
+/// identities aren't saved.
+pub const PASSPHRASE_VAR: &str = "GEMINI_IDENTITY_PASSPHRASE";
+
+/// How long a generated certificate is valid, capsules tie accounts to the certificate so
+/// it shouldn't expire while in use.
+const VALIDITY: Duration = Duration::days(5 * 365);
+
+/// The start of the identities file, followed by the salt, the nonce and the sealed
+/// identities.
+const MAGIC: &[u8] = b"gemini-identities 1\n";
+
+const SALT_LEN: usize = 16;
+
+/// Rounds of PBKDF2 that turn the passphrase into the key of the identities file.
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// A client certificate and its key, sent to the capsules below its prefixes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Identity {
+    pub name: String,
+    pub certificate: CertificateDer<'static>,
+    /// The PKCS#8 DER of the private key.
+    key: Vec<u8>,
+    /// The certificate applies to these URLs and the paths below them, see [covers].
+    pub prefixes: Vec<Url>,
+}
+
+impl Identity {
+    /// A new self-signed certificate with `name` as its common name.
+    pub fn generate(name: &str) -> Result<Self, rcgen::Error> {
+        let key_pair = KeyPair::generate()?;
+
+        let mut params = CertificateParams::new(vec![])?;
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.not_before = OffsetDateTime::now_utc();
+        params.not_after = params.not_before + VALIDITY;
+
+        let certificate = params.self_signed(&key_pair)?;
+
+        Ok(Identity {
+            name: name.to_string(),
+            certificate: certificate.der().clone(),
+            key: key_pair.serialize_der(),
+            prefixes: vec![],
+        })
+    }
+
+    pub fn key(&self) -> PrivatePkcs8KeyDer<'static> {
+        PrivatePkcs8KeyDer::from(self.key.clone())
+    }
+
+    /// The length of the longest prefix that covers `url`.
+    fn matches(&self, url: &Url) -> Option<usize> {
+        self.prefixes
+            .iter()
+            .filter(|prefix| covers(prefix, url))
+            .map(|prefix| prefix.path().len())
+            .max()
+    }
+
+    fn to_line(&self) -> String {
+        let mut line = format!(
+            "{} {} {}",
+            self.name,
+            hex(&self.certificate),
+            hex(&self.key)
+        );
+        for prefix in &self.prefixes {
+            line.push(' ');
+            line.push_str(prefix.as_str());
+        }
+
+        line
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace();
+        let name = parts.next()?.to_string();
+        let certificate = CertificateDer::from(unhex(parts.next()?)?);
+        let key = unhex(parts.next()?)?;
+        let prefixes = parts.map(Url::parse).collect::<Result<_, _>>().ok()?;
+
+        Some(Identity {
+            name,
+            certificate,
+            key,
+            prefixes,
+        })
+    }
+}
+
+/// Whether the certificate of `prefix` applies to `url`, which it does for the same
+/// capsule at the path of `prefix` or below it.
+fn covers(prefix: &Url, url: &Url) -> bool {
+    let Some(rest) = url.path().strip_prefix(prefix.path()) else {
+        return false;
+    };
+
+    prefix.scheme() == url.scheme()
+        && prefix.host_str() == url.host_str()
+        && prefix.port_or_known_default() == url.port_or_known_default()
+        && (rest.is_empty() || rest.starts_with('/') || prefix.path().ends_with('/'))
+}
+
+/// The client certificates of the user.
+///
+/// The file starts with [MAGIC], a salt and a nonce, then the identities sealed with
+/// ChaCha20-Poly1305 under a key derived from the passphrase. Sealed are lines of the name,
+/// the certificate and key in hex and the prefixes of an identity:
+///
+/// ```text
+/// example.org 3082...01 3081...a2 gemini://example.org/app/
+/// ```
+#[derive(Debug, Default)]
+pub struct IdentityStore {
+    /// Where the identities are saved, `None` keeps them in memory only.
+    path: Option<PathBuf>,
+    /// The salt and the key derived from the passphrase.
+    sealing: Option<([u8; SALT_LEN], LessSafeKey)>,
+    identities: Mutex<Vec<Identity>>,
+}
+
+impl IdentityStore {
+    /// `identities` in the `gemini` directory of the user's config directory.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("gemini").join("identities"))
+    }
+
+    /// Starts out empty when the file doesn't exist yet, fails with
+    /// [io::ErrorKind::InvalidData] when `passphrase` doesn't open it.
+    pub fn open(path: PathBuf, passphrase: &str) -> io::Result<Self> {
+        let (salt, key, identities) = match std::fs::read(&path) {
+            Ok(content) => {
+                let (salt, sealed) = content
+                    .strip_prefix(MAGIC)
+                    .filter(|rest| rest.len() >= SALT_LEN)
+                    .map(|rest| rest.split_at(SALT_LEN))
+                    .ok_or_else(|| invalid("Not an identities file"))?;
+                let salt = <[u8; SALT_LEN]>::try_from(salt).unwrap();
+                let key = derive_key(passphrase, &salt);
+                let identities = unseal(&key, sealed)?;
+
+                (salt, key, identities)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut salt = [0; SALT_LEN];
+                SystemRandom::new()
+                    .fill(&mut salt)
+                    .map_err(|_| io::Error::other("No randomness for the salt"))?;
+
+                (salt, derive_key(passphrase, &salt), vec![])
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(IdentityStore {
+            path: Some(path),
+            sealing: Some((salt, key)),
+            identities: Mutex::new(identities),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<Identity> {
+        self.identities
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|identity| identity.name == name)
+            .cloned()
+    }
+
+    /// The identity to send with a request for `url`, the one with the longest prefix
+    /// covering it.
+    pub fn for_url(&self, url: &Url) -> Option<Identity> {
+        self.identities
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|identity| Some((identity.matches(url)?, identity)))
+            .max_by_key(|(length, _)| *length)
+            .map(|(_, identity)| identity.clone())
+    }
+
+    /// Generates an identity named `name`, which has no whitespace, used below `prefix`.
+    pub fn create(&self, name: &str, prefix: &Url) -> io::Result<Identity> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(invalid(
+                "Identity names can't be empty or contain whitespace",
+            ));
+        }
+        if self.get(name).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("There is an identity named {} already", name),
+            ));
+        }
+
+        let mut identity = Identity::generate(name).map_err(io::Error::other)?;
+        identity.prefixes.push(prefix_of(prefix));
+
+        // Kept in memory only once it is saved, so it is never used without being stored.
+        let mut identities = self.identities.lock().unwrap();
+        self.save(&[identities.as_slice(), std::slice::from_ref(&identity)].concat())?;
+        identities.push(identity.clone());
+
+        Ok(identity)
+    }
+
+    /// Sends the identity named `name` below `prefix` as well.
+    pub fn associate(&self, name: &str, prefix: &Url) -> io::Result<()> {
+        let mut identities = self.identities.lock().unwrap();
+        let mut updated = identities.clone();
+        let identity = updated
+            .iter_mut()
+            .find(|identity| identity.name == name)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No identity named {}", name),
+                )
+            })?;
+
+        let prefix = prefix_of(prefix);
+        if identity.prefixes.contains(&prefix) {
+            return Ok(());
+        }
+        identity.prefixes.push(prefix);
+
+        self.save(&updated)?;
+        *identities = updated;
+
+        Ok(())
+    }
+
+    fn save(&self, identities: &[Identity]) -> io::Result<()> {
+        let (Some(path), Some((salt, key))) = (&self.path, &self.sealing) else {
+            return Ok(());
+        };
+
+        let mut content = String::new();
+        for identity in identities {
+            content.push_str(&identity.to_line());
+            content.push('\n');
+        }
+
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| io::Error::other("No randomness for the nonce"))?;
+
+        let mut sealed = content.into_bytes();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut sealed,
+        )
+        .map_err(|_| io::Error::other("Failed to seal the identities"))?;
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        // The sealed file is still only for the user to read.
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(path)?;
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+        file.write_all(&[MAGIC, salt, &nonce, &sealed].concat())
+    }
+}
+
+/// `url` without its query and fragment.
+fn prefix_of(url: &Url) -> Url {
+    let mut prefix = url.clone();
+    prefix.set_query(None);
+    prefix.set_fragment(None);
+
+    prefix
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &key).unwrap())
+}
+
+fn unseal(key: &LessSafeKey, sealed: &[u8]) -> io::Result<Vec<Identity>> {
+    if sealed.len() < NONCE_LEN {
+        return Err(invalid("Not an identities file"));
+    }
+    let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).unwrap();
+
+    let mut content = sealed.to_vec();
+    let content = key
+        .open_in_place(nonce, Aad::empty(), &mut content)
+        .map_err(|_| invalid("Wrong passphrase or a damaged identities file"))?;
+    let content = std::str::from_utf8(content).map_err(|_| invalid("Not an identities file"))?;
+
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Identity::from_line(line).ok_or_else(|| invalid("Invalid identity")))
+        .collect()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_url() {
+        let store = IdentityStore::default();
+        let app = Url::parse("gemini://example.org/app/?login").unwrap();
+        store.create("app", &app).unwrap();
+        store
+            .create(
+                "admin",
+                &Url::parse("gemini://example.org/app/admin").unwrap(),
+            )
+            .unwrap();
+        store
+            .associate("app", &Url::parse("gemini://example.net/").unwrap())
+            .unwrap();
+        assert!(store.create("app", &app).is_err());
+        assert!(store.create("my app", &app).is_err());
+
+        let name = |url: &str| {
+            store
+                .for_url(&Url::parse(url).unwrap())
+                .map(|identity| identity.name)
+        };
+        assert_eq!(name("gemini://example.org/app/"), Some("app".to_string()));
+        assert_eq!(
+            name("gemini://example.org/app/page?query"),
+            Some("app".to_string())
+        );
+        assert_eq!(
+            name("gemini://example.org/app/admin"),
+            Some("admin".to_string())
+        );
+        assert_eq!(
+            name("gemini://example.org/app/admin/users"),
+            Some("admin".to_string())
+        );
+        assert_eq!(
+            name("gemini://example.org/app/administration"),
+            Some("app".to_string())
+        );
+        assert_eq!(
+            name("gemini://example.net/anything"),
+            Some("app".to_string())
+        );
+        assert_eq!(name("gemini://example.org/"), None);
+        assert_eq!(name("gemini://example.org:1966/app/"), None);
+        assert_eq!(name("gemini://example.com/app/"), None);
+
+        let identity = store.get("app").unwrap();
+        assert_eq!(identity.prefixes[0].as_str(), "gemini://example.org/app/");
+        assert_eq!(Identity::from_line(&identity.to_line()), Some(identity));
+    }
+
+    #[test]
+    fn test_open() {
+        let path = std::env::temp_dir().join(format!("gemini-identities-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let store = IdentityStore::open(path.clone(), "secret").unwrap();
+        let created = store
+            .create("example.org", &Url::parse("gemini://example.org/").unwrap())
+            .unwrap();
+        assert!(!std::fs::read(&path)
+            .unwrap()
+            .windows(11)
+            .any(|w| w == b"example.org"));
+
+        let reopened = IdentityStore::open(path.clone(), "secret").unwrap();
+        assert_eq!(reopened.get("example.org"), Some(created));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let e = IdentityStore::open(path.clone(), "wrong").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // An identity that can't be saved isn't used either.
+        let blocked = path.with_extension("blocked");
+        let unwritable = IdentityStore::open(blocked.join("identities"), "secret").unwrap();
+        std::fs::write(&blocked, "not a directory").unwrap();
+        assert!(unwritable
+            .create("other.org", &Url::parse("gemini://other.org/").unwrap())
+            .is_err());
+        assert_eq!(unwritable.get("other.org"), None);
+
+        std::fs::remove_file(&blocked).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
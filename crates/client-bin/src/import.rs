@@ -0,0 +1,256 @@
+use crate::bookmarks::Bookmark;
+use std::io;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// A client whose bookmarks can be imported.
+///
+/// Only bookmarks are imported. This client has no client certificates yet, so there is
+/// nowhere to put the identities of the others.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Client {
+    /// `bookmarks.ini`, one numbered section per bookmark.
+    Lagrange,
+    /// `bookmarks.xml` in XBEL, or `bookmarks.toml` before Amfora 1.8.
+    Amfora,
+}
+
+impl Client {
+    pub const ALL: [Client; 2] = [Client::Lagrange, Client::Amfora];
+
+    /// Where the client keeps its bookmarks on this platform, whether or not they exist.
+    pub fn bookmark_paths(self) -> Vec<PathBuf> {
+        match self {
+            Client::Lagrange => {
+                let dir = if cfg!(target_os = "linux") {
+                    "lagrange"
+                } else {
+                    "fi.skyjake.Lagrange"
+                };
+
+                dirs::config_dir()
+                    .map(|config| vec![config.join(dir).join("bookmarks.ini")])
+                    .unwrap_or_default()
+            }
+            Client::Amfora => [dirs::data_dir(), dirs::config_dir()]
+                .into_iter()
+                .flatten()
+                .flat_map(|dir| {
+                    let dir = dir.join("amfora");
+                    [dir.join("bookmarks.xml"), dir.join("bookmarks.toml")]
+                })
+                .collect(),
+        }
+    }
+
+    pub fn parse_bookmarks(self, path: &Path, content: &str) -> Vec<Bookmark> {
+        match self {
+            Client::Lagrange => lagrange_bookmarks(content),
+            Client::Amfora if path.extension().is_some_and(|ext| ext == "toml") => {
+                amfora_toml_bookmarks(content)
+            }
+            Client::Amfora => amfora_xbel_bookmarks(content),
+        }
+    }
+}
+
+/// The bookmarks of every client that has some, skipping the files that don't exist.
+pub fn find_bookmarks() -> io::Result<Vec<(Client, Vec<Bookmark>)>> {
+    let mut found = vec![];
+
+    for client in Client::ALL {
+        for path in client.bookmark_paths() {
+            let content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            log::info!("Importing the {:?} bookmarks in {:?}", client, path);
+            found.push((client, client.parse_bookmarks(&path, &content)));
+        }
+    }
+
+    Ok(found)
+}
+
+/// ```text
+/// [1]
+/// url = "gemini://geminiprotocol.net/"
+/// title = "Project Gemini"
+/// tags = "homepage"
+/// ```
+fn lagrange_bookmarks(content: &str) -> Vec<Bookmark> {
+    let mut bookmarks = vec![];
+    let mut url = None;
+    let mut title = None;
+
+    // A section header ends the bookmark before it, the end of the file the last one.
+    for line in content.lines().map(str::trim).chain(["[]"]) {
+        if line.starts_with('[') {
+            if let Some(url) = url.take() {
+                bookmarks.extend(bookmark(url, title.take()));
+            }
+            title = None;
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match key.trim() {
+            "url" => url = Some(unquote(value)),
+            "title" => title = Some(unquote(value)),
+            _ => {}
+        }
+    }
+
+    bookmarks
+}
+
+/// ```text
+/// [bookmarks]
+/// "gemini://geminiprotocol.net/" = "Project Gemini"
+/// ```
+fn amfora_toml_bookmarks(content: &str) -> Vec<Bookmark> {
+    let mut in_bookmarks = false;
+
+    content
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| {
+            if line.starts_with('[') {
+                in_bookmarks = line == "[bookmarks]";
+                return None;
+            }
+            if !in_bookmarks {
+                return None;
+            }
+
+            let (url, title) = line.split_once("\" =")?;
+            bookmark(unquote(&format!("{}\"", url)), Some(unquote(title)))
+        })
+        .collect()
+}
+
+/// ```text
+/// <bookmark href="gemini://geminiprotocol.net/">
+///   <title>Project Gemini</title>
+/// </bookmark>
+/// ```
+fn amfora_xbel_bookmarks(content: &str) -> Vec<Bookmark> {
+    content
+        .split("<bookmark ")
+        .skip(1)
+        .filter_map(|element| {
+            let element = element.split("</bookmark>").next()?;
+            let href = element.split_once("href=\"")?.1.split_once('"')?.0;
+            let title = element
+                .split_once("<title>")
+                .and_then(|(_, rest)| rest.split_once("</title>"))
+                .map(|(title, _)| unescape_xml(title));
+
+            bookmark(unescape_xml(href), title)
+        })
+        .collect()
+}
+
+/// Links that don't parse are skipped, a missing title is the URL.
+fn bookmark(url: String, title: Option<String>) -> Option<Bookmark> {
+    let Ok(url) = Url::parse(&url) else {
+        log::warn!("Skipping the bookmark of {:?}, it isn't a URL", url);
+        return None;
+    };
+
+    Some(Bookmark {
+        title: title
+            .filter(|t| !t.trim().is_empty())
+            .unwrap_or_else(|| url.to_string()),
+        url,
+    })
+}
+
+/// A TOML or INI string, with its quotes and escapes removed.
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+        return value.to_string();
+    };
+
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+
+    out
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn titles(bookmarks: Vec<Bookmark>) -> Vec<(String, String)> {
+        bookmarks
+            .into_iter()
+            .map(|b| (b.url.to_string(), b.title))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_bookmarks() {
+        let lagrange = "[1]\nurl = \"gemini://geminiprotocol.net/\"\ntitle = \"Project \\\"Gemini\\\"\"\ntags = \"homepage\"\ncreated = 1600000000\n\n[2]\nurl = \"gemini://example.org/\"\n\n[3]\nurl = \"not a url\"\ntitle = \"Broken\"\n";
+        assert_eq!(
+            titles(Client::Lagrange.parse_bookmarks(Path::new("bookmarks.ini"), lagrange)),
+            vec![
+                (
+                    "gemini://geminiprotocol.net/".to_string(),
+                    "Project \"Gemini\"".to_string()
+                ),
+                (
+                    "gemini://example.org/".to_string(),
+                    "gemini://example.org/".to_string()
+                ),
+            ]
+        );
+
+        let xbel = "<?xml version=\"1.0\"?>\n<xbel version=\"1.0\">\n  <bookmark href=\"gemini://example.org/?a=1&amp;b=2\">\n    <title>Fish &amp; Chips</title>\n  </bookmark>\n  <folder><bookmark href=\"gemini://example.net/\"></bookmark></folder>\n</xbel>\n";
+        assert_eq!(
+            titles(Client::Amfora.parse_bookmarks(Path::new("bookmarks.xml"), xbel)),
+            vec![
+                (
+                    "gemini://example.org/?a=1&b=2".to_string(),
+                    "Fish & Chips".to_string()
+                ),
+                (
+                    "gemini://example.net/".to_string(),
+                    "gemini://example.net/".to_string()
+                ),
+            ]
+        );
+
+        let toml = "[other]\n\"gemini://skipped.org/\" = \"No\"\n\n[bookmarks]\n\"gemini://example.org/\" = \"Example\"\n";
+        assert_eq!(
+            titles(Client::Amfora.parse_bookmarks(Path::new("bookmarks.toml"), toml)),
+            vec![("gemini://example.org/".to_string(), "Example".to_string())]
+        );
+    }
+}
@@ -1,8 +1,19 @@
 use crate::window::GeminiRootWindow;
 use iced::Font;
 
+mod archive;
+mod bookmarks;
 mod document;
+mod downloads;
+mod events;
+mod handlers;
+mod identity;
+mod import;
 mod network;
+mod player;
+mod read_aloud;
+#[cfg(unix)]
+mod remote;
 mod window;
 
 const DEJA_VU_MONO: &[u8] = include_bytes!("../../../assets/DejaVuSansMono.ttf");
@@ -15,14 +26,43 @@ fn main() {
         .try_init()
         .unwrap();
 
+    // Sends the rest of the arguments to the running client as a command, see
+    // [remote::Command], instead of starting one.
+    #[cfg(unix)]
+    if let Some(at) = std::env::args().position(|arg| arg == "--remote") {
+        let command = std::env::args().skip(at + 1).collect::<Vec<_>>().join(" ");
+        let path = remote::socket_path();
+
+        match async_std::task::block_on(remote::send(&path, &command)) {
+            Ok(reply) => {
+                print!("{}", reply);
+                if reply.starts_with("error: ") {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to reach the client at {:?}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Writes the TLS secrets to SSLKEYLOGFILE, to decrypt captures while debugging.
+    let keylog = std::env::args().skip(1).any(|arg| arg == "--keylog");
+    if keylog {
+        log::warn!("--keylog is set, TLS secrets are written to SSLKEYLOGFILE");
+    }
+
     iced::application(
         "Gemini Browser",
         GeminiRootWindow::update,
         GeminiRootWindow::view,
     )
+    .subscription(GeminiRootWindow::subscription)
     .font(DEJA_VU_MONO)
     .font(NOTO_COLOR_EMOJI)
     .default_font(Font::with_name("DejaVu Sans"))
-    .run_with(GeminiRootWindow::new)
+    .run_with(move || GeminiRootWindow::new(keylog))
     .unwrap();
 }
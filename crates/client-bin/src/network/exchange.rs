@@ -0,0 +1,102 @@
+use crate::document::DEFAULT_PORT;
+use crate::network::tls_client::{SessionInfo, Termination, TlsClient};
+use crate::network::traffic;
+use crate::network::NetworkError;
+use iced::futures::io::{AllowStdIo, BufReader};
+use protocol::error::{ParserError, RequestError};
+use protocol::gemini_protocol::parser::{Header, ResponseStream};
+use protocol::gemini_protocol::request::Request;
+use rustls::ClientConfig;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Arc;
+use url::Url;
+
+/// A Gemini request whose response header has arrived, the body is left to read or to
+/// stream to disk.
+pub struct Exchange {
+    /// The request as it was sent.
+    pub request: Vec<u8>,
+    pub header: Header,
+    /// What was negotiated with the server.
+    pub session: SessionInfo,
+    pub body: Body,
+}
+
+/// The rest of the connection after the response header.
+pub type Body = BufReader<AllowStdIo<TlsClient>>;
+
+/// Where [Exchange::send] failed.
+pub enum ExchangeError {
+    Request(RequestError),
+    /// Connecting or the TLS handshake failed, see [NetworkError::HandshakeError] for a
+    /// certificate the server shouldn't have presented.
+    Connect(NetworkError),
+    Send(io::Error),
+    Header(ParserError),
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExchangeError::Request(e) => write!(f, "Invalid request: {}", e),
+            ExchangeError::Connect(e) => write!(f, "Failed to connect: {}", e),
+            ExchangeError::Send(e) => write!(f, "Failed to send request: {}", e),
+            ExchangeError::Header(e) => write!(f, "Invalid response: {}", e),
+        }
+    }
+}
+
+/// Connects to `host` and finishes the handshake, so the session is in the session cache
+/// of `tls_config` before anything is sent.
+pub async fn connect(
+    host: &str,
+    port: u16,
+    tls_config: Arc<ClientConfig>,
+) -> Result<TlsClient, NetworkError> {
+    let mut conn = TlsClient::new_from_host((host, port), tls_config, None).await?;
+    conn.complete_handshake()?;
+
+    Ok(conn)
+}
+
+impl Exchange {
+    /// Requests `url`, whose host has to be ASCII already, and reads the response header.
+    pub async fn send(url: &Url, tls_config: Arc<ClientConfig>) -> Result<Self, ExchangeError> {
+        let host = url
+            .host_str()
+            .ok_or(ExchangeError::Connect(NetworkError::InvalidAddress))?;
+        let port = url.port().unwrap_or(DEFAULT_PORT);
+        let request = Request::new(url)
+            .map_err(ExchangeError::Request)?
+            .to_bytes();
+
+        let mut conn = connect(host, port, tls_config)
+            .await
+            .map_err(ExchangeError::Connect)?;
+        let session = conn.session_info();
+
+        conn.write_all(&request).map_err(ExchangeError::Send)?;
+        traffic::record_request(host);
+
+        let stream = ResponseStream::new(BufReader::new(AllowStdIo::new(conn)))
+            .await
+            .map_err(ExchangeError::Header)?;
+
+        Ok(Exchange {
+            request,
+            header: stream.header,
+            session,
+            body: stream.body,
+        })
+    }
+}
+
+/// Reads the rest of the response into `buf`, see [TlsClient::read_to_close].
+pub fn read_body(body: Body, buf: &mut Vec<u8>) -> Result<Termination, NetworkError> {
+    // Whatever came in with the header is already in the reader's buffer.
+    buf.extend_from_slice(body.buffer());
+    let mut conn = body.into_inner().into_inner();
+
+    conn.read_to_close(buf)
+}
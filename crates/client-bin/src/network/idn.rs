@@ -0,0 +1,147 @@
+use crate::network::NetworkError;
+use percent_encoding::percent_decode_str;
+use protocol::url_util;
+use url::{Position, Url};
+
+/// Cyrillic letters that are drawn like Latin ones.
+const CYRILLIC_LOOKALIKES: &str = "аеіјорсухѕһԁӏԛԝ";
+
+/// Greek letters that are drawn like Latin ones.
+const GREEK_LOOKALIKES: &str = "αικνορτυχ";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+}
+
+impl Script {
+    fn of(c: char) -> Option<Self> {
+        match c {
+            'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' => Some(Script::Latin),
+            '\u{0370}'..='\u{03FF}' => Some(Script::Greek),
+            '\u{0400}'..='\u{052F}' => Some(Script::Cyrillic),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Script::Latin => "Latin",
+            Script::Greek => "Greek",
+            Script::Cyrillic => "Cyrillic",
+        }
+    }
+}
+
+/// The host as typed, `gemini://` isn't a special scheme for the url crate so it keeps
+/// Unicode hosts percent-encoded.
+fn decoded_host(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+
+    Some(percent_decode_str(host).decode_utf8_lossy().into_owned())
+}
+
+/// The URL with its host as A-labels (`xn--bcher-kva.example`), which is what DNS, SNI and
+/// the request line need.
+pub fn to_ascii(url: &Url) -> Result<Url, NetworkError> {
+    url_util::to_ascii_host(url).map_err(|_| NetworkError::InvalidAddress)
+}
+
+/// The URL with a Unicode host, for showing to the user.
+pub fn to_unicode(url: &Url) -> String {
+    let Some(host) = decoded_host(url) else {
+        return url.to_string();
+    };
+
+    let (unicode, result) = idna::domain_to_unicode(&host);
+    if result.is_err() {
+        return url.to_string();
+    }
+
+    format!(
+        "{}{}{}",
+        &url[..Position::BeforeHost],
+        unicode,
+        &url[Position::AfterHost..]
+    )
+}
+
+/// Why the host of the URL might imitate another name: a label mixing Latin, Greek and
+/// Cyrillic letters, or one written entirely in letters that look Latin.
+pub fn confusable_warning(url: &Url) -> Option<String> {
+    let host = decoded_host(url)?;
+    let (unicode, _) = idna::domain_to_unicode(&host);
+
+    for label in unicode.split('.') {
+        let mut scripts = label.chars().filter_map(Script::of).collect::<Vec<_>>();
+        scripts.sort_by_key(Script::name);
+        scripts.dedup();
+
+        if let [first, second, ..] = scripts[..] {
+            return Some(format!(
+                "{} mixes {} and {} letters, it may imitate another host",
+                unicode,
+                first.name(),
+                second.name()
+            ));
+        }
+
+        let lookalikes = match scripts[..] {
+            [Script::Cyrillic] => CYRILLIC_LOOKALIKES,
+            [Script::Greek] => GREEK_LOOKALIKES,
+            _ => continue,
+        };
+
+        if label
+            .chars()
+            .filter(|c| Script::of(*c).is_some())
+            .all(|c| lookalikes.contains(c))
+        {
+            return Some(format!(
+                "{} is written in {} letters that look Latin, it may imitate another host",
+                unicode,
+                scripts[0].name()
+            ));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let url = Url::parse("gemini://bücher.example/a?q").unwrap();
+
+        let ascii = to_ascii(&url).unwrap();
+        assert_eq!(ascii.as_str(), "gemini://xn--bcher-kva.example/a?q");
+        assert_eq!(to_unicode(&ascii), "gemini://bücher.example/a?q");
+        assert_eq!(to_unicode(&url), "gemini://bücher.example/a?q");
+
+        let plain = Url::parse("gemini://geminiprotocol.net:1965/").unwrap();
+        assert_eq!(to_ascii(&plain).unwrap(), plain);
+        assert_eq!(to_unicode(&plain), plain.as_str());
+    }
+
+    #[test]
+    fn test_confusable_warning() {
+        let warning =
+            |host: &str| confusable_warning(&Url::parse(&format!("gemini://{}/", host)).unwrap());
+
+        assert_eq!(warning("geminiprotocol.net"), None);
+        assert_eq!(warning("bücher.example"), None);
+        assert_eq!(warning("пример.рф"), None);
+        // A Cyrillic "а" in an otherwise Latin name.
+        assert!(warning("pаypal.com")
+            .unwrap()
+            .contains("Cyrillic and Latin"));
+        assert!(warning("xn--80ak6aa92e.com")
+            .unwrap()
+            .contains("look Latin"));
+    }
+}
@@ -0,0 +1,370 @@
+use rustls::pki_types::CertificateDer;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use time::{Duration, OffsetDateTime};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// How long before a pinned certificate expires the document starts warning about it.
+const EXPIRY_WARNING: Duration = Duration::days(14);
+
+/// A certificate trusted on first use.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Pin {
+    /// The SHA-256 of the certificate, in hex.
+    pub fingerprint: String,
+    pub not_after: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Expiry {
+    Valid,
+    /// Within [EXPIRY_WARNING] of its notAfter date.
+    Soon,
+    Expired,
+}
+
+impl Pin {
+    pub fn from_certificate(cert: &CertificateDer<'_>) -> Result<Self, String> {
+        let (_, parsed) =
+            X509Certificate::from_der(cert).map_err(|e| format!("Invalid certificate: {}", e))?;
+
+        Ok(Pin {
+            fingerprint: fingerprint(cert),
+            not_after: parsed.validity().not_after.to_datetime(),
+        })
+    }
+
+    pub fn expiry(&self, now: OffsetDateTime) -> Expiry {
+        if self.not_after <= now {
+            Expiry::Expired
+        } else if self.not_after - now <= EXPIRY_WARNING {
+            Expiry::Soon
+        } else {
+            Expiry::Valid
+        }
+    }
+
+    /// What the document shows above a page of `host` while its certificate is expiring
+    /// or has expired.
+    pub fn warning(&self, host: &str, now: OffsetDateTime) -> Option<String> {
+        let date = self.not_after.date();
+
+        match self.expiry(now) {
+            Expiry::Valid => None,
+            Expiry::Soon => Some(format!(
+                "The certificate of {} expires on {}. Once the capsule replaces it, you'll be asked to trust the new one.",
+                host, date
+            )),
+            Expiry::Expired => Some(format!(
+                "The certificate of {} expired on {}. Once the capsule replaces it, you'll be asked to trust the new one.",
+                host, date
+            )),
+        }
+    }
+}
+
+/// The SHA-256 of a DER encoded certificate, in hex.
+fn fingerprint(cert: &[u8]) -> String {
+    Sha256::digest(cert)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// How far the certificate of a host is trusted, shown next to the URL.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HostTrust {
+    /// Matches the certificate pinned on an earlier run.
+    Pinned,
+    /// Pinned on first use since the client started.
+    New,
+    /// Differs from the pinned certificate, see [CertificateChanged].
+    Changed,
+}
+
+/// A host presented a certificate other than the one pinned for it. Returned from the
+/// certificate verifier wrapped in a [rustls::Error], see [CertificateChanged::from_error].
+#[derive(Debug, Clone)]
+pub struct CertificateChanged {
+    pub host: String,
+    pub pinned: Pin,
+    pub presented: Pin,
+}
+
+impl CertificateChanged {
+    pub fn from_error(error: &rustls::Error) -> Option<&Self> {
+        match error {
+            rustls::Error::InvalidCertificate(rustls::CertificateError::Other(other)) => {
+                other.0.downcast_ref()
+            }
+            _ => None,
+        }
+    }
+
+    /// Why the certificate may have changed. Capsules replace their certificate when it
+    /// expires, a change while the pinned one is valid is more suspicious.
+    pub fn explanation(&self, now: OffsetDateTime) -> String {
+        let date = self.pinned.not_after.date();
+
+        match self.pinned.expiry(now) {
+            Expiry::Expired => format!(
+                "The pinned certificate expired on {}, so the capsule has most likely replaced it.",
+                date
+            ),
+            Expiry::Soon => format!(
+                "The pinned certificate expires on {}, so the capsule has most likely replaced it early.",
+                date
+            ),
+            Expiry::Valid => format!(
+                "The pinned certificate is valid until {}. Capsules rarely replace a valid certificate, someone may be intercepting the connection.",
+                date
+            ),
+        }
+    }
+}
+
+impl fmt::Display for CertificateChanged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The certificate of {} doesn't match the one trusted on first use",
+            self.host
+        )
+    }
+}
+
+impl std::error::Error for CertificateChanged {}
+
+/// The certificates pinned on first use, one per host. A host keeps its pin until the user
+/// trusts a new certificate.
+///
+/// Each line of the file is a host, the fingerprint of its certificate and the Unix time
+/// the certificate expires at:
+///
+/// ```text
+/// geminiprotocol.net 3f2a...c1 1767225600
+/// ```
+#[derive(Debug, Default)]
+pub struct KnownHosts {
+    /// Where the pins are saved, `None` keeps them in memory only.
+    path: Option<PathBuf>,
+    pins: Mutex<HashMap<String, Pin>>,
+    /// The hosts pinned on first use since the client started.
+    new_hosts: Mutex<HashSet<String>>,
+}
+
+impl KnownHosts {
+    /// `known_hosts` in the `gemini` directory of the user's config directory.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("gemini").join("known_hosts"))
+    }
+
+    /// Starts out empty when the file doesn't exist yet.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let pins = match std::fs::read_to_string(&path) {
+            Ok(content) => parse(&content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(KnownHosts {
+            path: Some(path),
+            pins: Mutex::new(pins),
+            new_hosts: Mutex::default(),
+        })
+    }
+
+    /// A copy that keeps new pins in memory only, for private tabs. Hosts stay pinned to
+    /// the certificates trusted so far.
+    pub fn ephemeral(&self) -> KnownHosts {
+        KnownHosts {
+            path: None,
+            pins: Mutex::new(self.pins.lock().unwrap().clone()),
+            new_hosts: Mutex::new(self.new_hosts.lock().unwrap().clone()),
+        }
+    }
+
+    pub fn get(&self, host: &str) -> Option<Pin> {
+        self.pins.lock().unwrap().get(host).cloned()
+    }
+
+    /// [HostTrust::New] or [HostTrust::Pinned] for a host with a pin, `None` for the others.
+    pub fn trust(&self, host: &str) -> Option<HostTrust> {
+        if self.new_hosts.lock().unwrap().contains(host) {
+            Some(HostTrust::New)
+        } else {
+            self.get(host).map(|_| HostTrust::Pinned)
+        }
+    }
+
+    /// Accepts the certificate `host` is pinned to, and pins the first one a host presents.
+    pub fn verify(&self, host: &str, presented: Pin) -> Result<(), CertificateChanged> {
+        match self.get(host) {
+            Some(pinned) if pinned.fingerprint == presented.fingerprint => Ok(()),
+            Some(pinned) => Err(CertificateChanged {
+                host: host.to_string(),
+                pinned,
+                presented,
+            }),
+            None => {
+                log::info!("Pinning the certificate of {}", host);
+                self.pin(host, presented);
+                self.new_hosts.lock().unwrap().insert(host.to_string());
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Trusts `pin` for `host` from now on, replacing its previous pin.
+    pub fn pin(&self, host: &str, pin: Pin) {
+        let mut pins = self.pins.lock().unwrap();
+        pins.insert(host.to_string(), pin);
+
+        if let Err(e) = self.save(&pins) {
+            log::error!("Failed to save the known hosts: {}", e);
+        }
+    }
+
+    fn save(&self, pins: &HashMap<String, Pin>) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let mut hosts = pins.iter().collect::<Vec<_>>();
+        hosts.sort_by_key(|(host, _)| *host);
+
+        let mut content = String::new();
+        for (host, pin) in hosts {
+            content.push_str(&format!(
+                "{} {} {}\n",
+                host,
+                pin.fingerprint,
+                pin.not_after.unix_timestamp()
+            ));
+        }
+
+        std::fs::write(path, content)
+    }
+}
+
+/// Lines that don't parse are skipped, the host is pinned again on its next visit.
+fn parse(content: &str) -> HashMap<String, Pin> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let host = fields.next()?;
+            let fingerprint = fields.next()?;
+            let not_after = fields.next()?.parse().ok()?;
+
+            let Ok(not_after) = OffsetDateTime::from_unix_timestamp(not_after) else {
+                log::warn!("The pin of {} has an invalid expiry date", host);
+                return None;
+            };
+
+            Some((
+                host.to_string(),
+                Pin {
+                    fingerprint: fingerprint.to_string(),
+                    not_after,
+                },
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin(fingerprint: &str, not_after: i64) -> Pin {
+        Pin {
+            fingerprint: fingerprint.to_string(),
+            not_after: OffsetDateTime::from_unix_timestamp(not_after).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let path = std::env::temp_dir().join(format!("gemini-known-hosts-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let known_hosts = KnownHosts::load(path.clone()).unwrap();
+        assert_eq!(known_hosts.trust("example.org"), None);
+        assert!(known_hosts
+            .verify("example.org", pin("aa", 2_000_000_000))
+            .is_ok());
+        assert_eq!(known_hosts.trust("example.org"), Some(HostTrust::New));
+        assert!(known_hosts
+            .verify("example.org", pin("aa", 2_000_000_000))
+            .is_ok());
+
+        let changed = known_hosts
+            .verify("example.org", pin("bb", 2_100_000_000))
+            .unwrap_err();
+        assert_eq!(changed.pinned.fingerprint, "aa");
+        assert_eq!(changed.presented.fingerprint, "bb");
+
+        let error = rustls::Error::InvalidCertificate(rustls::CertificateError::Other(
+            rustls::OtherError(std::sync::Arc::new(changed.clone())),
+        ));
+        assert_eq!(
+            CertificateChanged::from_error(&error).map(|c| c.host.as_str()),
+            Some("example.org")
+        );
+
+        known_hosts.pin("example.org", changed.presented);
+        let reloaded = KnownHosts::load(path.clone()).unwrap();
+        assert_eq!(reloaded.get("example.org"), Some(pin("bb", 2_100_000_000)));
+        assert_eq!(reloaded.trust("example.org"), Some(HostTrust::Pinned));
+        assert!(reloaded
+            .verify("example.org", pin("aa", 2_000_000_000))
+            .is_err());
+
+        let ephemeral = reloaded.ephemeral();
+        assert!(ephemeral
+            .verify("example.org", pin("aa", 2_000_000_000))
+            .is_err());
+        assert!(ephemeral
+            .verify("example.net", pin("cc", 2_000_000_000))
+            .is_ok());
+        assert!(ephemeral.get("example.net").is_some());
+        assert!(KnownHosts::load(path.clone())
+            .unwrap()
+            .get("example.net")
+            .is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_expiry() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let day = 24 * 60 * 60;
+
+        assert_eq!(
+            pin("aa", 1_700_000_000 + 30 * day).expiry(now),
+            Expiry::Valid
+        );
+        assert_eq!(pin("aa", 1_700_000_000 + 3 * day).expiry(now), Expiry::Soon);
+        assert_eq!(pin("aa", 1_700_000_000 - day).expiry(now), Expiry::Expired);
+
+        assert_eq!(
+            pin("aa", 1_700_000_000 + 30 * day).warning("example.org", now),
+            None
+        );
+        assert_eq!(
+            pin("aa", 1_700_000_000 - day).warning("example.org", now).unwrap(),
+            "The certificate of example.org expired on 2023-11-13. Once the capsule replaces it, you'll be asked to trust the new one."
+        );
+    }
+}
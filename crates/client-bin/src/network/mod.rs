@@ -1,13 +1,21 @@
 use std::fmt;
 use std::fmt::{Debug, Formatter};
 use rustls::{Error};
+use crate::network::tls_client::SessionInfo;
 
+pub mod exchange;
+pub mod idn;
+pub mod known_hosts;
 pub mod tls_client;
 pub mod tls_config;
+pub mod traffic;
 
 pub enum NetworkError {
     InvalidAddress,
+    DnsError(std::io::Error),
     TlsError(rustls::Error),
+    /// A TLS error on an open connection, with what was negotiated before it.
+    HandshakeError(rustls::Error, SessionInfo),
     IoError(std::io::Error),
 }
 
@@ -33,7 +41,9 @@ impl std::fmt::Display for NetworkError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             NetworkError::InvalidAddress => write!(f, "Invalid Address"),
+            NetworkError::DnsError(e) => write!(f, "DNS Error: {}", e),
             NetworkError::TlsError(e) => write!(f, "TLS Error: {:?}", e),
+            NetworkError::HandshakeError(e, info) => write!(f, "TLS Error: {:?} ({})", e, info.summary()),
             NetworkError::IoError(e) => write!(f, "IO Error: {:?}", e),
         }
     }
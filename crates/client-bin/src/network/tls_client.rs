@@ -1,13 +1,63 @@
+use crate::network::traffic::{self, Counted};
 use crate::network::NetworkError;
-use rustls::pki_types::ServerName;
-use rustls::ClientConnection;
+use async_std::net::ToSocketAddrs;
+use futures::stream::{FuturesUnordered, StreamExt};
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{CipherSuite, ClientConnection, HandshakeKind, ProtocolVersion};
+use std::collections::VecDeque;
 use std::io;
-use std::net::{TcpStream, ToSocketAddrs};
+use std::net::{SocketAddr, TcpStream};
 use std::sync::Arc;
+use std::time::Duration;
+
+/// How long an attempt gets before the next address is tried alongside it, RFC 8305
+/// recommends 250ms.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// How the server ended a response.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Termination {
+    /// The server sent a close_notify, the response is complete.
+    CloseNotify,
+    /// The connection ended without a close_notify, so the response may have been cut off
+    /// by the network or by an attacker.
+    Truncated,
+}
+
+/// What was negotiated with the server, as far as the handshake got.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SessionInfo {
+    pub protocol_version: Option<ProtocolVersion>,
+    pub cipher_suite: Option<CipherSuite>,
+    /// The chain the server presented, its own certificate first.
+    pub peer_certificates: Vec<CertificateDer<'static>>,
+}
+
+impl SessionInfo {
+    /// A one line description, like `TLS 1.3, TLS13_AES_256_GCM_SHA384, 1 certificate`.
+    pub fn summary(&self) -> String {
+        let version = match self.protocol_version {
+            Some(ProtocolVersion::TLSv1_2) => "TLS 1.2".to_string(),
+            Some(ProtocolVersion::TLSv1_3) => "TLS 1.3".to_string(),
+            Some(version) => format!("{:?}", version),
+            None => "Unknown version".to_string(),
+        };
+        let cipher_suite = match self.cipher_suite {
+            Some(suite) => format!("{:?}", suite),
+            None => "unknown cipher suite".to_string(),
+        };
+        let certificates = match self.peer_certificates.len() {
+            1 => "1 certificate".to_string(),
+            n => format!("{} certificates", n),
+        };
+
+        format!("{}, {}, {}", version, cipher_suite, certificates)
+    }
+}
 
 #[derive(Debug)]
 pub struct TlsClient {
-    socket: TcpStream,
+    socket: Counted<TcpStream>,
     client_connection: ClientConnection,
     #[allow(dead_code)]
     sni: ServerName<'static>,
@@ -20,35 +70,93 @@ impl TlsClient {
         tls_config: Arc<rustls::ClientConfig>,
     ) -> Result<Self, NetworkError> {
         Ok(Self {
-            socket: socket,
+            socket: Counted(socket),
             client_connection: rustls::ClientConnection::new(tls_config, server_name.clone())?,
             sni: server_name,
         })
     }
 
-    pub fn new_from_host(
+    pub async fn new_from_host(
         addr: (&str, u16),
         tls_config: Arc<rustls::ClientConfig>,
-        connection_timeout: Option<std::time::Duration>,
+        connection_timeout: Option<Duration>,
     ) -> Result<Self, NetworkError> {
-        let connection_timeout = connection_timeout.unwrap_or(std::time::Duration::from_secs(10));
+        let connection_timeout = connection_timeout.unwrap_or(Duration::from_secs(10));
 
         // NOTE: Does not accept ToSocketAddrs, as we need to know domain.
         let host = addr.0;
         let port = addr.1;
-        let addr = format!("{}:{}", host, port)
-            .to_socket_addrs()?
-            .next()
-            .ok_or(NetworkError::InvalidAddress)?;
-
-        let tcp = TcpStream::connect_timeout(&addr, connection_timeout)?;
         let server_name = ServerName::try_from(host)
             .map_err(|_| NetworkError::InvalidAddress)?
             .to_owned();
 
+        let addrs = (host, port)
+            .to_socket_addrs()
+            .await
+            .map_err(NetworkError::DnsError)?
+            .collect::<Vec<_>>();
+        if addrs.is_empty() {
+            return Err(NetworkError::DnsError(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No addresses found for {}", host),
+            )));
+        }
+
+        let tcp = connect_happy_eyeballs(interleave_families(addrs), connection_timeout).await?;
+
         Self::new(tcp, server_name, tls_config)
     }
 
+    /// Finishes the handshake without sending anything, which leaves a session to resume
+    /// in the session cache of the config.
+    pub fn complete_handshake(&mut self) -> Result<(), NetworkError> {
+        let handshaking = self.client_connection.is_handshaking();
+        while self.client_connection.is_handshaking() {
+            self.client_connection
+                .complete_io(&mut self.socket)
+                .map_err(|e| self.network_error(e))?;
+        }
+
+        if handshaking {
+            let kind = self.client_connection.handshake_kind();
+            traffic::record_handshake(kind == Some(HandshakeKind::Resumed));
+        }
+
+        Ok(())
+    }
+
+    pub fn session_info(&self) -> SessionInfo {
+        let conn = &self.client_connection;
+
+        SessionInfo {
+            protocol_version: conn.protocol_version(),
+            cipher_suite: conn.negotiated_cipher_suite().map(|s| s.suite()),
+            peer_certificates: conn
+                .peer_certificates()
+                .map(|certs| certs.iter().map(|c| c.clone().into_owned()).collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// TLS errors surface as IO errors of the socket, they carry what was negotiated
+    /// before the failure.
+    fn network_error(&self, e: io::Error) -> NetworkError {
+        match e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()) {
+            Some(tls) => NetworkError::HandshakeError(tls.clone(), self.session_info()),
+            None => NetworkError::IoError(e),
+        }
+    }
+
+    /// Reads until the server closes the connection. What arrived before an EOF without a
+    /// close_notify is kept in `buf`, as a [Termination::Truncated] response.
+    pub fn read_to_close(&mut self, buf: &mut Vec<u8>) -> Result<Termination, NetworkError> {
+        match io::Read::read_to_end(self, buf) {
+            Ok(_) => Ok(Termination::CloseNotify),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(Termination::Truncated),
+            Err(e) => Err(self.network_error(e)),
+        }
+    }
+
     fn complete_prior_io(&mut self) -> Result<(), std::io::Error> {
         if self.client_connection.is_handshaking() {
             self.client_connection.complete_io(&mut self.socket)?;
@@ -105,6 +213,65 @@ impl io::Read for TlsClient {
     }
 }
 
+/// Alternates between IPv6 and IPv4 addresses, starting with the family the resolver
+/// listed first, so a broken family only costs one attempt delay (RFC 8305, section 4).
+fn interleave_families(addrs: Vec<SocketAddr>) -> VecDeque<SocketAddr> {
+    let prefer_v6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let (mut first, mut second): (VecDeque<_>, VecDeque<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == prefer_v6);
+
+    let mut interleaved = VecDeque::with_capacity(first.len() + second.len());
+    while !first.is_empty() || !second.is_empty() {
+        interleaved.extend(first.pop_front());
+        interleaved.extend(second.pop_front());
+    }
+
+    interleaved
+}
+
+/// Starts a connection attempt to the next address whenever the previous one has failed or
+/// has not succeeded within [CONNECTION_ATTEMPT_DELAY], and keeps the first that succeeds.
+async fn connect_happy_eyeballs(
+    mut addrs: VecDeque<SocketAddr>,
+    connection_timeout: Duration,
+) -> Result<TcpStream, NetworkError> {
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+
+    loop {
+        if let Some(addr) = addrs.pop_front() {
+            log::debug!("Connecting to {}", addr);
+
+            attempts.push(async_std::io::timeout(
+                connection_timeout,
+                async_std::net::TcpStream::connect(addr),
+            ));
+        }
+
+        let next = if addrs.is_empty() {
+            Ok(attempts.next().await)
+        } else {
+            async_std::future::timeout(CONNECTION_ATTEMPT_DELAY, attempts.next()).await
+        };
+
+        match next {
+            Ok(Some(Ok(stream))) => return Ok(TcpStream::try_from(stream)?),
+            Ok(Some(Err(e))) => last_error = Some(e),
+            // No attempts left to wait for.
+            Ok(None) => {}
+            // Still connecting, the next address joins the race.
+            Err(_) => continue,
+        }
+
+        if addrs.is_empty() && attempts.is_empty() {
+            return Err(last_error
+                .map(NetworkError::IoError)
+                .unwrap_or(NetworkError::InvalidAddress));
+        }
+    }
+}
+
 // https://docs.rs/rustls/latest/rustls/manual/_03_howto/index.html#unexpected-eof
 impl Drop for TlsClient {
     fn drop(&mut self) {
@@ -112,3 +279,70 @@ impl Drop for TlsClient {
         self.client_connection.write_tls(&mut self.socket).ok();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_info_summary() {
+        let info = SessionInfo {
+            protocol_version: Some(ProtocolVersion::TLSv1_3),
+            cipher_suite: Some(CipherSuite::TLS13_AES_256_GCM_SHA384),
+            peer_certificates: vec![CertificateDer::from(vec![1, 2, 3])],
+        };
+        assert_eq!(
+            info.summary(),
+            "TLS 1.3, TLS13_AES_256_GCM_SHA384, 1 certificate"
+        );
+
+        assert_eq!(
+            SessionInfo::default().summary(),
+            "Unknown version, unknown cipher suite, 0 certificates"
+        );
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        let addrs = [
+            "[::1]:1965",
+            "[::2]:1965",
+            "[::3]:1965",
+            "127.0.0.1:1965",
+            "127.0.0.2:1965",
+        ]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect::<Vec<SocketAddr>>();
+
+        let expected = [0, 3, 1, 4, 2].map(|i| addrs[i]);
+        assert_eq!(interleave_families(addrs.clone()), expected);
+
+        let mut v4_first = addrs.clone();
+        v4_first.rotate_left(3);
+        let expected = [3, 0, 4, 1, 2].map(|i| addrs[i]);
+        assert_eq!(interleave_families(v4_first), expected);
+    }
+
+    #[test]
+    fn test_connect_happy_eyeballs() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let reachable = listener.local_addr().unwrap();
+
+        // Nothing listens on the first address, so the second one wins.
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let unreachable = closed.local_addr().unwrap();
+        drop(closed);
+
+        let addrs = VecDeque::from([unreachable, reachable]);
+        let stream =
+            async_std::task::block_on(connect_happy_eyeballs(addrs, Duration::from_secs(5)))
+                .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), reachable);
+
+        let addrs = VecDeque::from([unreachable]);
+        let result =
+            async_std::task::block_on(connect_happy_eyeballs(addrs, Duration::from_secs(5)));
+        assert!(matches!(result, Err(NetworkError::IoError(_))));
+    }
+}
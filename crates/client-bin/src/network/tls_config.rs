@@ -1,16 +1,50 @@
 use std::sync::Arc;
+use crate::identity::Identity;
+use crate::network::known_hosts::{KnownHosts, Pin};
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
-use rustls::crypto::{aws_lc_rs, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
-use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
-use rustls::{DigitallySignedStruct, Error, RootCertStore, SignatureScheme};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::client::{ResolvesClientCert, Resumption};
+use rustls::sign::CertifiedKey;
+use rustls::{CertificateError, DigitallySignedStruct, Error, OtherError, RootCertStore, SignatureScheme};
 
+/// Sessions kept for resumption, one per capsule visited recently.
+const SESSION_CACHE_SIZE: usize = 256;
+
+#[cfg(not(any(feature = "aws-lc-rs", feature = "ring")))]
+compile_error!("Either the `aws-lc-rs` or the `ring` feature has to be enabled");
+
+/// Ring with the `ring` feature, aws-lc-rs otherwise.
+fn crypto_provider() -> Arc<CryptoProvider> {
+    #[cfg(feature = "ring")]
+    let provider = rustls::crypto::ring::default_provider();
+    #[cfg(not(feature = "ring"))]
+    let provider = rustls::crypto::aws_lc_rs::default_provider();
+
+    Arc::new(provider)
+}
+
+/// Trusts the certificate a host presents first and only that one after, capsules mostly
+/// use self-signed certificates.
 #[derive(Debug)]
-struct NoCertificateVerification {
+struct PinnedCertificateVerification {
     provider: Arc<CryptoProvider>,
+    known_hosts: Arc<KnownHosts>,
 }
 
-impl ServerCertVerifier for NoCertificateVerification {
-    fn verify_server_cert(&self, _end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], _server_name: &ServerName<'_>, _ocsp_response: &[u8], _now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+impl ServerCertVerifier for PinnedCertificateVerification {
+    fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], server_name: &ServerName<'_>, _ocsp_response: &[u8], _now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_ascii_lowercase(),
+            ServerName::IpAddress(ip) => std::net::IpAddr::from(*ip).to_string(),
+            _ => return Err(Error::General(format!("Unsupported server name {:?}", server_name))),
+        };
+        let pin = Pin::from_certificate(end_entity).map_err(|_| Error::InvalidCertificate(CertificateError::BadEncoding))?;
+
+        self.known_hosts
+            .verify(&host, pin)
+            .map_err(|e| Error::InvalidCertificate(CertificateError::Other(OtherError(Arc::new(e)))))?;
+
         Ok(ServerCertVerified::assertion())
     }
 
@@ -27,12 +61,14 @@ impl ServerCertVerifier for NoCertificateVerification {
     }
 }
 
-pub fn make_tls_config() -> Result<Arc<rustls::ClientConfig>, rustls::Error>  {
+/// `keylog` writes the TLS secrets to SSLKEYLOGFILE, only for debugging. Certificates are
+/// checked against `known_hosts`.
+pub fn make_tls_config(keylog: bool, known_hosts: Arc<KnownHosts>) -> Result<Arc<rustls::ClientConfig>, rustls::Error>  {
     let mut root_store = RootCertStore::empty();
 
     root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
 
-    let provider = Arc::new(aws_lc_rs::default_provider());
+    let provider = crypto_provider();
     let versions = rustls::DEFAULT_VERSIONS.to_vec();
     let mut config = rustls::ClientConfig::builder_with_provider(provider.clone())
         .with_protocol_versions(&versions)?
@@ -40,11 +76,43 @@ pub fn make_tls_config() -> Result<Arc<rustls::ClientConfig>, rustls::Error>  {
         .with_no_client_auth();
 
     config.enable_sni = true;
-    config.key_log = Arc::new(rustls::KeyLogFile::new());
+    // Every request is a new connection, resuming the session of the previous one to the
+    // same capsule skips the full handshake.
+    config.resumption = Resumption::in_memory_sessions(SESSION_CACHE_SIZE);
+    if keylog {
+        config.key_log = Arc::new(rustls::KeyLogFile::new());
+    }
 
     config
         .dangerous()
-        .set_certificate_verifier(Arc::new(NoCertificateVerification { provider }));
+        .set_certificate_verifier(Arc::new(PinnedCertificateVerification { provider, known_hosts }));
+
+    Ok(Arc::new(config))
+}
+
+/// Presents the certificate of an identity to every server that asks for one.
+#[derive(Debug)]
+struct IdentityResolver(Arc<CertifiedKey>);
+
+impl ResolvesClientCert for IdentityResolver {
+    fn resolve(&self, _root_hint_subjects: &[&[u8]], _sigschemes: &[SignatureScheme]) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// `config` sending the certificate of `identity`. The sessions are kept apart from those of
+/// `config`, so resuming one doesn't carry the identity over or leave it out.
+pub fn with_identity(config: &rustls::ClientConfig, identity: &Identity) -> Result<Arc<rustls::ClientConfig>, rustls::Error> {
+    let key = config.crypto_provider().key_provider.load_private_key(PrivateKeyDer::Pkcs8(identity.key()))?;
+    let certified = CertifiedKey::new(vec![identity.certificate.clone()], key);
+
+    let mut config = config.clone();
+    config.client_auth_cert_resolver = Arc::new(IdentityResolver(Arc::new(certified)));
+    config.resumption = Resumption::in_memory_sessions(SESSION_CACHE_SIZE);
 
     Ok(Arc::new(config))
 }
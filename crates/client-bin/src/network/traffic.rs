@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Mutex;
+
+/// Everything this session sent and received, shown on `about:stats`.
+static TRAFFIC: Mutex<Traffic> = Mutex::new(Traffic::new());
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Traffic {
+    /// Bytes written to sockets, TLS records included.
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Gemini requests sent, by host.
+    pub requests: BTreeMap<String, u64>,
+    pub full_handshakes: u64,
+    /// Handshakes that resumed a session from the session cache.
+    pub resumed_handshakes: u64,
+}
+
+impl Traffic {
+    const fn new() -> Self {
+        Traffic {
+            bytes_sent: 0,
+            bytes_received: 0,
+            requests: BTreeMap::new(),
+            full_handshakes: 0,
+            resumed_handshakes: 0,
+        }
+    }
+
+    /// The share of handshakes that hit the session cache, `None` before the first.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let total = self.full_handshakes + self.resumed_handshakes;
+
+        (total > 0).then(|| self.resumed_handshakes as f64 / total as f64)
+    }
+
+    pub fn to_gemtext(&self) -> String {
+        let mut page = String::from("# Traffic statistics\n\nSince the browser was started.\n");
+
+        page.push_str(&format!(
+            "\n## Bytes\n\n* Sent: {}\n* Received: {}\n",
+            self.bytes_sent, self.bytes_received
+        ));
+
+        page.push_str("\n## Requests per host\n\n");
+        if self.requests.is_empty() {
+            page.push_str("No requests yet.\n");
+        }
+        for (host, requests) in &self.requests {
+            page.push_str(&format!("* {}: {}\n", host, requests));
+        }
+
+        page.push_str("\n## TLS session cache\n\n");
+        match self.cache_hit_rate() {
+            Some(rate) => page.push_str(&format!(
+                "* Resumed handshakes: {} of {} ({:.0}%)\n",
+                self.resumed_handshakes,
+                self.full_handshakes + self.resumed_handshakes,
+                rate * 100.0
+            )),
+            None => page.push_str("No handshakes yet.\n"),
+        }
+
+        page
+    }
+}
+
+pub fn snapshot() -> Traffic {
+    TRAFFIC.lock().unwrap().clone()
+}
+
+pub fn record_request(host: &str) {
+    *TRAFFIC
+        .lock()
+        .unwrap()
+        .requests
+        .entry(host.to_string())
+        .or_default() += 1;
+}
+
+pub fn record_handshake(resumed: bool) {
+    let mut traffic = TRAFFIC.lock().unwrap();
+    if resumed {
+        traffic.resumed_handshakes += 1;
+    } else {
+        traffic.full_handshakes += 1;
+    }
+}
+
+/// A socket that adds the bytes going through it to the traffic of the session.
+#[derive(Debug)]
+pub struct Counted<S>(pub S);
+
+impl<S: io::Read> io::Read for Counted<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.0.read(buf)?;
+        TRAFFIC.lock().unwrap().bytes_received += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl<S: io::Write> io::Write for Counted<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.0.write(buf)?;
+        TRAFFIC.lock().unwrap().bytes_sent += n as u64;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_gemtext() {
+        let mut traffic = Traffic {
+            bytes_sent: 120,
+            bytes_received: 4096,
+            ..Traffic::default()
+        };
+        assert!(traffic.to_gemtext().contains("No requests yet."));
+        assert_eq!(traffic.cache_hit_rate(), None);
+
+        traffic.requests.insert("geminiprotocol.net".to_string(), 3);
+        traffic.requests.insert("example.org".to_string(), 1);
+        traffic.full_handshakes = 1;
+        traffic.resumed_handshakes = 3;
+
+        let page = traffic.to_gemtext();
+        assert!(page.contains("* Sent: 120\n* Received: 4096\n"));
+        assert!(page.contains("* example.org: 1\n* geminiprotocol.net: 3\n"));
+        assert!(page.contains("* Resumed handshakes: 3 of 4 (75%)\n"));
+    }
+}
@@ -0,0 +1,157 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+/// The audio player runs headless, the document has the controls.
+const PLAYER_COMMAND: &str = "mpv";
+
+#[cfg(unix)]
+type Ipc = std::os::unix::net::UnixStream;
+/// mpv listens on a named pipe on Windows, which std can't open as a stream.
+#[cfg(not(unix))]
+type Ipc = std::net::TcpStream;
+
+/// Whether a response of `mime` is played in the document instead of being downloaded.
+pub fn is_inline_audio(mime: &str) -> bool {
+    let mime = mime.split(';').next().unwrap_or_default().trim();
+
+    cfg!(unix)
+        && ["audio/mpeg", "audio/ogg"]
+            .iter()
+            .any(|audio| mime.eq_ignore_ascii_case(audio))
+}
+
+/// Plays a file with mpv and controls it over its JSON IPC socket.
+#[derive(Debug)]
+pub struct Player {
+    child: Child,
+    socket: PathBuf,
+    /// Connected on the first command, mpv creates the socket once it has started.
+    ipc: Option<Ipc>,
+    pub paused: bool,
+    /// 0 to 100.
+    pub volume: u8,
+}
+
+impl Player {
+    pub fn start(path: &Path) -> io::Result<Self> {
+        let socket = path.with_extension("mpv-socket");
+
+        let child = Command::new(PLAYER_COMMAND)
+            .arg("--no-video")
+            .arg("--no-terminal")
+            .arg(format!("--input-ipc-server={}", socket.display()))
+            .arg("--")
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()?;
+
+        Ok(Player {
+            child,
+            socket,
+            ipc: None,
+            paused: false,
+            volume: 100,
+        })
+    }
+
+    pub fn set_paused(&mut self, paused: bool) -> io::Result<()> {
+        self.command(&["set_property", "\"pause\"", &paused.to_string()])?;
+        self.paused = paused;
+
+        Ok(())
+    }
+
+    /// Seeks `seconds` from the current position, backwards when negative.
+    pub fn seek(&mut self, seconds: i32) -> io::Result<()> {
+        self.command(&["seek", &seconds.to_string(), "\"relative\""])
+    }
+
+    pub fn set_volume(&mut self, volume: u8) -> io::Result<()> {
+        let volume = volume.min(100);
+        self.command(&["set_property", "\"volume\"", &volume.to_string()])?;
+        self.volume = volume;
+
+        Ok(())
+    }
+
+    /// Sends a command, its arguments are JSON values.
+    fn command(&mut self, args: &[&str]) -> io::Result<()> {
+        let ipc = match &mut self.ipc {
+            Some(ipc) => ipc,
+            None => {
+                let ipc = connect(&self.socket)?;
+                ipc.set_nonblocking(true)?;
+                self.ipc.insert(ipc)
+            }
+        };
+
+        // mpv writes replies and events to every client, they are read and dropped so
+        // the socket doesn't fill up.
+        let mut discard = [0; 4096];
+        loop {
+            match ipc.read(&mut discard) {
+                Ok(0) => break,
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        ipc.write_all(ipc_command(args).as_bytes())
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+#[cfg(unix)]
+fn connect(socket: &Path) -> io::Result<Ipc> {
+    Ipc::connect(socket)
+}
+
+#[cfg(not(unix))]
+fn connect(_socket: &Path) -> io::Result<Ipc> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// `{"command": ["set_property", "pause", true]}` and its newline.
+fn ipc_command(args: &[&str]) -> String {
+    let (name, args) = args.split_first().expect("a command has a name");
+
+    let mut command = format!("{{\"command\": [\"{}\"", name);
+    for arg in args {
+        command.push_str(", ");
+        command.push_str(arg);
+    }
+    command.push_str("]}\n");
+
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipc_command() {
+        assert_eq!(
+            ipc_command(&["set_property", "\"pause\"", "true"]),
+            "{\"command\": [\"set_property\", \"pause\", true]}\n"
+        );
+        assert_eq!(
+            ipc_command(&["seek", "-10", "\"relative\""]),
+            "{\"command\": [\"seek\", -10, \"relative\"]}\n"
+        );
+
+        assert!(is_inline_audio("audio/ogg"));
+        assert!(is_inline_audio("audio/mpeg; charset=binary"));
+        assert!(!is_inline_audio("audio/flac"));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::document::line_text;
+use protocol::gemtext::gemtext_body::Line;
+use std::io::{self, Write};
+use std::process::{Child, Command, Stdio};
+
+/// The speech synthesizer of the platform, which reads the text from stdin.
+#[cfg(target_os = "macos")]
+const TTS_COMMAND: (&str, &[&str]) = ("say", &["-f", "-"]);
+#[cfg(target_os = "windows")]
+const TTS_COMMAND: (&str, &[&str]) = (
+    "powershell",
+    &[
+        "-NoProfile",
+        "-Command",
+        "Add-Type -AssemblyName System.Speech; (New-Object System.Speech.Synthesis.SpeechSynthesizer).Speak([Console]::In.ReadToEnd())",
+    ],
+);
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+const TTS_COMMAND: (&str, &[&str]) = ("espeak-ng", &["--stdin"]);
+
+/// The text from a heading up to the next one, read in one go.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Section {
+    /// The line the section starts at.
+    pub line: usize,
+    pub text: String,
+}
+
+/// Splits the document at its headings. Preformatted blocks are read by their alt text,
+/// ASCII art and code don't read well.
+pub fn sections(lines: &[Line]) -> Vec<Section> {
+    let mut sections: Vec<Section> = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        let text = match line {
+            Line::Preformatted { alt, .. } => alt.clone().unwrap_or_default(),
+            line => line_text(line),
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        match sections.last_mut() {
+            Some(section) if !matches!(line, Line::Heading { .. }) => {
+                section.text.push('\n');
+                section.text.push_str(&text);
+            }
+            _ => sections.push(Section { line: i, text }),
+        }
+    }
+
+    sections
+}
+
+/// Reads the sections of a document aloud, one speech process per section.
+///
+/// Pausing stops the process, playing again starts the section over.
+#[derive(Debug)]
+pub struct ReadAloud {
+    sections: Vec<Section>,
+    current: usize,
+    speaking: Option<Child>,
+}
+
+impl ReadAloud {
+    pub fn new(sections: Vec<Section>) -> Self {
+        ReadAloud {
+            sections,
+            current: 0,
+            speaking: None,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.speaking.is_some()
+    }
+
+    /// The section being read, or that playing starts with.
+    pub fn current(&self) -> Option<&Section> {
+        self.sections.get(self.current)
+    }
+
+    pub fn play(&mut self) -> io::Result<()> {
+        self.stop();
+
+        let Some(section) = self.sections.get(self.current) else {
+            return Ok(());
+        };
+
+        let (program, args) = TTS_COMMAND;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(section.text.as_bytes())?;
+        }
+        self.speaking = Some(child);
+
+        Ok(())
+    }
+
+    pub fn pause(&mut self) {
+        self.stop();
+    }
+
+    /// Moves `by` sections, and keeps reading if it was.
+    pub fn skip(&mut self, by: isize) -> io::Result<()> {
+        let last = self.sections.len().saturating_sub(1);
+        self.current = self.current.saturating_add_signed(by).min(last);
+
+        if self.is_playing() {
+            self.play()?;
+        }
+
+        Ok(())
+    }
+
+    /// Goes on with the next section once the current one has been read, and stops after
+    /// the last.
+    pub fn poll(&mut self) -> io::Result<()> {
+        let Some(child) = &mut self.speaking else {
+            return Ok(());
+        };
+        if child.try_wait()?.is_none() {
+            return Ok(());
+        }
+
+        self.speaking = None;
+        if self.current + 1 < self.sections.len() {
+            self.current += 1;
+            self.play()?;
+        }
+
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut child) = self.speaking.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for ReadAloud {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::gemtext::parse_gemtext;
+    use url::Url;
+
+    #[test]
+    fn test_sections() {
+        let url = Url::parse("gemini://example.org/").unwrap();
+        let page = "Intro\n\n# First\nSome text\n```\n+--+\n```\n```A box\n+--+\n```\n=> /next The next page\n## Second\n> A quote";
+        let lines = parse_gemtext(&url, page.to_string()).unwrap().0;
+
+        assert_eq!(
+            sections(&lines),
+            vec![
+                Section {
+                    line: 0,
+                    text: "Intro".to_string()
+                },
+                Section {
+                    line: 2,
+                    text: "First\nSome text\nA box\nThe next page".to_string()
+                },
+                Section {
+                    line: 7,
+                    text: "Second\nA quote".to_string()
+                },
+            ]
+        );
+
+        let mut read_aloud = ReadAloud::new(sections(&lines));
+        read_aloud.skip(5).unwrap();
+        assert_eq!(read_aloud.current().map(|s| s.line), Some(7));
+        read_aloud.skip(-1).unwrap();
+        assert_eq!(read_aloud.current().map(|s| s.line), Some(2));
+        assert!(!read_aloud.is_playing());
+    }
+}
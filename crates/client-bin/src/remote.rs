@@ -0,0 +1,227 @@
+use async_std::io::{BufReader, ReadExt, WriteExt};
+use async_std::os::unix::net::{UnixListener, UnixStream};
+use futures::channel::{mpsc, oneshot};
+use futures::{AsyncBufReadExt, SinkExt, Stream};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+/// What a connection to the socket of the running client asks it to do. A connection sends
+/// one command on a line and reads the reply until the socket closes:
+///
+/// ```text
+/// open gemini://geminiprotocol.net/  ok
+/// tabs                               0 gemini://geminiprotocol.net/ *, a line per tab
+/// current                            gemini://geminiprotocol.net/
+/// close 0                            ok
+/// ```
+///
+/// A reply that starts with `error: ` is a command that failed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Command {
+    Open(Url),
+    /// The index and URL of every tab, followed by a `*` for the current one.
+    ListTabs,
+    CurrentUrl,
+    CloseTab(usize),
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (name, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+
+        match name {
+            "open" => Url::parse(argument)
+                .map(Command::Open)
+                .map_err(|e| format!("Invalid URL {:?}: {}", argument, e)),
+            "tabs" => Ok(Command::ListTabs),
+            "current" => Ok(Command::CurrentUrl),
+            "close" => argument
+                .parse()
+                .map(Command::CloseTab)
+                .map_err(|_| format!("Invalid tab {:?}", argument)),
+            _ => Err(format!("Unknown command {:?}", name)),
+        }
+    }
+}
+
+/// A command and where its reply goes, passed to the window as a message.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub command: Command,
+    /// Taken by the first reply, messages have to be `Clone`.
+    reply: Arc<Mutex<Option<oneshot::Sender<String>>>>,
+}
+
+impl Request {
+    pub fn respond(&self, reply: String) {
+        if let Some(sender) = self.reply.lock().unwrap().take() {
+            let _ = sender.send(reply);
+        }
+    }
+
+    pub fn fail(&self, error: &str) {
+        self.respond(format!("error: {}", error));
+    }
+}
+
+/// `gemini.sock` in the runtime directory of the user, the temporary directory on systems
+/// that don't have one.
+pub fn socket_path() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("gemini.sock")
+}
+
+/// The commands sent to [socket_path], for [iced::Subscription::run].
+pub fn listen() -> impl Stream<Item = Request> {
+    listen_at(socket_path())
+}
+
+/// Nothing is listened for when another client is listening at `path` already.
+pub fn listen_at(path: PathBuf) -> impl Stream<Item = Request> {
+    iced::stream::channel(16, move |mut output| async move {
+        let listener = match bind(&path).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                log::error!("Not listening for remote commands at {:?}: {}", path, e);
+                return;
+            }
+        };
+        log::info!("Listening for remote commands at {:?}", path);
+
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    log::error!("Failed to accept a remote connection: {}", e);
+                    continue;
+                }
+            };
+
+            if let Err(e) = serve(stream, &mut output).await {
+                log::error!("Failed to answer a remote command: {}", e);
+            }
+        }
+    })
+}
+
+/// A socket left behind by a client that crashed is replaced, one that answers isn't.
+async fn bind(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another client is running",
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    UnixListener::bind(path).await
+}
+
+async fn serve(stream: UnixStream, output: &mut mpsc::Sender<Request>) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+
+    let reply = match Command::parse(&line) {
+        Ok(command) => {
+            let (sender, receiver) = oneshot::channel();
+            let request = Request {
+                command,
+                reply: Arc::new(Mutex::new(Some(sender))),
+            };
+
+            match output.send(request).await {
+                Ok(()) => receiver
+                    .await
+                    .unwrap_or_else(|_| "error: No reply".to_string()),
+                Err(_) => "error: The window is closing".to_string(),
+            }
+        }
+        Err(e) => format!("error: {}", e),
+    };
+
+    let mut stream = &stream;
+    stream.write_all(reply.as_bytes()).await?;
+    if !reply.ends_with('\n') {
+        stream.write_all(b"\n").await?;
+    }
+
+    Ok(())
+}
+
+/// Sends `command` to the client listening at `path` and returns its reply, for
+/// `--remote`.
+pub async fn send(path: &Path, command: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path).await?;
+    stream
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).await?;
+
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Command::parse("open gemini://example.org/\n"),
+            Ok(Command::Open(Url::parse("gemini://example.org/").unwrap()))
+        );
+        assert_eq!(Command::parse("tabs"), Ok(Command::ListTabs));
+        assert_eq!(Command::parse("current\r\n"), Ok(Command::CurrentUrl));
+        assert_eq!(Command::parse("close 2"), Ok(Command::CloseTab(2)));
+        assert!(Command::parse("close two").is_err());
+        assert!(Command::parse("open").is_err());
+        assert!(Command::parse("reload").is_err());
+    }
+
+    #[test]
+    fn test_send() {
+        let path = std::env::temp_dir().join(format!("gemini-remote-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        async_std::task::block_on(async {
+            let mut requests = Box::pin(listen_at(path.clone()));
+            let answer = async {
+                let request = requests.next().await.unwrap();
+                assert_eq!(request.command, Command::CurrentUrl);
+                request.respond("gemini://example.org/".to_string());
+
+                // Keeps the listener running while it writes the reply.
+                requests.next().await;
+            };
+            let client = async {
+                // The listener binds once the stream is first polled.
+                while !path.exists() {
+                    async_std::task::yield_now().await;
+                }
+
+                send(&path, "current").await.unwrap()
+            };
+
+            let reply = match futures::future::select(Box::pin(client), Box::pin(answer)).await {
+                futures::future::Either::Left((reply, _)) => reply,
+                futures::future::Either::Right(_) => panic!("The listener stopped"),
+            };
+            assert_eq!(reply, "gemini://example.org/\n");
+
+            assert!(bind(&path).await.is_err());
+        });
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
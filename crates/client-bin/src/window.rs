@@ -1,26 +1,109 @@
-use crate::document::{Document, DocumentMessage};
+use crate::archive::{self, Saved};
+use crate::bookmarks::Bookmarks;
+use crate::document::{Document, DocumentMessage, TabStatus};
+use crate::events::{Event, EventBus, NavigationEvent, NavigationLog};
+use crate::handlers::Handlers;
+use crate::identity::{self, IdentityStore};
+use crate::import;
+use crate::network::idn;
+use crate::network::known_hosts::{HostTrust, KnownHosts};
 use crate::network::tls_config::make_tls_config;
-use iced::widget::{button, column, row, scrollable, text, text_input, Button, Row, Text};
-use iced::{Background, Center, Color, Length, Task};
+use crate::read_aloud::ReadAloud;
+#[cfg(unix)]
+use crate::remote::{self, Command};
+use iced::advanced::text::Shaping;
+use iced::keyboard::{self, Key};
+use iced::widget::scrollable::RelativeOffset;
+use iced::widget::{
+    button, checkbox, column, pick_list, row, scrollable, text, text_input, tooltip, Button,
+    Column, Row, Text, Tooltip,
+};
+use iced::{Background, Center, Color, Font, Length, Padding, Subscription, Task};
 use iced_aw::ContextMenu;
 use log::{debug, error, info};
+use native_dialog::FileDialog;
+use protocol::error::UrlError;
+use protocol::url_util;
 use rustls::ClientConfig;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use url::Url;
 
+/// Matches listed per tab, the rest are only counted.
+const MAX_FIND_RESULTS_PER_TAB: usize = 20;
+
+/// Characters of a matching line shown in the results.
+const FIND_SNIPPET_LENGTH: usize = 60;
+
+/// How often local files are checked for changes.
+const FILE_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Bytes of a captured response body shown in the developer panel.
+const MAX_CAPTURE_BODY_SHOWN: usize = 16 * 1024;
+
+/// How often reading aloud checks whether a section has been read.
+const READ_ALOUD_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The depths a capsule can be saved to, see [archive::save_capsule].
+const ARCHIVE_DEPTHS: [usize; 5] = [1, 2, 3, 4, 5];
+
+/// The frames of the spinner on a loading tab, advanced every [SPINNER_INTERVAL].
+const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Debug, Clone)]
 pub enum GeminiRootMessage {
     Search,
+    /// Opens the URL of the search box in a private tab.
+    PrivateSearch,
     SearchBoxChanged(String),
     DocumentMessage(usize, DocumentMessage),
     DocumentHasLoaded(usize, DocumentMessage),
     ViewDocument(usize),
     CloseDocument(usize),
     DocumentGoBack,
+    #[allow(dead_code)]
     DocumentGoForward,
     DebugPrintDocument,
     CurrentDocumentURLPotentialChange(String),
     UserWishesToNavigateDocument,
+    PreconnectToggled(bool),
+    CaptureToggled(bool),
+    /// Appends the capture of the current tab to a file, see [protocol::capture].
+    SaveCapture,
+    CaptureFileChosen(Option<PathBuf>),
+    FindQueryChanged(String),
+    /// The tab and line of a match.
+    FindResultPressed(usize, usize),
+    OutlineToggled,
+    /// A heading of the current document, by line.
+    OutlineEntryPressed(usize),
+    DocumentScrolled(RelativeOffset),
+    /// Ctrl+D or the star in the URL bar.
+    ToggleBookmark,
+    /// Adds the bookmarks of the other clients installed, see [crate::import].
+    ImportBookmarks,
+    OpenFile,
+    FileChosen(Option<PathBuf>),
+    /// Saves the capsule of the current tab for offline reading, see [archive::save_capsule].
+    SaveCapsule,
+    ArchiveDepthChanged(usize),
+    /// The capsule of the URL is saved into the directory, unless the dialog was cancelled.
+    ArchiveDirChosen(Url, Option<PathBuf>),
+    CapsuleSaved(Result<Saved, String>),
+    /// Checks the local files of the open documents for changes.
+    WatchTick,
+    /// Starts or pauses reading the current document aloud.
+    ReadAloudPressed,
+    /// Skips sections, backwards when negative.
+    ReadAloudSkip(isize),
+    ReadAloudTick,
+    /// Advances the spinner of the loading tabs.
+    SpinnerTick,
+    /// A command sent to the socket of the client, see [remote::Command].
+    #[cfg(unix)]
+    Remote(remote::Request),
 }
 
 #[derive(Debug)]
@@ -30,11 +113,36 @@ pub struct GeminiRootWindow {
     displayed_document_url: String,
     tls_config: Arc<ClientConfig>,
     documents: Vec<Document>,
+    preconnect: bool,
+    /// Record the raw bytes of every load and show them in a developer panel.
+    capture: bool,
+    /// TLS secrets are written to SSLKEYLOGFILE, see `--keylog`.
+    keylog: bool,
+    /// Searched for in every open document.
+    find_query: String,
+    outline_open: bool,
+    /// How far the current document is scrolled, 0 at the top and 1 at the bottom.
+    scroll_y: f32,
+    bookmarks: Bookmarks,
+    handlers: Arc<Handlers>,
+    known_hosts: Arc<KnownHosts>,
+    identities: Arc<IdentityStore>,
+    /// The TLS config of the private tabs, with its own session cache, an in-memory copy
+    /// of the known hosts and identities of their own. Dropped with the last private tab,
+    /// so nothing outlives them.
+    private_session: Option<(Arc<ClientConfig>, Arc<KnownHosts>, Arc<IdentityStore>)>,
+    events: EventBus,
+    /// The tab being read aloud, stopped when it navigates.
+    read_aloud: Option<(usize, ReadAloud)>,
+    /// The frame of [SPINNER_FRAMES] shown.
+    spinner: usize,
+    /// Links followed from the current page when saving its capsule.
+    archive_depth: usize,
 }
 
 impl GeminiRootWindow {
-    pub fn new() -> (Self, Task<GeminiRootMessage>) {
-        let urls = vec![
+    pub fn new(keylog: bool) -> (Self, Task<GeminiRootMessage>) {
+        let urls = [
             Url::parse("gemini://geminiprotocol.net/").unwrap(),
             Url::parse(&format!(
                 "file://{}/../../files/test.gemini",
@@ -43,18 +151,73 @@ impl GeminiRootWindow {
             .unwrap(),
         ];
 
-        let tls_config = make_tls_config().unwrap();
+        let known_hosts = match KnownHosts::default_path().map(KnownHosts::load) {
+            Some(Ok(known_hosts)) => known_hosts,
+            Some(Err(e)) => {
+                error!("Failed to load the known hosts: {}", e);
+                KnownHosts::default()
+            }
+            None => KnownHosts::default(),
+        };
+        let known_hosts = Arc::new(known_hosts);
+
+        let tls_config = make_tls_config(keylog, known_hosts.clone()).unwrap();
+
+        // Without a passphrase identities are kept for the session only.
+        let identities = match (
+            IdentityStore::default_path(),
+            std::env::var(identity::PASSPHRASE_VAR),
+        ) {
+            (Some(path), Ok(passphrase)) => match IdentityStore::open(path, &passphrase) {
+                Ok(identities) => identities,
+                Err(e) => {
+                    error!("Failed to open the identities: {}", e);
+                    IdentityStore::default()
+                }
+            },
+            _ => IdentityStore::default(),
+        };
+        let identities = Arc::new(identities);
+
+        let bookmarks = match Bookmarks::default_path().map(Bookmarks::load) {
+            Some(Ok(bookmarks)) => bookmarks,
+            Some(Err(e)) => {
+                error!("Failed to load bookmarks: {}", e);
+                Bookmarks::default()
+            }
+            None => Bookmarks::default(),
+        };
+
+        let handlers = match Handlers::default_path().map(|path| Handlers::load(&path)) {
+            Some(Ok(handlers)) => handlers,
+            Some(Err(e)) => {
+                error!("Failed to load the MIME type handlers: {}", e);
+                Handlers::default()
+            }
+            None => Handlers::default(),
+        };
+        let handlers = Arc::new(handlers);
+
+        let mut events = EventBus::default();
+        events.subscribe(NavigationLog);
 
         let mut documents = Vec::new();
         let mut tasks = Vec::new();
 
         for (index, url) in urls.iter().enumerate() {
-            let (document, task) = Document::new(tls_config.clone(), url.clone());
+            let (document, task) = Document::new(
+                tls_config.clone(),
+                url.clone(),
+                true,
+                false,
+                handlers.clone(),
+                known_hosts.clone(),
+                identities.clone(),
+                false,
+            );
             documents.push(document);
 
-            tasks.push(task.map(move |d| {
-                return GeminiRootMessage::DocumentHasLoaded(index, d);
-            }));
+            tasks.push(task.map(move |d| GeminiRootMessage::DocumentHasLoaded(index, d)));
         }
 
         (
@@ -64,24 +227,201 @@ impl GeminiRootWindow {
                 displayed_document_url: String::new(),
                 tls_config,
                 documents,
+                preconnect: true,
+                capture: false,
+                keylog,
+                find_query: String::new(),
+                outline_open: true,
+                scroll_y: 0.0,
+                bookmarks,
+                handlers,
+                known_hosts,
+                identities,
+                private_session: None,
+                events,
+                read_aloud: None,
+                spinner: 0,
+                archive_depth: 2,
             },
             Task::batch(tasks),
         )
     }
 
     pub fn update(&mut self, message: GeminiRootMessage) -> Task<GeminiRootMessage> {
+        let task = self.handle_message(message);
+        self.publish_events();
+
+        task
+    }
+
+    /// Passes what the documents did to the listeners of the event bus. Private tabs keep
+    /// their navigation to themselves.
+    fn publish_events(&mut self) {
+        for (tab, document) in self.documents.iter_mut().enumerate() {
+            for navigation in document.take_events() {
+                if matches!(navigation, NavigationEvent::Started(_))
+                    && self.read_aloud.as_ref().is_some_and(|(t, _)| *t == tab)
+                {
+                    self.read_aloud = None;
+                }
+
+                if !document.is_private() {
+                    self.events.publish(Event { tab, navigation });
+                }
+            }
+        }
+    }
+
+    fn handle_message(&mut self, message: GeminiRootMessage) -> Task<GeminiRootMessage> {
         match message {
             GeminiRootMessage::Search => {
                 info!("Search button pressed");
                 let url = canonicalize_url(&self.search_box);
 
-                let (document, task) = Document::new(self.tls_config.clone(), url);
-                self.documents.push(document);
+                self.open_tab(url, false)
+            }
+            GeminiRootMessage::PrivateSearch => {
+                let url = canonicalize_url(&self.search_box);
 
-                let index = self.documents.len() - 1;
-                task.map(move |d| {
-                    return GeminiRootMessage::DocumentHasLoaded(index, d);
-                })
+                self.open_tab(url, true)
+            }
+            GeminiRootMessage::OpenFile => Task::perform(
+                async_std::task::spawn_blocking(|| {
+                    FileDialog::new()
+                        .add_filter("Gemtext", &["gmi", "gemini"])
+                        .show_open_single_file()
+                        .unwrap_or_else(|e| {
+                            error!("Failed to show the file dialog: {}", e);
+                            None
+                        })
+                }),
+                GeminiRootMessage::FileChosen,
+            ),
+            GeminiRootMessage::FileChosen(path) => {
+                let Some(path) = path else {
+                    return Task::none();
+                };
+
+                match Url::from_file_path(&path) {
+                    Ok(url) => self.open_tab(url, false),
+                    Err(_) => {
+                        error!("Can't open {:?}, the path isn't absolute", path);
+                        Task::none()
+                    }
+                }
+            }
+            GeminiRootMessage::SaveCapture => Task::perform(
+                async_std::task::spawn_blocking(|| {
+                    FileDialog::new()
+                        .set_filename("capture.txt")
+                        .show_save_single_file()
+                        .unwrap_or_else(|e| {
+                            error!("Failed to show the file dialog: {}", e);
+                            None
+                        })
+                }),
+                GeminiRootMessage::CaptureFileChosen,
+            ),
+            GeminiRootMessage::CaptureFileChosen(path) => {
+                let Some(path) = path else {
+                    return Task::none();
+                };
+                let Some(raw) = self
+                    .documents
+                    .get(self.document_cursor)
+                    .and_then(|document| document.raw_capture())
+                else {
+                    return Task::none();
+                };
+
+                // Captures are appended, so one file can collect several loads.
+                let saved = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut file| raw.write_to(&mut file));
+                match saved {
+                    Ok(()) => info!("Saved the capture to {:?}", path),
+                    Err(e) => error!("Failed to save the capture to {:?}: {}", path, e),
+                }
+
+                Task::none()
+            }
+            GeminiRootMessage::SaveCapsule => {
+                let Some(url) = self.current_document_url() else {
+                    return Task::none();
+                };
+                if url.scheme() != "gemini" {
+                    error!("Can't save the capsule of {}, it isn't a Gemini URL", url);
+                    return Task::none();
+                }
+
+                Task::perform(
+                    async_std::task::spawn_blocking(|| {
+                        FileDialog::new()
+                            .show_open_single_dir()
+                            .unwrap_or_else(|e| {
+                                error!("Failed to show the file dialog: {}", e);
+                                None
+                            })
+                    }),
+                    move |dir| GeminiRootMessage::ArchiveDirChosen(url.clone(), dir),
+                )
+            }
+            GeminiRootMessage::ArchiveDepthChanged(depth) => {
+                self.archive_depth = depth;
+
+                Task::none()
+            }
+            GeminiRootMessage::ArchiveDirChosen(url, dir) => {
+                let Some(dir) = dir else {
+                    return Task::none();
+                };
+                let private = self
+                    .documents
+                    .get(self.document_cursor)
+                    .is_some_and(|d| d.is_private());
+                let tls_config = match &self.private_session {
+                    Some((tls_config, ..)) if private => tls_config.clone(),
+                    _ => self.tls_config.clone(),
+                };
+
+                info!("Saving the capsule of {} into {:?}", url, dir);
+                Task::perform(
+                    archive::save_capsule(tls_config, url, dir, self.archive_depth),
+                    GeminiRootMessage::CapsuleSaved,
+                )
+            }
+            GeminiRootMessage::CapsuleSaved(result) => {
+                let saved = match result {
+                    Ok(saved) => saved,
+                    Err(e) => {
+                        error!("Failed to save the capsule: {}", e);
+                        return Task::none();
+                    }
+                };
+                info!(
+                    "Saved {} pages into {:?}, skipped {}",
+                    saved.pages, saved.start, saved.skipped
+                );
+
+                match Url::from_file_path(&saved.start) {
+                    Ok(url) => self.open_tab(url, false),
+                    Err(_) => Task::none(),
+                }
+            }
+            GeminiRootMessage::WatchTick => {
+                let tasks = self
+                    .documents
+                    .iter_mut()
+                    .enumerate()
+                    .map(|(index, document)| {
+                        document
+                            .reload_if_changed()
+                            .map(move |msg| GeminiRootMessage::DocumentMessage(index, msg))
+                    });
+
+                Task::batch(tasks)
             }
             GeminiRootMessage::SearchBoxChanged(s) => {
                 debug!("Search box changed to {}", s);
@@ -90,9 +430,14 @@ impl GeminiRootWindow {
                 Task::none()
             }
             GeminiRootMessage::DocumentMessage(index, msg) => match self.documents.get_mut(index) {
-                Some(document) => document
-                    .update(msg)
-                    .map(move |msg| GeminiRootMessage::DocumentMessage(index, msg)),
+                Some(document) => {
+                    let task = document.update(msg);
+                    if index == self.document_cursor {
+                        document.mark_seen();
+                    }
+
+                    task.map(move |msg| GeminiRootMessage::DocumentMessage(index, msg))
+                }
                 None => {
                     error!("[DocumentMessage] Document index out of bounds: {}", index);
 
@@ -121,16 +466,27 @@ impl GeminiRootWindow {
             GeminiRootMessage::ViewDocument(index) => {
                 if index < self.documents.len() {
                     self.document_cursor = index;
+                    self.scroll_y = 0.0;
+                    self.documents[index].mark_seen();
 
-                    self.displayed_document_url = self.current_document_url().unwrap().to_string();
+                    self.displayed_document_url =
+                        idn::to_unicode(&self.current_document_url().unwrap());
                 }
                 Task::none()
             }
             GeminiRootMessage::CloseDocument(index) => {
                 self.documents.remove(index);
+                match &mut self.read_aloud {
+                    Some((tab, _)) if *tab == index => self.read_aloud = None,
+                    Some((tab, _)) if *tab > index => *tab -= 1,
+                    _ => {}
+                }
                 if self.document_cursor >= self.documents.len() {
                     self.document_cursor = self.documents.len().saturating_sub(1);
                 }
+                if !self.documents.iter().any(Document::is_private) {
+                    self.private_session = None;
+                }
                 Task::none()
             }
             GeminiRootMessage::DocumentGoBack => {
@@ -173,17 +529,164 @@ impl GeminiRootWindow {
                     None => Task::none(),
                 }
             }
+            GeminiRootMessage::FindQueryChanged(query) => {
+                self.find_query = query;
+
+                Task::none()
+            }
+            GeminiRootMessage::FindResultPressed(index, line) => {
+                let Some(document) = self.documents.get(index) else {
+                    return Task::none();
+                };
+
+                self.document_cursor = index;
+                self.displayed_document_url = idn::to_unicode(&document.url());
+
+                self.scroll_to_line(line)
+            }
+            GeminiRootMessage::OutlineToggled => {
+                self.outline_open = !self.outline_open;
+
+                Task::none()
+            }
+            GeminiRootMessage::OutlineEntryPressed(line) => self.scroll_to_line(line),
+            GeminiRootMessage::DocumentScrolled(offset) => {
+                self.scroll_y = offset.y;
+
+                Task::none()
+            }
+            GeminiRootMessage::ImportBookmarks => {
+                let found = match import::find_bookmarks() {
+                    Ok(found) => found,
+                    Err(e) => {
+                        error!("Failed to read the bookmarks of other clients: {}", e);
+                        return Task::none();
+                    }
+                };
+                if found.is_empty() {
+                    info!("No bookmarks of other clients found");
+                }
+
+                for (client, bookmarks) in found {
+                    match self.bookmarks.import(bookmarks) {
+                        Ok(added) => info!("Imported {} bookmarks from {:?}", added, client),
+                        Err(e) => error!("Failed to save bookmarks: {}", e),
+                    }
+                }
+
+                Task::none()
+            }
+            GeminiRootMessage::ToggleBookmark => {
+                let Some(document) = self.documents.get(self.document_cursor) else {
+                    return Task::none();
+                };
+                let url = document.url();
+                if url.scheme() == "about" {
+                    return Task::none();
+                }
+
+                match self.bookmarks.toggle(&url, &document.title()) {
+                    Ok(true) => info!("Bookmarked {}", url),
+                    Ok(false) => info!("Removed the bookmark of {}", url),
+                    Err(e) => error!("Failed to save bookmarks: {}", e),
+                }
+
+                Task::none()
+            }
+            GeminiRootMessage::CaptureToggled(capture) => {
+                self.capture = capture;
+                for document in &mut self.documents {
+                    document.capture = capture;
+                }
+
+                Task::none()
+            }
+            GeminiRootMessage::ReadAloudPressed => {
+                let cursor = self.document_cursor;
+                let Some(document) = self.documents.get(cursor) else {
+                    return Task::none();
+                };
+                if self.read_aloud_of_current().is_none() {
+                    let sections = document.read_aloud_sections();
+                    self.read_aloud = Some((cursor, ReadAloud::new(sections)));
+                }
+
+                let (_, read_aloud) = self.read_aloud.as_mut().unwrap();
+                if read_aloud.is_playing() {
+                    read_aloud.pause();
+                } else if let Err(e) = read_aloud.play() {
+                    error!("Failed to start reading aloud: {}", e);
+                }
+
+                Task::none()
+            }
+            GeminiRootMessage::ReadAloudSkip(by) => {
+                let Some((tab, read_aloud)) = &mut self.read_aloud else {
+                    return Task::none();
+                };
+                if let Err(e) = read_aloud.skip(by) {
+                    error!("Failed to read aloud: {}", e);
+                }
+
+                match read_aloud.current() {
+                    Some(section) if *tab == self.document_cursor => {
+                        let line = section.line;
+                        self.scroll_to_line(line)
+                    }
+                    _ => Task::none(),
+                }
+            }
+            GeminiRootMessage::ReadAloudTick => {
+                if let Some((_, read_aloud)) = &mut self.read_aloud
+                    && let Err(e) = read_aloud.poll()
+                {
+                    error!("Failed to read aloud: {}", e);
+                    read_aloud.pause();
+                }
+
+                Task::none()
+            }
+            GeminiRootMessage::SpinnerTick => {
+                self.spinner = (self.spinner + 1) % SPINNER_FRAMES.len();
+
+                Task::none()
+            }
+            #[cfg(unix)]
+            GeminiRootMessage::Remote(request) => self.answer_remote(request),
+            GeminiRootMessage::PreconnectToggled(preconnect) => {
+                self.preconnect = preconnect;
+                for document in &mut self.documents {
+                    document.preconnect = preconnect;
+                }
+
+                Task::none()
+            }
         }
     }
 
-    pub fn view(&self) -> iced::Element<GeminiRootMessage> {
+    pub fn view(&self) -> iced::Element<'_, GeminiRootMessage> {
         let controls = self.view_controls();
 
         let mut document_tabs = Row::new();
         for (index, document) in self.documents.iter().enumerate() {
-            let url = document.title();
-            let b = Button::new(Text::new(url).width(Length::FillPortion(1)))
-                .on_press(GeminiRootMessage::ViewDocument(index));
+            let status = match document.tab_status() {
+                TabStatus::Idle => None,
+                TabStatus::Loading => Some(text(SPINNER_FRAMES[self.spinner])),
+                TabStatus::Failed => Some(text("⚠").color(Color::from_rgb8(0xd0, 0x40, 0x20))),
+                TabStatus::Playing => Some(text("🔊").shaping(Shaping::Advanced)),
+                TabStatus::Updated => Some(text("●").color(Color::from_rgb8(0x40, 0xa0, 0xf0))),
+            };
+            let label = Row::new()
+                .push_maybe(status)
+                .push(Text::new(document.title()))
+                .spacing(5);
+            let b = Button::new(label.width(Length::FillPortion(1)))
+                .on_press(GeminiRootMessage::ViewDocument(index))
+                .style(if document.is_private() {
+                    private_tab_style
+                } else {
+                    button::primary
+                });
             let c = column![b];
 
             let menu = ContextMenu::new(c, move || {
@@ -204,17 +707,189 @@ impl GeminiRootWindow {
 
         let document = self.view_document();
 
-        column![controls, document_tabs, document]
+        let warning = self
+            .current_document_url()
+            .and_then(|url| idn::confusable_warning(&url))
+            .map(|warning| text(warning).color(Color::from_rgb8(0xd0, 0x40, 0x20)));
+
+        let session = self
+            .documents
+            .get(self.document_cursor)
+            .and_then(|d| d.session_info())
+            .map(|info| text(info.summary()).size(12));
+
+        let redirects = self
+            .documents
+            .get(self.document_cursor)
+            .filter(|d| !d.redirects().is_empty())
+            .map(|d| {
+                let trail = d
+                    .redirects()
+                    .iter()
+                    .chain([&d.url()])
+                    .map(idn::to_unicode)
+                    .collect::<Vec<_>>();
+
+                text(format!("Redirected: {}", trail.join(" → "))).size(12)
+            });
+
+        let keylog = self.keylog.then(|| {
+            text("TLS keylogging is on, the secrets of every connection are written to SSLKEYLOGFILE.")
+                .color(Color::from_rgb8(0xd0, 0x40, 0x20))
+        });
+
+        let document = Row::new()
+            .push_maybe(self.view_outline())
+            .push(document)
+            .push_maybe(self.view_find_results())
+            .spacing(10);
+
+        column![controls]
+            .push_maybe(keylog)
+            .push(document_tabs)
+            .push_maybe(session)
+            .push_maybe(redirects)
+            .push_maybe(warning)
+            .push(document)
+            .push_maybe(self.view_raw_capture())
             .spacing(10)
             .padding(10)
             .into()
     }
 
-    fn view_controls(&self) -> Row<GeminiRootMessage> {
+    pub fn subscription(&self) -> Subscription<GeminiRootMessage> {
+        let keys = keyboard::on_key_press(|key, modifiers| match key.as_ref() {
+            Key::Character("d") if modifiers.command() => Some(GeminiRootMessage::ToggleBookmark),
+            Key::Character("o") if modifiers.command() => Some(GeminiRootMessage::OpenFile),
+            _ => None,
+        });
+
+        let watch = if self.documents.iter().any(Document::is_watched) {
+            iced::time::every(FILE_WATCH_INTERVAL).map(|_| GeminiRootMessage::WatchTick)
+        } else {
+            Subscription::none()
+        };
+
+        let playing = self
+            .read_aloud
+            .as_ref()
+            .is_some_and(|(_, r)| r.is_playing());
+        let read_aloud = if playing {
+            iced::time::every(READ_ALOUD_INTERVAL).map(|_| GeminiRootMessage::ReadAloudTick)
+        } else {
+            Subscription::none()
+        };
+
+        let spinner = if self
+            .documents
+            .iter()
+            .any(|d| d.tab_status() == TabStatus::Loading)
+        {
+            iced::time::every(SPINNER_INTERVAL).map(|_| GeminiRootMessage::SpinnerTick)
+        } else {
+            Subscription::none()
+        };
+
+        #[allow(unused_mut)]
+        let mut subscriptions = vec![keys, watch, read_aloud, spinner];
+        #[cfg(unix)]
+        subscriptions.push(Subscription::run(remote::listen).map(GeminiRootMessage::Remote));
+
+        Subscription::batch(subscriptions)
+    }
+
+    #[cfg(unix)]
+    fn answer_remote(&mut self, request: remote::Request) -> Task<GeminiRootMessage> {
+        match &request.command {
+            Command::Open(url) => {
+                let task = self.open_tab(url.clone(), false);
+                request.respond("ok".to_string());
+
+                task
+            }
+            Command::ListTabs => {
+                let tabs = self
+                    .documents
+                    .iter()
+                    .enumerate()
+                    .map(|(index, document)| {
+                        let current = if index == self.document_cursor {
+                            " *"
+                        } else {
+                            ""
+                        };
+                        format!("{} {}{}\n", index, document.url(), current)
+                    })
+                    .collect();
+                request.respond(tabs);
+
+                Task::none()
+            }
+            Command::CurrentUrl => {
+                match self.current_document_url() {
+                    Some(url) => request.respond(url.to_string()),
+                    None => request.fail("No tab is open"),
+                }
+
+                Task::none()
+            }
+            Command::CloseTab(index) if *index < self.documents.len() => {
+                let task = self.update(GeminiRootMessage::CloseDocument(*index));
+                request.respond("ok".to_string());
+
+                task
+            }
+            Command::CloseTab(index) => {
+                request.fail(&format!("No tab {}", index));
+
+                Task::none()
+            }
+        }
+    }
+
+    /// Loads `url` in a new tab, which is shown once it has loaded.
+    fn open_tab(&mut self, url: Url, private: bool) -> Task<GeminiRootMessage> {
+        let (tls_config, known_hosts, identities) = if private {
+            let keylog = self.keylog;
+            let known_hosts = &self.known_hosts;
+
+            self.private_session
+                .get_or_insert_with(|| {
+                    let known_hosts = Arc::new(known_hosts.ephemeral());
+                    let tls_config = make_tls_config(keylog, known_hosts.clone()).unwrap();
+
+                    (tls_config, known_hosts, Arc::default())
+                })
+                .clone()
+        } else {
+            (
+                self.tls_config.clone(),
+                self.known_hosts.clone(),
+                self.identities.clone(),
+            )
+        };
+
+        let (document, task) = Document::new(
+            tls_config,
+            url,
+            self.preconnect,
+            self.capture,
+            self.handlers.clone(),
+            known_hosts,
+            identities,
+            private,
+        );
+        self.documents.push(document);
+
+        let index = self.documents.len() - 1;
+        task.map(move |d| GeminiRootMessage::DocumentHasLoaded(index, d))
+    }
+
+    fn view_controls(&self) -> Row<'_, GeminiRootMessage> {
         let back_button = if self
             .documents
             .get(self.document_cursor)
-            .map_or(false, |d| d.can_go_back())
+            .is_some_and(|d| d.can_go_back())
         {
             button("Back").on_press(GeminiRootMessage::DocumentGoBack)
         } else {
@@ -224,7 +899,29 @@ impl GeminiRootWindow {
             })
         };
 
+        let bookmarked = self
+            .current_document_url()
+            .is_some_and(|url| self.bookmarks.contains(&url));
+        let star = button(if bookmarked { "★" } else { "☆" })
+            .style(button::text)
+            .on_press(GeminiRootMessage::ToggleBookmark);
+
+        let read_aloud = self.read_aloud_of_current();
+        let read_aloud_label = match read_aloud {
+            Some(read_aloud) if read_aloud.is_playing() => "Pause",
+            _ => "Read aloud",
+        };
+        let read_aloud_controls = read_aloud.map(|_| {
+            row![
+                button("⏮").on_press(GeminiRootMessage::ReadAloudSkip(-1)),
+                button("⏭").on_press(GeminiRootMessage::ReadAloudSkip(1)),
+            ]
+            .spacing(5)
+        });
+
         row![
+            star,
+            self.view_url_indicator(),
             text_input("Current Document", &self.displayed_document_url.to_string())
                 .width(Length::Fill)
                 .padding(10)
@@ -235,14 +932,36 @@ impl GeminiRootWindow {
                 .on_input(GeminiRootMessage::SearchBoxChanged)
                 .on_submit(GeminiRootMessage::Search),
             button("Search").on_press(GeminiRootMessage::Search),
+            button("Private").on_press(GeminiRootMessage::PrivateSearch),
+            button("Open File").on_press(GeminiRootMessage::OpenFile),
+            button("Import bookmarks").on_press(GeminiRootMessage::ImportBookmarks),
+            button("Save capsule").on_press(GeminiRootMessage::SaveCapsule),
+            pick_list(
+                ARCHIVE_DEPTHS,
+                Some(self.archive_depth),
+                GeminiRootMessage::ArchiveDepthChanged
+            ),
             back_button,
-            button("Debug Print Document").on_press(GeminiRootMessage::DebugPrintDocument)
+            button(read_aloud_label).on_press(GeminiRootMessage::ReadAloudPressed),
         ]
+        .push_maybe(read_aloud_controls)
+        .push(
+            text_input("Find in all tabs", &self.find_query)
+                .width(200)
+                .padding(10)
+                .on_input(GeminiRootMessage::FindQueryChanged),
+        )
+        .push(
+            checkbox("Pre-connect", self.preconnect)
+                .on_toggle(GeminiRootMessage::PreconnectToggled),
+        )
+        .push(checkbox("Capture raw", self.capture).on_toggle(GeminiRootMessage::CaptureToggled))
+        .push(button("Debug Print Document").on_press(GeminiRootMessage::DebugPrintDocument))
         .spacing(10)
         .align_y(Center)
     }
 
-    fn view_document(&self) -> iced::Element<GeminiRootMessage> {
+    fn view_document(&self) -> iced::Element<'_, GeminiRootMessage> {
         match self.documents.get(self.document_cursor) {
             None => text("No document to display").into(),
             Some(document) => {
@@ -251,6 +970,10 @@ impl GeminiRootWindow {
                     .map(move |msg| GeminiRootMessage::DocumentMessage(self.document_cursor, msg));
 
                 scrollable(view)
+                    .id(document_scroll_id())
+                    .on_scroll(|viewport| {
+                        GeminiRootMessage::DocumentScrolled(viewport.relative_offset())
+                    })
                     .width(Length::Fill)
                     .height(Length::Fill)
                     .spacing(10)
@@ -259,19 +982,229 @@ impl GeminiRootWindow {
         }
     }
 
+    /// The headings of the current document, the one scrolled to highlighted.
+    fn view_outline(&self) -> Option<iced::Element<'_, GeminiRootMessage>> {
+        let document = self.documents.get(self.document_cursor)?;
+        let toc = document.table_of_contents();
+        if toc.is_empty() {
+            return None;
+        }
+
+        let label = if self.outline_open {
+            "Outline ▾"
+        } else {
+            "Outline ▸"
+        };
+        let toggle = button(label)
+            .style(button::secondary)
+            .on_press(GeminiRootMessage::OutlineToggled);
+        if !self.outline_open {
+            return Some(toggle.into());
+        }
+
+        let current_line =
+            (self.scroll_y * document.line_count().saturating_sub(1) as f32) as usize;
+        let current = toc.iter().rposition(|entry| entry.line <= current_line);
+
+        let mut outline = Column::new().push(toggle).spacing(5);
+        for (i, entry) in toc.into_iter().enumerate() {
+            let style = if Some(i) == current {
+                button::primary
+            } else {
+                button::text
+            };
+
+            let indent = 5.0 + 15.0 * entry.depth.saturating_sub(1) as f32;
+
+            outline = outline.push(
+                button(Text::new(entry.text).shaping(Shaping::Advanced))
+                    .style(style)
+                    .padding(Padding::from([2, 5]).left(indent))
+                    .on_press(GeminiRootMessage::OutlineEntryPressed(entry.line)),
+            );
+        }
+
+        Some(scrollable(outline).width(250).height(Length::Fill).into())
+    }
+
+    /// Scrolls the current document so `line` is about as far down as it is in the document.
+    fn scroll_to_line(&mut self, line: usize) -> Task<GeminiRootMessage> {
+        let Some(document) = self.documents.get(self.document_cursor) else {
+            return Task::none();
+        };
+
+        let y = line as f32 / document.line_count().saturating_sub(1).max(1) as f32;
+        self.scroll_y = y;
+
+        scrollable::snap_to(document_scroll_id(), RelativeOffset { x: 0.0, y })
+    }
+
+    /// The bytes sent and received for the current document, when they were captured.
+    fn view_raw_capture(&self) -> Option<iced::Element<'_, GeminiRootMessage>> {
+        if !self.capture {
+            return None;
+        }
+        let raw = self.documents.get(self.document_cursor)?.raw_capture()?;
+
+        let (header, body) = raw.split_response();
+        let mut shown = String::from_utf8_lossy(body).to_string();
+        if shown.len() > MAX_CAPTURE_BODY_SHOWN {
+            let end = shown.floor_char_boundary(MAX_CAPTURE_BODY_SHOWN);
+            shown.truncate(end);
+            shown.push_str("\n...");
+        }
+
+        let mono = Font::with_name("DejaVu Sans Mono");
+        let panel = column![
+            button("Save capture").on_press(GeminiRootMessage::SaveCapture),
+            text(format!(
+                "Request ({} bytes): {:?}",
+                raw.request.len(),
+                String::from_utf8_lossy(&raw.request)
+            ))
+            .font(mono),
+            text(format!(
+                "Response header: {:?}",
+                String::from_utf8_lossy(header)
+            ))
+            .font(mono),
+            text(format!("Response body ({} bytes):", body.len())).font(mono),
+            text(shown).font(mono).shaping(Shaping::Advanced),
+        ]
+        .spacing(5);
+
+        Some(scrollable(panel).width(Length::Fill).height(200).into())
+    }
+
+    /// The matches of the find query, grouped by tab.
+    fn view_find_results(&self) -> Option<iced::Element<'_, GeminiRootMessage>> {
+        if self.find_query.is_empty() {
+            return None;
+        }
+
+        let mut results = Column::new().spacing(5);
+        let mut total = 0;
+
+        for (index, document) in self.documents.iter().enumerate() {
+            let matches = document.find(&self.find_query);
+            if matches.is_empty() {
+                continue;
+            }
+            total += matches.len();
+
+            results = results.push(text(format!("{} ({})", document.title(), matches.len())));
+            for m in matches.into_iter().take(MAX_FIND_RESULTS_PER_TAB) {
+                let snippet: String = m.text.chars().take(FIND_SNIPPET_LENGTH).collect();
+
+                results = results.push(
+                    button(Text::new(snippet).shaping(Shaping::Advanced))
+                        .style(button::text)
+                        .on_press(GeminiRootMessage::FindResultPressed(index, m.line)),
+                );
+            }
+        }
+
+        if total == 0 {
+            results = results.push(text("No matches"));
+        }
+
+        Some(scrollable(results).width(300).height(Length::Fill).into())
+    }
+
+    /// What reads the current document aloud, if it is being read.
+    fn read_aloud_of_current(&self) -> Option<&ReadAloud> {
+        self.read_aloud
+            .as_ref()
+            .filter(|(tab, _)| *tab == self.document_cursor)
+            .map(|(_, read_aloud)| read_aloud)
+    }
+
     fn current_document_url(&self) -> Option<Url> {
         self.documents.get(self.document_cursor).map(|d| d.url())
     }
+
+    /// How far the host of the current document is trusted and, unless it is Gemini, the
+    /// scheme of its URL.
+    fn view_url_indicator(&self) -> Row<'_, GeminiRootMessage> {
+        let Some(document) = self
+            .documents
+            .get(self.document_cursor)
+            .filter(|d| d.tab_status() != TabStatus::Loading)
+        else {
+            return Row::new();
+        };
+
+        let trust = document.host_trust().map(|trust| {
+            let (label, color, explanation) = match trust {
+                HostTrust::Pinned => (
+                    "🔒",
+                    Color::from_rgb8(0x30, 0xa0, 0x50),
+                    "The certificate matches the one trusted on first use",
+                ),
+                HostTrust::New => (
+                    "New host",
+                    Color::from_rgb8(0xd0, 0x90, 0x20),
+                    "First visit, the certificate was trusted without anything to compare it to",
+                ),
+                HostTrust::Changed => (
+                    "Certificate changed",
+                    Color::from_rgb8(0xd0, 0x40, 0x20),
+                    "The certificate doesn't match the one trusted on first use",
+                ),
+            };
+
+            Tooltip::new(
+                text(label).color(color).shaping(Shaping::Advanced),
+                text(explanation),
+                tooltip::Position::Bottom,
+            )
+            .snap_within_viewport(true)
+        });
+
+        let url = document.url();
+        let scheme = (url.scheme() != "gemini")
+            .then(|| text(url.scheme().to_uppercase()).color(Color::from_rgb8(0xd0, 0x90, 0x20)));
+
+        Row::new()
+            .push_maybe(trust)
+            .push_maybe(scheme)
+            .spacing(5)
+            .align_y(Center)
+    }
+}
+
+/// The scrollable of the current document, to jump to the match of a find.
+fn document_scroll_id() -> scrollable::Id {
+    scrollable::Id::new("document")
+}
+
+/// Private tabs are purple, so they aren't mistaken for the others.
+fn private_tab_style(theme: &iced::Theme, status: button::Status) -> button::Style {
+    let color = match status {
+        button::Status::Hovered | button::Status::Pressed => Color::from_rgb8(0x7a, 0x3d, 0xb8),
+        button::Status::Active | button::Status::Disabled => Color::from_rgb8(0x5b, 0x2a, 0x8c),
+    };
+
+    button::Style {
+        background: Some(Background::Color(color)),
+        ..button::primary(theme, status)
+    }
 }
 
 fn canonicalize_url(url: &str) -> Url {
-    let url = if url.starts_with("gemini://") {
+    let url = if ["gemini://", "spartan://", "gopher://", "about:"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+    {
         Url::parse(url)
     } else {
         Url::parse(&format!("gemini://{}", url))
     };
 
     // FIXME: Handle invalid URLs better
+    let url = url
+        .map_err(UrlError::Parse)
+        .and_then(|url| url_util::normalize(&url));
     url.unwrap_or_else(|e| {
         error!("Invalid URL: {}", e);
         Url::parse("gemini://geminiprotocol.net/").unwrap()
@@ -0,0 +1,198 @@
+use std::io::{self, BufRead, Read, Write};
+use std::time::{Duration, SystemTime};
+use url::Url;
+use crate::error::ParserError;
+use crate::gemini_protocol::parse_response_bytes;
+use crate::gemini_protocol::response::Response;
+
+/// The first line of every record, with the version of the format.
+const MAGIC: &str = "gemini-capture 1";
+
+/// The exact bytes of a request and its response, with when they were exchanged. Captures
+/// are stored one after the other in a file, each as a few `key value` lines, a blank line,
+/// then the request and the response as they were on the wire:
+///
+/// ```text
+/// gemini-capture 1
+/// sent 1760000000000
+/// received 1760000000250
+/// request 23
+/// response 22
+///
+/// gemini://example.org/
+/// 20 text/gemini
+/// # Hi
+/// ```
+///
+/// Times are milliseconds since the Unix epoch, lengths are in bytes. Unknown keys are
+/// skipped, so later versions can add some.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Capture {
+    pub request: Vec<u8>,
+    /// The header with its line ending, then the body.
+    pub response: Vec<u8>,
+    pub sent: SystemTime,
+    /// When the response was read to its end.
+    pub received: SystemTime,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Self {
+            request: vec![],
+            response: vec![],
+            sent: SystemTime::UNIX_EPOCH,
+            received: SystemTime::UNIX_EPOCH,
+        }
+    }
+}
+
+impl Capture {
+    /// The URL of a Gemini request, `None` for requests of other protocols.
+    pub fn url(&self) -> Option<Url> {
+        let line = std::str::from_utf8(&self.request).ok()?;
+
+        Url::parse(line.strip_suffix("\r\n")?).ok()
+    }
+
+    /// The response header with its line ending, and the body.
+    pub fn split_response(&self) -> (&[u8], &[u8]) {
+        let end = self
+            .response
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .map(|i| i + 2)
+            .unwrap_or(self.response.len());
+
+        self.response.split_at(end)
+    }
+
+    /// The response of a Gemini request, parsed as it was when it was received.
+    pub fn parse_response(&self, url: &Url) -> Result<Response, ParserError> {
+        parse_response_bytes(url, &self.response)
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(
+            out,
+            "{}\nsent {}\nreceived {}\nrequest {}\nresponse {}\n\n",
+            MAGIC,
+            millis(self.sent),
+            millis(self.received),
+            self.request.len(),
+            self.response.len()
+        )?;
+        out.write_all(&self.request)?;
+        out.write_all(&self.response)
+    }
+
+    /// Reads the next capture of `input`, `None` at its end.
+    pub fn read_from<R: BufRead>(input: &mut R) -> io::Result<Option<Self>> {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.trim_end() != MAGIC {
+            return Err(invalid(format!("expected '{}', got '{}'", MAGIC, line.trim_end())));
+        }
+
+        let mut capture = Capture::default();
+        let (mut request, mut response) = (0, 0);
+        loop {
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let number = || value.parse::<u64>().map_err(|_| invalid(format!("invalid {} '{}'", key, value)));
+            match key {
+                "sent" => capture.sent = SystemTime::UNIX_EPOCH + Duration::from_millis(number()?),
+                "received" => capture.received = SystemTime::UNIX_EPOCH + Duration::from_millis(number()?),
+                "request" => request = number()?,
+                "response" => response = number()?,
+                _ => {}
+            }
+        }
+
+        capture.request = read_exact(input, request)?;
+        capture.response = read_exact(input, response)?;
+
+        Ok(Some(capture))
+    }
+
+    /// Every capture of `input`, in order.
+    pub fn read_all<R: BufRead>(input: &mut R) -> io::Result<Vec<Self>> {
+        std::iter::from_fn(|| Capture::read_from(input).transpose()).collect()
+    }
+}
+
+fn millis(time: SystemTime) -> u128 {
+    time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis()
+}
+
+/// `len` bytes, without trusting `len` with an allocation up front.
+fn read_exact<R: Read>(input: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    input.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(bytes)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let hi = Capture {
+            request: b"gemini://example.org/\r\n".to_vec(),
+            response: b"20 text/gemini\r\n# Hi\n".to_vec(),
+            sent: SystemTime::UNIX_EPOCH + Duration::from_millis(1_760_000_000_000),
+            received: SystemTime::UNIX_EPOCH + Duration::from_millis(1_760_000_000_250),
+        };
+        let gone = Capture {
+            request: b"gemini://example.org/old\r\n".to_vec(),
+            response: b"52 Gone\r\n".to_vec(),
+            ..Capture::default()
+        };
+
+        let mut file = vec![];
+        hi.write_to(&mut file).unwrap();
+        gone.write_to(&mut file).unwrap();
+        assert!(file.starts_with(b"gemini-capture 1\nsent 1760000000000\nreceived 1760000000250\nrequest 23\nresponse 21\n\ngemini://"));
+
+        let captures = Capture::read_all(&mut &file[..]).unwrap();
+        assert_eq!(captures, vec![hi.clone(), gone]);
+
+        let url = hi.url().unwrap();
+        assert_eq!(url.as_str(), "gemini://example.org/");
+        assert_eq!(hi.split_response(), (&b"20 text/gemini\r\n"[..], &b"# Hi\n"[..]));
+        let Response::Success(ok) = hi.parse_response(&url).unwrap() else {
+            panic!("not a success");
+        };
+        assert_eq!(ok.gemtext().unwrap().to_string(), "# Hi\n");
+
+        let spartan = Capture { request: b"example.org / 0\r\n".to_vec(), ..Capture::default() };
+        assert_eq!(spartan.url(), None);
+        assert_eq!(spartan.split_response(), (&b""[..], &b""[..]));
+
+        let truncated = &file[..file.len() - 3];
+        assert_eq!(Capture::read_all(&mut &truncated[..]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        let invalid = b"gemini-capture 1\nsent soon\n\n";
+        assert_eq!(Capture::read_all(&mut &invalid[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(Capture::read_all(&mut &b"HTTP/1.1 200 OK\r\n"[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
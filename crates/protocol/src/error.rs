@@ -16,6 +16,10 @@ pub enum ErrorKind {
     SyntaxMissingNewline,
     SyntaxMissingSpace,
     InvalidDigit,
+    /// The meta is longer than the 1024 bytes the spec allows, or the limit the client
+    /// read the header with.
+    HeaderTooLong,
+    Io(std::io::ErrorKind),
 }
 
 impl Display for ParserError {
@@ -34,10 +38,62 @@ impl Display for ErrorKind {
             ErrorKind::SyntaxMissingNewline => write!(f, "missing newline"),
             ErrorKind::SyntaxMissingSpace => write!(f, "missing space"),
             ErrorKind::InvalidDigit => write!(f, "invalid digit"),
+            ErrorKind::HeaderTooLong => write!(f, "header too long"),
+            ErrorKind::Io(e) => write!(f, "io error: {}", e),
         }
     }
 }
 
+/// Why a URL can't be requested.
+#[derive(Debug, Eq, PartialEq)]
+pub enum RequestError {
+    /// Gemini URLs must not carry a username or password.
+    UserInfo,
+    /// The URL is longer than the 1024 bytes a server has to accept.
+    TooLong(usize),
+    /// Spartan requests start with the host.
+    MissingHost,
+}
+
+impl Display for RequestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::UserInfo => write!(f, "URL contains userinfo"),
+            RequestError::TooLong(len) => write!(f, "URL is {} bytes, longer than 1024", len),
+            RequestError::MissingHost => write!(f, "URL has no host"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// Why a URL can't be normalized, see [crate::url_util].
+#[derive(Debug, Eq, PartialEq)]
+pub enum UrlError {
+    Parse(url::ParseError),
+    /// Gemini URLs must not carry a username or password.
+    UserInfo,
+    /// Requests don't carry the fragment, it is for the client.
+    Fragment,
+    MissingHost,
+    /// The host isn't a valid domain name, or can't be written as one.
+    InvalidHost,
+}
+
+impl Display for UrlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UrlError::Parse(e) => write!(f, "invalid URL: {}", e),
+            UrlError::UserInfo => write!(f, "URL contains userinfo"),
+            UrlError::Fragment => write!(f, "URL contains a fragment"),
+            UrlError::MissingHost => write!(f, "URL has no host"),
+            UrlError::InvalidHost => write!(f, "URL has an invalid host"),
+        }
+    }
+}
+
+impl std::error::Error for UrlError {}
+
 impl From<GemTextError> for ParserError {
     fn from(value: GemTextError) -> Self {
         ParserError {
@@ -46,3 +102,69 @@ impl From<GemTextError> for ParserError {
         }
     }
 }
+
+/// Why [crate::gemini_protocol::client::fetch] didn't get a response.
+#[cfg(feature = "client")]
+#[derive(Debug)]
+pub enum FetchError {
+    Request(RequestError),
+    /// The URL has no host, or one that isn't a valid server name.
+    InvalidHost,
+    /// Connecting, the TLS handshake or the transfer failed.
+    Io(std::io::Error),
+    /// The response is longer than the limit it was fetched with.
+    TooLarge(u64),
+    /// The connection closed without a close_notify, what was read may be cut short.
+    Truncated(Vec<u8>),
+    /// No whole response arrived within the time limit.
+    Timeout(std::time::Duration),
+    Response(ParserError),
+    /// More redirects in a row than allowed, the limit.
+    TooManyRedirects(usize),
+    /// A redirect back to a URL that was already visited.
+    RedirectLoop(url::Url),
+    /// A redirect target that isn't a URL, relative or absolute.
+    InvalidRedirect(String),
+}
+
+#[cfg(feature = "client")]
+impl Display for FetchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::Request(e) => write!(f, "invalid request: {}", e),
+            FetchError::InvalidHost => write!(f, "URL has no valid host"),
+            FetchError::Io(e) => write!(f, "io error: {}", e),
+            FetchError::TooLarge(limit) => write!(f, "response longer than {} bytes", limit),
+            FetchError::Truncated(read) => write!(f, "response may be truncated after {} bytes", read.len()),
+            FetchError::Timeout(timeout) => write!(f, "no response within {:?}", timeout),
+            FetchError::Response(e) => write!(f, "invalid response: {}", e),
+            FetchError::TooManyRedirects(limit) => write!(f, "more than {} redirects", limit),
+            FetchError::RedirectLoop(url) => write!(f, "redirect loop back to {}", url),
+            FetchError::InvalidRedirect(target) => write!(f, "invalid redirect to '{}'", target),
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+impl std::error::Error for FetchError {}
+
+#[cfg(feature = "client")]
+impl From<RequestError> for FetchError {
+    fn from(value: RequestError) -> Self {
+        FetchError::Request(value)
+    }
+}
+
+#[cfg(feature = "client")]
+impl From<std::io::Error> for FetchError {
+    fn from(value: std::io::Error) -> Self {
+        FetchError::Io(value)
+    }
+}
+
+#[cfg(feature = "client")]
+impl From<ParserError> for FetchError {
+    fn from(value: ParserError) -> Self {
+        FetchError::Response(value)
+    }
+}
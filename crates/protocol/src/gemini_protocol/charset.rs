@@ -0,0 +1,51 @@
+use encoding_rs::{Encoding, UTF_8};
+use crate::gemtext::gemtext_body::MimeType;
+
+/// The encoding named by the charset parameter of `mime`. Gemini defaults to UTF-8 when
+/// there is none, and so does an unknown label.
+pub fn encoding(mime: &MimeType) -> &'static Encoding {
+    mime.charset()
+        .and_then(|label| Encoding::for_label(label.trim().as_bytes()))
+        .unwrap_or(UTF_8)
+}
+
+/// Decodes a `text/*` body, replacing anything malformed with U+FFFD. A byte order mark
+/// takes precedence over the charset parameter.
+pub fn decode(mime: &MimeType, body: &[u8]) -> String {
+    let (text, _, _) = encoding(mime).decode(body);
+    text.into_owned()
+}
+
+/// Encodes a `text/*` body in the charset of `mime`, so that it matches the header it is
+/// sent with. Characters the charset lacks become HTML numeric character references, and
+/// UTF-16 is written as UTF-8 since [Encoding::encode] can't produce it.
+pub fn encode(mime: &MimeType, text: &str) -> Vec<u8> {
+    let (bytes, _, _) = encoding(mime).encode(text);
+    bytes.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    fn text(charset: Option<&str>) -> MimeType {
+        MimeType {
+            parameters: charset.map(|c| HashMap::from([("charset".to_string(), c.to_string())])),
+            ..MimeType::gemini()
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(&text(None), "Grüße".as_bytes()), "Grüße");
+        assert_eq!(decode(&text(Some("ISO-8859-1")), b"Gr\xfc\xdfe"), "Grüße");
+        assert_eq!(decode(&text(Some("shift_jis")), b"\x93\xfa\x96\x7b"), "日本");
+        assert_eq!(decode(&text(Some("utf-8")), b"bad \xff"), "bad \u{fffd}");
+        assert_eq!(decode(&text(Some("no-such-charset")), "Grüße".as_bytes()), "Grüße");
+
+        assert_eq!(encode(&text(Some("iso-8859-1")), "Grüße"), b"Gr\xfc\xdfe");
+        assert_eq!(encode(&text(Some("shift_jis")), "日本"), b"\x93\xfa\x96\x7b");
+        assert_eq!(encode(&text(None), "日本"), "日本".as_bytes());
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use rustls::ClientConfig;
+use rustls::pki_types::ServerName;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use url::Url;
+use crate::error::FetchError;
+use crate::gemini_protocol::{parse_response_bytes, DEFAULT_PORT};
+use crate::gemini_protocol::request::Request;
+use crate::gemini_protocol::response::Response;
+use crate::url_util::to_ascii_host;
+
+/// How long [fetch] waits for a whole response, from connecting to the last byte.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The longest response [fetch] reads, the whole body is held in memory.
+pub const MAX_RESPONSE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Redirects in a row [follow_redirects] is usually given, the spec suggests at most 5.
+pub const MAX_REDIRECTS: usize = 5;
+
+/// What [follow_redirects] ended up with.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Redirected<T = Response> {
+    /// The URL that gave `response`.
+    pub url: Url,
+    pub response: T,
+    /// The URLs that redirected to `url`, in the order they were visited.
+    pub redirects: Vec<Url>,
+}
+
+/// Requests `url` from its host and reads the whole response, in one call: connects, sends
+/// the host as SNI, writes the request and parses the response. `config` verifies the
+/// certificate of the host, and may present a client certificate.
+pub async fn fetch(url: &Url, config: Arc<ClientConfig>) -> Result<Response, FetchError> {
+    fetch_limited(url, config, MAX_RESPONSE_SIZE).await
+}
+
+/// Like [fetch], failing with [FetchError::TooLarge] for a response longer than `limit`.
+pub async fn fetch_limited(url: &Url, config: Arc<ClientConfig>, limit: u64) -> Result<Response, FetchError> {
+    let response = fetch_raw(url, config, limit).await?;
+
+    Ok(parse_response_bytes(url, &response)?)
+}
+
+/// Like [fetch_limited], the response as it was received, header and body. Gemtext is
+/// kept as it was written, e.g. with its relative links.
+pub async fn fetch_raw(url: &Url, config: Arc<ClientConfig>, limit: u64) -> Result<Vec<u8>, FetchError> {
+    let url = to_ascii_host(url).map_err(|_| FetchError::InvalidHost)?;
+
+    // IPv6 addresses are bracketed in URLs, not in server names.
+    let host = url.host_str().ok_or(FetchError::InvalidHost)?.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port().unwrap_or(DEFAULT_PORT);
+
+    fetch_raw_from(host, port, &url, config, limit).await
+}
+
+/// Like [fetch_raw], sending the request for `url` to `host` rather than to the host of the
+/// URL, e.g. to another server of the same capsule. `host` is also the server name.
+pub async fn fetch_raw_from(host: &str, port: u16, url: &Url, config: Arc<ClientConfig>, limit: u64) -> Result<Vec<u8>, FetchError> {
+    exchange(host, port, url, config, limit, TIMEOUT).await
+}
+
+async fn exchange(host: &str, port: u16, url: &Url, config: Arc<ClientConfig>, limit: u64, timeout: Duration) -> Result<Vec<u8>, FetchError> {
+    let request = Request::new(url)?;
+    let server_name = ServerName::try_from(host.to_string()).map_err(|_| FetchError::InvalidHost)?;
+
+    let exchange = async {
+        let socket = TcpStream::connect((host, port)).await?;
+        let mut stream = TlsConnector::from(config).connect(server_name, socket).await?;
+
+        stream.write_all(&request.to_bytes()).await?;
+        stream.flush().await?;
+
+        let mut response = vec![];
+        match (&mut stream).take(limit + 1).read_to_end(&mut response).await {
+            Ok(_) => {}
+            // Without a close_notify the response may have been cut short on the way.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !response.is_empty() => {
+                return Err(FetchError::Truncated(response));
+            }
+            Err(e) => return Err(e.into()),
+        }
+        if response.len() as u64 > limit {
+            return Err(FetchError::TooLarge(limit));
+        }
+
+        Ok(response)
+    };
+
+    tokio::time::timeout(timeout, exchange).await.map_err(|_| FetchError::Timeout(timeout))?
+}
+
+/// Fetches `url` with `fetch` and follows the redirects it gets, at most `max_redirects` in
+/// a row. Relative targets are resolved against the URL that redirected. A redirect to
+/// another scheme isn't followed, it is returned as the response for the caller to decide
+/// on.
+///
+/// `fetch` is usually [fetch] with a config, with [Response::redirect_target] as
+/// `redirect_of`. It can be anything that requests a URL, `redirect_of` tells where what it
+/// got redirects to.
+pub async fn follow_redirects<T, F, Fut>(url: &Url, max_redirects: usize, mut fetch: F, redirect_of: impl Fn(&T) -> Option<&str>) -> Result<Redirected<T>, FetchError>
+where
+    F: FnMut(Url) -> Fut,
+    Fut: Future<Output = Result<T, FetchError>>,
+{
+    let mut url = url.clone();
+    let mut redirects = vec![];
+    let mut visited = HashSet::new();
+
+    loop {
+        // The fragment isn't sent, `/page#a` and `/page#b` are the same request.
+        let mut request = url.clone();
+        request.set_fragment(None);
+        if !visited.insert(request) {
+            return Err(FetchError::RedirectLoop(url));
+        }
+
+        let response = fetch(url.clone()).await?;
+        let Some(target) = redirect_of(&response) else {
+            return Ok(Redirected { url, response, redirects });
+        };
+
+        let next = url.join(target).map_err(|_| FetchError::InvalidRedirect(target.to_string()))?;
+        if next.scheme() != url.scheme() {
+            return Ok(Redirected { url, response, redirects });
+        }
+        if redirects.len() == max_redirects {
+            return Err(FetchError::TooManyRedirects(max_redirects));
+        }
+
+        redirects.push(std::mem::replace(&mut url, next));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemini_protocol::response::OkResponse;
+    use crate::gemtext::gemtext_body::MimeType;
+    use rustls::{RootCertStore, ServerConfig};
+    use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    /// Serves `response` once on a free port, `59` for an unexpected request line. Without
+    /// `close_notify` the connection is dropped instead of shut down.
+    async fn serve_once(response: &'static [u8], close_notify: bool) -> (u16, Arc<ClientConfig>) {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec!["127.0.0.1".to_string()]).unwrap().self_signed(&key_pair).unwrap();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+
+        let server = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.der().clone()], key)
+            .unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.der().clone()).unwrap();
+        let client = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(TlsAcceptor::from(Arc::new(server)).accept(socket).await.unwrap());
+
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let response = match line == format!("gemini://127.0.0.1:{}/page\r\n", port) {
+                true => response,
+                false => b"59 Unexpected request\r\n",
+            };
+
+            // The client stops reading a response that is too long.
+            let _ = stream.write_all(response).await;
+            if close_notify {
+                let _ = stream.shutdown().await;
+            }
+        });
+
+        (port, Arc::new(client))
+    }
+
+    #[tokio::test]
+    async fn test_fetch() {
+        let url = |port| Url::parse(&format!("gemini://127.0.0.1:{}/page#top", port)).unwrap();
+
+        let (port, config) = serve_once(b"20 text/gemini\r\n# Page\n", true).await;
+        let Response::Success(ok) = fetch(&url(port), config).await.unwrap() else {
+            panic!("not a success");
+        };
+        assert_eq!(ok.gemtext().unwrap().to_string(), "# Page\n");
+
+        let (port, config) = serve_once(b"51 Not here\r\n", true).await;
+        assert_eq!(fetch(&url(port), config).await.unwrap(), Response::ResourceNotFound(Some("Not here".to_string())));
+
+        let (port, config) = serve_once(b"20 text/gemini\r\n# Pa", false).await;
+        assert!(matches!(fetch(&url(port), config).await, Err(FetchError::Truncated(read)) if read == b"20 text/gemini\r\n# Pa"));
+
+        let (port, config) = serve_once(b"20 text/plain\r\n0123456789", true).await;
+        assert!(matches!(fetch_limited(&url(port), config, 16).await, Err(FetchError::TooLarge(16))));
+
+        let (port, config) = serve_once(b"20 text/gemini\r\n# Page\n", true).await;
+        let other = Url::parse("gemini://example.org/page").unwrap();
+        let response = fetch_raw_from("127.0.0.1", port, &other, config, 1024).await.unwrap();
+        assert_eq!(response, b"59 Unexpected request\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        // Accepts the connection and never answers the handshake.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let (_, config) = serve_once(b"", true).await;
+        let url = Url::parse(&format!("gemini://127.0.0.1:{}/", port)).unwrap();
+        let timeout = Duration::from_millis(50);
+        let result = exchange("127.0.0.1", port, &url, config, 1024, timeout).await;
+        assert!(matches!(result, Err(FetchError::Timeout(t)) if t == timeout));
+    }
+
+    #[tokio::test]
+    async fn test_follow_redirects() {
+        let site = |url: Url| async move {
+            Ok(match url.path() {
+                "/a" => Response::TemporaryRedirect("b#top".to_string()),
+                "/b" => Response::PermanentRedirect("/c".to_string()),
+                "/loop" => Response::TemporaryRedirect("/a-loop".to_string()),
+                "/a-loop" => Response::TemporaryRedirect("/loop#again".to_string()),
+                "/away" => Response::TemporaryRedirect("https://example.org/".to_string()),
+                path => Response::Success(OkResponse::from_text(&url, MimeType::plain_text(), path.to_string())?),
+            })
+        };
+        let url = |path: &str| Url::parse(&format!("gemini://example.org{}", path)).unwrap();
+
+        let redirected = follow_redirects(&url("/a"), MAX_REDIRECTS, site, Response::redirect_target).await.unwrap();
+        assert_eq!(redirected.url, url("/c"));
+        assert_eq!(redirected.redirects, vec![url("/a"), url("/b#top")]);
+
+        assert!(matches!(follow_redirects(&url("/a"), 1, site, Response::redirect_target).await, Err(FetchError::TooManyRedirects(1))));
+        assert!(matches!(follow_redirects(&url("/loop"), MAX_REDIRECTS, site, Response::redirect_target).await, Err(FetchError::RedirectLoop(u)) if u == url("/loop#again")));
+
+        let redirected = follow_redirects(&url("/away"), MAX_REDIRECTS, site, Response::redirect_target).await.unwrap();
+        assert_eq!(redirected.url, url("/away"));
+        assert!(redirected.redirects.is_empty());
+
+        // Anything that tells where it redirects to can be followed, not only responses.
+        let pages = |url: Url| async move {
+            Ok(match url.path() {
+                "/old" => (url.path().to_string(), Some("/new")),
+                path => (path.to_string(), None),
+            })
+        };
+        let redirected = follow_redirects(&url("/old"), MAX_REDIRECTS, pages, |(_, target)| *target).await.unwrap();
+        assert_eq!(redirected.response.0, "/new");
+        assert_eq!(redirected.redirects, vec![url("/old")]);
+    }
+}
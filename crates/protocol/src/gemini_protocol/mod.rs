@@ -1,13 +1,32 @@
 use url::Url;
 use crate::error::ParserError;
 use crate::gemini_protocol::parser::Parser;
-use crate::gemini_protocol::response::Response;
+use crate::gemini_protocol::response::{OkResponse, Response};
 
 pub mod response;
+pub mod request;
 pub mod parser;
+pub mod charset;
+#[cfg(feature = "client")]
+pub mod client;
+
+/// The port of a `gemini://` URL that doesn't name one.
+pub const DEFAULT_PORT: u16 = 1965;
 
 pub fn parse_response(url: &Url, response: &str) -> Result<Response, ParserError> {
     let mut r = Parser::new(url, response);
 
     r.reply()
 }
+
+/// Like [parse_response], for a response as it was received, which may have a binary body.
+pub fn parse_response_bytes(url: &Url, response: &[u8]) -> Result<Response, ParserError> {
+    let end = response.iter().position(|&b| b == b'\n').map_or(response.len(), |i| i + 1);
+    let (header, body) = response.split_at(end);
+    let header = String::from_utf8_lossy(header);
+
+    match Parser::new(url, &header).reply()? {
+        Response::Success(ok) => Ok(Response::Success(OkResponse::new(url, ok.mime, body)?)),
+        response => Ok(response),
+    }
+}
@@ -1,9 +1,9 @@
 use std::collections::HashMap;
+use futures::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
 use url::Url;
 use crate::error::{ErrorKind, ParserError};
 use crate::gemtext::gemtext_body::{MimeType};
 use crate::gemini_protocol::response::{OkResponse, Response};
-use crate::gemtext::parse_gemtext;
 
 pub(super) struct Parser<'a> {
     url_path: &'a Url,
@@ -21,6 +21,12 @@ impl<'a> Parser<'a> {
     }
 
     pub(super) fn reply(&mut self) -> Result<Response, ParserError> {
+        // `<STATUS><SP>` comes before the meta.
+        let header = self.iter.as_str().split('\n').next().unwrap_or_default();
+        if header.trim_end_matches('\r').len().saturating_sub(3) > MAX_META_LENGTH {
+            return Err(self.make_err(ErrorKind::HeaderTooLong));
+        }
+
         let c = self.eat_char()?;
         match c {
             '1' => self.input(),
@@ -61,10 +67,7 @@ impl<'a> Parser<'a> {
 
         let body = self.eat_until(|_| false);
 
-        Ok(Response::Success(OkResponse {
-            mime: mimetype,
-            body: parse_gemtext(&self.url_path, body)?,
-        }))
+        Ok(Response::Success(OkResponse::from_text(self.url_path, mimetype, body)?))
      }
 
     fn redirect(&mut self) -> Result<Response, ParserError> {
@@ -143,10 +146,8 @@ impl<'a> Parser<'a> {
                 self.line += 1;
             }
 
-            if c == '\r' {
-                if let Some('\n') = self.iter.next() {
-                    break;
-                }
+            if c == '\r' && let Some('\n') = self.iter.next() {
+                break;
             }
             s.push(c);
         }
@@ -158,7 +159,7 @@ impl<'a> Parser<'a> {
         F: FnMut(char) -> bool,
     {
         let mut s = String::new();
-        while let Some(c) = self.iter.next() {
+        for c in self.iter.by_ref() {
             if c == '\n' {
                 self.line += 1;
             }
@@ -200,7 +201,7 @@ impl<'a> Parser<'a> {
 
         // Simply check for a singular semicolon to determine if there are parameters.
         let params_idx = s.find(';');
-        if let None = params_idx {
+        if params_idx.is_none() {
             return Ok(MimeType {
                 typ: t,
                 sub: s,
@@ -247,11 +248,205 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Longest `<META>` the spec allows, in bytes.
+pub const MAX_META_LENGTH: usize = 1024;
+
+/// Longest header line, `<STATUS><SP>` followed by up to 1024 bytes of meta and `\r\n`.
+pub const MAX_HEADER_LENGTH: usize = header_length(MAX_META_LENGTH);
+
+const fn header_length(max_meta: usize) -> usize {
+    3 + max_meta + 2
+}
+
+/// The header line of a response, read on its own so the body can be streamed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Header {
+    pub status: u8,
+    pub meta: String,
+}
+
+impl Header {
+    /// Reads the header and leaves `reader` at the first byte of the body.
+    pub async fn read<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Self, ParserError> {
+        Self::read_limited(reader, MAX_META_LENGTH).await
+    }
+
+    /// Like [Header::read], with a meta of at most `max_meta` bytes. No more than the
+    /// longest header is read, so a server can't make the client buffer an endless line.
+    pub async fn read_limited<R: AsyncBufRead + Unpin>(reader: &mut R, max_meta: usize) -> Result<Self, ParserError> {
+        let err = |kind| ParserError { line: 1, kind };
+        let max_length = header_length(max_meta);
+
+        let mut line = vec![];
+        reader
+            .take(max_length as u64)
+            .read_until(b'\n', &mut line)
+            .await
+            .map_err(|e| err(ErrorKind::Io(e.kind())))?;
+
+        if line.last() != Some(&b'\n') {
+            return Err(err(match line.len() {
+                len if len == max_length => ErrorKind::HeaderTooLong,
+                _ => ErrorKind::SyntaxMissingNewline,
+            }));
+        }
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        let line = String::from_utf8(line).map_err(|_| err(ErrorKind::SyntaxExpectedData))?;
+        let (status, meta) = line.split_once(' ').unwrap_or((&line, ""));
+        // Without the `\r` the line has room for one more byte.
+        if meta.len() > max_meta {
+            return Err(err(ErrorKind::HeaderTooLong));
+        }
+
+        let status = match status.as_bytes() {
+            [] => return Err(err(ErrorKind::MissingStatus)),
+            [a @ b'1'..=b'6', b @ b'0'..=b'9'] => (a - b'0') * 10 + (b - b'0'),
+            [b'0'..=b'9', b'0'..=b'9'] => return Err(err(ErrorKind::InvalidStatus(status.parse().unwrap_or(0)))),
+            _ => return Err(err(ErrorKind::InvalidDigit)),
+        };
+
+        Ok(Header { status, meta: meta.to_string() })
+    }
+
+    pub fn is_success(&self) -> bool {
+        (20..=29).contains(&self.status)
+    }
+
+    /// The whole response, once the body has been read.
+    pub fn response(&self, url: &Url, body: &[u8]) -> Result<Response, ParserError> {
+        let header = format!("{} {}\r\n", self.status, self.meta);
+
+        match Parser::new(url, &header).reply()? {
+            Response::Success(ok) => Ok(Response::Success(OkResponse::new(url, ok.mime, body)?)),
+            response => Ok(response),
+        }
+    }
+}
+
+/// A response whose header has been read, with the rest of the connection as its body.
+///
+/// Lets a client look at the status before reading, or skipping, a large or binary body.
+#[derive(Debug)]
+pub struct ResponseStream<R> {
+    pub header: Header,
+    pub body: R,
+}
+
+impl<R: AsyncBufRead + Unpin> ResponseStream<R> {
+    pub async fn new(reader: R) -> Result<Self, ParserError> {
+        Self::with_meta_limit(reader, MAX_META_LENGTH).await
+    }
+
+    /// See [Header::read_limited].
+    pub async fn with_meta_limit(mut reader: R, max_meta: usize) -> Result<Self, ParserError> {
+        let header = Header::read_limited(&mut reader, max_meta).await?;
+
+        Ok(ResponseStream { header, body: reader })
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::gemini_protocol::parse_response;
+    use crate::gemini_protocol::{parse_response, parse_response_bytes};
+    use crate::gemini_protocol::response::Body;
+    use crate::gemtext::parse_gemtext;
     use super::*;
 
+    #[test]
+    fn test_read_header() {
+        use futures::io::Cursor;
+        use futures::AsyncReadExt;
+
+        let read = |input: &[u8]| {
+            futures::executor::block_on(async {
+                let mut reader = Cursor::new(input.to_vec());
+                let header = Header::read(&mut reader).await?;
+
+                let mut body = vec![];
+                reader.read_to_end(&mut body).await.unwrap();
+                Ok((header, body))
+            })
+        };
+        let header = |status, meta: &str| Header { status, meta: meta.to_string() };
+
+        assert_eq!(read(b"20 text/gemini\r\n# Hi\n\x00\xff"), Ok((header(20, "text/gemini"), b"# Hi\n\x00\xff".to_vec())));
+        assert_eq!(read(b"51 Not found\r\n"), Ok((header(51, "Not found"), vec![])));
+        assert_eq!(read(b"52\r\n"), Ok((header(52, ""), vec![])));
+
+        let kind = |input: &[u8]| read(input).map_err(|e: ParserError| e.kind);
+        assert_eq!(kind(b""), Err(ErrorKind::SyntaxMissingNewline));
+        assert_eq!(kind(b"\r\n"), Err(ErrorKind::MissingStatus));
+        assert_eq!(kind(b"70 Nope\r\n"), Err(ErrorKind::InvalidStatus(70)));
+        assert_eq!(kind(b"2x text/gemini\r\n"), Err(ErrorKind::InvalidDigit));
+        assert_eq!(kind(b"20 text/gemini"), Err(ErrorKind::SyntaxMissingNewline));
+        assert_eq!(kind(&[b'a'; MAX_HEADER_LENGTH + 1]), Err(ErrorKind::HeaderTooLong));
+
+        let meta = "a".repeat(MAX_META_LENGTH);
+        assert!(read(format!("51 {}\r\n", meta).as_bytes()).is_ok());
+        assert_eq!(kind(format!("51 {}a\r\n", meta).as_bytes()), Err(ErrorKind::HeaderTooLong));
+        assert_eq!(kind(format!("51 {}a\n", meta).as_bytes()), Err(ErrorKind::HeaderTooLong));
+
+        let limited = |input: &[u8], max_meta| {
+            futures::executor::block_on(ResponseStream::with_meta_limit(Cursor::new(input.to_vec()), max_meta))
+                .map(|stream| stream.header)
+                .map_err(|e| e.kind)
+        };
+        assert_eq!(limited(b"20 text/gemini\r\n", 11), Ok(header(20, "text/gemini")));
+        assert_eq!(limited(b"20 text/gemini\r\n", 10), Err(ErrorKind::HeaderTooLong));
+        assert_eq!(limited(b"20 text/gemini; charset=utf-8 and more and more", 10), Err(ErrorKind::HeaderTooLong));
+
+        let stream = futures::executor::block_on(ResponseStream::new(Cursor::new(b"20 text/plain\r\nbody".to_vec()))).unwrap();
+        assert_eq!(stream.header, header(20, "text/plain"));
+        assert_eq!(stream.body.position(), 15);
+
+        let url = Url::parse("gemini://localhost/").unwrap();
+        let response = header(20, "text/gemini").response(&url, b"# Hi\n").unwrap();
+        assert_eq!(response.status(), 20);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let url: Url = Url::parse("gemini://localhost/").unwrap();
+        let body = "# Title\n\nSome text\n=> gemini://localhost/a A link\n```\n  raw\n```\n* item\n";
+
+        let responses = vec![
+            parse_response(&url, &format!("20 text/gemini; lang=en\r\n{}", body)).unwrap(),
+            Response::MustPromptForInput("Name?".to_string()),
+            Response::TemporaryRedirect("/elsewhere".to_string()),
+            Response::ResourceNotFound(Some("Not here".to_string())),
+            Response::CertificateRequired(Some("Identify yourself".to_string())),
+        ];
+
+        for response in responses {
+            let serialized = String::from_utf8(response.serialize()).unwrap();
+            assert_eq!(parse_response(&url, &serialized), Ok(response.clone()), "{serialized:?}");
+        }
+
+        let ok = parse_response(&url, &format!("20 text/gemini; lang=en\r\n{}", body)).unwrap();
+        assert_eq!(String::from_utf8(ok.serialize()).unwrap(), format!("20 text/gemini; lang=en\r\n{}", body));
+        assert_eq!(Response::ResourceGone(None).serialize(), b"52 \r\n");
+    }
+
+    #[test]
+    fn test_charset() {
+        let url = Url::parse("gemini://example.org/").unwrap();
+        let latin1 = b"20 text/gemini; charset=iso-8859-1\r\n# Gr\xfc\xdfe\n";
+
+        let response = parse_response_bytes(&url, latin1).unwrap();
+        let Response::Success(ok) = &response else { panic!("{response:?}") };
+        assert_eq!(ok.gemtext().unwrap().to_string(), "# Grüße\n");
+        assert_eq!(response.serialize(), latin1);
+
+        // A string was decoded already, the charset doesn't apply to it again.
+        let response = parse_response(&url, "20 text/gemini; charset=iso-8859-1\r\n# Grüße\n").unwrap();
+        let Response::Success(ok) = &response else { panic!("{response:?}") };
+        assert_eq!(ok.gemtext().unwrap().to_string(), "# Grüße\n");
+    }
+
     #[test]
     fn test_ten() -> Result<(), ParserError> {
         let url: Url = Url::parse("gemini://localhost/").unwrap();
@@ -274,8 +469,8 @@ mod tests {
         if let Response::Success(OkResponse { mime, body }) = r {
             assert_eq!(mime.typ, "text");
             assert_eq!(mime.sub, "gemini");
-            assert_eq!(mime.parameters.is_none(), true);
-            assert_eq!(body.0.len(), 2);
+            assert!(mime.parameters.is_none());
+            assert_eq!(body, Body::GemText(parse_gemtext(&url, "Hello, World!\nSomeData\n".to_string())?));
         } else {
             panic!("expected success response");
         }
@@ -283,6 +478,31 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_binary_body() -> Result<(), ParserError> {
+        let url: Url = Url::parse("gemini://localhost/image.png").unwrap();
+        let resp = b"20 image/png\r\n\x89PNG\r\n\x1a\n\x00\xff";
+
+        let r = parse_response_bytes(&url, resp)?;
+
+        let Response::Success(ok) = &r else {
+            panic!("expected success response");
+        };
+        assert_eq!(ok.mime.to_string(), "image/png");
+        assert_eq!(ok.body_bytes(), Some(&b"\x89PNG\r\n\x1a\n\x00\xff"[..]));
+        assert!(ok.gemtext().is_none());
+        assert_eq!(r.serialize(), resp);
+
+        let r = parse_response_bytes(&url, b"20 text/gemini\r\n# Hi\n")?;
+        let Response::Success(ok) = &r else {
+            panic!("expected success response");
+        };
+        assert_eq!(ok.gemtext().map(|body| body.len()), Some(1));
+        assert!(ok.body_bytes().is_none());
+
+        Ok(())
+    }
+
     #[test]
     fn test_mimetype() -> Result<(), ParserError> {
         let url: Url = Url::parse("gemini://localhost/").unwrap();
@@ -293,7 +513,7 @@ mod tests {
         if let Response::Success(OkResponse { mime, .. }) = r {
             assert_eq!(mime.typ, "text");
             assert_eq!(mime.sub, "gemini");
-            assert_eq!(mime.parameters.is_some(), true);
+            assert!(mime.parameters.is_some());
 
             let params = mime.parameters.unwrap();
             assert_eq!(params.get("lang").unwrap(), "zh-CN");
@@ -312,11 +532,11 @@ mod tests {
 
         for case in cases {
             let r = parse_response(&url, case);
-            assert_eq!(r.is_err(), true);
-            assert_eq!(r.err() == Some(ParserError {
+            assert!(r.is_err());
+            assert!(r.err() == Some(ParserError {
                 line: 1,
                 kind: ErrorKind::SyntaxExpectedData,
-            }), true);
+            }));
         }
 
         Ok(())
@@ -329,11 +549,11 @@ mod tests {
 
         for case in cases {
             let r = parse_response(&url, case);
-            assert_eq!(r.is_err(), true);
-            assert_eq!(r.err() == Some(ParserError {
+            assert!(r.is_err());
+            assert!(r.err() == Some(ParserError {
                 line: 1,
                 kind: ErrorKind::SyntaxMissingNewline,
-            }), true);
+            }));
         }
 
         Ok(())
@@ -346,16 +566,32 @@ mod tests {
 
         for case in cases {
             let r = parse_response(&url, case);
-            assert_eq!(r.is_err(), true);
-            assert_eq!(r.err() == Some(ParserError {
+            assert!(r.is_err());
+            assert!(r.err() == Some(ParserError {
                 line: 1,
                 kind: ErrorKind::SyntaxMissingSpace,
-            }), true);
+            }));
         }
 
         Ok(())
     }
 
+    #[test]
+    fn test_header_too_long() {
+        let url: Url = Url::parse("gemini://localhost/").unwrap();
+        let meta = "a".repeat(MAX_META_LENGTH);
+
+        assert!(parse_response(&url, &format!("51 {}\r\n", meta)).is_ok());
+        assert_eq!(parse_response(&url, &format!("51 {}a\r\n", meta)), Err(ParserError {
+            line: 1,
+            kind: ErrorKind::HeaderTooLong,
+        }));
+        assert_eq!(
+            parse_response_bytes(&url, format!("20 text/{}\r\nbody", meta).as_bytes()).map_err(|e| e.kind),
+            Err(ErrorKind::HeaderTooLong)
+        );
+    }
+
     #[test]
     fn test_invalid_digit() -> Result<(), ParserError> {
         let url: Url = Url::parse("gemini://localhost/").unwrap();
@@ -363,13 +599,31 @@ mod tests {
 
         for case in cases {
             let r = parse_response(&url, case);
-            assert_eq!(r.is_err(), true);
-            assert_eq!(r.err() == Some(ParserError {
+            assert!(r.is_err());
+            assert!(r.err() == Some(ParserError {
                 line: 1,
                 kind: ErrorKind::InvalidDigit,
-            }), true);
+            }));
         }
 
         Ok(())
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let url = Url::parse("gemini://localhost/").unwrap();
+        let cases = [
+            "20 text/gemini; lang=en\r\n# Title\n=> /about About\n```alt\npre\n```\n",
+            "20 image/png\r\n\x00PNG",
+            "51 Not found\r\n",
+        ];
+
+        for case in cases {
+            let response = parse_response_bytes(&url, case.as_bytes()).unwrap();
+            let json = serde_json::to_string(&response).unwrap();
+
+            assert_eq!(serde_json::from_str::<Response>(&json).unwrap(), response, "{}", json);
+        }
+    }
 }
@@ -1,15 +1,87 @@
+use crate::error::ParserError;
+use crate::gemini_protocol::charset;
 use crate::gemtext::gemtext_body::{GemTextBody, MimeType};
+use crate::gemtext::parse_gemtext;
 use std::fmt::{Debug, Display, Formatter};
+use url::Url;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OkResponse {
     pub mime: MimeType,
-    pub body: GemTextBody,
+    pub body: Body,
+}
+
+/// `text/*` bodies are parsed as gemtext, anything else is kept as it was received.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Body {
+    GemText(GemTextBody),
+    Bytes(Vec<u8>),
+}
+
+impl Body {
+    /// The body as it is sent, gemtext is serialized again as UTF-8. See
+    /// [OkResponse::to_bytes] for the charset of the response.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Body::GemText(body) => body.to_string().into_bytes(),
+            Body::Bytes(bytes) => bytes.clone(),
+        }
+    }
+}
+
+impl OkResponse {
+    /// A `text/*` body is decoded with the charset of `mime`, see [charset::decode].
+    pub fn new(url: &Url, mime: MimeType, body: &[u8]) -> Result<Self, ParserError> {
+        if mime.typ != "text" {
+            return Ok(OkResponse { mime, body: Body::Bytes(body.to_vec()) });
+        }
+
+        let text = charset::decode(&mime, body);
+        Self::from_text(url, mime, text)
+    }
+
+    /// For a body that is a string already, its charset isn't applied again.
+    pub fn from_text(url: &Url, mime: MimeType, text: String) -> Result<Self, ParserError> {
+        let body = if mime.typ == "text" {
+            Body::GemText(parse_gemtext(url, text)?)
+        } else {
+            Body::Bytes(text.into_bytes())
+        };
+
+        Ok(OkResponse { mime, body })
+    }
+
+    /// The body as it is sent, gemtext is encoded in the charset of the response.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match &self.body {
+            Body::GemText(body) => charset::encode(&self.mime, &body.to_string()),
+            Body::Bytes(bytes) => bytes.clone(),
+        }
+    }
+
+    /// The parsed body of a `text/*` response.
+    pub fn gemtext(&self) -> Option<&GemTextBody> {
+        match &self.body {
+            Body::GemText(body) => Some(body),
+            Body::Bytes(_) => None,
+        }
+    }
+
+    /// The body of a response that isn't text, e.g. `image/png`.
+    pub fn body_bytes(&self) -> Option<&[u8]> {
+        match &self.body {
+            Body::GemText(_) => None,
+            Body::Bytes(bytes) => Some(bytes),
+        }
+    }
 }
 
 // FIXME: Cow
 
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Response {
     /// Input Expected
     /// 10
@@ -60,6 +132,106 @@ pub enum Response {
     CertificateNotValid(Option<String>),
 }
 
+impl Response {
+    /// Where a `30` or `31` redirects to, as it was sent, `None` for other responses.
+    pub fn redirect_target(&self) -> Option<&str> {
+        match self {
+            Response::TemporaryRedirect(target) | Response::PermanentRedirect(target) => Some(target),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> u8 {
+        use Response::*;
+
+        match self {
+            MustPromptForInput(_) => 10,
+            MustPromptSensitiveInput(_) => 11,
+            Success(_) => 20,
+            TemporaryRedirect(_) => 30,
+            PermanentRedirect(_) => 31,
+            UnexpectedErrorTryAgain(_) => 40,
+            ServerUnavailable(_) => 41,
+            CGIError(_) => 42,
+            ProxyError(_) => 43,
+            SlowDown(_) => 44,
+            PermanentFailure(_) => 50,
+            ResourceNotFound(_) => 51,
+            ResourceGone(_) => 52,
+            ProxyRequestRefused(_) => 53,
+            BadRequest(_) => 59,
+            CertificateRequired(_) => 60,
+            CertificateNotAuthorized(_) => 61,
+            CertificateNotValid(_) => 62,
+        }
+    }
+
+    /// The status with its name as the spec has it, `(51, "Not Found")`, a label that is the
+    /// same for every response of the status.
+    pub fn short_code_and_title(&self) -> (u8, &'static str) {
+        use Response::*;
+
+        let title = match self {
+            MustPromptForInput(_) => "Input",
+            MustPromptSensitiveInput(_) => "Sensitive Input",
+            Success(_) => "Success",
+            TemporaryRedirect(_) => "Temporary Redirect",
+            PermanentRedirect(_) => "Permanent Redirect",
+            UnexpectedErrorTryAgain(_) => "Temporary Failure",
+            ServerUnavailable(_) => "Server Unavailable",
+            CGIError(_) => "CGI Error",
+            ProxyError(_) => "Proxy Error",
+            SlowDown(_) => "Slow Down",
+            PermanentFailure(_) => "Permanent Failure",
+            ResourceNotFound(_) => "Not Found",
+            ResourceGone(_) => "Gone",
+            ProxyRequestRefused(_) => "Proxy Request Refused",
+            BadRequest(_) => "Bad Request",
+            CertificateRequired(_) => "Client Certificate Required",
+            CertificateNotAuthorized(_) => "Certificate Not Authorized",
+            CertificateNotValid(_) => "Certificate Not Valid",
+        };
+
+        (self.status(), title)
+    }
+
+    /// The prompt, MIME type, redirect target or error message of the header.
+    pub fn meta(&self) -> String {
+        use Response::*;
+
+        match self {
+            MustPromptForInput(meta)
+            | MustPromptSensitiveInput(meta)
+            | TemporaryRedirect(meta)
+            | PermanentRedirect(meta) => meta.clone(),
+            Success(ok) => ok.mime.to_string(),
+            UnexpectedErrorTryAgain(meta)
+            | ServerUnavailable(meta)
+            | CGIError(meta)
+            | ProxyError(meta)
+            | SlowDown(meta)
+            | PermanentFailure(meta)
+            | ResourceNotFound(meta)
+            | ResourceGone(meta)
+            | ProxyRequestRefused(meta)
+            | BadRequest(meta)
+            | CertificateRequired(meta)
+            | CertificateNotAuthorized(meta)
+            | CertificateNotValid(meta) => meta.clone().unwrap_or_default(),
+        }
+    }
+
+    /// `<STATUS><SP><META>\r\n`, followed by the body of a success, as sent by a server.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = format!("{} {}\r\n", self.status(), self.meta()).into_bytes();
+        if let Response::Success(ok) = self {
+            out.extend(ok.to_bytes());
+        }
+
+        out
+    }
+}
+
 impl Display for Response {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         use Response::*;
@@ -105,3 +277,16 @@ impl Display for Response {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_code_and_title() {
+        assert_eq!(Response::ResourceNotFound(Some("No such page".to_string())).short_code_and_title(), (51, "Not Found"));
+        assert_eq!(Response::ResourceNotFound(None).short_code_and_title(), (51, "Not Found"));
+        assert_eq!(Response::MustPromptSensitiveInput("Password".to_string()).short_code_and_title(), (11, "Sensitive Input"));
+        assert_eq!(Response::SlowDown(Some("10".to_string())).short_code_and_title(), (44, "Slow Down"));
+    }
+}
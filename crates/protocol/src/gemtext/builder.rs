@@ -0,0 +1,97 @@
+use url::Url;
+use crate::gemtext::gemtext_body::{GemTextBody, Line};
+
+/// Puts a document together line by line, to generate gemtext without writing out its
+/// syntax:
+///
+/// ```
+/// # use protocol::gemtext::builder::GemTextBuilder;
+/// # use url::Url;
+/// let url = Url::parse("gemini://example.org/posts/").unwrap();
+/// let page = GemTextBuilder::new()
+///     .heading(1, "Posts")
+///     .text("")
+///     .link(url.join("first.gmi").unwrap(), Some("First post"))
+///     .build();
+///
+/// assert_eq!(page.to_string(), "# Posts\n\n=> gemini://example.org/posts/first.gmi First post\n");
+/// ```
+///
+/// Text is written as it is given, text that starts like another kind of line, e.g. with a
+/// `#`, reads as that kind when the document is parsed again.
+#[derive(Debug, Clone, Default)]
+pub struct GemTextBuilder {
+    lines: Vec<Line>,
+}
+
+impl GemTextBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A heading of `depth` 1 to 3, deeper ones are written as 3.
+    pub fn heading(self, depth: u8, text: impl Into<String>) -> Self {
+        self.line(Line::Heading { text: text.into(), depth: depth.clamp(1, 3) })
+    }
+
+    /// A line of text, `""` for a blank line.
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.line(Line::Text(text.into()))
+    }
+
+    pub fn link(self, url: Url, description: Option<&str>) -> Self {
+        self.line(Line::Link { url, description: description.map(str::to_string) })
+    }
+
+    pub fn list_item(self, text: impl Into<String>) -> Self {
+        self.line(Line::ListItem(text.into()))
+    }
+
+    pub fn quote(self, text: impl Into<String>) -> Self {
+        self.line(Line::Quote(text.into()))
+    }
+
+    /// A preformatted block of the lines of `text`, with `alt` describing it.
+    pub fn preformatted(self, alt: Option<&str>, text: &str) -> Self {
+        self.line(Line::Preformatted {
+            alt: alt.map(str::to_string),
+            lines: text.lines().map(str::to_string).collect(),
+        })
+    }
+
+    pub fn line(mut self, line: Line) -> Self {
+        self.lines.push(line);
+        self
+    }
+
+    pub fn build(self) -> GemTextBody {
+        GemTextBody(self.lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::parse_gemtext;
+
+    #[test]
+    fn test_builder() {
+        let url = Url::parse("gemini://example.org/").unwrap();
+        let body = GemTextBuilder::new()
+            .heading(1, "Title")
+            .heading(6, "Deep")
+            .text("")
+            .link(url.join("/next").unwrap(), Some("Next"))
+            .link(url.clone(), None)
+            .list_item("item")
+            .quote("quoted")
+            .preformatted(Some("code"), "fn main() {}\n")
+            .build();
+
+        assert_eq!(
+            body.to_string(),
+            "# Title\n### Deep\n\n=> gemini://example.org/next Next\n=> gemini://example.org/\n* item\n> quoted\n```code\nfn main() {}\n```\n"
+        );
+        assert_eq!(parse_gemtext(&url, body.to_string()).unwrap(), body);
+    }
+}
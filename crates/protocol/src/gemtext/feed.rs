@@ -0,0 +1,101 @@
+use crate::gemtext::gemtext_body::{GemTextBody, Line};
+use url::Url;
+
+/// A page subscribed to as a feed, see
+/// gemini://geminiprotocol.net/docs/companion/subscription.gmi
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Feed {
+    /// The first level one heading of the page.
+    pub title: Option<String>,
+    /// A level two heading right after the title.
+    pub subtitle: Option<String>,
+    /// In the order of the page, which is usually newest first.
+    pub entries: Vec<FeedEntry>,
+}
+
+/// A link line whose description starts with a date, `=> post.gmi 2024-05-01 - Title`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FeedEntry {
+    pub url: Url,
+    /// `YYYY-MM-DD`
+    pub date: String,
+    /// The rest of the description, the date when there is nothing else.
+    pub title: String,
+}
+
+/// The feed of a page. Every page is one, a page without dated links has no entries.
+pub fn parse_feed(body: &GemTextBody) -> Feed {
+    let mut title = None;
+    let mut subtitle = None;
+    let mut entries = vec![];
+
+    for (index, line) in body.iter().enumerate() {
+        match line {
+            Line::Heading { text, depth: 1 } if title.is_none() => {
+                title = Some(text.clone());
+
+                let next = body[index + 1..].iter().find(|l| !matches!(l, Line::Text(t) if t.trim().is_empty()));
+                if let Some(Line::Heading { text, depth: 2 }) = next {
+                    subtitle = Some(text.clone());
+                }
+            }
+            Line::Link { url, description: Some(description) } => {
+                entries.extend(entry(url, description));
+            }
+            _ => {}
+        }
+    }
+
+    Feed { title, subtitle, entries }
+}
+
+fn entry(url: &Url, description: &str) -> Option<FeedEntry> {
+    let date = description.get(..10)?;
+    let valid = date.char_indices().all(|(idx, c)| match idx {
+        4 | 7 => c == '-',
+        _ => c.is_ascii_digit(),
+    });
+    if !valid {
+        return None;
+    }
+
+    let title = description[10..].trim_start_matches([' ', '\t', '-', ':']).trim();
+    let title = if title.is_empty() { date } else { title };
+
+    Some(FeedEntry { url: url.clone(), date: date.to_string(), title: title.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::parse_gemtext;
+
+    #[test]
+    fn test_parse_feed() {
+        let url = Url::parse("gemini://example.org/gemlog/").unwrap();
+        let page = "# My gemlog\n\n## Thoughts, mostly\n\n=> / Home\n=> 2024-05-01-fish.gmi 2024-05-01 - Fish & Chips\n=> 2024-04-20.gmi 2024-04-20\n=> gemini://example.net/ 2024-04-01: Elsewhere\n=> 2024-5-1.gmi 2024-5-1 Not a date\n# Another title\n";
+        let feed = parse_feed(&parse_gemtext(&url, page.to_string()).unwrap());
+
+        let entry = |u: &str, date: &str, title: &str| FeedEntry {
+            url: Url::parse(u).unwrap(),
+            date: date.to_string(),
+            title: title.to_string(),
+        };
+        assert_eq!(
+            feed,
+            Feed {
+                title: Some("My gemlog".to_string()),
+                subtitle: Some("Thoughts, mostly".to_string()),
+                entries: vec![
+                    entry("gemini://example.org/gemlog/2024-05-01-fish.gmi", "2024-05-01", "Fish & Chips"),
+                    entry("gemini://example.org/gemlog/2024-04-20.gmi", "2024-04-20", "2024-04-20"),
+                    entry("gemini://example.net/", "2024-04-01", "Elsewhere"),
+                ],
+            }
+        );
+
+        let feed = parse_feed(&parse_gemtext(&url, "# Title\nText\n## Not a subtitle\n".to_string()).unwrap());
+        assert_eq!(feed.subtitle, None);
+        assert!(feed.entries.is_empty());
+    }
+}
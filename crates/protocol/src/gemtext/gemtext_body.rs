@@ -1,8 +1,11 @@
 use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
+use std::ops::{Index, Range};
+use std::slice::SliceIndex;
 use url::Url;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Line {
     Text(String),
     Link {
@@ -15,27 +18,225 @@ pub enum Line {
     },
     ListItem(String),
     Quote(String),
-    Raw(String),
+    /// The lines between two preformat toggles, kept as they are.
+    Preformatted {
+        /// The text after the opening toggle, which describes the block to those who
+        /// can't see it.
+        alt: Option<String>,
+        lines: Vec<String>,
+    },
 }
 
+/// The line as it is written in gemtext, without its line ending. A preformatted block is
+/// written with its toggles, over several lines.
+impl Display for Line {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Line::Text(text) => write!(f, "{}", text),
+            Line::Link { url, description: Some(description) } => write!(f, "=> {} {}", url, description),
+            Line::Link { url, description: None } => write!(f, "=> {}", url),
+            Line::Heading { text, depth } => write!(f, "{} {}", "#".repeat(*depth as usize), text),
+            Line::ListItem(text) => write!(f, "* {}", text),
+            Line::Quote(text) => write!(f, "> {}", text),
+            Line::Preformatted { alt, lines } => {
+                writeln!(f, "```{}", alt.as_deref().unwrap_or_default())?;
+                for line in lines {
+                    writeln!(f, "{}", line)?;
+                }
+                write!(f, "```")
+            }
+        }
+    }
+}
+
+/// A parsed line with where it was in the source, see [crate::gemtext::parse_gemtext_spanned].
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spanned<T> {
+    pub value: T,
+    /// The bytes of the source the line was parsed from, without its line ending. A
+    /// preformatted block spans from its opening toggle to the end of its closing one.
+    pub span: Range<usize>,
+    /// The number of the (first) line, from 1.
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GemTextBody(pub Vec<Line>);
 
+/// The document as gemtext, a line ending after each line.
+impl Display for GemTextBody {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for line in self {
+            writeln!(f, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `body[0]` for the first line, `body[1..]` for the others.
+impl<I: SliceIndex<[Line]>> Index<I> for GemTextBody {
+    type Output = I::Output;
+
+    fn index(&self, index: I) -> &Self::Output {
+        &self.0[index]
+    }
+}
+
+impl IntoIterator for GemTextBody {
+    type Item = Line;
+    type IntoIter = std::vec::IntoIter<Line>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a GemTextBody {
+    type Item = &'a Line;
+    type IntoIter = std::slice::Iter<'a, Line>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// A heading of a document, as listed in its table of contents.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TocEntry {
+    /// The index of the heading in the lines of the document.
+    pub line: usize,
+    pub depth: u8,
+    pub text: String,
+}
+
+impl GemTextBody {
+    /// The document as gemtext that parses back into the same lines, links with absolute
+    /// URLs.
+    pub fn to_gemtext(&self) -> String {
+        self.to_string()
+    }
+
+    /// The number of lines, a preformatted block counting as one.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Line> {
+        self.0.iter()
+    }
+
+    /// The text and depth of every heading, in order.
+    pub fn headings(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.iter().filter_map(|line| match line {
+            Line::Heading { text, depth } => Some((text.as_str(), *depth)),
+            _ => None,
+        })
+    }
+
+    /// The text of the plain text lines, blank ones included.
+    pub fn text_lines(&self) -> impl Iterator<Item = &str> {
+        self.iter().filter_map(|line| match line {
+            Line::Text(text) => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The headings of the document, in order.
+    pub fn table_of_contents(&self) -> Vec<TocEntry> {
+        self.iter()
+            .enumerate()
+            .filter_map(|(line, l)| match l {
+                Line::Heading { text, depth } => Some(TocEntry {
+                    line,
+                    depth: *depth,
+                    text: text.clone(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
 #[derive(Eq, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MimeType {
     pub typ: String,
     pub sub: String,
     pub parameters: Option<HashMap<String, String>>,
 }
 
+/// `text/gemini`, what a response without a MIME type is.
 impl Default for MimeType {
     fn default() -> Self {
+        Self::gemini()
+    }
+}
+
+impl MimeType {
+    /// `text/gemini`.
+    pub fn gemini() -> Self {
+        Self::new("text", "gemini")
+    }
+
+    /// `text/plain`.
+    pub fn plain_text() -> Self {
+        Self::new("text", "plain")
+    }
+
+    fn new(typ: &str, sub: &str) -> Self {
         Self {
-            typ: "text".to_string(),
-            sub: "gemini".to_string(),
+            typ: typ.to_string(),
+            sub: sub.to_string(),
             parameters: None,
         }
     }
+
+    /// Parses `type/subtype; key=value`, the type, subtype and parameter names lowercased.
+    /// `None` without a type or subtype.
+    pub fn parse(mime: &str) -> Option<Self> {
+        let mut parts = mime.split(';');
+        let (typ, sub) = parts.next()?.split_once('/')?;
+        let (typ, sub) = (typ.trim(), sub.trim());
+        if typ.is_empty() || sub.is_empty() {
+            return None;
+        }
+
+        let parameters = parts
+            .filter_map(|parameter| {
+                let (key, value) = parameter.split_once('=')?;
+                Some((key.trim().to_ascii_lowercase(), value.trim().to_string()))
+            })
+            .collect::<HashMap<_, _>>();
+
+        Some(Self {
+            parameters: (!parameters.is_empty()).then_some(parameters),
+            ..Self::new(&typ.to_ascii_lowercase(), &sub.to_ascii_lowercase())
+        })
+    }
+
+    /// Whether the type is `pattern`, `text/gemini`, `text/*` or `*/*`, ignoring case and
+    /// parameters.
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = pattern.split(';').next().unwrap_or_default().trim();
+        let Some((typ, sub)) = pattern.split_once('/') else {
+            return false;
+        };
+
+        (typ == "*" || typ.eq_ignore_ascii_case(&self.typ)) && (sub == "*" || sub.eq_ignore_ascii_case(&self.sub))
+    }
+
+    /// The charset parameter, e.g. `iso-8859-1` for `text/gemini; charset=iso-8859-1`.
+    pub fn charset(&self) -> Option<&str> {
+        self.parameters.as_ref()?.get("charset").map(String::as_str)
+    }
 }
 
 impl Debug for MimeType {
@@ -51,8 +252,67 @@ impl Debug for MimeType {
     }
 }
 
+/// As it appears in the meta of a response, `text/gemini; lang=en`.
 impl Display for MimeType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        Debug::fmt(self, f)
+        write!(f, "{}/{}", self.typ, self.sub)?;
+        if let Some(parameters) = &self.parameters {
+            let mut parameters = parameters.iter().collect::<Vec<_>>();
+            parameters.sort();
+
+            for (key, value) in parameters {
+                write!(f, "; {}={}", key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::parse_gemtext;
+
+    #[test]
+    fn test_lines() {
+        let url = Url::parse("gemini://example.org/").unwrap();
+        let body = parse_gemtext(&url, "# Title\nFirst\n\n## Section\n=> /next Next\nLast\n".to_string()).unwrap();
+
+        assert_eq!(body.len(), 6);
+        assert!(!body.is_empty());
+        assert!(GemTextBody(vec![]).is_empty());
+
+        assert_eq!(body[1], Line::Text("First".to_string()));
+        assert_eq!(body[4..].len(), 2);
+        assert_eq!(body.headings().collect::<Vec<_>>(), vec![("Title", 1), ("Section", 2)]);
+        assert_eq!(body.text_lines().collect::<Vec<_>>(), vec!["First", "", "Last"]);
+
+        let links = (&body).into_iter().filter(|line| matches!(line, Line::Link { .. })).count();
+        assert_eq!(links, 1);
+        assert_eq!(body.clone().into_iter().last(), Some(Line::Text("Last".to_string())));
+    }
+
+    #[test]
+    fn test_mime_type() {
+        assert_eq!(MimeType::parse("text/gemini"), Some(MimeType::gemini()));
+        assert_eq!(MimeType::parse(" Text/Plain "), Some(MimeType::plain_text()));
+        assert_eq!(MimeType::default(), MimeType::gemini());
+
+        let mime = MimeType::parse("text/gemini; Charset=utf-8;lang=en").unwrap();
+        assert_eq!(mime.charset(), Some("utf-8"));
+        assert_eq!(mime.to_string(), "text/gemini; charset=utf-8; lang=en");
+
+        for invalid in ["", "text", "text/", "/gemini", "; charset=utf-8"] {
+            assert_eq!(MimeType::parse(invalid), None, "{}", invalid);
+        }
+
+        let image = MimeType::parse("image/png").unwrap();
+        assert!(image.matches("image/png"));
+        assert!(image.matches("IMAGE/*"));
+        assert!(image.matches("*/*"));
+        assert!(!image.matches("text/*"));
+        assert!(!image.matches("image/jpeg"));
+        assert!(!image.matches("image"));
+        assert!(mime.matches("text/gemini; lang=de"));
     }
 }
@@ -1,23 +1,32 @@
-use crate::gemtext::gemtext_body::{GemTextBody, Line};
+use std::ops::Range;
+use crate::gemtext::gemtext_body::{GemTextBody, Line, Spanned};
 use crate::gemtext::{GemTextError, GemTextErrorKind};
 use url::Url;
 
-const LINK_START: &'static str = "=>";
-const PREFORMAT_TOGGLE: &'static str = "```";
-const HEADING_START: &'static str = "#";
-const LIST_ITEM: &'static str = "*";
-const QUOTE_START: &'static str = ">";
+const LINK_START: &str = "=>";
+const PREFORMAT_TOGGLE: &str = "```";
+const HEADING_START: &str = "#";
+const LIST_ITEM: &str = "*";
+const QUOTE_START: &str = ">";
 
 const WSP: &[char; 2] = &[' ', '\t'];
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum ParserMode {
     Normal,
-    Preformat,
+    /// The block being read, emitted as one line at the closing toggle.
+    Preformat {
+        alt: Option<String>,
+        lines: Vec<String>,
+        /// Where the opening toggle starts, and its line number.
+        start: usize,
+        line: usize,
+    },
 }
 
 #[derive(Debug)]
 pub struct GemTextParser<'a> {
+    source: &'a str,
     line_iter: std::str::Lines<'a>,
     url_path: &'a Url,
     pub body: Vec<Line>,
@@ -29,9 +38,10 @@ pub struct GemTextParser<'a> {
 impl<'a> GemTextParser<'a> {
     pub(super) fn new(url_path: &'a Url, str: &'a str) -> GemTextParser<'a> {
         GemTextParser {
+            source: str,
             line_iter: str.lines(),
             body: Vec::new(),
-            url_path: url_path,
+            url_path,
             cursor: "",
             line_num: 0,
             mode: ParserMode::Normal,
@@ -39,31 +49,47 @@ impl<'a> GemTextParser<'a> {
     }
 
     pub(super) fn gemtext_document(&mut self) -> Result<GemTextBody, GemTextError> {
-        let mut b = GemTextBody(vec![]);
+        let lines = self.spanned_lines()?;
+
+        Ok(GemTextBody(lines.into_iter().map(|line| line.value).collect()))
+    }
+
+    pub(super) fn spanned_lines(&mut self) -> Result<Vec<Spanned<Line>>, GemTextError> {
+        let mut b = vec![];
+        let mut end = 0;
 
         // FIXME: Remove clone
         for line in self.line_iter.clone() {
             self.line_num += 1;
             self.cursor = line;
 
-            b.0.push(match self.gemtext_line(line) {
+            // The lines are slices of the source, without their line endings.
+            let start = line.as_ptr() as usize - self.source.as_ptr() as usize;
+            end = start + line.len();
+
+            b.push(match self.gemtext_line(line, start..end) {
                 Some(line) => line?,
                 None => continue,
             });
         }
 
+        // A block that is never closed runs to the end of the document.
+        if let ParserMode::Preformat { .. } = self.mode {
+            b.extend(self.preformat_toggle("", end..end));
+        }
+
         Ok(b)
     }
 
-    fn gemtext_line(&mut self, line: &'a str) -> Option<Result<Line, GemTextError>> {
+    fn gemtext_line(&mut self, line: &'a str, span: Range<usize>) -> Option<Result<Spanned<Line>, GemTextError>> {
         if line.starts_with(PREFORMAT_TOGGLE) {
-            self.preformat_toggle();
-
-            return None;
+            return self.preformat_toggle(line, span).map(Ok);
         }
 
-        if self.mode == ParserMode::Preformat {
-            return Some(Ok(Line::Raw(line.to_string())));
+        if let ParserMode::Preformat { lines, .. } = &mut self.mode {
+            lines.push(line.to_string());
+
+            return None;
         }
 
         let line = {
@@ -80,7 +106,7 @@ impl<'a> GemTextParser<'a> {
             }
         };
 
-        Some(line)
+        Some(line.map(|value| Spanned { value, span, line: self.line_num }))
     }
 
     fn text_line(&self, line: &'a str) -> Result<Line, GemTextError> {
@@ -102,7 +128,7 @@ impl<'a> GemTextParser<'a> {
             .filter(|s| !s.is_empty())
             .collect::<Vec<&str>>();
 
-        if split.len() == 0 {
+        if split.is_empty() {
             return Err(self.make_err(GemTextErrorKind::LinkLineMissingUrl));
         }
 
@@ -122,14 +148,26 @@ impl<'a> GemTextParser<'a> {
         })
     }
 
-    fn preformat_toggle(&mut self) {
-        match self.mode {
+    /// Opens a block with the alt text of the toggle, or closes the open one and returns
+    /// it, spanning both toggles. The text after a closing toggle is ignored.
+    fn preformat_toggle(&mut self, line: &str, span: Range<usize>) -> Option<Spanned<Line>> {
+        match std::mem::replace(&mut self.mode, ParserMode::Normal) {
             ParserMode::Normal => {
-                self.mode = ParserMode::Preformat;
-            }
-            ParserMode::Preformat => {
-                self.mode = ParserMode::Normal;
+                let alt = line[PREFORMAT_TOGGLE.len()..].trim();
+                self.mode = ParserMode::Preformat {
+                    alt: (!alt.is_empty()).then(|| alt.to_string()),
+                    lines: vec![],
+                    start: span.start,
+                    line: self.line_num,
+                };
+
+                None
             }
+            ParserMode::Preformat { alt, lines, start, line } => Some(Spanned {
+                value: Line::Preformatted { alt, lines },
+                span: start..span.end,
+                line,
+            }),
         }
     }
 
@@ -153,16 +191,16 @@ impl<'a> GemTextParser<'a> {
     fn quote_line(&mut self) -> Result<Line, GemTextError> {
         const START: usize = ">".len();
 
-        let line = self.take_cursor_whitespace(START);
+        let line = self.skip_cursor_whitespace(START);
 
         Ok(Line::Quote(line))
     }
 
-    fn take_cursor_whitespace(&mut self, start: usize) -> String {
+    fn skip_cursor_whitespace(&mut self, start: usize) -> String {
         self.cursor
             .chars()
             .skip(start)
-            .take_while(|c| c.is_whitespace())
+            .skip_while(|c| c.is_whitespace())
             .collect::<String>()
     }
 
@@ -191,7 +229,7 @@ impl<'a> GemTextParser<'a> {
 #[cfg(test)]
 mod test {
     use crate::gemtext::gemtext_body::Line::{Heading, Link, Text};
-    use crate::gemtext::{gemtext_body::Line, parse_gemtext, GemTextErrorKind};
+    use crate::gemtext::{gemtext_body::Line, parse_gemtext, parse_gemtext_spanned, GemTextErrorKind};
     use url::Url;
 
     #[test]
@@ -203,7 +241,7 @@ mod test {
         let parsed = parsed.unwrap();
         assert_eq!(parsed.0.len(), 1);
         assert_eq!(
-            parsed.0.get(0).unwrap(),
+            parsed.0.first().unwrap(),
             &Line::Link {
                 url: Url::parse("gemini://gemini.circumlunar.space/docs/faq.gmi").unwrap(),
                 description: Some("The Gemini FAQ".to_string())
@@ -211,6 +249,27 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_spans() {
+        let url = Url::parse("gemini://example.org/").unwrap();
+        let input = "# Title\r\n=> /next Next\n\n```alt\ncode\n```\n* item\n```\nopen";
+        let lines = parse_gemtext_spanned(&url, input).unwrap();
+
+        let spans = lines.iter().map(|line| (&input[line.span.clone()], line.line)).collect::<Vec<_>>();
+        assert_eq!(spans, vec![
+            ("# Title", 1),
+            ("=> /next Next", 2),
+            ("", 3),
+            ("```alt\ncode\n```", 4),
+            ("* item", 7),
+            ("```\nopen", 8),
+        ]);
+        assert_eq!(lines[1].value, Link { url: Url::parse("gemini://example.org/next").unwrap(), description: Some("Next".to_string()) });
+
+        let body = parse_gemtext(&url, input.to_string()).unwrap();
+        assert_eq!(body.0, lines.into_iter().map(|line| line.value).collect::<Vec<_>>());
+    }
+
     #[test]
     fn test_link_line() {
         let url = Url::parse("gemini://gemini.circumlunar.space/docs/faq.gmi").unwrap();
@@ -220,7 +279,7 @@ mod test {
         let parsed = parsed.unwrap();
         assert_eq!(parsed.0.len(), 1);
         assert_eq!(
-            parsed.0.get(0).unwrap(),
+            parsed.0.first().unwrap(),
             &Line::Link {
                 url: Url::parse("gemini://gemini.circumlunar.space/docs/faq.gmi").unwrap(),
                 description: None
@@ -228,6 +287,55 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_table_of_contents() {
+        let url = Url::parse("gemini://geminiprotocol.net/").unwrap();
+        let input = "# Gemini\nIntro\n## Clients\n=> /clients Clients\n### Mobile\n## Servers".to_string();
+        let toc = parse_gemtext(&url, input).unwrap().table_of_contents();
+
+        assert_eq!(
+            toc.iter().map(|e| (e.line, e.depth, e.text.as_str())).collect::<Vec<_>>(),
+            vec![(0, 1, "Gemini"), (2, 2, "Clients"), (4, 3, "Mobile"), (5, 2, "Servers")]
+        );
+    }
+
+    #[test]
+    fn test_to_gemtext() {
+        let url = Url::parse("gemini://geminiprotocol.net/docs/").unwrap();
+        let input = "# Docs\n\n=> faq.gmi  The FAQ\n=> gemini://example.org/\n### Small\n* item\n>quoted\n```\n  => not a link\n# nor a heading\n```\nafter".to_string();
+
+        let parsed = parse_gemtext(&url, input).unwrap();
+        assert_eq!(parsed[6], Line::Quote("quoted".to_string()));
+
+        let gemtext = parsed.to_gemtext();
+        assert_eq!(
+            gemtext,
+            "# Docs\n\n=> gemini://geminiprotocol.net/docs/faq.gmi  The FAQ\n=> gemini://example.org/\n### Small\n* item\n> quoted\n```\n  => not a link\n# nor a heading\n```\nafter\n"
+        );
+        assert_eq!(parse_gemtext(&url, gemtext).unwrap(), parsed);
+    }
+
+    #[test]
+    fn test_preformatted() {
+        let url = Url::parse("gemini://geminiprotocol.net/").unwrap();
+        let input = "```  A cat, sitting \n /\\_/\\\n( o.o )\n``` ignored\n```\n```\n=> /next Next\n```\n# unclosed".to_string();
+
+        let parsed = parse_gemtext(&url, input).unwrap();
+        assert_eq!(parsed.0, vec![
+            Line::Preformatted {
+                alt: Some("A cat, sitting".to_string()),
+                lines: vec![" /\\_/\\".to_string(), "( o.o )".to_string()],
+            },
+            Line::Preformatted { alt: None, lines: vec![] },
+            Link {
+                url: Url::parse("gemini://geminiprotocol.net/next").unwrap(),
+                description: Some("Next".to_string()),
+            },
+            Line::Preformatted { alt: None, lines: vec!["# unclosed".to_string()] },
+        ]);
+        assert_eq!(parse_gemtext(&url, parsed.to_gemtext()).unwrap(), parsed);
+    }
+
     #[test]
     fn test_link_line_missing_url() {
         let url = Url::parse("gemini://gemini.circumlunar.space/docs/faq.gmi").unwrap();
@@ -243,7 +351,7 @@ mod test {
     #[test]
     fn test_homepage() {
         let url = Url::parse("gemini://geminiprotocol.net/").unwrap();
-        const INPUT: &'static str = r#"# Project Gemini
+        const INPUT: &str = r#"# Project Gemini
 
 ## Gemini in 100 words
 
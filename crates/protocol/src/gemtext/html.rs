@@ -0,0 +1,96 @@
+use crate::gemtext::gemtext_body::{GemTextBody, Line};
+
+/// Link schemes that run code or embed content in a browser, their links become text.
+const UNSAFE_SCHEMES: &[&str] = &["javascript", "vbscript", "data"];
+
+/// The document as an HTML fragment. Text is escaped, so the output is safe to embed in a
+/// page whatever the gemtext contains.
+///
+/// Consecutive list items become one `<ul>` and a preformatted block a `<pre>` labelled
+/// with its alt text, blank lines are dropped as the paragraphs already separate the text.
+pub fn to_html(body: &GemTextBody) -> String {
+    let mut html = String::new();
+    let mut lines = body.iter().peekable();
+
+    while let Some(line) = lines.next() {
+        match line {
+            Line::Text(text) if text.trim().is_empty() => {}
+            Line::Text(text) => html.push_str(&format!("<p>{}</p>\n", escape(text))),
+            Line::Link { url, description } => {
+                let text = escape(description.as_deref().map(str::trim).unwrap_or(url.as_str()));
+
+                if UNSAFE_SCHEMES.contains(&url.scheme()) {
+                    html.push_str(&format!("<p>{}</p>\n", text));
+                } else {
+                    html.push_str(&format!("<p><a href=\"{}\">{}</a></p>\n", escape(url.as_str()), text));
+                }
+            }
+            Line::Heading { text, depth } => {
+                let depth = (*depth).clamp(1, 6);
+                html.push_str(&format!("<h{depth}>{}</h{depth}>\n", escape(text)));
+            }
+            Line::Quote(text) => html.push_str(&format!("<blockquote>{}</blockquote>\n", escape(text))),
+            Line::ListItem(text) => {
+                html.push_str("<ul>\n");
+                html.push_str(&format!("<li>{}</li>\n", escape(text)));
+                while let Some(Line::ListItem(text)) = lines.peek() {
+                    html.push_str(&format!("<li>{}</li>\n", escape(text)));
+                    lines.next();
+                }
+                html.push_str("</ul>\n");
+            }
+            Line::Preformatted { alt, lines } => {
+                match alt {
+                    Some(alt) => html.push_str(&format!("<pre aria-label=\"{}\">", escape(alt))),
+                    None => html.push_str("<pre>"),
+                }
+                html.push_str(&escape(&lines.join("\n")));
+                html.push_str("</pre>\n");
+            }
+        }
+    }
+
+    html
+}
+
+/// Escapes the characters that are markup in text and in quoted attributes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::parse_gemtext;
+    use url::Url;
+
+    #[test]
+    fn test_to_html() {
+        let url = Url::parse("gemini://example.org/docs/").unwrap();
+        let input = "# Title & <b>more</b>\n\nSome \"text\"\n=> faq.gmi The FAQ\n=> javascript:alert(1) Click me\n* one\n* two\n> quoted <script>\n```rust \"main\"\nfn main() {\n    x < y\n}\n```\n```\n```\n#### Deep";
+
+        let html = to_html(&parse_gemtext(&url, input.to_string()).unwrap());
+
+        assert_eq!(html, "<h1>Title &amp; &lt;b&gt;more&lt;/b&gt;</h1>\n\
+<p>Some &quot;text&quot;</p>\n\
+<p><a href=\"gemini://example.org/docs/faq.gmi\">The FAQ</a></p>\n\
+<p>Click me</p>\n\
+<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n\
+<blockquote>quoted &lt;script&gt;</blockquote>\n\
+<pre aria-label=\"rust &quot;main&quot;\">fn main() {\n    x &lt; y\n}</pre>\n\
+<pre></pre>\n\
+<h4>Deep</h4>\n");
+    }
+}
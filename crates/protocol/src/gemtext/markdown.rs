@@ -0,0 +1,314 @@
+use crate::gemtext::gemtext_body::{GemTextBody, Line};
+use url::Url;
+
+const FENCE: &str = "```";
+
+/// Converts Markdown into gemtext, line by line.
+///
+/// Gemtext has no inline markup, so emphasis and code spans lose their markers and inline
+/// links and images become link lines after their paragraph. Headings deeper than three
+/// levels become level three, ordered lists and tables stay text, rules are dropped.
+/// Link targets are resolved against `url`, links that don't parse stay text.
+pub fn from_markdown(url: &Url, markdown: &str) -> GemTextBody {
+    let mut lines = vec![];
+    let mut paragraph: Vec<String> = vec![];
+    let mut links = vec![];
+    // The info string of the opening fence and the lines of the open code block.
+    let mut preformatted: Option<(Option<String>, Vec<String>)> = None;
+
+    for line in markdown.lines() {
+        if let Some(info) = line.trim_start().strip_prefix(FENCE) {
+            flush(&mut lines, &mut paragraph, &mut links);
+            match preformatted.take() {
+                Some((alt, code)) => lines.push(Line::Preformatted { alt, lines: code }),
+                None => {
+                    let info = info.trim();
+                    preformatted = Some(((!info.is_empty()).then(|| info.to_string()), vec![]));
+                }
+            }
+            continue;
+        }
+        if let Some((_, code)) = &mut preformatted {
+            code.push(line.to_string());
+            continue;
+        }
+
+        let trimmed = line.trim();
+        let mut block_links = vec![];
+        let block = if trimmed.is_empty() {
+            Some(Line::Text(String::new()))
+        } else if is_rule(trimmed) {
+            None
+        } else if let Some(heading) = heading(trimmed) {
+            Some(heading)
+        } else if let Some(item) = ["- ", "* ", "+ "].iter().find_map(|m| trimmed.strip_prefix(m)) {
+            Some(Line::ListItem(inline(item, url, &mut block_links)))
+        } else if let Some(quote) = trimmed.strip_prefix('>') {
+            Some(Line::Quote(inline(quote.trim_start(), url, &mut block_links)))
+        } else if let Some(link) = reference_definition(trimmed, url) {
+            Some(link)
+        } else {
+            paragraph.push(inline(trimmed, url, &mut links));
+            continue;
+        };
+
+        flush(&mut lines, &mut paragraph, &mut links);
+        match block {
+            // Blank lines only separate blocks, a run of them is kept as one.
+            Some(Line::Text(_)) if lines.last().is_none_or(|last| *last == Line::Text(String::new())) => {}
+            Some(block) => lines.push(block),
+            None => {}
+        }
+        lines.append(&mut block_links);
+    }
+    flush(&mut lines, &mut paragraph, &mut links);
+    if let Some((alt, code)) = preformatted {
+        lines.push(Line::Preformatted { alt, lines: code });
+    }
+
+    GemTextBody(lines)
+}
+
+/// Converts gemtext into Markdown, each gemtext line its own block. The alt text of a
+/// preformatted block becomes the info string of its code block.
+pub fn to_markdown(body: &GemTextBody) -> String {
+    let mut markdown = String::new();
+    let mut lines = body.iter().peekable();
+
+    while let Some(line) = lines.next() {
+        match line {
+            Line::Text(text) => markdown.push_str(&escape(text)),
+            Line::Link { url, description } => {
+                let text = description.as_deref().map(str::trim).unwrap_or(url.as_str());
+                markdown.push_str(&format!("[{}](<{}>)", escape_inline(text), url));
+            }
+            Line::Heading { text, depth } => {
+                markdown.push_str(&format!("{} {}", "#".repeat(*depth as usize), escape_inline(text)))
+            }
+            Line::ListItem(text) => markdown.push_str(&format!("* {}", escape_inline(text))),
+            Line::Quote(text) => markdown.push_str(&format!("> {}", escape_inline(text))),
+            Line::Preformatted { alt, lines } => {
+                markdown.push_str(FENCE);
+                markdown.push_str(alt.as_deref().unwrap_or_default());
+                markdown.push('\n');
+                for line in lines {
+                    markdown.push_str(line);
+                    markdown.push('\n');
+                }
+                markdown.push_str(FENCE);
+            }
+        }
+        markdown.push('\n');
+
+        // Lines of text would be joined into one paragraph, as would list items and quotes
+        // with the text that follows them.
+        let separate = match (line, lines.peek()) {
+            (Line::Text(t), _) if t.is_empty() => false,
+            (_, Some(Line::Text(t))) if t.is_empty() => false,
+            (Line::ListItem(_), Some(Line::ListItem(_))) | (Line::Quote(_), Some(Line::Quote(_))) => false,
+            (_, Some(_)) => true,
+            (_, None) => false,
+        };
+        if separate {
+            markdown.push('\n');
+        }
+    }
+
+    markdown
+}
+
+/// Ends the current paragraph, followed by the links it contained.
+fn flush(lines: &mut Vec<Line>, paragraph: &mut Vec<String>, links: &mut Vec<Line>) {
+    if !paragraph.is_empty() {
+        lines.push(Line::Text(paragraph.join(" ")));
+        paragraph.clear();
+    }
+    lines.append(links);
+}
+
+/// `---`, `***` or `_ _ _`.
+fn is_rule(line: &str) -> bool {
+    let line = line.replace(' ', "");
+    let first = line.chars().next();
+
+    line.len() >= 3 && matches!(first, Some('-' | '*' | '_')) && line.chars().all(|c| Some(c) == first)
+}
+
+fn heading(line: &str) -> Option<Line> {
+    let depth = line.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&depth) {
+        return None;
+    }
+
+    let text = line[depth..].strip_prefix(' ')?.trim().trim_end_matches('#').trim_end();
+
+    Some(Line::Heading {
+        text: strip_markers(text),
+        depth: depth.min(3) as u8,
+    })
+}
+
+/// `[label]: https://example.org/ "Title"`, which Markdown doesn't show.
+fn reference_definition(line: &str, url: &Url) -> Option<Line> {
+    let (label, rest) = line.strip_prefix('[')?.split_once("]:")?;
+    let target = rest.split_whitespace().next()?;
+    let target = target.trim_start_matches('<').trim_end_matches('>');
+
+    Some(Line::Link {
+        url: url.join(target).ok()?,
+        description: Some(label.to_string()),
+    })
+}
+
+/// The text of a line without its inline markup. Links and images are replaced by their
+/// text and added to `links`.
+fn inline(text: &str, url: &Url, links: &mut Vec<Line>) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('[') {
+        let image = start > 0 && rest[..start].ends_with('!');
+        let before = if image { &rest[..start - 1] } else { &rest[..start] };
+
+        let Some((label, target, after)) = link_at(&rest[start..]) else {
+            out.push_str(&rest[..start + 1]);
+            rest = &rest[start + 1..];
+            continue;
+        };
+        out.push_str(before);
+
+        let label = strip_markers(label);
+        if let Ok(target) = url.join(target) {
+            let description = match (image, label.is_empty()) {
+                (true, true) => "Image".to_string(),
+                (true, false) => format!("Image: {}", label),
+                (false, _) => label.clone(),
+            };
+            links.push(Line::Link {
+                url: target,
+                description: (!description.is_empty()).then_some(description),
+            });
+        }
+        if !image {
+            out.push_str(&label);
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+
+    strip_markers(out.trim())
+}
+
+/// `[label](target "title")` at the start of `text`, and what follows it.
+fn link_at(text: &str) -> Option<(&str, &str, &str)> {
+    let (label, rest) = text.strip_prefix('[')?.split_once("](")?;
+    let (inside, after) = rest.split_once(')')?;
+    let target = inside.split_whitespace().next().unwrap_or_default();
+    let target = target.trim_start_matches('<').trim_end_matches('>');
+
+    Some((label, target, after))
+}
+
+/// Drops the markers of strong emphasis and code spans and resolves backslash escapes.
+/// `*` and `_` alone are too often part of the text.
+fn strip_markers(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(next)) if next.is_ascii_punctuation() => out.push(chars.next().unwrap()),
+            ('*', Some('*')) | ('_', Some('_')) => {
+                chars.next();
+            }
+            ('`', _) => {}
+            (c, _) => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Text that Markdown would read as a block marker at the start of the line is escaped.
+fn escape(text: &str) -> String {
+    let escaped = escape_inline(text);
+
+    if let Some((number, rest)) = escaped.split_once(". ")
+        && !number.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit())
+    {
+        return format!("{}\\. {}", number, rest);
+    }
+
+    if ["#", ">", "-", "+", "=", "|"].iter().any(|m| escaped.starts_with(m)) {
+        format!("\\{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+fn escape_inline(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::parse_gemtext;
+
+    #[test]
+    fn test_from_markdown() {
+        let url = Url::parse("gemini://example.org/blog/").unwrap();
+        let markdown = "# A **bold** title #\n\nSome text with a [link](post.gmi \"Post\")\nthat wraps, and `code`.\n\n\n---\n#### Deep\n- one\n* two ![a cat](cat.png)\n> quoted\n\n```rust\nfn main() {}\n```\n[home]: <gemini://example.org/>\nsnake_case *stays*";
+
+        let body = from_markdown(&url, markdown);
+        let link = |u: &str, d: &str| Line::Link {
+            url: Url::parse(u).unwrap(),
+            description: Some(d.to_string()),
+        };
+
+        assert_eq!(body.0, vec![
+            Line::Heading { text: "A bold title".to_string(), depth: 1 },
+            Line::Text("".to_string()),
+            Line::Text("Some text with a link that wraps, and code.".to_string()),
+            link("gemini://example.org/blog/post.gmi", "link"),
+            Line::Text("".to_string()),
+            Line::Heading { text: "Deep".to_string(), depth: 3 },
+            Line::ListItem("one".to_string()),
+            Line::ListItem("two".to_string()),
+            link("gemini://example.org/blog/cat.png", "Image: a cat"),
+            Line::Quote("quoted".to_string()),
+            Line::Text("".to_string()),
+            Line::Preformatted {
+                alt: Some("rust".to_string()),
+                lines: vec!["fn main() {}".to_string()],
+            },
+            link("gemini://example.org/", "home"),
+            Line::Text("snake_case *stays*".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        let url = Url::parse("gemini://example.org/").unwrap();
+        let gemtext = "# Title\nFirst line\nsecond line\n=> /post A [post]\n\n* one\n* two\n> quoted\n```\n  code\n```\n1. not a list";
+
+        let markdown = to_markdown(&parse_gemtext(&url, gemtext.to_string()).unwrap());
+        assert_eq!(markdown, "# Title\n\nFirst line\n\nsecond line\n\n[A \\[post\\]](<gemini://example.org/post>)\n\n* one\n* two\n\n> quoted\n\n```\n  code\n```\n\n1\\. not a list\n");
+
+        let back = from_markdown(&url, &markdown);
+        assert_eq!(back[0], Line::Heading { text: "Title".to_string(), depth: 1 });
+        assert!(back.0.contains(&Line::Link {
+            url: Url::parse("gemini://example.org/post").unwrap(),
+            description: Some("A [post]".to_string()),
+        }));
+    }
+}
@@ -1,10 +1,14 @@
 use std::fmt::{Display, Formatter};
 use url::Url;
-use crate::gemtext::gemtext_body::GemTextBody;
+use crate::gemtext::gemtext_body::{GemTextBody, Line, Spanned};
 use crate::gemtext::gemtext_parser::GemTextParser;
 
+pub mod builder;
+pub mod feed;
 pub mod gemtext_body;
 pub mod gemtext_parser;
+pub mod html;
+pub mod markdown;
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct GemTextError {
@@ -38,3 +42,11 @@ pub fn parse_gemtext(url_path: &Url, str: String) -> Result<GemTextBody, GemText
 
     parser.gemtext_document()
 }
+
+/// Parses like [parse_gemtext], keeping where each line is in `str`, to map what is shown
+/// back to the source.
+pub fn parse_gemtext_spanned(url_path: &Url, str: &str) -> Result<Vec<Spanned<Line>>, GemTextError> {
+    let mut parser = GemTextParser::new(url_path, str);
+
+    parser.spanned_lines()
+}
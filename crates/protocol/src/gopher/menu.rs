@@ -0,0 +1,132 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use url::Url;
+use crate::gemtext::gemtext_body::{GemTextBody, Line};
+use crate::gopher::DEFAULT_PORT;
+
+/// Encoded in the selector of a URL, so it isn't read as a query or fragment.
+const SELECTOR: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>').add(b'?').add(b'`');
+
+/// A line of a menu, the item type and display string followed by the selector, host and
+/// port of the item, separated by tabs: `1Phlog`, `/phlog`, `example.org`, `70`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MenuItem {
+    pub item_type: char,
+    pub display: String,
+    pub selector: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl MenuItem {
+    /// Missing fields are left empty, as servers tend to for informational lines.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut chars = line.chars();
+        let item_type = chars.next()?;
+        let mut fields = chars.as_str().split('\t');
+
+        Some(MenuItem {
+            item_type,
+            display: fields.next().unwrap_or_default().to_string(),
+            selector: fields.next().unwrap_or_default().to_string(),
+            host: fields.next().unwrap_or_default().to_string(),
+            port: fields.next().and_then(|port| port.trim().parse().ok()).unwrap_or(DEFAULT_PORT),
+        })
+    }
+
+    /// What the item links to. An `h` item whose selector starts with `URL:` links to that
+    /// URL, a telnet session to its `telnet://` URL.
+    pub fn url(&self) -> Result<Url, url::ParseError> {
+        if self.item_type == 'h' && let Some(url) = self.selector.strip_prefix("URL:") {
+            return Url::parse(url);
+        }
+        if matches!(self.item_type, '8' | 'T') {
+            return Url::parse(&format!("telnet://{}:{}", self.host, self.port));
+        }
+
+        let port = if self.port == DEFAULT_PORT { String::new() } else { format!(":{}", self.port) };
+        let selector = utf8_percent_encode(&self.selector, SELECTOR);
+
+        Url::parse(&format!("gopher://{}{}/{}{}", self.host, port, self.item_type, selector))
+    }
+
+    /// Informational and error lines are text, the other items links to them.
+    pub fn to_line(&self) -> Line {
+        match self.item_type {
+            'i' => Line::Text(self.display.clone()),
+            '3' => Line::Text(format!("Error: {}", self.display)),
+            _ => match self.url() {
+                Ok(url) => Line::Link { url, description: Some(self.display.clone()) },
+                Err(_) => Line::Text(self.display.clone()),
+            },
+        }
+    }
+}
+
+/// The lines of a menu up to the `.` that ends it, as gemtext. Relative links don't occur,
+/// every item names its host, `url` is only the base of links that fail to.
+pub fn parse_menu(url: &Url, text: &str) -> GemTextBody {
+    let lines = text
+        .lines()
+        .take_while(|line| *line != ".")
+        .filter_map(MenuItem::parse)
+        .map(|mut item| {
+            if item.host.is_empty() && !matches!(item.item_type, 'i' | '3') {
+                item.host = url.host_str().unwrap_or_default().to_string();
+                item.port = url.port().unwrap_or(DEFAULT_PORT);
+            }
+
+            item.to_line()
+        })
+        .collect();
+
+    GemTextBody(lines)
+}
+
+#[cfg(test)]
+mod test {
+    use url::Url;
+    use crate::gemtext::gemtext_body::Line;
+    use crate::gopher::menu::{parse_menu, MenuItem};
+
+    fn link(url: &str, description: &str) -> Line {
+        Line::Link { url: Url::parse(url).unwrap(), description: Some(description.to_string()) }
+    }
+
+    #[test]
+    fn test_menu_item() {
+        let item = MenuItem::parse("1Phlog\t/phlog\texample.org\t70").unwrap();
+        assert_eq!(item.selector, "/phlog");
+        assert_eq!(item.url().unwrap().as_str(), "gopher://example.org/1/phlog");
+
+        let item = MenuItem::parse("0Read me\t/files/read me?.txt\texample.org\t7070").unwrap();
+        assert_eq!(item.url().unwrap().as_str(), "gopher://example.org:7070/0/files/read%20me%3F.txt");
+
+        let item = MenuItem::parse("hWeb\tURL:https://example.org/\texample.org\t70").unwrap();
+        assert_eq!(item.url().unwrap().as_str(), "https://example.org/");
+
+        let item = MenuItem::parse("iJust text").unwrap();
+        assert_eq!(item.to_line(), Line::Text("Just text".to_string()));
+        assert_eq!(MenuItem::parse(""), None);
+    }
+
+    #[test]
+    fn test_parse_menu() {
+        let url = Url::parse("gopher://example.org:7070/1/").unwrap();
+        let menu = "iWelcome\tfake\t(NULL)\t0\r\n\
+                    1Phlog\t/phlog\texample.org\t70\r\n\
+                    7Search\t/search\t\t\r\n\
+                    3Gone\t\terror.host\t1\r\n\
+                    .\r\n\
+                    iAfter the end\r\n";
+
+        assert_eq!(
+            parse_menu(&url, menu).0,
+            vec![
+                Line::Text("Welcome".to_string()),
+                link("gopher://example.org/1/phlog", "Phlog"),
+                link("gopher://example.org:7070/7/search", "Search"),
+                Line::Text("Error: Gone".to_string()),
+            ]
+        );
+    }
+}
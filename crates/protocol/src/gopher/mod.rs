@@ -0,0 +1,12 @@
+//! Gopher, RFC 1436: the client sends a selector and the server answers with the item,
+//! without a header. Menus are mapped to gemtext so they render like any other page, see
+//! [menu::parse_menu].
+//!
+//! A URL names the item type before the selector, `gopher://host/1/phlog` is the menu
+//! `/phlog`, see [request::Request::from_url].
+
+pub mod menu;
+pub mod request;
+
+/// The port of a `gopher://` URL that doesn't name one.
+pub const DEFAULT_PORT: u16 = 70;
@@ -0,0 +1,195 @@
+use std::fmt::{Display, Formatter};
+use percent_encoding::percent_decode_str;
+use url::Url;
+use crate::error::{ParserError, RequestError};
+use crate::gemini_protocol::response::{Body, OkResponse, Response};
+use crate::gemtext::gemtext_body::MimeType;
+use crate::gopher::menu::parse_menu;
+
+/// A Gopher request, the selector and the words of a search separated by a tab.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Request {
+    pub host: String,
+    /// The type of the item the selector names, which says how to read the response.
+    pub item_type: char,
+    pub selector: String,
+    /// What a search server (type `7`) is asked for.
+    pub search: Option<String>,
+}
+
+impl Request {
+    /// The path of the URL is the item type followed by the percent-encoded selector, a menu
+    /// when it is empty. The search follows an encoded tab, or is the query of a search
+    /// server's URL, the query of other items is part of their selector.
+    pub fn from_url(url: &Url) -> Result<Self, RequestError> {
+        if !url.username().is_empty() || url.password().is_some() {
+            return Err(RequestError::UserInfo);
+        }
+        let host = url.host_str().ok_or(RequestError::MissingHost)?;
+
+        let path = percent_decode_str(url.path()).decode_utf8_lossy();
+        let mut path = path.strip_prefix('/').unwrap_or(&path).chars();
+        let item_type = path.next().unwrap_or('1');
+        let path = path.as_str();
+
+        let (selector, search) = match path.split_once('\t') {
+            Some((selector, search)) => (selector.to_string(), Some(search.to_string())),
+            None => (path.to_string(), None),
+        };
+        let query = url.query().map(|query| percent_decode_str(query).decode_utf8_lossy().into_owned());
+
+        let (selector, search) = match query {
+            Some(query) if item_type == '7' => (selector, Some(query)),
+            Some(query) => (format!("{}?{}", selector, query), search),
+            None => (selector, search),
+        };
+
+        Ok(Request { host: host.to_string(), item_type, selector, search })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+
+    /// A search server needs something to search for before it is requested.
+    pub fn needs_search(&self) -> bool {
+        self.item_type == '7' && self.search.is_none()
+    }
+
+    /// The MIME type of the item. Menus are `text/gemini` once mapped by [Request::response],
+    /// files of the generic types are guessed from the extension of the selector.
+    pub fn mime(&self) -> &'static str {
+        match self.item_type {
+            '1' | '7' => "text/gemini",
+            '0' => "text/plain",
+            'h' => "text/html",
+            'g' => "image/gif",
+            'd' => "application/pdf",
+            'I' | 's' | '9' | ';' => mime_from_extension(&self.selector),
+            _ => "application/octet-stream",
+        }
+    }
+
+    /// The item as a Gemini response. Menus are parsed into gemtext and text files have
+    /// their terminating `.` line removed, other items are kept as they are.
+    pub fn response(&self, url: &Url, body: &[u8]) -> Result<Response, ParserError> {
+        let mime = MimeType::parse(self.mime()).unwrap_or_default();
+
+        match self.item_type {
+            '1' | '7' => {
+                let menu = parse_menu(url, &String::from_utf8_lossy(body));
+
+                Ok(Response::Success(OkResponse { mime, body: Body::GemText(menu) }))
+            }
+            '0' => {
+                let text = String::from_utf8_lossy(body);
+                Ok(Response::Success(OkResponse::from_text(url, mime, unterminate(&text))?))
+            }
+            _ => Ok(Response::Success(OkResponse::new(url, mime, body)?)),
+        }
+    }
+}
+
+/// The line sent to the server.
+impl Display for Request {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.search {
+            Some(search) => write!(f, "{}\t{}\r\n", self.selector, search),
+            None => write!(f, "{}\r\n", self.selector),
+        }
+    }
+}
+
+fn mime_from_extension(selector: &str) -> &'static str {
+    let extension = selector.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).unwrap_or_default();
+
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "mp4" => "video/mp4",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A text file without the `.` line that ends it, and with the leading dot of the lines
+/// that start with one removed.
+fn unterminate(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for line in text.lines() {
+        if line == "." {
+            break;
+        }
+        out.push_str(if line.starts_with("..") { &line[1..] } else { line });
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use url::Url;
+    use crate::gemini_protocol::response::Response;
+    use crate::gemtext::gemtext_body::Line;
+    use crate::gopher::request::Request;
+
+    #[test]
+    fn test_request() {
+        let url = Url::parse("gopher://example.org/1/phlog").unwrap();
+        let request = Request::from_url(&url).unwrap();
+        assert_eq!(request.item_type, '1');
+        assert_eq!(request.to_bytes(), b"/phlog\r\n");
+
+        let url = Url::parse("gopher://example.org").unwrap();
+        assert_eq!(Request::from_url(&url).unwrap().to_bytes(), b"\r\n");
+
+        let url = Url::parse("gopher://example.org/7/search%09fish%20chips").unwrap();
+        assert_eq!(Request::from_url(&url).unwrap().to_bytes(), b"/search\tfish chips\r\n");
+
+        let url = Url::parse("gopher://example.org/7/search?fish%20chips").unwrap();
+        assert_eq!(Request::from_url(&url).unwrap().search.as_deref(), Some("fish chips"));
+        let url = Url::parse("gopher://example.org/7/search").unwrap();
+        assert!(Request::from_url(&url).unwrap().needs_search());
+
+        let url = Url::parse("gopher://example.org/0/cgi?page=2").unwrap();
+        assert_eq!(Request::from_url(&url).unwrap().selector, "/cgi?page=2");
+
+        let url = Url::parse("gopher://example.org/I/cat.JPG").unwrap();
+        assert_eq!(Request::from_url(&url).unwrap().mime(), "image/jpeg");
+    }
+
+    #[test]
+    fn test_response() {
+        let url = Url::parse("gopher://example.org/0/notes.txt").unwrap();
+        let request = Request::from_url(&url).unwrap();
+        let Response::Success(ok) = request.response(&url, b"Notes\r\n..dotted\r\n.\r\nafter\r\n").unwrap() else {
+            panic!("expected success");
+        };
+        assert_eq!(ok.mime.to_string(), "text/plain");
+        assert_eq!(ok.gemtext().unwrap().0, vec![Line::Text("Notes".to_string()), Line::Text(".dotted".to_string())]);
+
+        let url = Url::parse("gopher://example.org/1/").unwrap();
+        let request = Request::from_url(&url).unwrap();
+        let Response::Success(ok) = request.response(&url, b"iWelcome\t\terror.host\t1\r\n.\r\n").unwrap() else {
+            panic!("expected success");
+        };
+        assert_eq!(ok.mime.to_string(), "text/gemini");
+        assert_eq!(ok.gemtext().unwrap().0, vec![Line::Text("Welcome".to_string())]);
+
+        let url = Url::parse("gopher://example.org/9/archive.zip").unwrap();
+        let request = Request::from_url(&url).unwrap();
+        let Response::Success(ok) = request.response(&url, b"PK\x03\x04").unwrap() else {
+            panic!("expected success");
+        };
+        assert_eq!(ok.body_bytes(), Some(&b"PK\x03\x04"[..]));
+    }
+}
@@ -1,3 +1,8 @@
 pub mod gemtext;
+pub mod capture;
 pub mod error;
 pub mod gemini_protocol;
+pub mod gopher;
+pub mod robots;
+pub mod spartan;
+pub mod url_util;
@@ -0,0 +1,164 @@
+//! robots.txt as the Gemini companion spec uses it: groups of `User-agent` lines naming
+//! virtual agents by what a bot does rather than who runs it, each followed by the
+//! `Disallow` prefixes of the paths they may not request.
+//!
+//! gemini://geminiprotocol.net/docs/companion/robots.gmi
+
+use std::fmt::{Display, Formatter};
+use url::Url;
+use crate::gemini_protocol::response::Response;
+
+pub const ROBOTS_PATH: &str = "/robots.txt";
+
+/// The virtual agents of the companion spec. A bot obeys the rules of its own and of `*`.
+pub const VIRTUAL_AGENTS: [&str; 4] = ["archiver", "indexer", "researcher", "webproxy"];
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Robots {
+    pub groups: Vec<Group>,
+}
+
+/// The rules shared by the agents of a group, an empty `disallow` allows everything.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Group {
+    pub agents: Vec<String>,
+    pub disallow: Vec<String>,
+}
+
+impl Robots {
+    /// Lines other than `User-agent` and `Disallow` are skipped, as are comments. Agents
+    /// are lowercased, the spec's names are.
+    pub fn parse(text: &str) -> Self {
+        let mut groups: Vec<Group> = vec![];
+        // A `User-agent` after a rule starts a new group, consecutive ones share it.
+        let mut in_rules = true;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((field, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match field.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    if in_rules {
+                        groups.push(Group::default());
+                        in_rules = false;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_ascii_lowercase());
+                    }
+                }
+                "disallow" => {
+                    in_rules = true;
+                    if let Some(group) = groups.last_mut() && !value.is_empty() {
+                        group.disallow.push(value.to_string());
+                    }
+                }
+                _ => in_rules = true,
+            }
+        }
+
+        Robots { groups }
+    }
+
+    /// The robots.txt of a response to its [url], a capsule that has none allows everything.
+    pub fn from_response(response: &Response) -> Self {
+        match response {
+            Response::Success(ok) => Robots::parse(&ok.gemtext().map(|body| body.to_string()).unwrap_or_default()),
+            _ => Robots::default(),
+        }
+    }
+
+    /// Whether `agent` may request `path`, by the groups of `agent` and of `*`.
+    pub fn is_allowed(&self, path: &str, agent: &str) -> bool {
+        let agent = agent.to_ascii_lowercase();
+
+        !self
+            .groups
+            .iter()
+            .filter(|group| group.agents.iter().any(|a| a == "*" || *a == agent))
+            .flat_map(|group| &group.disallow)
+            .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+}
+
+/// The robots.txt, a blank line after each group.
+impl Display for Robots {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for group in &self.groups {
+            for agent in &group.agents {
+                writeln!(f, "User-agent: {}", agent)?;
+            }
+            if group.disallow.is_empty() {
+                writeln!(f, "Disallow:")?;
+            }
+            for path in &group.disallow {
+                writeln!(f, "Disallow: {}", path)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where the robots.txt of the capsule of `url` is.
+pub fn url(capsule: &Url) -> Option<Url> {
+    capsule.join(ROBOTS_PATH).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use url::Url;
+    use crate::gemini_protocol::parse_response;
+    use crate::robots::{url, Group, Robots};
+
+    #[test]
+    fn test_is_allowed() {
+        let robots = Robots::parse(
+            "User-agent: indexer\nDisallow: /search\n\n\
+             User-agent: archiver\nUser-agent: researcher\nDisallow: /private/ # drafts too\n\n\
+             User-agent: *\nDisallow: /cgi-bin/\nDisallow:\n",
+        );
+
+        assert!(robots.is_allowed("/", "archiver"));
+        assert!(robots.is_allowed("/search", "archiver"));
+        assert!(!robots.is_allowed("/search?q", "indexer"));
+        assert!(!robots.is_allowed("/private/notes.gmi", "Researcher"));
+        assert!(!robots.is_allowed("/cgi-bin/guestbook", "webproxy"));
+        assert!(robots.is_allowed("/private/notes.gmi", "webproxy"));
+
+        assert!(Robots::parse("User-agent: *\nDisallow:\n").is_allowed("/", "indexer"));
+        assert!(!Robots::parse("user-agent: ARCHIVER\ndisallow: /\n").is_allowed("/index.gmi", "archiver"));
+        assert!(Robots::parse("Disallow: /\n").is_allowed("/", "archiver"));
+    }
+
+    #[test]
+    fn test_display() {
+        let robots = Robots {
+            groups: vec![
+                Group { agents: vec!["archiver".to_string(), "indexer".to_string()], disallow: vec!["/private/".to_string()] },
+                Group { agents: vec!["*".to_string()], disallow: vec![] },
+            ],
+        };
+        let text = robots.to_string();
+
+        assert_eq!(text, "User-agent: archiver\nUser-agent: indexer\nDisallow: /private/\n\nUser-agent: *\nDisallow:\n\n");
+        assert_eq!(Robots::parse(&text), robots);
+    }
+
+    #[test]
+    fn test_from_response() {
+        let capsule = Url::parse("gemini://example.org/gemlog/post.gmi").unwrap();
+        let robots_url = url(&capsule).unwrap();
+        assert_eq!(robots_url.as_str(), "gemini://example.org/robots.txt");
+
+        let response = parse_response(&robots_url, "20 text/plain\r\nUser-agent: *\nDisallow: /gemlog/\n").unwrap();
+        assert!(!Robots::from_response(&response).is_allowed("/gemlog/post.gmi", "archiver"));
+
+        let response = parse_response(&robots_url, "51 Not found\r\n").unwrap();
+        assert_eq!(Robots::from_response(&response), Robots::default());
+    }
+}
@@ -0,0 +1,22 @@
+//! Spartan, Gemini's plain-text sibling: no TLS, one-digit statuses and requests that can
+//! carry content. Bodies are gemtext like Gemini's, see [response::Response::Success].
+//!
+//! gemini://spartan.mozz.us/specification
+
+use url::Url;
+use crate::error::ParserError;
+use crate::spartan::response::{Header, Response};
+
+pub mod request;
+pub mod response;
+
+/// The port of a `spartan://` URL that doesn't name one.
+pub const DEFAULT_PORT: u16 = 300;
+
+/// Like [crate::gemini_protocol::parse_response_bytes], for a Spartan response.
+pub fn parse_response_bytes(url: &Url, response: &[u8]) -> Result<Response, ParserError> {
+    let end = response.iter().position(|&b| b == b'\n').map_or(response.len(), |i| i + 1);
+    let (header, body) = response.split_at(end);
+
+    Header::parse(header)?.response(url, body)
+}
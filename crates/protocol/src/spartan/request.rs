@@ -0,0 +1,117 @@
+use std::fmt::{Display, Formatter};
+use percent_encoding::percent_decode_str;
+use url::Url;
+use crate::error::{ErrorKind, ParserError, RequestError};
+
+/// A Spartan request, `<host> <path> <content-length>\r\n` followed by that many bytes of
+/// content.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Request {
+    pub host: String,
+    /// Absolute and percent-encoded, without the query.
+    pub path: String,
+    pub content: Vec<u8>,
+}
+
+/// The first line of a request, before the content it announces has been read.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RequestLine {
+    pub host: String,
+    pub path: String,
+    pub content_length: usize,
+}
+
+impl Request {
+    /// The query of the URL is decoded and uploaded as the content, the fragment is for the
+    /// client only.
+    pub fn from_url(url: &Url) -> Result<Self, RequestError> {
+        if !url.username().is_empty() || url.password().is_some() {
+            return Err(RequestError::UserInfo);
+        }
+        let host = url.host_str().ok_or(RequestError::MissingHost)?;
+
+        let path = match url.path() {
+            "" => "/",
+            path => path,
+        };
+        let content = percent_decode_str(url.query().unwrap_or_default()).collect();
+
+        Ok(Request { host: host.to_string(), path: path.to_string(), content })
+    }
+
+    /// The request line followed by the content, as sent to the server.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.to_string().into_bytes();
+        out.extend_from_slice(&self.content);
+
+        out
+    }
+}
+
+/// The request line, without the content.
+impl Display for Request {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}\r\n", self.host, self.path, self.content.len())
+    }
+}
+
+impl RequestLine {
+    /// Parses the line a server received, with or without its line ending.
+    pub fn parse(line: &str) -> Result<Self, ParserError> {
+        let err = |kind| ParserError { line: 1, kind };
+
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        let mut fields = line.split(' ');
+        let (Some(host), Some(path), Some(length), None) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+            return Err(err(ErrorKind::SyntaxMissingSpace));
+        };
+
+        if host.is_empty() || !path.starts_with('/') {
+            return Err(err(ErrorKind::SyntaxExpectedData));
+        }
+        let content_length = length.parse().map_err(|_| err(ErrorKind::InvalidDigit))?;
+
+        Ok(RequestLine { host: host.to_string(), path: path.to_string(), content_length })
+    }
+
+    pub fn with_content(self, content: Vec<u8>) -> Request {
+        Request { host: self.host, path: self.path, content }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use url::Url;
+    use crate::error::{ErrorKind, RequestError};
+    use crate::spartan::request::{Request, RequestLine};
+
+    #[test]
+    fn test_request() {
+        let url = Url::parse("spartan://example.org:3000/guestbook?Hello%20there#top").unwrap();
+        let request = Request::from_url(&url).unwrap();
+        assert_eq!(request.to_bytes(), b"example.org /guestbook 11\r\nHello there");
+
+        let url = Url::parse("spartan://example.org").unwrap();
+        assert_eq!(Request::from_url(&url).unwrap().to_bytes(), b"example.org / 0\r\n");
+
+        let url = Url::parse("spartan://user@example.org/").unwrap();
+        assert_eq!(Request::from_url(&url), Err(RequestError::UserInfo));
+    }
+
+    #[test]
+    fn test_request_line() {
+        let line = RequestLine::parse("example.org /guestbook 11\r\n").unwrap();
+        assert_eq!(line.content_length, 11);
+        assert_eq!(
+            line.with_content(b"Hello there".to_vec()),
+            Request { host: "example.org".to_string(), path: "/guestbook".to_string(), content: b"Hello there".to_vec() }
+        );
+
+        assert_eq!(RequestLine::parse("example.org /").unwrap_err().kind, ErrorKind::SyntaxMissingSpace);
+        assert_eq!(RequestLine::parse("example.org / 0 extra").unwrap_err().kind, ErrorKind::SyntaxMissingSpace);
+        assert_eq!(RequestLine::parse("example.org relative 0").unwrap_err().kind, ErrorKind::SyntaxExpectedData);
+        assert_eq!(RequestLine::parse("example.org / -1").unwrap_err().kind, ErrorKind::InvalidDigit);
+    }
+}
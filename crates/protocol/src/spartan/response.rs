@@ -0,0 +1,198 @@
+use std::fmt::{Display, Formatter};
+use futures::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+use url::Url;
+use crate::error::{ErrorKind, ParserError};
+use crate::gemini_protocol::parse_response;
+use crate::gemini_protocol::parser::MAX_HEADER_LENGTH;
+use crate::gemini_protocol::response::{OkResponse, Response as GeminiResponse};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Response {
+    /// 2, the meta is the MIME type of the body.
+    Success(OkResponse),
+    /// 3, to an absolute path on the same host.
+    Redirect(String),
+    /// 4
+    ClientError(String),
+    /// 5
+    ServerError(String),
+}
+
+impl Response {
+    pub fn status(&self) -> u8 {
+        match self {
+            Response::Success(_) => 2,
+            Response::Redirect(_) => 3,
+            Response::ClientError(_) => 4,
+            Response::ServerError(_) => 5,
+        }
+    }
+
+    pub fn meta(&self) -> String {
+        match self {
+            Response::Success(ok) => ok.mime.to_string(),
+            Response::Redirect(meta) | Response::ClientError(meta) | Response::ServerError(meta) => meta.clone(),
+        }
+    }
+
+    /// `<STATUS><SP><META>\r\n`, followed by the body of a success, as sent by a server.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = format!("{} {}\r\n", self.status(), self.meta()).into_bytes();
+        if let Response::Success(ok) = self {
+            out.extend(ok.to_bytes());
+        }
+
+        out
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Response::Success(ok) => write!(f, "Success ({})", ok.mime),
+            Response::Redirect(path) => write!(f, "Redirect to {}", path),
+            Response::ClientError(msg) => write!(f, "Client Error ({})", msg),
+            Response::ServerError(msg) => write!(f, "Server Error ({})", msg),
+        }
+    }
+}
+
+/// For clients that handle both protocols alike. A Spartan error only says whose fault it
+/// was, so it becomes the generic Gemini failure of that kind.
+impl From<Response> for GeminiResponse {
+    fn from(response: Response) -> Self {
+        match response {
+            Response::Success(ok) => GeminiResponse::Success(ok),
+            Response::Redirect(path) => GeminiResponse::TemporaryRedirect(path),
+            Response::ClientError(msg) => GeminiResponse::PermanentFailure(Some(msg)),
+            Response::ServerError(msg) => GeminiResponse::UnexpectedErrorTryAgain(Some(msg)),
+        }
+    }
+}
+
+/// The Spartan status of a Gemini one, for a server answering both from the same routes.
+/// Spartan has no input prompts or client certificates, their statuses are client errors.
+pub fn status_from_gemini(status: u8) -> u8 {
+    match status / 10 {
+        2 => 2,
+        3 => 3,
+        4 => 5,
+        _ => 4,
+    }
+}
+
+/// The header line of a response, read on its own so the body can be streamed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Header {
+    pub status: u8,
+    pub meta: String,
+}
+
+impl Header {
+    /// Parses the header line, with or without its line ending.
+    pub fn parse(line: &[u8]) -> Result<Self, ParserError> {
+        let err = |kind| ParserError { line: 1, kind };
+
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let line = std::str::from_utf8(line).map_err(|_| err(ErrorKind::SyntaxExpectedData))?;
+
+        let (status, meta) = line.split_once(' ').ok_or(err(ErrorKind::SyntaxMissingSpace))?;
+        let status = match status.as_bytes() {
+            [] => return Err(err(ErrorKind::MissingStatus)),
+            [s @ b'2'..=b'5'] => s - b'0',
+            [b'0'..=b'9'] => return Err(err(ErrorKind::InvalidStatus(status.parse().unwrap_or(0)))),
+            _ => return Err(err(ErrorKind::InvalidDigit)),
+        };
+
+        Ok(Header { status, meta: meta.to_string() })
+    }
+
+    /// Reads the header and leaves `reader` at the first byte of the body. Spartan sets no
+    /// limit, the header is held to the longest a Gemini one can be.
+    pub async fn read<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Self, ParserError> {
+        let mut line = vec![];
+        reader
+            .take(MAX_HEADER_LENGTH as u64)
+            .read_until(b'\n', &mut line)
+            .await
+            .map_err(|e| ParserError { line: 1, kind: ErrorKind::Io(e.kind()) })?;
+
+        if line.last() != Some(&b'\n') {
+            let kind = match line.len() {
+                MAX_HEADER_LENGTH => ErrorKind::HeaderTooLong,
+                _ => ErrorKind::SyntaxMissingNewline,
+            };
+            return Err(ParserError { line: 1, kind });
+        }
+
+        Self::parse(&line)
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status == 2
+    }
+
+    /// The whole response, once the body has been read.
+    pub fn response(&self, url: &Url, body: &[u8]) -> Result<Response, ParserError> {
+        match self.status {
+            2 => match parse_response(url, &format!("20 {}\r\n", self.meta))? {
+                GeminiResponse::Success(ok) => Ok(Response::Success(OkResponse::new(url, ok.mime, body)?)),
+                _ => unreachable!("a 20 header is a success"),
+            },
+            3 => Ok(Response::Redirect(self.meta.clone())),
+            4 => Ok(Response::ClientError(self.meta.clone())),
+            _ => Ok(Response::ServerError(self.meta.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::io::Cursor;
+    use url::Url;
+    use crate::error::ErrorKind;
+    use crate::gemini_protocol::response::Response as GeminiResponse;
+    use crate::spartan::parse_response_bytes;
+    use crate::spartan::response::{status_from_gemini, Header, Response};
+
+    #[test]
+    fn test_response() {
+        let url = Url::parse("spartan://example.org/").unwrap();
+
+        let response = parse_response_bytes(&url, b"2 text/gemini; charset=iso-8859-1\r\n# Gr\xfc\xdfe\n").unwrap();
+        let Response::Success(ok) = &response else { panic!("{response:?}") };
+        assert_eq!(ok.gemtext().unwrap().to_string(), "# Grüße\n");
+        assert_eq!(response.serialize(), b"2 text/gemini; charset=iso-8859-1\r\n# Gr\xfc\xdfe\n");
+
+        let response = parse_response_bytes(&url, b"3 /new\r\n").unwrap();
+        assert_eq!(response, Response::Redirect("/new".to_string()));
+        assert_eq!(GeminiResponse::from(response), GeminiResponse::TemporaryRedirect("/new".to_string()));
+
+        assert_eq!(parse_response_bytes(&url, b"4 Not found\r\n").unwrap(), Response::ClientError("Not found".to_string()));
+        assert_eq!(parse_response_bytes(&url, b"5 Oops\r\n").unwrap().to_string(), "Server Error (Oops)");
+
+        assert_eq!(parse_response_bytes(&url, b"20 text/gemini\r\n").unwrap_err().kind, ErrorKind::InvalidDigit);
+        assert_eq!(parse_response_bytes(&url, b"1 Name?\r\n").unwrap_err().kind, ErrorKind::InvalidStatus(1));
+        assert_eq!(parse_response_bytes(&url, b"2\r\n").unwrap_err().kind, ErrorKind::SyntaxMissingSpace);
+    }
+
+    #[test]
+    fn test_read_header() {
+        futures::executor::block_on(async {
+            let mut reader = Cursor::new(b"2 text/plain\r\nbody".to_vec());
+            let header = Header::read(&mut reader).await.unwrap();
+            assert_eq!(header, Header { status: 2, meta: "text/plain".to_string() });
+            assert_eq!(reader.position(), 14);
+
+            let mut reader = Cursor::new(format!("2 {}", "a".repeat(2000)).into_bytes());
+            assert_eq!(Header::read(&mut reader).await.unwrap_err().kind, ErrorKind::HeaderTooLong);
+        });
+    }
+
+    #[test]
+    fn test_status_from_gemini() {
+        let statuses = [10, 20, 31, 40, 44, 51, 59, 60].map(status_from_gemini);
+        assert_eq!(statuses, [4, 2, 3, 5, 5, 4, 4, 4]);
+    }
+}
@@ -0,0 +1,94 @@
+use percent_encoding::percent_decode_str;
+use url::{Host, Url};
+use crate::error::UrlError;
+
+/// The port a URL of `scheme` means when it names none.
+pub fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "gemini" | "titan" => Some(1965),
+        "spartan" => Some(300),
+        "gopher" => Some(70),
+        _ => None,
+    }
+}
+
+/// The URL as it is compared and requested, so `gemini://Example.org:1965` and
+/// `gemini://example.org/` are the same page: the host in lowercase with IDN labels as
+/// A-labels (`xn--bcher-kva.example`), no default port and `/` for an empty path. The
+/// fragment is kept, it is for the client. URLs with a username or password are rejected,
+/// the spec forbids them.
+pub fn normalize(url: &Url) -> Result<Url, UrlError> {
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err(UrlError::UserInfo);
+    }
+
+    let mut url = to_ascii_host(url)?;
+    if url.port().is_some() && url.port() == default_port(url.scheme()) {
+        url.set_port(None).map_err(|_| UrlError::InvalidHost)?;
+    }
+    if url.has_host() && url.path().is_empty() {
+        url.set_path("/");
+    }
+
+    Ok(url)
+}
+
+/// The URL with its host in lowercase A-labels, which is what DNS, SNI and the request
+/// line need. `gemini://` isn't a special scheme for the url crate, so it keeps the host
+/// as it was written, Unicode percent-encoded.
+pub fn to_ascii_host(url: &Url) -> Result<Url, UrlError> {
+    let Some(Host::Domain(host)) = url.host() else {
+        return Ok(url.clone());
+    };
+
+    let decoded = percent_decode_str(host).decode_utf8().map_err(|_| UrlError::InvalidHost)?;
+    let ascii = idna::domain_to_ascii(&decoded).map_err(|_| UrlError::InvalidHost)?;
+    if ascii == host {
+        return Ok(url.clone());
+    }
+
+    let mut url = url.clone();
+    url.set_host(Some(&ascii)).map_err(|_| UrlError::InvalidHost)?;
+    Ok(url)
+}
+
+/// The URL of a request line, without its `\r\n`, normalized. A request is an absolute
+/// URL with a host and without a fragment.
+pub fn parse_request_url(line: &str) -> Result<Url, UrlError> {
+    let url = Url::parse(line).map_err(UrlError::Parse)?;
+    if url.fragment().is_some() {
+        return Err(UrlError::Fragment);
+    }
+    if !url.has_host() {
+        return Err(UrlError::MissingHost);
+    }
+
+    normalize(&url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let normalize = |url: &str| normalize(&Url::parse(url).unwrap()).map(String::from);
+
+        assert_eq!(normalize("gemini://Example.ORG:1965"), Ok("gemini://example.org/".to_string()));
+        assert_eq!(normalize("gemini://example.org:1966/a#top"), Ok("gemini://example.org:1966/a#top".to_string()));
+        assert_eq!(normalize("gemini://bücher.example/a?q"), Ok("gemini://xn--bcher-kva.example/a?q".to_string()));
+        assert_eq!(normalize("gemini://[::1]:1965/"), Ok("gemini://[::1]/".to_string()));
+        assert_eq!(normalize("spartan://example.org:300"), Ok("spartan://example.org/".to_string()));
+        assert_eq!(normalize("about:blank"), Ok("about:blank".to_string()));
+        assert_eq!(normalize("gemini://user@example.org/"), Err(UrlError::UserInfo));
+        assert_eq!(normalize("gemini://exa%FFmple.org/"), Err(UrlError::InvalidHost));
+    }
+
+    #[test]
+    fn test_parse_request_url() {
+        assert_eq!(parse_request_url("gemini://EXAMPLE.org").map(String::from), Ok("gemini://example.org/".to_string()));
+        assert_eq!(parse_request_url("gemini://example.org/#top"), Err(UrlError::Fragment));
+        assert_eq!(parse_request_url("gemini:example"), Err(UrlError::MissingHost));
+        assert!(matches!(parse_request_url("/page"), Err(UrlError::Parse(_))));
+    }
+}
@@ -0,0 +1,316 @@
+// https://github.com/rustls/tokio-rustls/blob/main/tests/certs/main.rs
+use crate::config::{GetProperty, VHost};
+use anyhow::Context;
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, DnValue,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SanType,
+};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use sha2::{Digest, Sha256};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+/// A certificate for one host, signed by an intermediate of a freshly generated root.
+pub struct GeneratedCert {
+    /// The end entity followed by the intermediate, as served to clients.
+    pub chain_pem: String,
+    pub key_pem: String,
+    pub root_pem: String,
+}
+
+fn issuer_params(common_name: &str) -> CertificateParams {
+    let mut issuer_name = DistinguishedName::new();
+    issuer_name.push(DnType::CommonName, common_name);
+    let mut issuer_params = CertificateParams::default();
+    issuer_params.distinguished_name = issuer_name;
+    issuer_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    issuer_params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    issuer_params
+}
+
+pub fn generate(host: &str) -> anyhow::Result<GeneratedCert> {
+    let root_key = KeyPair::generate()?;
+    let root_ca = issuer_params(&format!("{} root", host)).self_signed(&root_key)?;
+
+    let intermediate_key = KeyPair::generate()?;
+    let intermediate_ca = issuer_params(&format!("{} intermediate", host)).signed_by(
+        &intermediate_key,
+        &root_ca,
+        &root_key,
+    )?;
+
+    let end_entity_key = KeyPair::generate()?;
+    let mut end_entity_params = CertificateParams::new(vec![host.to_string()])?;
+    end_entity_params
+        .distinguished_name
+        .push(DnType::CommonName, host);
+    end_entity_params.is_ca = IsCa::ExplicitNoCa;
+    end_entity_params.extended_key_usages = vec![
+        ExtendedKeyUsagePurpose::ServerAuth,
+        ExtendedKeyUsagePurpose::ClientAuth,
+    ];
+    let end_entity =
+        end_entity_params.signed_by(&end_entity_key, &intermediate_ca, &intermediate_key)?;
+
+    Ok(GeneratedCert {
+        chain_pem: format!("{}{}", end_entity.pem(), intermediate_ca.pem()),
+        key_pem: end_entity_key.serialize_pem(),
+        root_pem: root_ca.pem(),
+    })
+}
+
+fn write_file(path: &Path, contents: &str, force: bool, mode: u32) -> anyhow::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).truncate(true);
+    if force {
+        options.create(true);
+    } else {
+        options.create_new(true);
+    }
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create {:?}", path))?;
+    file.write_all(contents.as_bytes())
+        .with_context(|| format!("Failed to write {:?}", path))?;
+
+    Ok(())
+}
+
+/// Writes a new certificate for the vhost to its `tls_cert` and `tls_key` files, existing
+/// files are only replaced with `force`.
+pub fn generate_for_vhost(vhost: &VHost, root: Option<&Path>, force: bool) -> anyhow::Result<()> {
+    let property = |name: &str| {
+        vhost
+            .get_property_string(name)
+            .map(Path::new)
+            .with_context(|| {
+                format!(
+                    "The vhost '{}' is missing the '{}' property",
+                    vhost.vhost, name
+                )
+            })
+    };
+    let cert_path = property("tls_cert")?;
+    let key_path = property("tls_key")?;
+
+    let generated = generate(&vhost.vhost.0)?;
+
+    write_file(cert_path, &generated.chain_pem, force, 0o644)?;
+    write_file(key_path, &generated.key_pem, force, 0o600)?;
+    if let Some(root) = root {
+        write_file(root, &generated.root_pem, force, 0o644)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct CertInfo {
+    pub common_name: Option<String>,
+    pub names: Vec<String>,
+    pub not_before: String,
+    pub not_after: String,
+    pub expires: SystemTime,
+    pub fingerprint: String,
+}
+
+/// Colon separated SHA-256 of the DER encoded certificate, as shown by most TOFU clients.
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+pub fn info(der: &CertificateDer) -> anyhow::Result<CertInfo> {
+    let params = CertificateParams::from_ca_cert_der(der)?;
+
+    let common_name = params
+        .distinguished_name
+        .get(&DnType::CommonName)
+        .map(|value| match value {
+            DnValue::Utf8String(s) => s.clone(),
+            DnValue::PrintableString(s) => s.as_str().to_string(),
+            DnValue::Ia5String(s) => s.as_str().to_string(),
+            other => format!("{:?}", other),
+        });
+
+    let names = params
+        .subject_alt_names
+        .iter()
+        .map(|name| match name {
+            SanType::DnsName(s) | SanType::Rfc822Name(s) | SanType::URI(s) => s.to_string(),
+            SanType::IpAddress(ip) => ip.to_string(),
+            other => format!("{:?}", other),
+        })
+        .collect();
+
+    Ok(CertInfo {
+        common_name,
+        names,
+        not_before: params.not_before.to_string(),
+        not_after: params.not_after.to_string(),
+        expires: params.not_after.into(),
+        fingerprint: fingerprint(der),
+    })
+}
+
+/// Used when a vhost with an `acme_command` has no `acme_renew_before`.
+pub const DEFAULT_RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Whether the certificate at `path` is missing or expires within `before`.
+pub fn needs_renewal(path: &Path, before: Duration) -> anyhow::Result<bool> {
+    if !path.exists() {
+        return Ok(true);
+    }
+
+    let der = CertificateDer::from_pem_file(path)
+        .with_context(|| format!("Failed to read certificate: {:?}", path))?;
+
+    Ok(info(&der)?.expires <= SystemTime::now() + before)
+}
+
+/// Runs the `acme_command` of the vhost through `sh` when its certificate needs renewal
+/// (or with `force`). The command gets `GEMINI_HOST`, `GEMINI_TLS_CERT`, `GEMINI_TLS_KEY`
+/// and, with an HTTP-01 helper listener, `GEMINI_ACME_CHALLENGE_DIR` in its environment
+/// and has to leave a PEM chain and key at the two paths, e.g. by running an ACME client
+/// in webroot mode and copying its output. `false` when nothing needed renewing.
+pub fn renew_for_vhost(
+    vhost: &VHost,
+    challenge_dir: Option<&str>,
+    force: bool,
+) -> anyhow::Result<bool> {
+    let property = |name: &str| {
+        vhost.get_property_string(name).with_context(|| {
+            format!(
+                "The vhost '{}' is missing the '{}' property",
+                vhost.vhost, name
+            )
+        })
+    };
+    let command = property("acme_command")?;
+    let cert_path = property("tls_cert")?;
+    let key_path = property("tls_key")?;
+
+    let before = vhost
+        .get_property_duration("acme_renew_before")
+        .unwrap_or(DEFAULT_RENEW_BEFORE);
+    if !force && !needs_renewal(Path::new(cert_path), before)? {
+        return Ok(false);
+    }
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(command)
+        .env("GEMINI_HOST", &vhost.vhost.0)
+        .env("GEMINI_TLS_CERT", cert_path)
+        .env("GEMINI_TLS_KEY", key_path);
+    if let Some(dir) = challenge_dir {
+        cmd.env("GEMINI_ACME_CHALLENGE_DIR", dir);
+    }
+
+    let status = cmd
+        .status()
+        .with_context(|| format!("Failed to run the acme_command of '{}'", vhost.vhost))?;
+    if !status.success() {
+        anyhow::bail!("The acme_command of '{}' failed: {}", vhost.vhost, status);
+    }
+
+    let der = CertificateDer::from_pem_file(cert_path)
+        .with_context(|| format!("The acme_command left no certificate at {:?}", cert_path))?;
+    if !info(&der)?.names.contains(&vhost.vhost.0) {
+        anyhow::bail!(
+            "The new certificate of '{}' does not cover its hostname",
+            vhost.vhost
+        );
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::pem::PemObject;
+
+    #[test]
+    fn test_generate() {
+        let generated = generate("example.org").unwrap();
+
+        let chain = CertificateDer::pem_slice_iter(generated.chain_pem.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(chain.len(), 2);
+
+        let info = info(&chain[0]).unwrap();
+        assert_eq!(info.common_name.as_deref(), Some("example.org"));
+        assert_eq!(info.names, vec!["example.org"]);
+        assert_eq!(info.fingerprint.len(), 32 * 3 - 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_renew_for_vhost() {
+        let dir = std::env::temp_dir().join(format!("gemini-renew-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let generated = generate("example.org").unwrap();
+        let issued = dir.join("issued.pem");
+        std::fs::write(&issued, &generated.chain_pem).unwrap();
+
+        let input = format!(
+            r#"
+server
+{{
+    port 1965;
+
+    vhost
+    {{
+        hostname "example.org";
+        tls_cert "{dir}/cert.pem";
+        tls_key "{dir}/key.pem";
+        acme_command "cp {issued} $GEMINI_TLS_CERT && echo $GEMINI_HOST > $GEMINI_TLS_KEY";
+    }}
+}}
+"#,
+            dir = dir.display(),
+            issued = issued.display()
+        );
+        let config = server_core::config::read_and_parse_config(&input).unwrap();
+        let vhost = &config.server.vhosts[0];
+
+        assert!(renew_for_vhost(vhost, None, false).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("key.pem")).unwrap(),
+            "example.org\n"
+        );
+
+        // The generated certificate is valid for far longer than 30 days.
+        assert!(!renew_for_vhost(vhost, None, false).unwrap());
+        assert!(renew_for_vhost(vhost, None, true).unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(
+            fingerprint(b""),
+            "E3:B0:C4:42:98:FC:1C:14:9A:FB:F4:C8:99:6F:B9:24:27:AE:41:E4:64:9B:93:4C:A4:95:99:1B:78:52:B8:55"
+        );
+    }
+}
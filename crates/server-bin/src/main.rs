@@ -1,15 +1,13 @@
-pub mod config;
-mod tls_store;
-
-use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
-use tokio::{
-    io::BufReader,
-    net::{TcpListener, TcpStream},
-};
-use tokio_rustls::TlsAcceptor;
+mod certs;
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use server_core::config::{self, read_and_parse_config, validate_config, Config, GetProperty};
+use server_core::routing::shadowed_routes;
+use server_core::{logging, Listeners, Server};
+use std::path::{Path, PathBuf};
 
 #[cfg(target_os = "xd")]
 #[tokio::main(flavor = "current_thread")]
@@ -44,228 +42,257 @@ async fn main() {
     main.call(&mut store, ()).unwrap();
 }
 
-// https://github.com/rustls/tokio-rustls/blob/main/tests/certs/main.rs
-use crate::config::{read_and_parse_config, Config, GetProperty};
-use crate::tls_store::make_tls_config;
-use rcgen::{
-    BasicConstraints, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
-    KeyPair, KeyUsagePurpose,
-};
-use std::fs::File;
-use std::io::Write;
-use std::str::FromStr;
-
-// TODO: Remove :)
-fn regenerate_certs(domain: String) {
-    let root_key = KeyPair::generate().unwrap();
-    let root_ca = issuer_params("asdasd").self_signed(&root_key).unwrap();
-
-    let mut root_file = File::create("root.pem").unwrap();
-    root_file.write_all(root_ca.pem().as_bytes()).unwrap();
-
-    let intermediate_key = KeyPair::generate().unwrap();
-    let intermediate_ca = issuer_params("asdasd - 2")
-        .signed_by(&intermediate_key, &root_ca, &root_key)
-        .unwrap();
-
-    let end_entity_key = KeyPair::generate().unwrap();
-    let mut end_entity_params = CertificateParams::new(vec![domain]).unwrap();
-    end_entity_params.is_ca = IsCa::ExplicitNoCa;
-    end_entity_params.extended_key_usages = vec![
-        ExtendedKeyUsagePurpose::ServerAuth,
-        ExtendedKeyUsagePurpose::ClientAuth,
-    ];
-    let end_entity = end_entity_params
-        .signed_by(&end_entity_key, &intermediate_ca, &intermediate_key)
-        .unwrap();
+/// `worker_threads 4;` spreads connections over a multi-threaded runtime, `0` uses one
+/// worker per core. Without it (or with `1`) everything runs on the main thread.
+fn build_runtime(config: &Config) -> anyhow::Result<tokio::runtime::Runtime> {
+    let mut builder = match config.get_property_number("worker_threads") {
+        None | Some(1) => tokio::runtime::Builder::new_current_thread(),
+        Some(0) => tokio::runtime::Builder::new_multi_thread(),
+        Some(n) => {
+            let mut builder = tokio::runtime::Builder::new_multi_thread();
+            builder.worker_threads(n as usize);
+            builder
+        }
+    };
 
-    let mut chain_file = File::create("cert.pem").unwrap();
-    chain_file.write_all(end_entity.pem().as_bytes()).unwrap();
-    chain_file
-        .write_all(intermediate_ca.pem().as_bytes())
-        .unwrap();
+    builder
+        .enable_all()
+        .build()
+        .context("Failed to build the tokio runtime")
+}
 
-    let mut key_file = File::create("key.key").unwrap();
-    key_file
-        .write_all(end_entity_key.serialize_pem().as_bytes())
-        .unwrap();
+/// Completes on Ctrl-C, or never when the signal handler could not be installed.
+async fn shutdown_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("Failed to listen for Ctrl-C; error = {:?}", e);
+        std::future::pending::<()>().await;
+    }
 }
 
-fn issuer_params(common_name: &str) -> CertificateParams {
-    let mut issuer_name = DistinguishedName::new();
-    issuer_name.push(DnType::CommonName, common_name);
-    let mut issuer_params = CertificateParams::default();
-    issuer_params.distinguished_name = issuer_name;
-    issuer_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
-    issuer_params.key_usages = vec![
-        KeyUsagePurpose::KeyCertSign,
-        KeyUsagePurpose::DigitalSignature,
-    ];
-    issuer_params
+#[derive(Parser)]
+#[command(version, about = "A Gemini server")]
+struct Cli {
+    /// The config file
+    #[arg(short, long, global = true, default_value = "config.cfg")]
+    config: PathBuf,
+
+    /// Checks the config and exits, the same as the `check` command
+    #[arg(long)]
+    validate: bool,
+
+    /// The most verbose level that is logged
+    #[arg(long, global = true, default_value = "info")]
+    log_level: log::LevelFilter,
+
+    /// Stays attached to the terminal instead of detaching into the background
+    #[arg(long)]
+    foreground: bool,
+
+    /// Where logs are written once detached, they are discarded otherwise
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-struct GlobalState<'a> {
-    tls_config: Arc<rustls::ServerConfig>,
-    config: Arc<Config<'a>>,
+#[derive(Subcommand)]
+enum Command {
+    /// Checks the config, its route files and the TLS setup, then exits
+    Check,
+    /// Manages the TLS certificates of the vhosts
+    Cert {
+        #[command(subcommand)]
+        command: CertCommand,
+    },
 }
 
-type GlobalStateArc<'a> = Arc<GlobalState<'a>>;
-
-const MAX_REQUEST_SIZE: usize = 1024;
-
-async fn handle_client_request<'a>(
-    conn: TlsConnection,
-    global_state: GlobalStateArc<'a>,
-) -> anyhow::Result<()> {
-    log::info!("Accepted connection from {:?}", conn.addr);
-
-    // let (sni, valid, mut stream) = {
-    //     let mut sni = None;
-    //     let mut valid = false;
-    let stream = conn
-        .acceptor
-        // .accept_with(conn.socket, |sc| {
-        //     if let Some(server_name) = sc.server_name() {
-        //         sni = Some(server_name.to_string());
-        //         valid = global_state
-        //             .config
-        //             .get_blocks("vhost")
-        //             .iter()
-        //             .find(|block| {
-        //                 block
-        //                     .get_property_string("for")
-        //                     .map_or(false, |s| s == server_name)
-        //             })
-        //             .is_some();
-        //     }
-        // })
-        .accept(conn.socket)
-        .await?;
-
-    //     (sni.unwrap_or_default(), valid, stream)
-    // };
-    //
-    // if !valid {
-    //     log::warn!(
-    //         "Invalid domain name: {:?}",
-    //         stream.into_inner().1.server_name()
-    //     );
-    //     return Ok(());
-    // }
-    //
-    // // TODO: Merge them
-    // let vhost = global_state
-    //     .config
-    //     .get_blocks("vhost")
-    //     .iter()
-    //     .find(|block| block.get_property_string("for").map_or(false, |s| s == sni))
-    //     .unwrap();
-
-    let mut line_reader = BufReader::new(stream);
-
-    loop {
-        let mut req = String::new();
-
-        match line_reader.read_line(&mut req).await {
-            Ok(0) => {
-                log::info!("Connection closed by client");
-                break;
-            }
-            Ok(_) => {}
-            Err(e) => {
-                log::error!("Failed to read from socket; error = {:?}", e);
-                break;
-            }
-        }
+#[derive(Subcommand)]
+enum CertCommand {
+    /// Generates a certificate for a vhost and writes it to its `tls_cert` and `tls_key` files
+    Generate {
+        /// The hostname of the vhost
+        #[arg(long)]
+        host: String,
+        /// Also writes the root CA the certificate chains up to
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Replaces existing certificate and key files
+        #[arg(long)]
+        force: bool,
+    },
+    /// Prints the certificate of every vhost
+    Info,
+    /// Runs the `acme_command` of every vhost whose certificate expires soon
+    Renew {
+        /// Only renews the vhost with this hostname
+        #[arg(long)]
+        host: Option<String>,
+        /// Renews even certificates that are not about to expire
+        #[arg(long)]
+        force: bool,
+    },
+}
 
-        if req.is_empty() {
-            log::debug!("Empty request; closing connection");
+fn describe_config_error(path: &Path, input: &str, e: config::error::Error) -> anyhow::Error {
+    anyhow::anyhow!("{}: {}", path.display(), e.describe(input))
+}
 
-            break;
-        }
-        if req.len() > MAX_REQUEST_SIZE {
-            log::warn!("Request too large: {:?}", req);
-            todo!("Handle this error");
-            break;
+/// Reads, parses and validates the config.
+fn load_config(path: &Path) -> anyhow::Result<Config> {
+    let input = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+    let config =
+        read_and_parse_config(&input).map_err(|e| describe_config_error(path, &input, e))?;
+    validate_config(&config).map_err(|e| describe_config_error(path, &input, e))?;
+
+    for vhost in &config.server.vhosts {
+        for (route, by) in shadowed_routes(vhost) {
+            log::warn!(
+                "Route '{}' of vhost '{}' is shadowed by route '{}' and will never match",
+                route.path,
+                vhost.vhost,
+                by.path
+            );
         }
+    }
 
-        log::debug!("Received request: {:?}", req);
+    Ok(config)
+}
 
-        let resp = "20 text/gemini\r\n# Hi :)\r\n=> /index.gmi\r\n".to_string();
+/// Everything the server would fail on at startup, without binding or serving anything.
+fn check(path: &Path) -> anyhow::Result<()> {
+    Server::from_config(load_config(path)?)?;
 
-        let stream = line_reader.get_mut();
-        stream.write_all(&resp.as_bytes()).await?;
-        stream.shutdown().await?;
-    }
+    println!("{}: OK", path.display());
 
     Ok(())
 }
 
-struct TlsConnection {
-    socket: TcpStream,
-    addr: SocketAddr,
-    acceptor: TlsAcceptor,
-}
+fn cert(config: &Path, command: CertCommand) -> anyhow::Result<()> {
+    match command {
+        CertCommand::Generate { host, root, force } => {
+            let config = load_config(config)?;
+            let vhost = config
+                .server
+                .vhosts
+                .iter()
+                .find(|vhost| vhost.vhost.0 == host)
+                .with_context(|| format!("No vhost with the hostname '{}'", host))?;
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> anyhow::Result<()> {
-    env_logger::builder()
-        .filter_level(log::LevelFilter::Debug)
-        .init();
-
-    let config_str: &'static str = {
-        let path = if std::env::args().len() > 1 {
-            PathBuf::from_str(&std::env::args().nth(1).unwrap())
-        } else {
-            PathBuf::from_str("config.cfg")
-        }
-        .expect("Failed to parse config file path");
+            certs::generate_for_vhost(vhost, root.as_deref(), force)?;
 
-        let data = std::fs::read_to_string(path).expect("Failed to read config file");
+            println!("Generated a certificate for '{}'", host);
+        }
+        CertCommand::Info => {
+            let config = load_config(config)?;
+
+            for vhost in &config.server.vhosts {
+                let Some(path) = vhost.get_property_string("tls_cert") else {
+                    println!("{}: no certificate configured", vhost.vhost);
+                    continue;
+                };
+
+                let der = CertificateDer::from_pem_file(path)
+                    .with_context(|| format!("Failed to read certificate: {:?}", path))?;
+                let info = certs::info(&der)?;
+
+                println!("{}: {}", vhost.vhost, path);
+                println!(
+                    "  subject:     {}",
+                    info.common_name.as_deref().unwrap_or("-")
+                );
+                println!("  names:       {}", info.names.join(", "));
+                println!("  not before:  {}", info.not_before);
+                println!("  not after:   {}", info.not_after);
+                println!("  fingerprint: {}", info.fingerprint);
+
+                if !info.names.contains(&vhost.vhost.0) {
+                    log::warn!(
+                        "The certificate of '{}' does not cover its hostname",
+                        vhost.vhost
+                    );
+                }
+            }
+        }
+        CertCommand::Renew { host, force } => {
+            let config = load_config(config)?;
+            let challenge_dir = config.get_property_string("acme_challenge_dir");
+
+            let vhosts = config
+                .server
+                .vhosts
+                .iter()
+                .filter(|vhost| vhost.get_property_string("acme_command").is_some())
+                .filter(|vhost| host.as_ref().is_none_or(|host| vhost.vhost.0 == *host))
+                .collect::<Vec<_>>();
+            if vhosts.is_empty() {
+                anyhow::bail!("No vhost to renew has an 'acme_command' property");
+            }
 
-        data.leak()
-    };
+            for vhost in vhosts {
+                if certs::renew_for_vhost(vhost, challenge_dir, force)? {
+                    println!("Renewed the certificate of '{}'", vhost.vhost);
+                } else {
+                    println!(
+                        "The certificate of '{}' is not due for renewal",
+                        vhost.vhost
+                    );
+                }
+            }
+        }
+    }
 
-    let config = Arc::new(read_and_parse_config(&config_str).unwrap());
+    Ok(())
+}
 
-    println!("{:#?}", &config);
-    let port = config.get_property_number("port").unwrap();
+/// Detaches from the terminal. The working directory is kept so relative paths in the
+/// config keep resolving.
+#[cfg(unix)]
+fn daemonize(log_file: Option<&Path>) -> anyhow::Result<()> {
+    let mut daemon = daemonize::Daemonize::new().working_directory(std::env::current_dir()?);
+
+    if let Some(path) = log_file {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open log file {}", path.display()))?;
+        daemon = daemon.stderr(file);
+    }
 
-    regenerate_certs("localhost".into());
+    daemon
+        .start()
+        .context("Failed to detach into the background")
+}
 
-    let tls_config = make_tls_config(&config)?;
-    let global_state = Arc::new(GlobalState { config, tls_config });
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
 
-    let tcp_listener = TcpListener::bind(format!("[::]:{port}"))
-        .await
-        .expect("Failed to bind to port");
+    logging::init(cli.log_level);
 
-    log::info!(
-        "Listening on: {}",
-        tcp_listener.local_addr().expect("Failed to get local addr")
-    );
+    match cli.command {
+        Some(Command::Check) => return check(&cli.config),
+        Some(Command::Cert { command }) => return cert(&cli.config, command),
+        None if cli.validate => return check(&cli.config),
+        None => {}
+    }
 
-    loop {
-        let (socket, addr) = match tcp_listener.accept().await {
-            Ok((socket, addr)) => (socket, addr),
-            Err(e) => {
-                log::error!("Failed to accept connection; error = {:?}", e);
-                continue;
-            }
-        };
+    let config = load_config(&cli.config)?;
 
-        let global_state = global_state.clone();
+    log::debug!("{:#?}", &config);
 
-        tokio::spawn(async move {
-            let socket = TlsConnection {
-                socket,
-                addr,
-                acceptor: TlsAcceptor::from(global_state.tls_config.clone()),
-            };
+    let server = Server::from_config(config)?;
+    let listeners = Listeners::bind(server.config())?;
 
-            if let Err(e) = handle_client_request(socket, global_state).await {
-                log::error!("failed to handle client request; error = {:?}", e);
-            }
-        });
+    #[cfg(unix)]
+    if !cli.foreground {
+        daemonize(cli.log_file.as_deref())?;
     }
+
+    // Built after detaching, a runtime's threads would not survive the fork.
+    let runtime = build_runtime(server.config())?;
+
+    runtime.block_on(server.serve(listeners, shutdown_signal()))
 }
@@ -0,0 +1,165 @@
+use crate::config::{Config, GetProperty};
+use anyhow::Context;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Where HTTP-01 challenges are requested, followed by the token.
+pub const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// A challenge request has to arrive within this long.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Only the request line and a few headers are expected.
+const MAX_REQUEST_SIZE: u64 = 8 * 1024;
+
+/// `acme_http_listen "0.0.0.0:80";` with `acme_challenge_dir "/var/lib/gemini/acme";`
+/// answers the HTTP-01 challenges of an ACME client running in webroot mode (certbot
+/// `--webroot`, lego `--http.webroot`, acme.sh `-w`), which writes its tokens below
+/// `.well-known/acme-challenge/` of the directory. Nothing else is served over HTTP.
+pub struct ChallengeListenerConfig {
+    pub addr: SocketAddr,
+    pub dir: PathBuf,
+}
+
+impl ChallengeListenerConfig {
+    pub fn from_config(config: &Config) -> anyhow::Result<Option<Self>> {
+        let Some(addr) = config.get_property_string("acme_http_listen") else {
+            return Ok(None);
+        };
+
+        let addr = addr
+            .parse()
+            .with_context(|| format!("Invalid acme_http_listen address '{}'", addr))?;
+        let dir = config
+            .get_property_string("acme_challenge_dir")
+            .context("acme_http_listen needs an 'acme_challenge_dir' property")?;
+
+        Ok(Some(ChallengeListenerConfig {
+            addr,
+            dir: dir.into(),
+        }))
+    }
+}
+
+/// The token of a `GET /.well-known/acme-challenge/<token> HTTP/1.1` request line, tokens
+/// are base64url so anything else is refused.
+fn challenge_token(request_line: &str) -> Option<&str> {
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next()?, parts.next()?);
+    if method != "GET" || !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+
+    let token = target.strip_prefix(CHALLENGE_PREFIX)?;
+    let valid = !token.is_empty()
+        && token
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_');
+
+    valid.then_some(token)
+}
+
+async fn http_response(dir: &Path, request_line: &str) -> String {
+    let key = match challenge_token(request_line) {
+        Some(token) => tokio::fs::read_to_string(dir.join(&CHALLENGE_PREFIX[1..]).join(token))
+            .await
+            .ok(),
+        None => None,
+    };
+
+    let (status, body) = match &key {
+        Some(key) => ("200 OK", key.as_str()),
+        None => ("404 Not Found", "Not found\n"),
+    };
+
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+async fn answer_challenge(socket: TcpStream, dir: &Path) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(socket).take(MAX_REQUEST_SIZE);
+
+    let mut request_line = String::new();
+    tokio::time::timeout(REQUEST_TIMEOUT, reader.read_line(&mut request_line))
+        .await
+        .context("Timed out reading the challenge request")??;
+
+    log::info!("ACME challenge request: {:?}", request_line.trim_end());
+
+    let resp = http_response(dir, &request_line).await;
+
+    let mut socket = reader.into_inner().into_inner();
+    socket.write_all(resp.as_bytes()).await?;
+    socket.shutdown().await?;
+
+    Ok(())
+}
+
+pub async fn accept_challenge_loop(listener: TcpListener, dir: Arc<PathBuf>) {
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                log::error!("Failed to accept connection; error = {:?}", e);
+                continue;
+            }
+        };
+
+        let dir = dir.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = answer_challenge(socket, &dir).await {
+                log::warn!("Failed to answer ACME challenge; error = {:?}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_token() {
+        let cases = vec![
+            (
+                "GET /.well-known/acme-challenge/abc-DEF_1 HTTP/1.1\r\n",
+                Some("abc-DEF_1"),
+            ),
+            ("GET /.well-known/acme-challenge/ HTTP/1.1\r\n", None),
+            ("GET /.well-known/acme-challenge/../key HTTP/1.1\r\n", None),
+            ("POST /.well-known/acme-challenge/abc HTTP/1.1\r\n", None),
+            ("GET /index.html HTTP/1.1\r\n", None),
+            ("GET /.well-known/acme-challenge/abc\r\n", None),
+        ];
+
+        for (request_line, expected) in cases {
+            assert_eq!(challenge_token(request_line), expected, "{request_line}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_response() {
+        let dir = std::env::temp_dir().join(format!("gemini-acme-{}", std::process::id()));
+        let challenges = dir.join(".well-known/acme-challenge");
+        std::fs::create_dir_all(&challenges).unwrap();
+        std::fs::write(challenges.join("token"), "token.thumbprint").unwrap();
+
+        let found = http_response(&dir, "GET /.well-known/acme-challenge/token HTTP/1.1").await;
+        assert!(found.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(found.ends_with("\r\n\r\ntoken.thumbprint"));
+
+        let missing = http_response(&dir, "GET /.well-known/acme-challenge/other HTTP/1.1").await;
+        assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,284 @@
+use crate::client_cert::ClientCert;
+use crate::config::{Config, GetProperty, Route};
+use crate::response::Response;
+use crate::stats::Target;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Used when the server has no `cache_size`.
+pub const DEFAULT_CACHE_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Used when a cached route has no `cache_max_size`.
+pub const DEFAULT_CACHE_MAX_SIZE: u64 = 1024 * 1024;
+
+/// The caching of a route, `cache_ttl 30s;` turns it on. Responses larger than
+/// `cache_max_size` are always generated fresh.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CachePolicy {
+    pub ttl: Duration,
+    pub max_size: u64,
+}
+
+impl CachePolicy {
+    pub fn for_route(route: &Route) -> Option<Self> {
+        let ttl = route
+            .get_property_duration("cache_ttl")
+            .filter(|ttl| !ttl.is_zero())?;
+
+        Some(CachePolicy {
+            ttl,
+            max_size: route
+                .get_property_size("cache_max_size")
+                .unwrap_or(DEFAULT_CACHE_MAX_SIZE),
+        })
+    }
+}
+
+struct Entry {
+    response: Response,
+    expires: Instant,
+    /// The route that generated the response.
+    route: Target,
+}
+
+impl Entry {
+    fn size(key: &str, response: &Response) -> u64 {
+        (key.len() + response.header.len() + response.body.len()) as u64
+    }
+}
+
+/// The key of the response to `line`. Responses to a client certificate are kept apart
+/// from everyone else's, templates and scripts see who the certificate names.
+pub fn cache_key<'a>(line: &'a str, cert: Option<&ClientCert>) -> Cow<'a, str> {
+    match cert {
+        // A request line has no line break, so the two can't run together.
+        Some(cert) => Cow::Owned(format!("{}\n{}", line, cert.hash)),
+        None => Cow::Borrowed(line),
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    by_url: HashMap<String, Entry>,
+    size: u64,
+}
+
+/// Successful responses of templates and scripts, keyed by [cache_key]. The server's
+/// `cache_size` bounds all entries together, once it is reached expired entries are
+/// dropped and new responses are not cached until there is room again.
+pub struct ResponseCache {
+    capacity: u64,
+    entries: Mutex<Entries>,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: u64) -> Self {
+        ResponseCache {
+            capacity,
+            entries: Mutex::default(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            config
+                .get_property_size("cache_size")
+                .unwrap_or(DEFAULT_CACHE_SIZE),
+        )
+    }
+
+    pub fn get(&self, url: &str) -> Option<Response> {
+        self.get_at(url, Instant::now())
+    }
+
+    pub fn insert(&self, url: &str, response: &Response, policy: CachePolicy, route: Target) {
+        self.insert_at(url, response, policy, route, Instant::now());
+    }
+
+    /// Drops the responses of `routes`, returns how many there were.
+    pub fn remove_routes(&self, routes: &[Target]) -> usize {
+        self.remove_where(|entry| routes.contains(&entry.route))
+    }
+
+    /// Drops every response, returns how many there were.
+    pub fn clear(&self) -> usize {
+        self.remove_where(|_| true)
+    }
+
+    fn remove_where(&self, mut remove: impl FnMut(&Entry) -> bool) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let Entries { by_url, size } = &mut *entries;
+
+        let before = by_url.len();
+        by_url.retain(|url, entry| {
+            let keep = !remove(entry);
+            if !keep {
+                *size -= Entry::size(url, &entry.response);
+            }
+            keep
+        });
+
+        before - by_url.len()
+    }
+
+    fn get_at(&self, url: &str, now: Instant) -> Option<Response> {
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.by_url.get(url) {
+            Some(entry) if entry.expires > now => Some(entry.response.clone()),
+            Some(_) => {
+                if let Some(entry) = entries.by_url.remove(url) {
+                    entries.size -= Entry::size(url, &entry.response);
+                }
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert_at(
+        &self,
+        url: &str,
+        response: &Response,
+        policy: CachePolicy,
+        route: Target,
+        now: Instant,
+    ) {
+        if !response.header.starts_with('2') {
+            return;
+        }
+
+        let size = Entry::size(url, response);
+        if size > policy.max_size || size > self.capacity {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(old) = entries.by_url.remove(url) {
+            entries.size -= Entry::size(url, &old.response);
+        }
+
+        if entries.size + size > self.capacity {
+            let Entries { by_url, size } = &mut *entries;
+            by_url.retain(|url, entry| {
+                let keep = entry.expires > now;
+                if !keep {
+                    *size -= Entry::size(url, &entry.response);
+                }
+                keep
+            });
+        }
+
+        if entries.size + size > self.capacity {
+            return;
+        }
+
+        entries.size += size;
+        entries.by_url.insert(
+            url.to_string(),
+            Entry {
+                response: response.clone(),
+                expires: now + policy.ttl,
+                route,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: CachePolicy = CachePolicy {
+        ttl: Duration::from_secs(10),
+        max_size: 1024,
+    };
+
+    #[test]
+    fn test_expiry() {
+        let cache = ResponseCache::new(1024);
+        let now = Instant::now();
+        let url = "gemini://localhost/index";
+
+        cache.insert_at(
+            url,
+            &Response::new(20, "text/gemini", "hi"),
+            POLICY,
+            Target::default(),
+            now,
+        );
+
+        let cached = cache.get_at(url, now + Duration::from_secs(5)).unwrap();
+        assert_eq!(cached.body, "hi");
+        assert!(cache.get_at("gemini://localhost/other", now).is_none());
+
+        assert!(cache.get_at(url, now + Duration::from_secs(10)).is_none());
+        assert_eq!(cache.entries.lock().unwrap().size, 0);
+    }
+
+    #[test]
+    fn test_limits() {
+        let cache = ResponseCache::new(100);
+        let now = Instant::now();
+        let ok = |body: &str| Response::new(20, "text/gemini", body.to_string());
+
+        // Failures are not cached.
+        cache.insert_at(
+            "a",
+            &Response::new(42, "CGI error", ""),
+            POLICY,
+            Target::default(),
+            now,
+        );
+        assert!(cache.get_at("a", now).is_none());
+
+        let small = CachePolicy {
+            max_size: 30,
+            ..POLICY
+        };
+        cache.insert_at("b", &ok(&"x".repeat(20)), small, Target::default(), now);
+        assert!(cache.get_at("b", now).is_none());
+
+        // Two of these fit, the third only once the first has expired.
+        let resp = ok(&"x".repeat(30));
+        let short = CachePolicy {
+            ttl: Duration::from_secs(1),
+            ..POLICY
+        };
+        cache.insert_at("c", &resp, short, Target::default(), now);
+        cache.insert_at("d", &resp, POLICY, Target::default(), now);
+        cache.insert_at("e", &resp, POLICY, Target::default(), now);
+        assert!(cache.get_at("e", now).is_none());
+
+        let later = now + Duration::from_secs(2);
+        cache.insert_at("e", &resp, POLICY, Target::default(), later);
+        assert!(cache.get_at("c", later).is_none());
+        assert!(cache.get_at("d", later).is_some());
+        assert!(cache.get_at("e", later).is_some());
+    }
+
+    #[test]
+    fn test_remove_routes() {
+        let cache = ResponseCache::new(1024);
+        let now = Instant::now();
+        let resp = Response::new(20, "text/gemini", "hi");
+        let route = |route| Target {
+            vhost: Some(0),
+            route: Some(route),
+        };
+
+        cache.insert_at("a", &resp, POLICY, route(0), now);
+        cache.insert_at("b", &resp, POLICY, route(0), now);
+        cache.insert_at("c", &resp, POLICY, route(1), now);
+
+        assert_eq!(cache.remove_routes(&[route(0)]), 2);
+        assert!(cache.get_at("a", now).is_none());
+        assert!(cache.get_at("c", now).is_some());
+
+        assert_eq!(cache.clear(), 1);
+        assert_eq!(cache.entries.lock().unwrap().size, 0);
+    }
+}
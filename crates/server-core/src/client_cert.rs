@@ -0,0 +1,123 @@
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{CertificateError, DigitallySignedStruct, DistinguishedName, SignatureScheme};
+use sha2::{Digest, Sha256};
+
+/// The certificate a client identified itself with. Gemini client certificates are
+/// usually self-signed, so they name a user rather than prove who it is, and applications
+/// recognise returning users by the hash.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ClientCert {
+    /// `SHA256:` followed by the uppercase hex digest of the DER certificate, the format of
+    /// `TLS_CLIENT_HASH` in the CGI environment of other Gemini servers.
+    pub hash: String,
+    pub common_name: Option<String>,
+}
+
+impl ClientCert {
+    pub fn from_der(der: &[u8]) -> Self {
+        let hash = Sha256::digest(der)
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<String>();
+
+        let common_name = x509_parser::parse_x509_certificate(der)
+            .ok()
+            .and_then(|(_, cert)| {
+                let cn = cert.subject().iter_common_name().next()?;
+                cn.as_str().ok().map(str::to_string)
+            });
+
+        ClientCert {
+            hash: format!("SHA256:{}", hash),
+            common_name,
+        }
+    }
+}
+
+/// Asks every client for a certificate without requiring one, and accepts any well-formed
+/// certificate whose key signed the handshake, whoever issued it and whether or not it has
+/// expired.
+#[derive(Debug)]
+pub struct AcceptAnyClientCert {
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl AcceptAnyClientCert {
+    pub fn new(algorithms: WebPkiSupportedAlgorithms) -> Self {
+        AcceptAnyClientCert { algorithms }
+    }
+}
+
+impl ClientCertVerifier for AcceptAnyClientCert {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        match x509_parser::parse_x509_certificate(end_entity) {
+            Ok(_) => Ok(ClientCertVerified::assertion()),
+            Err(_) => Err(CertificateError::BadEncoding.into()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, DnType, KeyPair};
+
+    #[test]
+    fn test_client_cert() {
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, "alice");
+        let cert = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+
+        let client_cert = ClientCert::from_der(cert.der());
+        assert_eq!(client_cert.common_name.as_deref(), Some("alice"));
+        assert_eq!(client_cert.hash.len(), "SHA256:".len() + 64);
+        assert!(client_cert.hash.starts_with("SHA256:"));
+        assert_eq!(client_cert, ClientCert::from_der(cert.der()));
+
+        let algorithms = crate::tls_store::crypto_provider().signature_verification_algorithms;
+        let verifier = AcceptAnyClientCert::new(algorithms);
+        let now = UnixTime::now();
+        assert!(verifier.verify_client_cert(cert.der(), &[], now).is_ok());
+        assert!(verifier
+            .verify_client_cert(&CertificateDer::from(vec![1, 2, 3]), &[], now)
+            .is_err());
+    }
+}
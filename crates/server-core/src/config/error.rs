@@ -0,0 +1,151 @@
+use std::fmt::Display;
+#[derive(Debug, Eq, PartialEq)]
+pub enum Error<'a> {
+    StringExpectedStartingQuote(&'a str),
+    StringExpectedEndingQuote(&'a str),
+    ExpectedIdentifier(&'a str),
+    InvalidNumber(&'a str),
+    InvalidQuantity(&'a str),
+    MissingEnvironmentVariable(&'a str),
+    ExpectedList(&'a str),
+    ExpectedListEnd(&'a str),
+    ExpectedSemicolon(&'a str),
+    /// What was expected, like `'{'`, and where.
+    Expected(&'static str, &'a str),
+    UnexpectedCharacter(&'a str),
+    MissingServerBlock,
+    InvalidBlockTag(String),
+    UnableToMaterializeStructure(&'a str),
+    InvalidRouteRegex(&'a str, String),
+    InvalidRouteBody(&'a str, String),
+    InvalidRouteScript(&'a str, String),
+    UnreadableRouteFile(&'a str, String),
+    InvalidPropertyValue(&'a str, &'a str),
+    UnreadableInclude(String, String),
+    /// An error in an included file, with its position in that file already formatted.
+    InvalidInclude(String, String),
+    IncludeTooDeep(String),
+}
+
+impl Error<'_> {
+    /// The 1-based `(line, column)` in `input` of the text the error points at, for errors
+    /// carrying a slice of `input`. Validation errors point into the owned config and have none.
+    pub fn position(&self, input: &str) -> Option<(usize, usize)> {
+        let slice = match self {
+            Error::StringExpectedStartingQuote(s)
+            | Error::StringExpectedEndingQuote(s)
+            | Error::ExpectedIdentifier(s)
+            | Error::InvalidNumber(s)
+            | Error::InvalidQuantity(s)
+            | Error::MissingEnvironmentVariable(s)
+            | Error::ExpectedList(s)
+            | Error::ExpectedListEnd(s)
+            | Error::ExpectedSemicolon(s)
+            | Error::Expected(_, s)
+            | Error::UnexpectedCharacter(s)
+            | Error::UnableToMaterializeStructure(s)
+            | Error::InvalidRouteRegex(s, _)
+            | Error::InvalidRouteBody(s, _)
+            | Error::InvalidRouteScript(s, _)
+            | Error::UnreadableRouteFile(s, _)
+            | Error::InvalidPropertyValue(_, s) => s,
+            _ => return None,
+        };
+
+        let offset = (slice.as_ptr() as usize)
+            .checked_sub(input.as_ptr() as usize)
+            .filter(|offset| *offset <= input.len())?;
+
+        let before = &input[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before[before.rfind('\n').map_or(0, |i| i + 1)..]
+            .chars()
+            .count()
+            + 1;
+
+        Some((line, column))
+    }
+
+    /// The error with its position and the offending line, or just the error when it has
+    /// no position:
+    ///
+    /// ```text
+    /// error at line 3, column 10: Invalid number: x
+    ///     port x;
+    ///          ^
+    /// ```
+    pub fn describe(&self, input: &str) -> String {
+        let Some((line, column)) = self.position(input) else {
+            return self.to_string();
+        };
+
+        let text = input.lines().nth(line - 1).unwrap_or_default();
+        // Keep tabs so the caret lines up with the text above it
+        let caret = text
+            .chars()
+            .take(column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+
+        format!(
+            "error at line {}, column {}: {}\n{}\n{}^",
+            line, column, self, text, caret
+        )
+    }
+}
+
+/// The token an error points at, rather than the whole rest of the input.
+fn token(s: &str) -> &str {
+    let end = s
+        .find(|c: char| c.is_whitespace() || ";{}[],".contains(c))
+        .unwrap_or(s.len());
+
+    match end {
+        0 => s
+            .chars()
+            .next()
+            .map_or("end of input", |c| &s[..c.len_utf8()]),
+        _ => &s[..end],
+    }
+}
+
+impl Display for Error<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::StringExpectedStartingQuote(i) => {
+                write!(f, "Expected starting quote, got: {}", token(i))
+            }
+            Error::StringExpectedEndingQuote(i) => {
+                write!(f, "Expected ending quote for: {}", token(i))
+            }
+            Error::ExpectedIdentifier(i) => write!(f, "Expected identifier, got: {}", token(i)),
+            Error::InvalidNumber(n) => write!(f, "Invalid number: {}", token(n)),
+            Error::MissingEnvironmentVariable(v) => {
+                write!(f, "Environment variable '{}' is not set", v)
+            }
+            Error::InvalidQuantity(q) => write!(f, "Invalid duration or size: {}", token(q)),
+            Error::ExpectedList(i) => write!(f, "Expected '[', got: {}", token(i)),
+            Error::ExpectedListEnd(i) => write!(f, "Expected ',' or ']', got: {}", token(i)),
+            Error::ExpectedSemicolon(i) => write!(f, "Expected ';', got: {}", token(i)),
+            Error::Expected(what, i) => write!(f, "Expected {}, got: {}", what, token(i)),
+            Error::UnexpectedCharacter(c) => write!(f, "Unexpected character: {}", c),
+            Error::MissingServerBlock => write!(f, "Missing server block"),
+            Error::InvalidBlockTag(t) => write!(f, "Invalid block tag: {}", t),
+            Error::UnableToMaterializeStructure(s) => {
+                write!(f, "Unable to materialize structure: {}", s)
+            }
+            Error::InvalidRouteRegex(r, e) => write!(f, "Invalid route regex '{}': {}", r, e),
+            Error::InvalidRouteBody(r, e) => write!(f, "Invalid gemtext body for '{}': {}", r, e),
+            Error::InvalidRouteScript(p, e) => write!(f, "Invalid script '{}': {}", p, e),
+            Error::UnreadableRouteFile(p, e) => {
+                write!(f, "Unable to read route file '{}': {}", p, e)
+            }
+            Error::InvalidPropertyValue(n, v) => {
+                write!(f, "Invalid value for property '{}': {}", n, v)
+            }
+            Error::UnreadableInclude(p, e) => write!(f, "Unable to read include '{}': {}", p, e),
+            Error::InvalidInclude(p, e) => write!(f, "{}: {}", p, e),
+            Error::IncludeTooDeep(p) => write!(f, "Includes nested too deep at: {}", p),
+        }
+    }
+}
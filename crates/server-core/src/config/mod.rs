@@ -0,0 +1,1223 @@
+use crate::config::{error::Error, parser::config};
+use crate::errors::Failure;
+use crate::feed::FeedFormat;
+use crate::files;
+use crate::routing::RoutePattern;
+use crate::scripting;
+use crate::template;
+use protocol::gemtext::parse_gemtext;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use url::Url;
+
+pub mod error;
+pub mod parser;
+
+pub type Properties = HashMap<String, Property>;
+pub type Result<'a, T> = std::result::Result<T, Error<'a>>;
+
+/// Properties a vhost takes from the server, and a route from its vhost, unless it sets
+/// them itself.
+const INHERITED_PROPERTIES: &[&str] = &[
+    "lang",
+    "charset",
+    "root",
+    "timeout",
+    "access_log",
+    "deny_files",
+    "deny_default_files",
+    "default_mime",
+    "default_charset",
+];
+
+/// A file including itself is cut off after this many levels.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Tag(pub String);
+
+/// A block as parsed, borrowing from the config text. Materialized into the owned
+/// [Server], [VHost] and [Route] structures once parsing is done.
+#[derive(Debug, Eq, PartialEq)]
+struct Block<'a> {
+    pub tag: &'a str,
+    pub properties: RawProperties<'a>,
+    pub children: Vec<Block<'a>>,
+}
+
+type RawProperties<'a> = HashMap<&'a str, RawProperty<'a>>;
+
+#[derive(Debug, Eq, PartialEq)]
+struct RawProperty<'a> {
+    name: &'a str,
+    value: RawValue<'a>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum RawValue<'a> {
+    String(&'a str),
+    /// A `"""` string, indented with the config.
+    Text(&'a str),
+    Number(u32),
+    Bool(bool),
+    Duration(Duration),
+    Size(u64),
+    List(Vec<RawValue<'a>>),
+}
+
+impl<'a> RawValue<'a> {
+    /// Repeating a property appends to it, `listen "a"; listen "b";` is `listen ["a", "b"];`.
+    fn append(&mut self, other: RawValue<'a>) {
+        let mut values = match std::mem::replace(self, RawValue::List(Vec::new())) {
+            RawValue::List(values) => values,
+            value => vec![value],
+        };
+
+        match other {
+            RawValue::List(other) => values.extend(other),
+            other => values.push(other),
+        }
+
+        *self = RawValue::List(values);
+    }
+
+    fn values(&self) -> Vec<&RawValue<'a>> {
+        match self {
+            RawValue::List(values) => values.iter().collect(),
+            value => vec![value],
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Server {
+    pub properties: Properties,
+    pub vhosts: Vec<VHost>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct VHost {
+    pub vhost: Tag,
+    pub properties: Properties,
+    pub routes: Vec<Route>,
+    pub robots: Option<Robots>,
+    pub errors: Option<ErrorResponses>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Route {
+    pub path: Tag,
+    pub pattern: RoutePattern,
+    pub properties: Properties,
+}
+
+/// `errors { not_found "Nothing here"; }`, see [crate::errors::Failure].
+#[derive(Debug, Eq, PartialEq)]
+pub struct ErrorResponses {
+    pub properties: Properties,
+}
+
+/// `robots { archiver { disallow "/private/ /drafts/"; } }`
+#[derive(Debug, Eq, PartialEq)]
+pub struct Robots {
+    pub agents: Vec<RobotsAgent>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct RobotsAgent {
+    /// One of the virtual agents from the Gemini robots.txt companion spec, `all` means `*`.
+    pub agent: Tag,
+    pub disallow: Vec<String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Value {
+    /// `"..."` with `\"`, `\\` and `\n` escapes, or a multi-line `"""..."""`
+    String(String),
+    Number(u32),
+    /// `on`/`true` or `off`/`false`
+    Bool(bool),
+    /// `250ms`, `30s`, `5m`, `2h` or `1d`
+    Duration(Duration),
+    /// `512B`, `64KB`, `10MB` or `1GB`, in bytes with 1024 based units
+    Size(u64),
+    /// `["a", "b"]` or a repeated property
+    List(Vec<Value>),
+}
+
+impl Value {
+    /// See [RawValue::append].
+    fn append(&mut self, other: Value) {
+        let mut values = match std::mem::replace(self, Value::List(Vec::new())) {
+            Value::List(values) => values,
+            value => vec![value],
+        };
+
+        match other {
+            Value::List(other) => values.extend(other),
+            other => values.push(other),
+        }
+
+        *self = Value::List(values);
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Property {
+    name: String,
+    value: Value,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Config {
+    pub server: Server,
+}
+
+/// The single value accessors see the last value of a repeated property.
+pub trait GetProperty {
+    fn get_property(&self, name: &str) -> Option<&Property>;
+
+    fn get_property_value(&self, name: &str) -> Option<&Value> {
+        self.get_property(name).and_then(|p| match &p.value {
+            Value::List(values) => values.last(),
+            value => Some(value),
+        })
+    }
+    fn get_property_string(&self, name: &str) -> Option<&str> {
+        self.get_property_value(name).and_then(|v| match v {
+            Value::String(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+    fn get_property_number(&self, name: &str) -> Option<u32> {
+        self.get_property_value(name).and_then(|v| match v {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        })
+    }
+    fn get_property_bool(&self, name: &str) -> Option<bool> {
+        self.get_property_value(name).and_then(|v| match v {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        })
+    }
+    /// A plain number is taken as seconds.
+    fn get_property_duration(&self, name: &str) -> Option<Duration> {
+        self.get_property_value(name).and_then(|v| match v {
+            Value::Duration(d) => Some(*d),
+            Value::Number(n) => Some(Duration::from_secs(*n as u64)),
+            _ => None,
+        })
+    }
+    /// A plain number is taken as bytes.
+    fn get_property_size(&self, name: &str) -> Option<u64> {
+        self.get_property_value(name).and_then(|v| match v {
+            Value::Size(s) => Some(*s),
+            Value::Number(n) => Some(*n as u64),
+            _ => None,
+        })
+    }
+    /// Every value of a repeated or `[...]` list property, a single value otherwise.
+    fn get_property_values(&self, name: &str) -> Vec<&Value> {
+        match self.get_property(name).map(|p| &p.value) {
+            Some(Value::List(values)) => values.iter().collect(),
+            Some(value) => vec![value],
+            None => Vec::new(),
+        }
+    }
+    fn get_property_strings(&self, name: &str) -> Vec<&str> {
+        self.get_property_values(name)
+            .into_iter()
+            .filter_map(|v| match v {
+                Value::String(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl GetProperty for Server {
+    fn get_property(&self, name: &str) -> Option<&Property> {
+        self.properties.get(name)
+    }
+}
+
+impl GetProperty for VHost {
+    fn get_property(&self, name: &str) -> Option<&Property> {
+        self.properties.get(name)
+    }
+}
+
+impl GetProperty for ErrorResponses {
+    fn get_property(&self, name: &str) -> Option<&Property> {
+        self.properties.get(name)
+    }
+}
+
+impl GetProperty for Route {
+    fn get_property(&self, name: &str) -> Option<&Property> {
+        self.properties.get(name)
+    }
+}
+
+impl GetProperty for Config {
+    fn get_property(&self, name: &str) -> Option<&Property> {
+        self.server.get_property(name)
+    }
+}
+
+impl From<&str> for Tag {
+    fn from(s: &str) -> Self {
+        Tag(s.to_string())
+    }
+}
+
+impl<'a> RawProperty<'a> {
+    /// The value of a property naming its block, like `hostname` or `path`.
+    fn tag(&self) -> Result<'a, &'a str> {
+        match self.value {
+            RawValue::String(s) => Ok(s),
+            _ => Err(Error::InvalidBlockTag(self.name.to_string())),
+        }
+    }
+}
+
+impl Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `\"`, `\\` and `\n` are escapes, any other backslash is kept as is so regexes like
+/// `\d+` don't need doubling.
+fn push_unescaped(out: &mut String, s: &str) {
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        let escaped = match chars.clone().next() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            _ => {
+                out.push('\\');
+                continue;
+            }
+        };
+
+        out.push(escaped);
+        chars.next();
+    }
+}
+
+/// Replaces `${NAME}` with the value of `lookup(NAME)`, `$${` is a literal `${`.
+/// Anything else, like the `${1}` of a regex capture, is left alone.
+///
+/// Escapes are expanded in the config text but not in the substituted values.
+fn expand_vars<'a>(value: &'a str, lookup: impl Fn(&str) -> Option<String>) -> Result<'a, String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            push_unescaped(&mut out, &rest[..start - 1]);
+            out.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        push_unescaped(&mut out, &rest[..start]);
+        let after = &rest[start + 2..];
+
+        match after.find('}').filter(|len| is_env_name(&after[..*len])) {
+            Some(len) => {
+                let name = &after[..len];
+                let var = lookup(name).ok_or(Error::MissingEnvironmentVariable(name))?;
+                out.push_str(&var);
+                rest = &after[len + 1..];
+            }
+            None => {
+                out.push_str("${");
+                rest = after;
+            }
+        }
+    }
+
+    push_unescaped(&mut out, rest);
+
+    Ok(out)
+}
+
+/// String values are copied out of the config text with escapes and `${ENV_VAR}`s expanded.
+fn expand_env(value: &str) -> Result<'_, String> {
+    expand_vars(value, |name| std::env::var(name).ok())
+}
+
+/// `"""` strings start on the line after the quotes and lose the indentation common to
+/// their non-blank lines, so multi-line bodies can be indented with the rest of the config.
+fn trim_indent(s: &str) -> String {
+    let s = s
+        .strip_prefix('\n')
+        .or_else(|| s.strip_prefix("\r\n"))
+        .unwrap_or(s);
+    // The closing quotes on their own line
+    let s = match s.rfind('\n') {
+        Some(idx) if s[idx + 1..].trim().is_empty() => &s[..idx + 1],
+        _ => s,
+    };
+
+    let blank = |line: &str| line.trim().is_empty();
+    let indent = s
+        .lines()
+        .filter(|line| !blank(line))
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+
+    s.split_inclusive('\n')
+        .map(|line| {
+            if blank(line) {
+                line.trim_start_matches([' ', '\t'])
+            } else {
+                &line[indent..]
+            }
+        })
+        .collect()
+}
+
+impl<'a> TryFrom<RawValue<'a>> for Value {
+    type Error = Error<'a>;
+
+    fn try_from(value: RawValue<'a>) -> Result<'a, Value> {
+        Ok(match value {
+            RawValue::String(s) => Value::String(expand_env(s)?),
+            RawValue::Text(s) => Value::String(trim_indent(&expand_env(s)?)),
+            RawValue::Number(n) => Value::Number(n),
+            RawValue::Bool(b) => Value::Bool(b),
+            RawValue::Duration(d) => Value::Duration(d),
+            RawValue::Size(s) => Value::Size(s),
+            RawValue::List(values) => Value::List(
+                values
+                    .into_iter()
+                    .map(Value::try_from)
+                    .collect::<Result<_>>()?,
+            ),
+        })
+    }
+}
+
+/// Copies the borrowed properties out of the config text.
+fn owned_properties(properties: RawProperties<'_>) -> Result<'_, Properties> {
+    properties
+        .into_iter()
+        .map(|(name, property)| {
+            let property = Property {
+                name: property.name.to_string(),
+                value: property.value.try_into()?,
+            };
+
+            Ok((name.to_string(), property))
+        })
+        .collect()
+}
+
+/// Included properties are appended as if repeated after the including block's own.
+fn merge_properties(properties: &mut Properties, other: Properties) {
+    for (name, property) in other {
+        match properties.entry(name) {
+            Entry::Occupied(mut entry) => entry.get_mut().value.append(property.value),
+            Entry::Vacant(entry) => {
+                entry.insert(property);
+            }
+        }
+    }
+}
+
+/// The files named by an `include`: every `*.cfg` file of a directory, the files matching
+/// a glob in the last path segment like `vhosts/*.cfg`, or a single file. Paths are relative
+/// to the working directory like every other path in the config, matches are in name order.
+fn include_paths(pattern: &str) -> std::io::Result<Vec<PathBuf>> {
+    let path = Path::new(pattern);
+    let glob = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| name.contains(['*', '?']));
+
+    let (dir, file_pattern) = if path.is_dir() {
+        (path, RoutePattern::new("*.cfg"))
+    } else if let Some(glob) = glob {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+
+        (dir.unwrap_or(Path::new(".")), RoutePattern::new(glob))
+    } else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let matches = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| file_pattern.matches(name));
+
+        if matches && entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Errors of an included file can't borrow its text, so they are formatted with their
+/// position before the text is dropped.
+fn include_file(path: &Path, depth: usize) -> Result<'static, Server> {
+    let name = path.display().to_string();
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(Error::IncludeTooDeep(name));
+    }
+
+    let input = std::fs::read_to_string(path)
+        .map_err(|e| Error::UnreadableInclude(name.clone(), e.to_string()))?;
+
+    parser::included(&input)
+        .and_then(|(properties, children)| {
+            let block = Block {
+                tag: "server",
+                properties,
+                children,
+            };
+
+            server_from_block(block, depth)
+        })
+        .map_err(|e| match e {
+            Error::UnreadableInclude(p, e) => Error::UnreadableInclude(p, e),
+            Error::InvalidInclude(p, e) => Error::InvalidInclude(p, e),
+            Error::IncludeTooDeep(p) => Error::IncludeTooDeep(p),
+            e => Error::InvalidInclude(name, e.describe(&input)),
+        })
+}
+
+/// `include "vhosts/*.cfg";` adds the properties and vhosts of other files to the server
+/// block, so every capsule can keep its own file. See [include_paths].
+fn server_from_block(mut block: Block<'_>, depth: usize) -> Result<'_, Server> {
+    let mut includes = Vec::new();
+    if let Some(include) = block.properties.remove("include") {
+        for value in include.value.values() {
+            match value {
+                RawValue::String(s) => includes.push(expand_env(s)?),
+                _ => return Err(Error::InvalidPropertyValue("include", block.tag)),
+            }
+        }
+    }
+
+    let mut properties = owned_properties(block.properties)?;
+    let mut vhosts = block
+        .children
+        .into_iter()
+        .filter(|b| b.tag == "vhost")
+        .map(VHost::try_from)
+        .collect::<Result<Vec<_>>>()?;
+
+    for pattern in includes {
+        let paths = include_paths(&pattern)
+            .map_err(|e| Error::UnreadableInclude(pattern.clone(), e.to_string()))?;
+
+        for path in paths {
+            let included = include_file(&path, depth + 1)?;
+
+            merge_properties(&mut properties, included.properties);
+            vhosts.extend(included.vhosts);
+        }
+    }
+
+    Ok(Server { properties, vhosts })
+}
+
+fn inherit(properties: &mut Properties, parent: &Properties) {
+    for name in INHERITED_PROPERTIES {
+        if let Some(property) = parent.get(*name) {
+            properties
+                .entry(name.to_string())
+                .or_insert_with(|| property.clone());
+        }
+    }
+}
+
+impl<'a> TryFrom<Block<'a>> for Server {
+    type Error = Error<'a>;
+
+    /// Inheritance happens once every include has been merged, see [INHERITED_PROPERTIES].
+    fn try_from(block: Block<'a>) -> Result<'a, Server> {
+        let mut server = server_from_block(block, 0)?;
+
+        for vhost in &mut server.vhosts {
+            inherit(&mut vhost.properties, &server.properties);
+
+            for route in &mut vhost.routes {
+                inherit(&mut route.properties, &vhost.properties);
+            }
+        }
+
+        Ok(server)
+    }
+}
+
+impl<'a> TryFrom<Block<'a>> for VHost {
+    type Error = Error<'a>;
+
+    fn try_from(block: Block<'a>) -> Result<'a, VHost> {
+        if block.tag != "vhost" {
+            return Err(Error::InvalidBlockTag(format!(
+                "Expected 'vhost', got '{}'",
+                block.tag
+            )));
+        }
+
+        let vhost = block
+            .properties
+            .get("hostname")
+            .ok_or(Error::UnableToMaterializeStructure(
+                "Missing 'hostname' property",
+            ))?;
+
+        let vhost = Tag(expand_env(vhost.tag()?)?);
+
+        let properties = owned_properties(block.properties)?;
+        let mut routes = Vec::new();
+        let mut robots = None;
+        let mut errors = None;
+
+        for child in block.children {
+            match child.tag {
+                "route" => routes.push(Route::try_from(child)?),
+                "robots" => robots = Some(Robots::try_from(child)?),
+                "errors" => {
+                    errors = Some(ErrorResponses {
+                        properties: owned_properties(child.properties)?,
+                    })
+                }
+                _ => {}
+            }
+        }
+
+        Ok(VHost {
+            vhost,
+            properties,
+            routes,
+            robots,
+            errors,
+        })
+    }
+}
+
+const ROBOTS_AGENTS: &[&str] = &["all", "archiver", "indexer", "researcher", "webproxy"];
+
+impl<'a> TryFrom<Block<'a>> for Robots {
+    type Error = Error<'a>;
+
+    fn try_from(block: Block<'a>) -> Result<'a, Robots> {
+        let agents = block
+            .children
+            .into_iter()
+            .map(|agent| {
+                if !ROBOTS_AGENTS.contains(&agent.tag) {
+                    return Err(Error::InvalidBlockTag(format!(
+                        "Expected one of {:?}, got '{}'",
+                        ROBOTS_AGENTS, agent.tag
+                    )));
+                }
+
+                let mut disallow = Vec::new();
+                if let Some(property) = agent.properties.get("disallow") {
+                    for value in property.value.values() {
+                        match value {
+                            RawValue::String(s) | RawValue::Text(s) => disallow
+                                .extend(expand_env(s)?.split_whitespace().map(str::to_string)),
+                            _ => return Err(Error::InvalidPropertyValue("disallow", agent.tag)),
+                        }
+                    }
+                }
+
+                Ok(RobotsAgent {
+                    agent: agent.tag.into(),
+                    disallow,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Robots { agents })
+    }
+}
+
+impl<'a> TryFrom<Block<'a>> for Route {
+    type Error = Error<'a>;
+
+    fn try_from(block: Block<'a>) -> Result<'a, Route> {
+        if block.tag != "route" {
+            return Err(Error::InvalidBlockTag(format!(
+                "Expected 'route', got '{}'",
+                block.tag
+            )));
+        }
+
+        let (path, pattern) = if let Some(path) = block.properties.get("path_regex") {
+            let path = path.tag()?;
+            let pattern = RoutePattern::new_regex(&expand_env(path)?)
+                .map_err(|e| Error::InvalidRouteRegex(path, e.to_string()))?;
+
+            (path, pattern)
+        } else if let Some(path) = block.properties.get("path_prefix") {
+            let path = path.tag()?;
+            let pattern = RoutePattern::Prefix(expand_env(path)?);
+
+            (path, pattern)
+        } else {
+            let path = block
+                .properties
+                .get("path")
+                .ok_or(Error::UnableToMaterializeStructure("missing 'path'"))?;
+            let path = path.tag()?;
+            let pattern = RoutePattern::new(&expand_env(path)?);
+
+            (path, pattern)
+        };
+
+        Ok(Route {
+            path: Tag(expand_env(path)?),
+            pattern,
+            properties: owned_properties(block.properties)?,
+        })
+    }
+}
+
+pub fn read_and_parse_config(conf_str: &str) -> Result<'_, Config> {
+    config(conf_str)
+}
+
+/// Bodies may be templates, so only the template syntax and the static gemtext are checked.
+fn validate_body(base: &Url, body: &str) -> std::result::Result<(), String> {
+    template::check(body).map_err(|e| e.to_string())?;
+    parse_gemtext(base, body.to_string()).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Catches mistakes the parser cannot before the first request is served: route bodies
+/// must be valid gemtext, route files and alias directories must exist and be readable, a
+/// favicon must be a single word (an emoji) and a hostname can't be served by two vhosts.
+pub fn validate_config(config: &Config) -> Result<'_, ()> {
+    let mut hostnames = HashSet::new();
+    for vhost in &config.server.vhosts {
+        if !hostnames.insert(vhost.vhost.0.as_str()) {
+            return Err(Error::InvalidPropertyValue("hostname", &vhost.vhost.0));
+        }
+    }
+
+    for vhost in &config.server.vhosts {
+        // An alternate hostname that is also served would never be redirected.
+        for name in vhost.get_property_strings("alternate_hostnames") {
+            if !hostnames.insert(name) {
+                return Err(Error::InvalidPropertyValue("alternate_hostnames", name));
+            }
+        }
+
+        let base = Url::parse(&format!("gemini://{}/", vhost.vhost))
+            .map_err(|e| Error::InvalidRouteBody(&vhost.vhost.0, e.to_string()))?;
+
+        if let Some(errors) = &vhost.errors {
+            for failure in [
+                Failure::NotFound,
+                Failure::ProxyRequestRefused,
+                Failure::BadRequest,
+            ] {
+                let name = format!("{}_body", failure.config_name().unwrap_or_default());
+
+                if let Some(body) = errors.get_property_string(&name) {
+                    validate_body(&base, body)
+                        .map_err(|e| Error::InvalidRouteBody(&vhost.vhost.0, e))?;
+                }
+            }
+        }
+
+        let extension = vhost.get_property_string("gmi_extension");
+        if files::GmiExtension::from_property(extension).is_none() {
+            return Err(Error::InvalidPropertyValue(
+                "gmi_extension",
+                extension.unwrap_or_default(),
+            ));
+        }
+
+        let slash = vhost.get_property_string("trailing_slash");
+        if files::TrailingSlash::from_property(slash).is_none() {
+            return Err(Error::InvalidPropertyValue(
+                "trailing_slash",
+                slash.unwrap_or_default(),
+            ));
+        }
+
+        if let Some(favicon) = vhost.get_property_string("favicon")
+            && (favicon.is_empty() || favicon.contains(char::is_whitespace))
+        {
+            return Err(Error::InvalidPropertyValue("favicon", favicon));
+        }
+
+        let aliases = files::aliases(vhost).ok_or(Error::InvalidPropertyValue(
+            "alias",
+            "expected pairs of a request prefix and a directory",
+        ))?;
+        for (prefix, dir) in aliases {
+            // Without the slash `/pics` would also take `/picsX`.
+            if !prefix.ends_with('/') {
+                return Err(Error::InvalidPropertyValue("alias", prefix));
+            }
+            std::fs::read_dir(dir).map_err(|e| Error::UnreadableRouteFile(dir, e.to_string()))?;
+        }
+
+        for route in &vhost.routes {
+            if let Some(body) = route.get_property_string("respond_body") {
+                validate_body(&base, body)
+                    .map_err(|e| Error::InvalidRouteBody(&route.path.0, e))?;
+            }
+
+            if let Some(file) = route.get_property_string("respond_file") {
+                let body = std::fs::read_to_string(file)
+                    .map_err(|e| Error::UnreadableRouteFile(file, e.to_string()))?;
+
+                validate_body(&base, &body).map_err(|e| Error::InvalidRouteBody(file, e))?;
+            }
+
+            if let Some(file) = route.get_property_string("script") {
+                let source = std::fs::read_to_string(file)
+                    .map_err(|e| Error::UnreadableRouteFile(file, e.to_string()))?;
+
+                scripting::check(&source)
+                    .map_err(|e| Error::InvalidRouteScript(file, e.to_string()))?;
+            }
+
+            if let Some(dir) = route.get_property_string("upload_directory") {
+                std::fs::read_dir(dir)
+                    .map_err(|e| Error::UnreadableRouteFile(dir, e.to_string()))?;
+            }
+
+            if let Some(format) = route.get_property_string("status_page")
+                && !matches!(format, "gemtext" | "prometheus" | "json")
+            {
+                return Err(Error::InvalidPropertyValue("status_page", format));
+            }
+
+            if let Some(dir) = route.get_property_string("feed_directory") {
+                std::fs::read_dir(dir)
+                    .map_err(|e| Error::UnreadableRouteFile(dir, e.to_string()))?;
+
+                let format = route.get_property_string("feed_format");
+                if FeedFormat::from_property(format).is_none() {
+                    return Err(Error::InvalidPropertyValue(
+                        "feed_format",
+                        format.unwrap_or_default(),
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::error::Error;
+    use crate::config::{read_and_parse_config, validate_config, GetProperty, Value};
+    use std::time::Duration;
+
+    #[test]
+    fn test_validate_config() {
+        let route = |props: &str| {
+            format!(
+                r#"
+server
+{{
+    vhost
+    {{
+        hostname "localhost";
+
+        route
+        {{
+            path "/index";
+            {props}
+        }}
+    }}
+}}
+    "#
+            )
+        };
+
+        let input = route(r#"respond_body "=> /about About";"#);
+        let config = read_and_parse_config(&input).unwrap();
+        assert_eq!(validate_config(&config), Ok(()));
+
+        let input = route(r#"respond_body "=> ";"#);
+        let config = read_and_parse_config(&input).unwrap();
+        assert!(matches!(
+            validate_config(&config),
+            Err(Error::InvalidRouteBody("/index", _))
+        ));
+
+        let input = route(r##"respond_body "# {{ nope }}";"##);
+        let config = read_and_parse_config(&input).unwrap();
+        assert!(matches!(
+            validate_config(&config),
+            Err(Error::InvalidRouteBody("/index", _))
+        ));
+
+        let input = route(r#"respond_file "does/not/exist.gmi";"#);
+        let config = read_and_parse_config(&input).unwrap();
+        assert!(matches!(
+            validate_config(&config),
+            Err(Error::UnreadableRouteFile("does/not/exist.gmi", _))
+        ));
+
+        let input = route(r#"script "does/not/exist.rhai";"#);
+        let config = read_and_parse_config(&input).unwrap();
+        assert!(matches!(
+            validate_config(&config),
+            Err(Error::UnreadableRouteFile("does/not/exist.rhai", _))
+        ));
+
+        let input = r#"server { vhost { hostname "localhost"; favicon "🚀 🌍"; } }"#;
+        let config = read_and_parse_config(input).unwrap();
+        assert_eq!(
+            validate_config(&config),
+            Err(Error::InvalidPropertyValue("favicon", "🚀 🌍"))
+        );
+
+        let input = r#"server { vhost { hostname "localhost"; alternate_hostnames ["www.localhost", "127.0.0.1"]; } }"#;
+        let config = read_and_parse_config(input).unwrap();
+        assert_eq!(validate_config(&config), Ok(()));
+
+        let input = r#"server { vhost { hostname "a.example"; alternate_hostnames "b.example"; } vhost { hostname "b.example"; } }"#;
+        let config = read_and_parse_config(input).unwrap();
+        assert_eq!(
+            validate_config(&config),
+            Err(Error::InvalidPropertyValue(
+                "alternate_hostnames",
+                "b.example"
+            ))
+        );
+
+        let input = r#"server { vhost { hostname "a.example"; } vhost { hostname "a.example"; } }"#;
+        let config = read_and_parse_config(input).unwrap();
+        assert_eq!(
+            validate_config(&config),
+            Err(Error::InvalidPropertyValue("hostname", "a.example"))
+        );
+
+        let input = r#"server { vhost { hostname "localhost"; gmi_extension "strip"; trailing_slash "sometimes"; } }"#;
+        let config = read_and_parse_config(input).unwrap();
+        assert_eq!(
+            validate_config(&config),
+            Err(Error::InvalidPropertyValue("trailing_slash", "sometimes"))
+        );
+
+        let input = r#"server { vhost { hostname "localhost"; alias "/pics" "/tmp"; } }"#;
+        let config = read_and_parse_config(input).unwrap();
+        assert_eq!(
+            validate_config(&config),
+            Err(Error::InvalidPropertyValue("alias", "/pics"))
+        );
+    }
+
+    #[test]
+    fn test_error_position() {
+        let input = r#"server
+{
+    vhost
+    {
+        hostname "localhost";
+        route { path_regex "(["; }
+    }
+}"#;
+        let error = read_and_parse_config(input).unwrap_err();
+        assert!(matches!(error, Error::InvalidRouteRegex("([", _)));
+        assert_eq!(error.position(input), Some((6, 29)));
+
+        let input = "server\n{\n    port x;\n}";
+        let error = read_and_parse_config(input).unwrap_err();
+        assert_eq!(error.position(input), Some((3, 10)));
+        assert_eq!(
+            error.describe(input),
+            "error at line 3, column 10: Expected a value, got: x\n    port x;\n         ^"
+        );
+
+        let input = "server\n{\n    port 1965\n    vhost { hostname \"localhost\"; }\n}";
+        let error = read_and_parse_config(input).unwrap_err();
+        assert_eq!(
+            error.describe(input),
+            "error at line 4, column 5: Expected ';', got: vhost\n    vhost { hostname \"localhost\"; }\n    ^"
+        );
+
+        assert_eq!(Error::InvalidNumber("elsewhere").position(input), None);
+    }
+
+    #[test]
+    fn test_config_outlives_input() {
+        let input = String::from(r#"server { port 1965; vhost { hostname "localhost"; } }"#);
+        let config = read_and_parse_config(&input).unwrap();
+        drop(input);
+
+        assert_eq!(config.get_property_number("port"), Some(1965));
+        assert_eq!(config.server.vhosts[0].vhost.to_string(), "localhost");
+    }
+
+    #[test]
+    fn test_bool_property() {
+        let input = r#"server { port 1965; autoindex on; require_cert false; vhost { hostname "localhost"; } }"#;
+        let config = read_and_parse_config(input).unwrap();
+
+        assert_eq!(config.get_property_bool("autoindex"), Some(true));
+        assert_eq!(config.get_property_bool("require_cert"), Some(false));
+        assert_eq!(config.get_property_bool("port"), None);
+        assert_eq!(config.get_property_number("port"), Some(1965));
+    }
+
+    #[test]
+    fn test_repeated_property() {
+        let input = r#"
+server
+{
+    listen "127.0.0.1:1965";
+    listen ["[::1]:1965", "0.0.0.0:1966"];
+    port 1965;
+    port 1966;
+
+    vhost
+    {
+        hostname "localhost";
+
+        robots { all { disallow "/a/ /b/"; disallow ["/c/"]; } }
+    }
+}
+    "#;
+        let config = read_and_parse_config(input).unwrap();
+
+        assert_eq!(
+            config.get_property_strings("listen"),
+            vec!["127.0.0.1:1965", "[::1]:1965", "0.0.0.0:1966"]
+        );
+        assert_eq!(config.get_property_number("port"), Some(1966));
+        assert_eq!(config.get_property_values("nope"), Vec::<&Value>::new());
+
+        let robots = config.server.vhosts[0].robots.as_ref().unwrap();
+        assert_eq!(robots.agents[0].disallow, vec!["/a/", "/b/", "/c/"]);
+    }
+
+    #[test]
+    fn test_quantity_property() {
+        let input = r#"server { timeout 30s; max_upload_size 10MB; port 1965; vhost { hostname "localhost"; } }"#;
+        let config = read_and_parse_config(input).unwrap();
+
+        assert_eq!(
+            config.get_property_duration("timeout"),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            config.get_property_size("max_upload_size"),
+            Some(10 * 1024 * 1024)
+        );
+        assert_eq!(
+            config.get_property_duration("port"),
+            Some(Duration::from_secs(1965))
+        );
+        assert_eq!(config.get_property_size("timeout"), None);
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let input = r#"
+server
+{
+    vhost
+    {
+        hostname "localhost";
+
+        route { path "/quote"; respond_body "> \"Hi\"\nC:\\gemini\\"; }
+        route { path_regex "^/(\d+)$"; respond_body "digits"; }
+        route
+        {
+            path "/multi";
+            respond_body """
+                # Multi-line
+
+                => /quote "Quoted"
+                    indented
+                """;
+        }
+    }
+}
+    "#;
+        let config = read_and_parse_config(input).unwrap();
+        let body = |idx: usize| {
+            config.server.vhosts[0].routes[idx]
+                .get_property_string("respond_body")
+                .unwrap()
+        };
+
+        assert_eq!(body(0), "> \"Hi\"\nC:\\gemini\\");
+        assert_eq!(config.server.vhosts[0].routes[1].path.0, r"^/(\d+)$");
+        assert_eq!(
+            body(2),
+            "# Multi-line\n\n=> /quote \"Quoted\"\n    indented\n"
+        );
+    }
+
+    #[test]
+    fn test_inherited_properties() {
+        let input = r#"
+server
+{
+    lang "en";
+    charset "utf-8";
+    port 1965;
+
+    vhost
+    {
+        hostname "localhost";
+        lang "de";
+
+        route { path "/"; respond_body "Hallo"; }
+        route { path "/ascii"; charset "us-ascii"; respond_body "Hallo"; }
+    }
+}
+    "#;
+        let config = read_and_parse_config(input).unwrap();
+        let vhost = &config.server.vhosts[0];
+
+        assert_eq!(vhost.get_property_string("lang"), Some("de"));
+        assert_eq!(vhost.get_property_string("charset"), Some("utf-8"));
+        assert_eq!(vhost.get_property_number("port"), None);
+
+        let route = |idx: usize, name: &str| vhost.routes[idx].get_property_string(name);
+        assert_eq!(route(0, "lang"), Some("de"));
+        assert_eq!(route(0, "charset"), Some("utf-8"));
+        assert_eq!(route(1, "charset"), Some("us-ascii"));
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("gemini-include-{}", std::process::id()));
+        let write = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, contents).unwrap();
+            path.display().to_string()
+        };
+
+        write("a.cfg", r#"listen "a"; vhost { hostname "a.example"; }"#);
+        write("b.cfg", r#"vhost { hostname "b.example"; }"#);
+        write("notes.txt", "not a config");
+        let bad = write("bad/bad.cfg", "port 1965;\nport x;");
+        let looped = dir.join("loop/loop.cfg").display().to_string();
+        write("loop/loop.cfg", &format!(r#"include "{looped}";"#));
+
+        let config = |include: &str| {
+            format!(
+                r#"server {{ listen "main"; include "{include}"; vhost {{ hostname "main.example"; }} }}"#
+            )
+        };
+        let hostnames = |config: &super::Config| {
+            config
+                .server
+                .vhosts
+                .iter()
+                .map(|v| v.vhost.0.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let input = config(&dir.display().to_string());
+        let parsed = read_and_parse_config(&input).unwrap();
+        assert_eq!(
+            hostnames(&parsed),
+            vec!["main.example", "a.example", "b.example"]
+        );
+        assert_eq!(parsed.get_property_strings("listen"), vec!["main", "a"]);
+
+        let input = config(&dir.join("b*.cfg").display().to_string());
+        let parsed = read_and_parse_config(&input).unwrap();
+        assert_eq!(hostnames(&parsed), vec!["main.example", "b.example"]);
+
+        let input = config(&bad);
+        assert_eq!(
+            read_and_parse_config(&input),
+            Err(Error::InvalidInclude(
+                bad.clone(),
+                "error at line 2, column 6: Expected a value, got: x\nport x;\n     ^".to_string()
+            ))
+        );
+
+        let input = config(&looped);
+        assert_eq!(
+            read_and_parse_config(&input),
+            Err(Error::IncludeTooDeep(looped.clone()))
+        );
+
+        let input = config("does/not/exist.cfg");
+        assert!(matches!(
+            read_and_parse_config(&input),
+            Err(Error::UnreadableInclude(..))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_vars() {
+        let lookup = |name: &str| match name {
+            "ROOT" => Some("/srv/gemini".to_string()),
+            "HOST" => Some("example.org".to_string()),
+            _ => None,
+        };
+
+        let cases = vec![
+            ("no vars", Ok("no vars".to_string())),
+            ("${ROOT}/index.gmi", Ok("/srv/gemini/index.gmi".to_string())),
+            ("${HOST}:${ROOT}", Ok("example.org:/srv/gemini".to_string())),
+            ("/people/${2}.gmi", Ok("/people/${2}.gmi".to_string())),
+            (
+                "$${HOST} ${ unterminated",
+                Ok("${HOST} ${ unterminated".to_string()),
+            ),
+            ("cost: $5", Ok("cost: $5".to_string())),
+            (r#"\"${HOST}\"\n"#, Ok("\"example.org\"\n".to_string())),
+            ("${NOPE}", Err(Error::MissingEnvironmentVariable("NOPE"))),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(super::expand_vars(input, lookup), expected, "{input}");
+        }
+
+        let input = r#"server { port 1965; vhost { hostname "${GEMINI_SURELY_UNSET_VAR}"; } }"#;
+        let error = read_and_parse_config(input).unwrap_err();
+        assert_eq!(
+            error,
+            Error::MissingEnvironmentVariable("GEMINI_SURELY_UNSET_VAR")
+        );
+        assert_eq!(error.position(input), Some((1, 41)));
+    }
+}
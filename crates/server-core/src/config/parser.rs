@@ -0,0 +1,686 @@
+use crate::config::{
+    error::Error, Block, Config, RawProperties, RawProperty, RawValue, Result, Server,
+};
+use std::collections::hash_map::Entry;
+use std::iter::Peekable;
+use std::time::Duration;
+use std::vec;
+
+const TRIPLE_QUOTE: &str = r#"""""#;
+
+/// Everything else is an identifier, a string or a number.
+const PUNCTUATION: &[char] = &['{', '}', '[', ']', ',', ';'];
+
+/// Milliseconds per duration unit.
+const DURATION_UNITS: &[(&str, u64)] = &[
+    ("ms", 1),
+    ("s", 1000),
+    ("m", 60 * 1000),
+    ("h", 60 * 60 * 1000),
+    ("d", 24 * 60 * 60 * 1000),
+];
+
+/// Bytes per size unit.
+const SIZE_UNITS: &[(&str, u64)] = &[
+    ("B", 1),
+    ("KB", 1024),
+    ("MB", 1024 * 1024),
+    ("GB", 1024 * 1024 * 1024),
+];
+
+#[derive(Debug, Eq, PartialEq)]
+enum TokenKind<'a> {
+    Ident(&'a str),
+    /// A string, number, duration or size.
+    Value(RawValue<'a>),
+    Punct(char),
+}
+
+/// A token and the config text starting at it, which errors point at.
+#[derive(Debug, Eq, PartialEq)]
+struct Token<'a> {
+    kind: TokenKind<'a>,
+    at: &'a str,
+}
+
+/// Skips whitespace and `#` comments.
+fn skip_trivia(mut i: &str) -> &str {
+    loop {
+        i = i.trim_start();
+        match i.strip_prefix('#') {
+            Some(comment) => i = comment.find('\n').map_or("", |end| &comment[end..]),
+            None => return i,
+        }
+    }
+}
+
+/// ident = ( alpha | "_" ) { alnum | "_" }
+fn ident(i: &str) -> Result<'_, (&str, &str)> {
+    let end = i
+        .char_indices()
+        .find(|&(idx, c)| !(c.is_alphabetic() || c == '_' || (idx > 0 && c.is_alphanumeric())))
+        .map_or(i.len(), |(idx, _)| idx);
+
+    match end {
+        0 => Err(Error::ExpectedIdentifier(i)),
+        _ => Ok((&i[end..], &i[..end])),
+    }
+}
+
+/// string = '"' { char | "\\" char } '"' | '"""' { char } '"""'
+///
+/// The escapes themselves are expanded when the config is materialized.
+fn string(i: &str) -> Result<'_, (&str, RawValue<'_>)> {
+    if let Some(inner) = i.strip_prefix(TRIPLE_QUOTE) {
+        let end = inner
+            .find(TRIPLE_QUOTE)
+            .ok_or(Error::StringExpectedEndingQuote(i))?;
+
+        return Ok((
+            inner[end + TRIPLE_QUOTE.len()..].trim_start(),
+            RawValue::Text(&inner[..end]),
+        ));
+    }
+
+    if !i.starts_with('"') {
+        return Err(Error::StringExpectedStartingQuote(i));
+    }
+
+    // Skip the first quote
+    let mut escaped = false;
+    let end = i
+        .char_indices()
+        .skip(1)
+        .find(|&(_, c)| {
+            let end = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            end
+        })
+        .map(|(idx, _)| idx)
+        .ok_or(Error::StringExpectedEndingQuote(i))?;
+
+    Ok((i[end + 1..].trim_start(), RawValue::String(&i[1..end])))
+}
+
+fn number(i: &str) -> Result<'_, (&str, RawValue<'_>)> {
+    let chars = i.chars();
+    let mut number_len = 0;
+    for c in chars {
+        if c.is_numeric() {
+            number_len += 1;
+        } else {
+            break;
+        }
+    }
+
+    if number_len == 0 {
+        return Err(Error::InvalidNumber(i.trim()));
+    }
+
+    let number_str = &i[..number_len];
+    let number = number_str
+        .parse()
+        .map_err(|_| Error::InvalidNumber(number_str))?;
+
+    Ok((&i[number_len..], RawValue::Number(number)))
+}
+
+/// quantity = digit { digit } ( "ms" | "s" | "m" | "h" | "d" | "B" | "KB" | "MB" | "GB" )
+fn quantity(i: &str) -> Result<'_, (&str, RawValue<'_>)> {
+    let digits = i.find(|c: char| !c.is_ascii_digit()).unwrap_or(i.len());
+    let end = i[digits..]
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .map_or(i.len(), |len| digits + len);
+
+    if digits == 0 || end == digits {
+        return Err(Error::InvalidQuantity(i.trim()));
+    }
+
+    let (number, unit, rest) = (&i[..digits], &i[digits..end], &i[end..]);
+    let invalid = || Error::InvalidQuantity(&i[..end]);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let scaled = |units: &[(&str, u64)]| {
+        units
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .map(|(_, factor)| number.checked_mul(*factor).ok_or_else(invalid))
+    };
+
+    if let Some(ms) = scaled(DURATION_UNITS) {
+        return Ok((rest, RawValue::Duration(Duration::from_millis(ms?))));
+    }
+    if let Some(bytes) = scaled(SIZE_UNITS) {
+        return Ok((rest, RawValue::Size(bytes?)));
+    }
+
+    Err(invalid())
+}
+
+/// A number, or a duration or size when a unit follows the digits without a space.
+fn numeric(i: &str) -> Result<'_, (&str, RawValue<'_>)> {
+    let end = i
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(i.len());
+    let (word, rest) = i.split_at(end);
+
+    let (left, value) = if word.bytes().all(|b| b.is_ascii_digit()) {
+        number(word)?
+    } else {
+        quantity(word)?
+    };
+
+    if !left.is_empty() {
+        return Err(Error::InvalidQuantity(word));
+    }
+
+    Ok((rest, value))
+}
+
+/// Splits the config text into tokens, skipping whitespace and `#` comments.
+fn tokenize(i: &str) -> Result<'_, Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    let mut i = skip_trivia(i);
+
+    while let Some(c) = i.chars().next() {
+        let (rest, kind) = if PUNCTUATION.contains(&c) {
+            (&i[1..], TokenKind::Punct(c))
+        } else if c == '"' {
+            let (rest, value) = string(i)?;
+            (rest, TokenKind::Value(value))
+        } else if c.is_ascii_digit() {
+            let (rest, value) = numeric(i)?;
+            (rest, TokenKind::Value(value))
+        } else if c.is_alphabetic() || c == '_' {
+            let (rest, name) = ident(i)?;
+            (rest, TokenKind::Ident(name))
+        } else {
+            return Err(Error::UnexpectedCharacter(&i[..c.len_utf8()]));
+        };
+
+        tokens.push(Token { kind, at: i });
+        i = skip_trivia(rest);
+    }
+
+    Ok(tokens)
+}
+
+/// The tokens of one config text, consumed front to back by the parsers below.
+struct Tokens<'a> {
+    tokens: Peekable<vec::IntoIter<Token<'a>>>,
+    /// The empty end of the config text, where errors about missing tokens point.
+    end: &'a str,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(i: &'a str) -> Result<'a, Self> {
+        Ok(Tokens {
+            tokens: tokenize(i)?.into_iter().peekable(),
+            end: &i[i.len()..],
+        })
+    }
+
+    fn peek(&mut self) -> Option<&TokenKind<'a>> {
+        self.tokens.peek().map(|t| &t.kind)
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        self.tokens.next()
+    }
+
+    /// The config text at the next token, or its end.
+    fn at(&mut self) -> &'a str {
+        self.tokens.peek().map_or(self.end, |t| t.at)
+    }
+
+    /// Takes the next token if it is the punctuation `c`.
+    fn punct(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(&TokenKind::Punct(c));
+        if found {
+            self.next();
+        }
+
+        found
+    }
+}
+
+/// boolean = "on" | "off" | "true" | "false"
+fn boolean(word: &str) -> Option<bool> {
+    match word {
+        "on" | "true" => Some(true),
+        "off" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn starts_scalar(token: Option<&TokenKind>) -> bool {
+    match token {
+        Some(TokenKind::Value(_)) => true,
+        Some(TokenKind::Ident(word)) => boolean(word).is_some(),
+        _ => false,
+    }
+}
+
+/// scalar = string | number | quantity | boolean
+fn scalar<'a>(tokens: &mut Tokens<'a>) -> Result<'a, RawValue<'a>> {
+    let at = tokens.at();
+
+    match tokens.next().map(|t| t.kind) {
+        Some(TokenKind::Value(value)) => Ok(value),
+        Some(TokenKind::Ident(word)) if let Some(b) = boolean(word) => Ok(RawValue::Bool(b)),
+        _ => Err(Error::Expected("a value", at)),
+    }
+}
+
+/// list = "[" [ scalar { "," scalar } [ "," ] ] "]"
+fn list<'a>(tokens: &mut Tokens<'a>) -> Result<'a, RawValue<'a>> {
+    if !tokens.punct('[') {
+        return Err(Error::ExpectedList(tokens.at()));
+    }
+
+    let mut values = Vec::new();
+    while !tokens.punct(']') {
+        values.push(scalar(tokens)?);
+
+        if !tokens.punct(',') && tokens.peek() != Some(&TokenKind::Punct(']')) {
+            return Err(Error::ExpectedListEnd(tokens.at()));
+        }
+    }
+
+    Ok(RawValue::List(values))
+}
+
+/// property = ident ( list | scalar { scalar } ) ";"
+///
+/// Several scalars are a list, `alias "/pics/" "/mnt/photos/";` is
+/// `alias ["/pics/", "/mnt/photos/"];`.
+fn property_with_name<'a>(tokens: &mut Tokens<'a>, name: &'a str) -> Result<'a, RawProperty<'a>> {
+    let value = match tokens.peek() {
+        Some(TokenKind::Punct('[')) => list(tokens)?,
+        _ => {
+            let mut values = vec![scalar(tokens)?];
+            while starts_scalar(tokens.peek()) {
+                values.push(scalar(tokens)?);
+            }
+
+            match values.len() {
+                1 => values.remove(0),
+                _ => RawValue::List(values),
+            }
+        }
+    };
+
+    if !tokens.punct(';') {
+        return Err(Error::ExpectedSemicolon(tokens.at()));
+    }
+
+    Ok(RawProperty { name, value })
+}
+
+/// block = ident "{" { property | block } "}"
+fn block_with_tag<'a>(tokens: &mut Tokens<'a>, tag: &'a str) -> Result<'a, Block<'a>> {
+    if !tokens.punct('{') {
+        return Err(Error::Expected("'{'", tokens.at()));
+    }
+
+    let (properties, children) = properties_and_blocks(tokens)?;
+
+    if !tokens.punct('}') {
+        return Err(Error::Expected("'}'", tokens.at()));
+    }
+
+    Ok(Block {
+        tag,
+        properties,
+        children,
+    })
+}
+
+/// The properties and blocks up to the closing `}` or the end of the config text.
+fn properties_and_blocks<'a>(
+    tokens: &mut Tokens<'a>,
+) -> Result<'a, (RawProperties<'a>, Vec<Block<'a>>)> {
+    let mut props = RawProperties::new();
+    let mut blocks = Vec::new();
+
+    loop {
+        let at = tokens.at();
+        let name = match tokens.peek() {
+            None | Some(TokenKind::Punct('}')) => break,
+            Some(TokenKind::Ident(name)) => *name,
+            Some(_) => return Err(Error::ExpectedIdentifier(at)),
+        };
+        tokens.next();
+
+        if tokens.peek() == Some(&TokenKind::Punct('{')) {
+            blocks.push(block_with_tag(tokens, name)?);
+            continue;
+        }
+
+        let property = property_with_name(tokens, name)?;
+        match props.entry(property.name) {
+            Entry::Occupied(mut entry) => entry.get_mut().value.append(property.value),
+            Entry::Vacant(entry) => {
+                entry.insert(property);
+            }
+        }
+    }
+
+    Ok((props, blocks))
+}
+
+/// An included file is the inside of the block it is included into, without the braces.
+pub(super) fn included(i: &str) -> Result<'_, (RawProperties<'_>, Vec<Block<'_>>)> {
+    let mut tokens = Tokens::new(i)?;
+    let parsed = properties_and_blocks(&mut tokens)?;
+
+    match tokens.next() {
+        Some(token) => Err(Error::ExpectedIdentifier(token.at)),
+        None => Ok(parsed),
+    }
+}
+
+/// config = "server" "{" { property | block } "}"
+pub(super) fn config(i: &str) -> Result<'_, Config> {
+    let mut tokens = Tokens::new(i)?;
+
+    let at = tokens.at();
+    match tokens.next().map(|t| t.kind) {
+        Some(TokenKind::Ident("server")) => {}
+        None => return Err(Error::MissingServerBlock),
+        Some(_) => return Err(Error::Expected("'server'", at)),
+    }
+
+    let block = block_with_tag(&mut tokens, "server")?;
+
+    if tokens.peek().is_some() {
+        return Err(Error::Expected("the end of the config", tokens.at()));
+    }
+
+    Ok(Config {
+        server: Server::try_from(block)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::error::Error::*;
+    use crate::config::parser::RawValue;
+    use crate::config::{read_and_parse_config, GetProperty};
+
+    #[test]
+    fn test_file() {
+        let input = r#"
+server
+{
+    port 1965;
+
+    vhost
+    {
+        hostname  "localhost";
+        tls_cert  "cert.pem";
+        tls_key   "key.key";
+
+        route
+        {
+            path         "/index";
+            respond_body "=> Hello, World!";
+        }
+    }
+}
+    "#;
+
+        let config = read_and_parse_config(input);
+        assert!(config.is_ok());
+    }
+
+    #[test]
+    fn test_string() {
+        let cases = vec![
+            ("hello", Err(StringExpectedStartingQuote("hello"))),
+            (r#"hello""#, Err(StringExpectedStartingQuote("hello\""))),
+            (r#""hello"world"#, Ok(("world", RawValue::String("hello")))),
+            (
+                r#""unterminated"#,
+                Err(StringExpectedEndingQuote("\"unterminated")),
+            ),
+            ("\"", Err(StringExpectedEndingQuote("\""))),
+            ("''", Err(StringExpectedStartingQuote("''"))),
+            (r#""""#, Ok(("", RawValue::String("")))),
+            ("", Err(StringExpectedStartingQuote(""))),
+            (" ", Err(StringExpectedStartingQuote(" "))),
+            (r#"42"#, Err(StringExpectedStartingQuote("42"))),
+            (
+                r#""say \"hi\"";"#,
+                Ok((";", RawValue::String(r#"say \"hi\""#))),
+            ),
+            (r#""a\\" "b""#, Ok((r#""b""#, RawValue::String(r#"a\\"#)))),
+            (r#""a\""#, Err(StringExpectedEndingQuote(r#""a\""#))),
+            (r#""héllo" ;"#, Ok((";", RawValue::String("héllo")))),
+            (
+                "\"\"\"\n# \"Hi\"\n\"\"\";",
+                Ok((";", RawValue::Text("\n# \"Hi\"\n"))),
+            ),
+            (
+                "\"\"\"unterminated\"\"",
+                Err(StringExpectedEndingQuote("\"\"\"unterminated\"\"")),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(super::string(input), expected);
+        }
+    }
+
+    #[test]
+    fn test_number() {
+        let cases = vec![
+            ("hello", Err(InvalidNumber("hello"))),
+            ("42", Ok(("", RawValue::Number(42)))),
+            ("42 ", Ok((" ", RawValue::Number(42)))),
+            ("42hello", Ok(("hello", RawValue::Number(42)))),
+            ("42.0", Ok((".0", RawValue::Number(42)))),
+            ("42.0 ", Ok((".0 ", RawValue::Number(42)))),
+            ("42.0hello", Ok((".0hello", RawValue::Number(42)))),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(super::number(input), expected);
+        }
+    }
+
+    #[test]
+    fn test_boolean() {
+        let cases = vec![
+            ("on;", Ok(RawValue::Bool(true))),
+            ("true ;", Ok(RawValue::Bool(true))),
+            ("off;", Ok(RawValue::Bool(false))),
+            ("false", Ok(RawValue::Bool(false))),
+            ("yes;", Err(Expected("a value", "yes;"))),
+            ("online;", Err(Expected("a value", "online;"))),
+            ("\"on\";", Ok(RawValue::String("on"))),
+        ];
+
+        for (input, expected) in cases {
+            let mut tokens = super::Tokens::new(input).unwrap();
+            assert_eq!(super::scalar(&mut tokens), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_quantity() {
+        use super::RawValue::{Duration, Size};
+        use std::time::Duration as D;
+
+        let cases = vec![
+            ("30s;", Ok((";", Duration(D::from_secs(30))))),
+            ("250ms", Ok(("", Duration(D::from_millis(250))))),
+            ("5m ;", Ok((" ;", Duration(D::from_secs(5 * 60))))),
+            ("2h", Ok(("", Duration(D::from_secs(2 * 60 * 60))))),
+            ("1d", Ok(("", Duration(D::from_secs(24 * 60 * 60))))),
+            ("10MB;", Ok((";", Size(10 * 1024 * 1024)))),
+            ("512B", Ok(("", Size(512)))),
+            ("42", Err(InvalidQuantity("42"))),
+            ("s", Err(InvalidQuantity("s"))),
+            ("10mb;", Err(InvalidQuantity("10mb"))),
+            (
+                "99999999999999999999GB",
+                Err(InvalidQuantity("99999999999999999999GB")),
+            ),
+            ("99999999999GB", Err(InvalidQuantity("99999999999GB"))),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(super::quantity(input), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_list() {
+        use super::RawValue::{Bool, List, Number, String};
+
+        let cases = vec![
+            ("[]", Ok(List(vec![]))),
+            (
+                r#"["a", 1 , on,]; "#,
+                Ok(List(vec![String("a"), Number(1), Bool(true)])),
+            ),
+            (r#"[ "a" "b" ]"#, Err(ExpectedListEnd(r#""b" ]"#))),
+            (r#"["a""#, Err(ExpectedListEnd(""))),
+            (r#"[,]"#, Err(Expected("a value", ",]"))),
+            (r#""a""#, Err(ExpectedList(r#""a""#))),
+        ];
+
+        for (input, expected) in cases {
+            let mut tokens = super::Tokens::new(input).unwrap();
+            assert_eq!(super::list(&mut tokens), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_semicolon() {
+        let property = |value| {
+            Ok(super::RawProperty {
+                name: "hello",
+                value,
+            })
+        };
+
+        let cases = vec![
+            ("1234", Err(ExpectedSemicolon(""))),
+            ("1234 vhost", Err(ExpectedSemicolon("vhost"))),
+            ("1234;asd", property(RawValue::Number(1234))),
+            ("4567 ;", property(RawValue::Number(4567))),
+            ("8910 ; ", property(RawValue::Number(8910))),
+            (
+                r#""a" 2 off;"#,
+                property(RawValue::List(vec![
+                    RawValue::String("a"),
+                    RawValue::Number(2),
+                    RawValue::Bool(false),
+                ])),
+            ),
+            (r#""a" "b""#, Err(ExpectedSemicolon(""))),
+            (";", Err(Expected("a value", ";"))),
+        ];
+
+        for (input, expected) in cases {
+            let mut tokens = super::Tokens::new(input).unwrap();
+            assert_eq!(
+                super::property_with_name(&mut tokens, "hello"),
+                expected,
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_tokenize() {
+        use super::TokenKind::{Ident, Punct, Value};
+        use std::time::Duration;
+
+        let kinds = |input| {
+            super::tokenize(input).map(|tokens| {
+                tokens
+                    .into_iter()
+                    .map(|token| token.kind)
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(
+            kinds("route{path\"/a\";timeout 30s;} # done"),
+            Ok(vec![
+                Ident("route"),
+                Punct('{'),
+                Ident("path"),
+                Value(RawValue::String("/a")),
+                Punct(';'),
+                Ident("timeout"),
+                Value(RawValue::Duration(Duration::from_secs(30))),
+                Punct(';'),
+                Punct('}'),
+            ])
+        );
+        assert_eq!(
+            kinds("tls_cert2 # a comment with \"quotes\n\t;"),
+            Ok(vec![Ident("tls_cert2"), Punct(';')])
+        );
+        assert_eq!(kinds("port 42hello;"), Err(InvalidQuantity("42hello")));
+        assert_eq!(kinds("port 1d5;"), Err(InvalidQuantity("1d5")));
+        assert_eq!(kinds("port 42.0;"), Err(UnexpectedCharacter(".")));
+        assert_eq!(kinds("  \n# only a comment"), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_messy_formatting() {
+        let inputs = [
+            r#"server{port 1965;vhost{hostname"localhost";route{path"/";respond_body"hi";}}}"#,
+            "\r\n\tserver\r\n{\r\n\tport\t1965 ;\r\n\r\n\tvhost {\r\n\t\thostname \"localhost\" ;\r\n\t\troute { path \"/\"; respond_body \"hi\"; }\r\n\t}\r\n}\r\n",
+            r#"
+# Leading comment
+server # the only server
+{
+    port
+        1965;
+    unused { } # empty blocks are fine
+    vhost
+    {
+        hostname "localhost"; route { path "/"; respond_body "hi"; }
+    }
+}
+# Trailing comment
+"#,
+        ];
+
+        for input in inputs {
+            let config = read_and_parse_config(input).unwrap();
+            let vhost = config.server.vhosts.last().unwrap();
+
+            assert_eq!(config.get_property_number("port"), Some(1965), "{input}");
+            assert_eq!(vhost.vhost.0, "localhost", "{input}");
+            assert_eq!(
+                vhost.routes[0].get_property_string("respond_body"),
+                Some("hi"),
+                "{input}"
+            );
+        }
+
+        let cases = vec![
+            ("", MissingServerBlock),
+            ("vhost { }", Expected("'server'", "vhost { }")),
+            ("server port 1965;", Expected("'{'", "port 1965;")),
+            ("server { port 1965 }", ExpectedSemicolon("}")),
+            ("server { port 1965;", Expected("'}'", "")),
+            (
+                "server { } server { }",
+                Expected("the end of the config", "server { }"),
+            ),
+            ("server { ; }", ExpectedIdentifier("; }")),
+            ("server { port = 1965; }", UnexpectedCharacter("=")),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(read_and_parse_config(input), Err(expected), "{input}");
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use crate::config::{GetProperty, VHost};
+use crate::response::Response;
+
+/// Failures generated by the server itself, as opposed to ones configured on a route.
+///
+/// A vhost can replace the meta text of the 51, 53 and 59 families and attach a gemtext
+/// body to them with an `errors { }` block, e.g. `not_found "Nothing here";` and
+/// `not_found_body "=> / Back home";`. Most clients never show bodies of failures, so the
+/// body is best treated as a courtesy for those that do.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Failure {
+    Temporary,
+    ServerUnavailable,
+    CGIError,
+    NotFound,
+    ProxyRequestRefused,
+    BadRequest,
+}
+
+impl Failure {
+    pub fn status(self) -> u8 {
+        match self {
+            Failure::Temporary => 40,
+            Failure::ServerUnavailable => 41,
+            Failure::CGIError => 42,
+            Failure::NotFound => 51,
+            Failure::ProxyRequestRefused => 53,
+            Failure::BadRequest => 59,
+        }
+    }
+
+    fn default_meta(self) -> &'static str {
+        match self {
+            Failure::Temporary => "Temporary failure",
+            Failure::ServerUnavailable => "Server unavailable",
+            Failure::CGIError => "CGI error",
+            Failure::NotFound => "Not found",
+            Failure::ProxyRequestRefused => "Proxy request refused",
+            Failure::BadRequest => "Bad request",
+        }
+    }
+
+    /// The property name within `errors { }`, for the families a vhost may customize.
+    pub fn config_name(self) -> Option<&'static str> {
+        match self {
+            Failure::NotFound => Some("not_found"),
+            Failure::ProxyRequestRefused => Some("proxy_refused"),
+            Failure::BadRequest => Some("bad_request"),
+            _ => None,
+        }
+    }
+
+    pub fn response(self, vhost: Option<&VHost>) -> Response {
+        let errors = vhost
+            .and_then(|vhost| vhost.errors.as_ref())
+            .zip(self.config_name());
+
+        let (meta, body) = match errors {
+            Some((errors, name)) => (
+                errors.get_property_string(name),
+                errors.get_property_string(&format!("{}_body", name)),
+            ),
+            None => (None, None),
+        };
+
+        Response::new(
+            self.status(),
+            meta.unwrap_or(self.default_meta()),
+            body.unwrap_or_default().to_string(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Failure;
+    use crate::config::read_and_parse_config;
+
+    #[test]
+    fn test_response() {
+        let input = r#"
+server
+{
+    vhost
+    {
+        hostname "localhost";
+
+        errors
+        {
+            not_found      "Nothing here";
+            not_found_body "=> / Back home";
+            bad_request    "Try again";
+        }
+    }
+}
+    "#;
+
+        let config = read_and_parse_config(input).unwrap();
+        let vhost = Some(&config.server.vhosts[0]);
+
+        let cases = vec![
+            (
+                Failure::NotFound,
+                vhost,
+                "51 Nothing here\r\n=> / Back home",
+            ),
+            (Failure::BadRequest, vhost, "59 Try again\r\n"),
+            (
+                Failure::ProxyRequestRefused,
+                vhost,
+                "53 Proxy request refused\r\n",
+            ),
+            (Failure::NotFound, None, "51 Not found\r\n"),
+            (Failure::CGIError, vhost, "42 CGI error\r\n"),
+        ];
+
+        for (failure, vhost, expected) in cases {
+            let resp = failure.response(vhost);
+            let body = String::from_utf8(resp.body.to_vec()).unwrap();
+
+            assert_eq!(format!("{}{}", resp.header, body), expected);
+        }
+    }
+}
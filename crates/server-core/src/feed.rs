@@ -0,0 +1,252 @@
+use protocol::gemtext::builder::GemTextBuilder;
+use std::path::Path;
+use url::Url;
+
+/// A dated gemlog post found in a feed directory.
+#[derive(Debug, Eq, PartialEq)]
+pub struct FeedEntry {
+    /// `YYYY-MM-DD`
+    pub date: String,
+    pub title: String,
+    pub file_name: String,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum FeedFormat {
+    /// https://geminiprotocol.net/docs/companion/subscription.gmi
+    Gmisub,
+    Atom,
+}
+
+impl FeedFormat {
+    pub fn from_property(value: Option<&str>) -> Option<Self> {
+        match value {
+            None | Some("gmisub") => Some(FeedFormat::Gmisub),
+            Some("atom") => Some(FeedFormat::Atom),
+            _ => None,
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            FeedFormat::Gmisub => "text/gemini",
+            FeedFormat::Atom => "application/atom+xml",
+        }
+    }
+}
+
+/// Takes a `YYYY-MM-DD` date from the start of `s`.
+fn leading_date(s: &str) -> Option<&str> {
+    let date = s.get(..10)?;
+    let valid = date.char_indices().all(|(idx, c)| match idx {
+        4 | 7 => c == '-',
+        _ => c.is_ascii_digit(),
+    });
+
+    valid.then_some(date)
+}
+
+fn trim_title(s: &str) -> &str {
+    s.trim_start_matches([' ', '-', '_']).trim()
+}
+
+/// Dates come from the file name (`2024-05-01-hello.gmi`) or, failing that, from the
+/// first heading (`# 2024-05-01 Hello`). Files without a date are not part of the feed.
+pub fn entry_from_file(file_name: &str, content: &str) -> Option<FeedEntry> {
+    let stem = file_name.strip_suffix(".gmi")?;
+    let heading = content
+        .lines()
+        .find_map(|line| line.strip_prefix('#'))
+        .map(|h| h.trim_start_matches('#').trim());
+
+    let (date, title) = match (leading_date(stem), heading) {
+        (Some(date), Some(heading)) => {
+            let heading = leading_date(heading).map_or(heading, |d| &heading[d.len()..]);
+            (date, trim_title(heading))
+        }
+        (Some(date), None) => (date, trim_title(&stem[date.len()..])),
+        (None, Some(heading)) => {
+            let date = leading_date(heading)?;
+            (date, trim_title(&heading[date.len()..]))
+        }
+        (None, None) => return None,
+    };
+
+    let title = if title.is_empty() { stem } else { title };
+
+    Some(FeedEntry {
+        date: date.to_string(),
+        title: title.to_string(),
+        file_name: file_name.to_string(),
+    })
+}
+
+/// Collects the dated `.gmi` files of a directory, newest first.
+pub fn read_entries(dir: &Path) -> std::io::Result<Vec<FeedEntry>> {
+    let mut entries = Vec::new();
+
+    for file in std::fs::read_dir(dir)? {
+        let file = file?;
+        let file_name = file.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+
+        if !file_name.ends_with(".gmi") || !file.file_type()?.is_file() {
+            continue;
+        }
+
+        let content = std::fs::read_to_string(file.path())?;
+        if let Some(entry) = entry_from_file(file_name, &content) {
+            entries.push(entry);
+        }
+    }
+
+    entries.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.title.cmp(&b.title)));
+
+    Ok(entries)
+}
+
+/// Renders the feed, linking entries relative to the URL the feed was requested from.
+pub fn render(format: &FeedFormat, title: &str, feed_url: &Url, entries: &[FeedEntry]) -> String {
+    let link = |entry: &FeedEntry| {
+        feed_url
+            .join(&entry.file_name)
+            .map_or_else(|_| entry.file_name.clone(), |u| u.to_string())
+    };
+
+    match format {
+        FeedFormat::Gmisub => {
+            let mut page = GemTextBuilder::new().heading(1, title).text("");
+            for entry in entries {
+                let description = format!("{} - {}", entry.date, entry.title);
+                // The names of files in the directory of the feed always join onto it.
+                if let Ok(url) = feed_url.join(&entry.file_name) {
+                    page = page.link(url, Some(&description));
+                }
+            }
+            page.build().to_string()
+        }
+        FeedFormat::Atom => {
+            let updated = entries.first().map_or("1970-01-01", |e| e.date.as_str());
+
+            let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+            out.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+            out.push_str(&format!("  <title>{}</title>\n", xml_escape(title)));
+            out.push_str(&format!("  <id>{}</id>\n", xml_escape(feed_url.as_str())));
+            out.push_str(&format!(
+                "  <link href=\"{}\"/>\n",
+                xml_escape(feed_url.as_str())
+            ));
+            out.push_str(&format!("  <updated>{}T00:00:00Z</updated>\n", updated));
+            for entry in entries {
+                let link = xml_escape(&link(entry));
+                out.push_str("  <entry>\n");
+                out.push_str(&format!(
+                    "    <title>{}</title>\n",
+                    xml_escape(&entry.title)
+                ));
+                out.push_str(&format!("    <id>{}</id>\n", link));
+                out.push_str(&format!("    <link href=\"{}\"/>\n", link));
+                out.push_str(&format!(
+                    "    <updated>{}T00:00:00Z</updated>\n",
+                    entry.date
+                ));
+                out.push_str("  </entry>\n");
+            }
+            out.push_str("</feed>\n");
+            out
+        }
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::gemtext::feed::parse_feed;
+    use protocol::gemtext::parse_gemtext;
+
+    #[test]
+    fn test_entry_from_file() {
+        let entry = |date: &str, title: &str, file_name: &str| {
+            Some(FeedEntry {
+                date: date.to_string(),
+                title: title.to_string(),
+                file_name: file_name.to_string(),
+            })
+        };
+
+        let cases = vec![
+            (
+                "2024-05-01-hello-world.gmi",
+                "# Hello, World!\nText",
+                entry("2024-05-01", "Hello, World!", "2024-05-01-hello-world.gmi"),
+            ),
+            (
+                "2024-05-01-hello-world.gmi",
+                "No heading",
+                entry("2024-05-01", "hello-world", "2024-05-01-hello-world.gmi"),
+            ),
+            (
+                "hello.gmi",
+                "## 2023-12-24 - Christmas",
+                entry("2023-12-24", "Christmas", "hello.gmi"),
+            ),
+            (
+                "2024-05-01.gmi",
+                "",
+                entry("2024-05-01", "2024-05-01", "2024-05-01.gmi"),
+            ),
+            ("index.gmi", "# My gemlog", None),
+            ("2024-05-01.txt", "", None),
+            ("2024-5-01-nope.gmi", "", None),
+        ];
+
+        for (file_name, content, expected) in cases {
+            assert_eq!(entry_from_file(file_name, content), expected, "{file_name}");
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let url = Url::parse("gemini://localhost/gemlog/feed.gmi").unwrap();
+        let entries = vec![FeedEntry {
+            date: "2024-05-01".to_string(),
+            title: "Fish & <Chips>".to_string(),
+            file_name: "2024-05-01-fish.gmi".to_string(),
+        }];
+
+        assert_eq!(
+            render(&FeedFormat::Gmisub, "Gemlog", &url, &entries),
+            "# Gemlog\n\n=> gemini://localhost/gemlog/2024-05-01-fish.gmi 2024-05-01 - Fish & <Chips>\n"
+        );
+
+        // Subscribers read the page back with the protocol's parser.
+        let gmisub = render(&FeedFormat::Gmisub, "Gemlog", &url, &entries);
+        let feed = parse_feed(&parse_gemtext(&url, gmisub).unwrap());
+        assert_eq!(feed.title.as_deref(), Some("Gemlog"));
+        assert_eq!(feed.entries.len(), 1);
+        assert_eq!(feed.entries[0].date, "2024-05-01");
+        assert_eq!(feed.entries[0].title, "Fish & <Chips>");
+
+        let atom = render(&FeedFormat::Atom, "Gemlog", &url, &entries);
+        assert!(atom.contains("<title>Fish &amp; &lt;Chips&gt;</title>"));
+        assert!(atom.contains("<link href=\"gemini://localhost/gemlog/2024-05-01-fish.gmi\"/>"));
+        assert!(atom.contains("<updated>2024-05-01T00:00:00Z</updated>"));
+    }
+}
@@ -0,0 +1,375 @@
+use crate::config::{GetProperty, VHost};
+use crate::routing::glob_match;
+use std::path::{Path, PathBuf};
+
+/// Served for a request to a directory.
+pub const INDEX_FILE: &str = "index.gmi";
+
+/// Answered with the `favicon "🚀";` of a vhost, following the gemini favicon convention
+/// of a single emoji in a text file.
+pub const FAVICON_PATH: &str = "/favicon.txt";
+
+/// Used for files without a known extension, unless the route or vhost sets `default_mime`.
+pub const DEFAULT_MIME: &str = "application/octet-stream";
+
+/// Dotfiles (and with them everything below `.git/`), editor backups and swap files. Only
+/// served with `deny_default_files off;`.
+pub const DEFAULT_DENIED_FILES: &[&str] = &[".*", "*~", "*.bak", "*.orig", "*.swp", "#*#"];
+
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("gmi", "text/gemini"),
+    ("gemini", "text/gemini"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("xml", "application/xml"),
+    ("atom", "application/atom+xml"),
+    ("json", "application/json"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("flac", "audio/flac"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+/// The MIME type for the extension of `path`, `None` for unknown extensions.
+pub fn mime_for(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+
+    MIME_TYPES
+        .iter()
+        .find(|(known, _)| *known == ext)
+        .map(|(_, mime)| *mime)
+}
+
+/// Whether a static request path is hidden by [DEFAULT_DENIED_FILES] or the globs of
+/// `deny_files`. A glob with a `/` is matched against the whole path, others against each
+/// segment, so `*.bak` hides backups in every directory.
+pub fn is_denied(props: &dyn GetProperty, path: &str) -> bool {
+    let defaults = match props.get_property_bool("deny_default_files") {
+        Some(false) => &[][..],
+        _ => DEFAULT_DENIED_FILES,
+    };
+    let configured = props.get_property_strings("deny_files");
+
+    defaults.iter().chain(&configured).any(|glob| {
+        if glob.contains('/') {
+            glob_match(glob.as_bytes(), path.as_bytes())
+        } else {
+            path.split('/').any(|segment| {
+                !segment.is_empty() && glob_match(glob.as_bytes(), segment.as_bytes())
+            })
+        }
+    })
+}
+
+/// The `alias "/pics/" "/mnt/photos/";` pairs of a vhost, `None` when a prefix is missing
+/// its directory. Repeated aliases append to the same list, so it is read in pairs.
+pub fn aliases(vhost: &VHost) -> Option<Vec<(&str, &str)>> {
+    let values = vhost.get_property_strings("alias");
+    if !values.len().is_multiple_of(2) || values.len() != vhost.get_property_values("alias").len() {
+        return None;
+    }
+
+    Some(values.chunks(2).map(|pair| (pair[0], pair[1])).collect())
+}
+
+/// `/~alice/notes.gmi` as `alice` and `notes.gmi`, `None` for other paths and for names
+/// that aren't plain user names.
+fn user_path(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix("/~")?;
+    let (user, rest) = rest.split_once('/').unwrap_or((rest, ""));
+
+    let valid = !user.is_empty()
+        && !user.starts_with(['.', '-'])
+        && user
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+
+    valid.then_some((user, rest))
+}
+
+/// The rest of `path` below the alias `prefix`, matched on whole segments so `/pics` is no
+/// prefix of `/picsX`.
+fn below_alias<'p>(path: &'p str, prefix: &str) -> Option<&'p str> {
+    let rest = path.strip_prefix(prefix)?;
+    if prefix.ends_with('/') {
+        return Some(rest);
+    }
+
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+/// `rest` below `dir`. A `rest` that starts with `/` would replace `dir` when joined, and the
+/// result is checked to stay below `dir` all the same.
+fn join_below(dir: &Path, rest: &str) -> Option<PathBuf> {
+    let path = dir.join(rest.trim_start_matches('/'));
+
+    path.starts_with(dir).then_some(path)
+}
+
+/// The file for a normalized request path. With `user_directory "/home/{user}/public_gemini";`
+/// on the vhost, `/~alice/...` is served from the directory of `alice`. Otherwise the file is
+/// below the directory of the longest matching alias, or below `root`. `None` when none of
+/// them applies.
+pub fn resolve(vhost: &VHost, props: &dyn GetProperty, path: &str) -> Option<PathBuf> {
+    if let Some(template) = vhost.get_property_string("user_directory")
+        && let Some((user, rest)) = user_path(path)
+    {
+        return join_below(&PathBuf::from(template.replace("{user}", user)), rest);
+    }
+
+    let alias = aliases(vhost)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(prefix, dir)| Some((prefix, dir, below_alias(path, prefix)?)))
+        .max_by_key(|(prefix, ..)| prefix.len());
+
+    match alias {
+        Some((_, dir, rest)) => join_below(Path::new(dir), rest),
+        None => join_below(Path::new(props.get_property_string("root")?), path),
+    }
+}
+
+/// `gmi_extension "strip";` serves `/page` from `page.gmi` and redirects `/page.gmi` there,
+/// `gmi_extension "add";` redirects `/page` to `/page.gmi` when only the latter exists.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum GmiExtension {
+    #[default]
+    Keep,
+    Strip,
+    Add,
+}
+
+/// `trailing_slash "strip";` serves directories without the slash and redirects to drop it,
+/// `trailing_slash "add";` gives the pages of `gmi_extension "strip";` one as well. By
+/// default only directories have one.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum TrailingSlash {
+    #[default]
+    Directories,
+    Strip,
+    Add,
+}
+
+impl GmiExtension {
+    pub fn from_property(value: Option<&str>) -> Option<Self> {
+        match value {
+            None | Some("keep") => Some(GmiExtension::Keep),
+            Some("strip") => Some(GmiExtension::Strip),
+            Some("add") => Some(GmiExtension::Add),
+            _ => None,
+        }
+    }
+}
+
+impl TrailingSlash {
+    pub fn from_property(value: Option<&str>) -> Option<Self> {
+        match value {
+            None | Some("directories") => Some(TrailingSlash::Directories),
+            Some("strip") => Some(TrailingSlash::Strip),
+            Some("add") => Some(TrailingSlash::Add),
+            _ => None,
+        }
+    }
+}
+
+/// How the static files of a vhost are addressed, validated at startup.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct UrlStyle {
+    pub extension: GmiExtension,
+    pub slash: TrailingSlash,
+}
+
+impl UrlStyle {
+    pub fn of(vhost: &VHost) -> Self {
+        UrlStyle {
+            extension: GmiExtension::from_property(vhost.get_property_string("gmi_extension"))
+                .unwrap_or_default(),
+            slash: TrailingSlash::from_property(vhost.get_property_string("trailing_slash"))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Where a request for `path` is redirected before a file is looked up, `None` when
+    /// the path is already how the vhost writes it.
+    pub fn redirect(&self, path: &str) -> Option<String> {
+        let mut canonical = path;
+
+        if self.extension == GmiExtension::Strip
+            && let Some(page) = canonical.strip_suffix(".gmi")
+            && !page.ends_with('/')
+        {
+            canonical = page;
+        }
+        if self.slash == TrailingSlash::Strip && canonical.len() > 1 {
+            canonical = canonical.trim_end_matches('/');
+        }
+
+        let mut canonical = canonical.to_string();
+        if self.slash == TrailingSlash::Add && canonical.len() < path.len() {
+            canonical.push('/');
+        }
+
+        (canonical != path).then_some(canonical)
+    }
+
+    /// The request path of the `.gmi` file a request for `path` falls back to when `path`
+    /// isn't a file, `None` when the vhost keeps extensions.
+    pub fn gmi_fallback(&self, path: &str) -> Option<String> {
+        let page = path.trim_end_matches('/');
+
+        match self.extension {
+            GmiExtension::Keep => None,
+            _ if page.is_empty() || page.ends_with(".gmi") => None,
+            _ => Some(format!("{}.gmi", page)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_and_parse_config;
+
+    #[test]
+    fn test_resolve() {
+        let input = r#"
+server
+{
+    root "/srv/gemini";
+
+    vhost
+    {
+        hostname "localhost";
+        alias "/pics/" "/mnt/photos/";
+        alias "/pics/old/" "/mnt/archive";
+        user_directory "/home/{user}/public_gemini";
+    }
+}
+"#;
+
+        let config = read_and_parse_config(input).unwrap();
+        let vhost = &config.server.vhosts[0];
+
+        let cases = vec![
+            ("/", "/srv/gemini/"),
+            ("/blog/post.gmi", "/srv/gemini/blog/post.gmi"),
+            ("/pics", "/srv/gemini/pics"),
+            ("/picsX", "/srv/gemini/picsX"),
+            ("/pics/cat.png", "/mnt/photos/cat.png"),
+            ("/pics/old/dog.png", "/mnt/archive/dog.png"),
+            ("/~alice", "/home/alice/public_gemini"),
+            ("/~alice/", "/home/alice/public_gemini/"),
+            ("/~bob.b/log/a.gmi", "/home/bob.b/public_gemini/log/a.gmi"),
+            ("/~/x", "/srv/gemini/~/x"),
+            ("/~.hidden/x", "/srv/gemini/~.hidden/x"),
+            ("/~a%2fb", "/srv/gemini/~a%2fb"),
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!(
+                resolve(vhost, vhost, path),
+                Some(PathBuf::from(expected)),
+                "{path}"
+            );
+        }
+
+        // Aliases without a trailing slash are rejected by the config check, and still
+        // don't reach outside their directory.
+        let input =
+            r#"server { port 1965; vhost { hostname "localhost"; alias "/pics" "/mnt/photos"; } }"#;
+        let config = read_and_parse_config(input).unwrap();
+        let vhost = &config.server.vhosts[0];
+        assert_eq!(
+            resolve(vhost, vhost, "/pics/etc/passwd"),
+            Some(PathBuf::from("/mnt/photos/etc/passwd"))
+        );
+        assert_eq!(resolve(vhost, vhost, "/picsX"), None);
+        assert_eq!(
+            resolve(vhost, vhost, "/pics"),
+            Some(PathBuf::from("/mnt/photos"))
+        );
+
+        let input = r#"server { port 1965; vhost { hostname "localhost"; alias "/pics/"; } }"#;
+        let config = read_and_parse_config(input).unwrap();
+        assert_eq!(aliases(&config.server.vhosts[0]), None);
+    }
+
+    #[test]
+    fn test_is_denied() {
+        let input = r#"
+server
+{
+    port 1965;
+    deny_files ["*.log", "/private/**"];
+
+    vhost { hostname "localhost"; }
+    vhost { hostname "other"; deny_default_files off; }
+}
+"#;
+
+        let config = read_and_parse_config(input).unwrap();
+        let (vhost, other) = (&config.server.vhosts[0], &config.server.vhosts[1]);
+
+        let cases = vec![
+            ("/index.gmi", false, false),
+            ("/dir/", false, false),
+            ("/.env", true, false),
+            ("/.git/config", true, false),
+            ("/notes/draft.gmi~", true, false),
+            ("/notes/draft.bak", true, false),
+            ("/access.log", true, true),
+            ("/private/a/b.gmi", true, true),
+            ("/public/private/a.gmi", false, false),
+        ];
+
+        for (path, denied, denied_other) in cases {
+            assert_eq!(is_denied(vhost, path), denied, "{path}");
+            assert_eq!(is_denied(other, path), denied_other, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_mime_for() {
+        assert_eq!(mime_for(Path::new("a/b.gmi")), Some("text/gemini"));
+        assert_eq!(mime_for(Path::new("photo.JPG")), Some("image/jpeg"));
+        assert_eq!(mime_for(Path::new("README")), None);
+        assert_eq!(mime_for(Path::new("archive.tar.xz")), None);
+    }
+
+    #[test]
+    fn test_url_style() {
+        let style = |extension, slash| UrlStyle { extension, slash };
+
+        let keep = UrlStyle::default();
+        assert_eq!(keep.redirect("/page.gmi"), None);
+        assert_eq!(keep.gmi_fallback("/page"), None);
+
+        let strip = style(GmiExtension::Strip, TrailingSlash::Directories);
+        assert_eq!(strip.redirect("/page.gmi").as_deref(), Some("/page"));
+        assert_eq!(strip.redirect("/dir/"), None);
+        assert_eq!(strip.redirect("/dir/.gmi"), None);
+        assert_eq!(strip.gmi_fallback("/page").as_deref(), Some("/page.gmi"));
+        assert_eq!(strip.gmi_fallback("/"), None);
+
+        let slashes = style(GmiExtension::Strip, TrailingSlash::Add);
+        assert_eq!(slashes.redirect("/page.gmi").as_deref(), Some("/page/"));
+        assert_eq!(slashes.gmi_fallback("/page/").as_deref(), Some("/page.gmi"));
+
+        let no_slashes = style(GmiExtension::Keep, TrailingSlash::Strip);
+        assert_eq!(no_slashes.redirect("/dir/").as_deref(), Some("/dir"));
+        assert_eq!(no_slashes.redirect("/"), None);
+    }
+}
@@ -0,0 +1,1179 @@
+//! The Gemini server as a library: the listeners, the router and the request handlers.
+//!
+//! ```no_run
+//! # async fn example(input: &str) -> anyhow::Result<()> {
+//! use server_core::config::{read_and_parse_config, validate_config};
+//!
+//! let describe = |e: server_core::config::error::Error| anyhow::anyhow!(e.describe(input));
+//! let config = read_and_parse_config(input).map_err(describe)?;
+//! validate_config(&config).map_err(describe)?;
+//!
+//! let server = server_core::Server::from_config(config)?;
+//! server.run(std::future::pending()).await
+//! # }
+//! ```
+//!
+//! Every Gemini request passes through the [router::Middleware] added with
+//! [Server::with_middleware] before its route answers it.
+
+mod acme;
+mod cache;
+pub mod client_cert;
+pub mod config;
+mod errors;
+mod feed;
+mod files;
+mod listener;
+pub mod logging;
+mod mirror;
+mod pool;
+mod request_mirror;
+pub mod response;
+mod robots;
+pub mod router;
+pub mod routing;
+mod scripting;
+mod spartan;
+pub mod stats;
+mod template;
+mod titan;
+mod tls_store;
+mod watch;
+
+use crate::cache::{cache_key, CachePolicy, ResponseCache};
+use crate::client_cert::ClientCert;
+use crate::config::{Config, GetProperty, Route, VHost};
+use crate::errors::Failure;
+use crate::feed::FeedFormat;
+use crate::files::{GmiExtension, TrailingSlash, UrlStyle};
+use crate::mirror::Mirror;
+use crate::pool::Pools;
+use crate::request_mirror::RequestMirror;
+use crate::response::Response;
+use crate::router::{Middleware, Request, Router};
+use crate::routing::{find_route, normalize_path, RoutePattern};
+use crate::stats::{index_of, Stats, Target};
+use crate::template::TemplateContext;
+use crate::tls_store::make_tls_config;
+use crate::watch::FileWatcher;
+use anyhow::Context;
+use percent_encoding::percent_decode_str;
+use protocol::gemini_protocol::response::Response as GeminiResponse;
+use protocol::gemtext::gemtext_body::MimeType;
+use protocol::url_util::parse_request_url;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::task::JoinSet;
+use tokio::{
+    io::BufReader,
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::TlsAcceptor;
+use url::Url;
+
+pub(crate) struct GlobalState {
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    config: Arc<Config>,
+    cache: ResponseCache,
+    stats: Stats,
+    pools: Pools,
+    router: Router,
+}
+
+pub(crate) type GlobalStateArc = Arc<GlobalState>;
+
+// Connections are spawned onto whichever runtime `worker_threads` selects.
+const _: () = {
+    fn assert_send_sync<T: Send + Sync>() {}
+    let _ = assert_send_sync::<GlobalState>;
+};
+
+const MAX_REQUEST_SIZE: usize = 1024;
+
+pub(crate) async fn handle_client_request(
+    conn: TlsConnection,
+    global_state: GlobalStateArc,
+) -> anyhow::Result<()> {
+    log::info!("Accepted connection from {:?}", conn.addr);
+
+    // let (sni, valid, mut stream) = {
+    //     let mut sni = None;
+    //     let mut valid = false;
+    let stream = conn
+        .acceptor
+        // .accept_with(conn.socket, |sc| {
+        //     if let Some(server_name) = sc.server_name() {
+        //         sni = Some(server_name.to_string());
+        //         valid = global_state
+        //             .config
+        //             .get_blocks("vhost")
+        //             .iter()
+        //             .find(|block| {
+        //                 block
+        //                     .get_property_string("for")
+        //                     .map_or(false, |s| s == server_name)
+        //             })
+        //             .is_some();
+        //     }
+        // })
+        .accept(conn.socket)
+        .await?;
+
+    // Offered to every client by the verifier, but optional.
+    let client_cert = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .map(|cert| ClientCert::from_der(cert));
+
+    log::debug!("TLS handshake completed");
+
+    //     (sni.unwrap_or_default(), valid, stream)
+    // };
+    //
+    // if !valid {
+    //     log::warn!(
+    //         "Invalid domain name: {:?}",
+    //         stream.into_inner().1.server_name()
+    //     );
+    //     return Ok(());
+    // }
+    //
+    // // TODO: Merge them
+    // let vhost = global_state
+    //     .config
+    //     .get_blocks("vhost")
+    //     .iter()
+    //     .find(|block| block.get_property_string("for").map_or(false, |s| s == sni))
+    //     .unwrap();
+
+    serve_requests(stream, global_state, client_cert).await
+}
+
+/// The request pipeline shared by every kind of listener, after any TLS handshake.
+pub(crate) async fn serve_requests<S>(
+    stream: S,
+    global_state: GlobalStateArc,
+    client_cert: Option<ClientCert>,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut line_reader = BufReader::new(stream);
+
+    // Reused for every request on the connection.
+    let mut req = String::new();
+
+    loop {
+        req.clear();
+
+        match line_reader.read_line(&mut req).await {
+            Ok(0) => {
+                log::info!("Connection closed by client");
+                break;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("Failed to read from socket; error = {:?}", e);
+                break;
+            }
+        }
+
+        if req.is_empty() {
+            log::debug!("Empty request; closing connection");
+
+            break;
+        }
+        if req.len() > MAX_REQUEST_SIZE {
+            log::warn!("Request too large: {:?}", req);
+
+            let stream = line_reader.get_mut();
+            Failure::BadRequest.response(None).write_to(stream).await?;
+            stream.shutdown().await?;
+            break;
+        }
+
+        log::debug!("Received request: {:?}", req);
+
+        let mut target = Target::default();
+        let resp = if req.starts_with("titan://") {
+            receive_upload(
+                &global_state.config,
+                req.trim_end(),
+                &mut line_reader,
+                &mut target,
+            )
+            .await
+        } else {
+            respond(
+                &global_state,
+                req.trim_end(),
+                client_cert.as_ref(),
+                &mut target,
+            )
+            .await
+        };
+
+        global_state.stats.record(target, &resp);
+
+        // One line per request, with the client certificate (if any) that made it.
+        log::info!(
+            target: "access",
+            "{:?} {} {}",
+            req.trim_end(),
+            resp.header_line(),
+            client_cert.as_ref().map_or("-", |cert| cert.hash.as_str())
+        );
+
+        log::debug!("Sending response: {:?}", resp.header_line());
+
+        let stream = line_reader.get_mut();
+        resp.write_to(stream).await?;
+        stream.shutdown().await?;
+    }
+
+    Ok(())
+}
+
+fn find_vhost<'c>(config: &'c Config, url: &Url) -> Option<&'c VHost> {
+    config
+        .server
+        .vhosts
+        .iter()
+        .find(|vhost| url.host_str() == Some(vhost.vhost.0.as_str()))
+}
+
+/// The vhost that lists the host of `url` in `alternate_hostnames ["www.example.org",
+/// "192.0.2.1"];`. Requests to those are redirected to its hostname, so a capsule has one
+/// set of URLs.
+fn find_alternate_vhost<'c>(config: &'c Config, url: &Url) -> Option<&'c VHost> {
+    // IPv6 addresses are bracketed in URLs but not in the config.
+    let host = url
+        .host_str()?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+
+    config.server.vhosts.iter().find(|vhost| {
+        vhost
+            .get_property_strings("alternate_hostnames")
+            .contains(&host)
+    })
+}
+
+/// Handles a Gemini request, noting the vhost and route it reaches in `target`.
+async fn respond(
+    global_state: &GlobalState,
+    req: &str,
+    client_cert: Option<&ClientCert>,
+    target: &mut Target,
+) -> Response {
+    let config = &global_state.config;
+
+    let Ok(url) = parse_request_url(req) else {
+        return Failure::BadRequest.response(None);
+    };
+
+    let Some(vhost) = find_vhost(config, &url) else {
+        let Some(vhost) = find_alternate_vhost(config, &url) else {
+            return Failure::ProxyRequestRefused.response(None);
+        };
+        target.vhost = index_of(&config.server.vhosts, vhost);
+
+        let mut canonical = url.clone();
+        return match canonical.set_host(Some(&vhost.vhost.0)) {
+            Ok(()) => GeminiResponse::PermanentRedirect(canonical.to_string()).into(),
+            Err(_) => Failure::BadRequest.response(Some(vhost)),
+        };
+    };
+    target.vhost = index_of(&config.server.vhosts, vhost);
+
+    let Some(path) = normalize_path(url.path()) else {
+        return Failure::BadRequest.response(Some(vhost));
+    };
+
+    let mut request = Request {
+        line: req,
+        url: &url,
+        vhost,
+        path: &path,
+        route_path: path.clone(),
+        client_cert,
+        target: *target,
+    };
+    let resp = global_state.router.handle(global_state, &mut request).await;
+    *target = request.target;
+
+    resp
+}
+
+/// Answers a request that made it through the middleware of the [Router]: the well-known
+/// files of the vhost, then the handlers of its route, then its static files.
+async fn dispatch(global_state: &GlobalState, req: &mut Request<'_>) -> Response {
+    let config = &global_state.config;
+    let (vhost, url, path) = (req.vhost, req.url, req.path);
+
+    if let Some(robots) = &vhost.robots
+        && path == robots::ROBOTS_PATH
+    {
+        return Response::new(20, "text/plain", robots::render(robots));
+    }
+
+    if let Some(favicon) = vhost.get_property_string("favicon")
+        && path == files::FAVICON_PATH
+    {
+        return Response::new(
+            20,
+            &success_meta("text/plain", vhost),
+            format!("{}\n", favicon),
+        );
+    }
+
+    let file_path = req.route_path.as_str();
+    // `/server-status.json` is the JSON variant of a status page at `/server-status`.
+    let status_json = file_path
+        .strip_suffix(".json")
+        .and_then(|p| find_route(vhost, p))
+        .filter(|m| {
+            matches!(m.route.pattern, RoutePattern::Exact(_))
+                && m.route.get_property_string("status_page").is_some()
+        });
+    let json = status_json.is_some();
+
+    let Some(matched) = status_json.or_else(|| find_route(vhost, file_path)) else {
+        return respond_static(vhost, vhost, file_path, url).await;
+    };
+    req.target.route = index_of(&vhost.routes, matched.route);
+
+    if let Some(redirect) = matched.route.get_property_string("redirect") {
+        return GeminiResponse::TemporaryRedirect(matched.expand_url(redirect)).into();
+    }
+
+    if let Some(format) = matched.route.get_property_string("status_page") {
+        let status = &global_state.stats;
+
+        let format = if json { "json" } else { format };
+
+        return match format {
+            "json" => Response::new(20, "application/json", status.render_json(config)),
+            "prometheus" => Response::new(
+                20,
+                "text/plain; version=0.0.4",
+                status.render_prometheus(config),
+            ),
+            // Validated at startup.
+            _ => Response::new(
+                20,
+                &success_meta("text/gemini", matched.route),
+                status.render_gemtext(config),
+            ),
+        };
+    }
+
+    // Queries are user input, so they are handed on decoded.
+    let query = match url.query().filter(|q| !q.is_empty()) {
+        Some(query) => match percent_decode_str(query).decode_utf8() {
+            Ok(query) => Some(query),
+            Err(_) => return Failure::BadRequest.response(Some(vhost)),
+        },
+        None => None,
+    };
+
+    if let Some(prompt) = matched.route.get_property_string("prompt")
+        && query.is_none()
+    {
+        return GeminiResponse::MustPromptForInput(prompt.to_string()).into();
+    }
+
+    let ctx = TemplateContext {
+        host: &vhost.vhost.0,
+        path,
+        query: query.as_deref(),
+        cert_cn: req.client_cert.and_then(|cert| cert.common_name.as_deref()),
+        cert_hash: req.client_cert.map(|cert| cert.hash.as_str()),
+    };
+
+    if let Some(dir) = matched.route.get_property_string("feed_directory") {
+        let title = matched
+            .route
+            .get_property_string("feed_title")
+            .unwrap_or(&vhost.vhost.0);
+
+        return respond_feed(matched.route, dir, title, url);
+    }
+
+    // Only what templates and scripts generate is cached, keyed by the whole request URL
+    // and the client certificate they may greet by name.
+    let cache = &global_state.cache;
+    let policy = CachePolicy::for_route(matched.route);
+    let key = cache_key(req.line, req.client_cert);
+    if policy.is_some()
+        && let Some(resp) = cache.get(&key)
+    {
+        log::debug!("Serving {:?} from the cache", req.line);
+
+        return resp;
+    }
+
+    // Held until the response is generated, so a slow route can't tie up every worker.
+    let _permit = match global_state.pools.get(req.target) {
+        Some(pool) => match pool.acquire().await {
+            Some(permit) => Some(permit),
+            None => {
+                log::warn!("Route '{}' is saturated", matched.route.path);
+
+                return Failure::ServerUnavailable.response(None);
+            }
+        },
+        None => None,
+    };
+
+    let Some(resp) = respond_dynamic(matched.route, &ctx).await else {
+        return respond_static(vhost, matched.route, file_path, url).await;
+    };
+
+    if let Some(policy) = policy {
+        cache.insert(&key, &resp, policy, req.target);
+    }
+
+    resp
+}
+
+/// The response of a route's `respond_body`, `respond_file` or `script`, `None` when it has
+/// none of them.
+async fn respond_dynamic(route: &Route, ctx: &TemplateContext<'_>) -> Option<Response> {
+    let meta = success_meta("text/gemini", route);
+
+    if let Some(body) = route.get_property_string("respond_body") {
+        return Some(render_body(body, ctx, Path::new(""), &meta).await);
+    }
+
+    if let Some(file) = route.get_property_string("respond_file") {
+        let dir = Path::new(file).parent().unwrap_or(Path::new(""));
+        let resp = match tokio::fs::read_to_string(file).await {
+            Ok(body) => render_body(&body, ctx, dir, &meta).await,
+            Err(e) => {
+                log::error!("Failed to read route file {:?}; error = {:?}", file, e);
+
+                Failure::Temporary.response(None)
+            }
+        };
+
+        return Some(resp);
+    }
+
+    if let Some(file) = route.get_property_string("script") {
+        let resp = match tokio::fs::read_to_string(file).await {
+            Ok(source) => match scripting::run(&source, ctx, &meta) {
+                Ok(resp) => Response::from(resp),
+                Err(e) => {
+                    log::error!("Script {:?} failed for {:?}; error = {}", file, ctx.path, e);
+
+                    Failure::CGIError.response(None)
+                }
+            },
+            Err(e) => {
+                log::error!("Failed to read route script {:?}; error = {:?}", file, e);
+
+                Failure::Temporary.response(None)
+            }
+        };
+
+        return Some(resp);
+    }
+
+    None
+}
+
+/// Serves a file below the `root` or an `alias` directory, unless [files::is_denied] hides
+/// it. A directory is served by its [files::INDEX_FILE], a request for it without the
+/// trailing slash is redirected.
+async fn respond_static(
+    vhost: &VHost,
+    props: &(impl GetProperty + Sync),
+    path: &str,
+    url: &Url,
+) -> Response {
+    if files::is_denied(props, path) {
+        log::debug!("Denied static request for {:?}", path);
+
+        return Failure::NotFound.response(Some(vhost));
+    }
+
+    // Redirects are made from the requested path, which routes may have rewritten.
+    let style = UrlStyle::of(vhost);
+    if let Some(canonical) = style.redirect(url.path()) {
+        return redirect_to_path(url, &canonical);
+    }
+
+    let Some(mut file) = files::resolve(vhost, props, path) else {
+        return Failure::NotFound.response(Some(vhost));
+    };
+
+    match tokio::fs::metadata(&file).await {
+        Ok(metadata) if metadata.is_dir() => {
+            if !path.ends_with('/') && style.slash != TrailingSlash::Strip {
+                return redirect_to_path(url, &format!("{}/", url.path()));
+            }
+
+            file.push(files::INDEX_FILE);
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            // `/page` of `page.gmi`, see [UrlStyle].
+            let Some(page) = style
+                .gmi_fallback(path)
+                .filter(|page| !files::is_denied(props, page))
+            else {
+                return Failure::NotFound.response(Some(vhost));
+            };
+            let Some(page_file) = files::resolve(vhost, props, &page) else {
+                return Failure::NotFound.response(Some(vhost));
+            };
+            if !tokio::fs::metadata(&page_file)
+                .await
+                .is_ok_and(|metadata| metadata.is_file())
+            {
+                return Failure::NotFound.response(Some(vhost));
+            }
+
+            match (style.extension, style.slash) {
+                (GmiExtension::Add, _) => {
+                    let page = style.gmi_fallback(url.path()).unwrap_or(page);
+                    return redirect_to_path(url, &page);
+                }
+                (_, TrailingSlash::Add) if !path.ends_with('/') => {
+                    return redirect_to_path(url, &format!("{}/", url.path()));
+                }
+                (_, TrailingSlash::Directories) if path.ends_with('/') => {
+                    return redirect_to_path(url, url.path().trim_end_matches('/'));
+                }
+                _ => file = page_file,
+            }
+        }
+        Err(e) => {
+            log::error!("Failed to read {:?}; error = {:?}", file, e);
+
+            return Failure::Temporary.response(None);
+        }
+    }
+
+    match tokio::fs::read(&file).await {
+        Ok(body) => {
+            let mime = files::mime_for(&file)
+                .or(props.get_property_string("default_mime"))
+                .unwrap_or(files::DEFAULT_MIME);
+
+            Response::new(20, &success_meta(mime, props), body)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Failure::NotFound.response(Some(vhost))
+        }
+        Err(e) => {
+            log::error!("Failed to read {:?}; error = {:?}", file, e);
+
+            Failure::Temporary.response(None)
+        }
+    }
+}
+
+/// A permanent redirect to another path of the capsule, without the query.
+fn redirect_to_path(url: &Url, path: &str) -> Response {
+    let mut target = url.clone();
+    target.set_path(path);
+    target.set_query(None);
+
+    GeminiResponse::PermanentRedirect(target.to_string()).into()
+}
+
+/// Stores a Titan upload in the `upload_directory` of its route, once [titan::check]
+/// has accepted it. Only the file name of the request path is used.
+async fn receive_upload<R>(
+    config: &Config,
+    req: &str,
+    body: &mut R,
+    target: &mut Target,
+) -> Response
+where
+    R: AsyncRead + Unpin,
+{
+    let Ok(url) = parse_request_url(req) else {
+        return Failure::BadRequest.response(None);
+    };
+
+    let Some(vhost) = find_vhost(config, &url) else {
+        return Failure::ProxyRequestRefused.response(None);
+    };
+    target.vhost = index_of(&config.server.vhosts, vhost);
+
+    let Some(upload) = titan::Upload::from_path(url.path()) else {
+        return Failure::BadRequest.response(Some(vhost));
+    };
+
+    let Some(path) = normalize_path(upload.path) else {
+        return Failure::BadRequest.response(Some(vhost));
+    };
+
+    let Some(matched) = find_route(vhost, &path) else {
+        return Failure::NotFound.response(Some(vhost));
+    };
+    target.route = index_of(&vhost.routes, matched.route);
+
+    let Some(dir) = matched.route.get_property_string("upload_directory") else {
+        let meta = "Uploads are not accepted here".to_string();
+        return GeminiResponse::BadRequest(Some(meta)).into();
+    };
+
+    if let Err(meta) = titan::check(matched.route, &upload) {
+        log::info!("Rejected upload to {:?}: {}", path, meta);
+
+        return GeminiResponse::BadRequest(Some(meta)).into();
+    }
+
+    let Some(name) = path.rsplit('/').next().filter(|name| !name.is_empty()) else {
+        return Failure::BadRequest.response(Some(vhost));
+    };
+
+    let target = Path::new(dir).join(name);
+    let partial = Path::new(dir).join(format!(".{}.partial", name));
+
+    match store_upload(body, upload.size, &partial, &target).await {
+        Ok(()) => {
+            log::info!("Stored upload of {} bytes at {:?}", upload.size, target);
+
+            Response::new(
+                20,
+                "text/gemini",
+                format!("Uploaded {} bytes to {}\n", upload.size, path),
+            )
+        }
+        Err(e) => {
+            log::error!("Failed to store upload at {:?}; error = {:?}", target, e);
+            let _ = tokio::fs::remove_file(&partial).await;
+
+            Failure::Temporary.response(None)
+        }
+    }
+}
+
+/// Writes the body to `partial` first, so an interrupted upload never replaces `target`.
+async fn store_upload<R>(
+    body: &mut R,
+    size: u64,
+    partial: &Path,
+    target: &Path,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut file = tokio::fs::File::create(partial).await?;
+    let copied = tokio::io::copy(&mut body.take(size), &mut file).await?;
+    if copied != size {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("upload ended after {} of {} bytes", copied, size),
+        ));
+    }
+
+    file.sync_all().await?;
+    tokio::fs::rename(partial, target).await
+}
+
+/// The MIME type followed by the `charset` (for text) and `lang` (for gemtext) parameters
+/// of the route (or vhost), which inherits them from the vhost and server. Text without a
+/// `charset` gets the `default_charset`, if any.
+fn success_meta(mime: &str, props: &dyn GetProperty) -> String {
+    let mut meta = mime.to_string();
+
+    let parsed = MimeType::parse(mime);
+    let is = |pattern| parsed.as_ref().is_some_and(|mime| mime.matches(pattern));
+    let params = [("charset", is("text/*")), ("lang", is("text/gemini"))];
+
+    for (param, _) in params.iter().filter(|(_, applies)| *applies) {
+        let value = match *param {
+            "charset" => props
+                .get_property_string("charset")
+                .or(props.get_property_string("default_charset")),
+            _ => props.get_property_string(param),
+        };
+
+        if let Some(value) = value {
+            meta.push_str(&format!("; {}={}", param, value));
+        }
+    }
+
+    meta
+}
+
+fn respond_feed(route: &Route, dir: &str, title: &str, url: &Url) -> Response {
+    // Validated at startup.
+    let format = FeedFormat::from_property(route.get_property_string("feed_format"))
+        .unwrap_or(FeedFormat::Gmisub);
+
+    match feed::read_entries(Path::new(dir)) {
+        Ok(entries) => Response::new(
+            20,
+            &success_meta(format.mime(), route),
+            feed::render(&format, title, url, &entries),
+        ),
+        Err(e) => {
+            log::error!("Failed to read feed directory {:?}; error = {:?}", dir, e);
+
+            Failure::Temporary.response(None)
+        }
+    }
+}
+
+/// Renders a template read from a file in `dir`, see [template::render].
+async fn render_body(body: &str, ctx: &TemplateContext<'_>, dir: &Path, meta: &str) -> Response {
+    match template::render(body, ctx, dir).await {
+        Ok(body) => Response::new(20, meta, body),
+        Err(e) => {
+            log::error!(
+                "Failed to render template for {:?}; error = {}",
+                ctx.path,
+                e
+            );
+
+            Failure::CGIError.response(None)
+        }
+    }
+}
+
+pub(crate) struct TlsConnection {
+    pub(crate) socket: TcpStream,
+    pub(crate) addr: SocketAddr,
+    pub(crate) acceptor: TlsAcceptor,
+}
+
+/// A server built from a validated [Config], everything it needs except its sockets.
+pub struct Server {
+    state: GlobalStateArc,
+    mirrors: Vec<Mirror>,
+    watcher: Option<FileWatcher>,
+}
+
+impl Server {
+    /// Checks the listen, mirror and watch properties and loads the certificates. A server only
+    /// listening on a Unix socket needs none.
+    pub fn from_config(config: Config) -> anyhow::Result<Self> {
+        let addresses = listener::listen_addresses(&config)?;
+        listener::spartan_addresses(&config)?;
+        #[cfg(unix)]
+        listener::UnixListenerConfig::from_config(&config)?;
+        acme::ChallengeListenerConfig::from_config(&config)?;
+        let mirrors = Mirror::from_config(&config)?;
+        let watcher = FileWatcher::from_config(&config)?;
+
+        let mut router = Router::default();
+        if let Some(request_mirror) = RequestMirror::from_config(&config)? {
+            router.push(request_mirror);
+        }
+
+        let tls_config = if addresses.is_empty() {
+            None
+        } else {
+            Some(make_tls_config(&config)?)
+        };
+
+        Ok(Server {
+            state: Arc::new(GlobalState {
+                tls_config,
+                cache: ResponseCache::from_config(&config),
+                stats: Stats::new(&config),
+                pools: Pools::new(&config),
+                router,
+                config: Arc::new(config),
+            }),
+            mirrors,
+            watcher,
+        })
+    }
+
+    /// Adds a hook around every Gemini request, see [Middleware].
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        // Only shared once the server runs.
+        let state = Arc::get_mut(&mut self.state).expect("state shared before serving");
+        state.router.push(middleware);
+
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.state.config
+    }
+
+    /// The request counters, shared with the running server.
+    pub fn stats(&self) -> &Stats {
+        &self.state.stats
+    }
+
+    /// Binds the listeners of the config and serves them until `shutdown` completes.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> anyhow::Result<()> {
+        let listeners = Listeners::bind(self.config())?;
+
+        self.serve(listeners, shutdown).await
+    }
+
+    /// Serves listeners bound earlier, see [Listeners::bind]. Once `shutdown` completes no
+    /// more connections are accepted, requests already being handled are not waited for.
+    pub async fn serve(
+        self,
+        listeners: Listeners,
+        shutdown: impl Future<Output = ()>,
+    ) -> anyhow::Result<()> {
+        let mut tasks = JoinSet::new();
+
+        for tcp_listener in listeners.tcp {
+            let tls_config = self
+                .state
+                .tls_config
+                .clone()
+                .context("Listening on TCP needs a TLS config")?;
+
+            tasks.spawn(listener::accept_loop(
+                TcpListener::from_std(tcp_listener)?,
+                TlsAcceptor::from(tls_config),
+                self.state.clone(),
+            ));
+        }
+
+        for spartan_listener in listeners.spartan {
+            tasks.spawn(listener::accept_spartan_loop(
+                TcpListener::from_std(spartan_listener)?,
+                self.state.clone(),
+            ));
+        }
+
+        #[cfg(unix)]
+        if let Some(unix_listener) = listeners.unix {
+            tasks.spawn(listener::accept_unix_loop(
+                UnixListener::from_std(unix_listener)?,
+                self.state.clone(),
+            ));
+        }
+
+        if let Some((acme_listener, dir)) = listeners.acme {
+            tasks.spawn(acme::accept_challenge_loop(
+                TcpListener::from_std(acme_listener)?,
+                Arc::new(dir),
+            ));
+        }
+
+        for mirror in self.mirrors {
+            tasks.spawn(mirror::mirror_loop(mirror));
+        }
+
+        if let Some(watcher) = self.watcher {
+            tasks.spawn(watch::watch_loop(watcher, self.state.clone()));
+        }
+
+        shutdown.await;
+
+        log::info!("Shutting down");
+        tasks.shutdown().await;
+
+        Ok(())
+    }
+}
+
+/// The sockets of a config, bound without a runtime so a daemon can bind them before it
+/// detaches and report bind errors on the terminal.
+pub struct Listeners {
+    tcp: Vec<std::net::TcpListener>,
+    spartan: Vec<std::net::TcpListener>,
+    #[cfg(unix)]
+    unix: Option<std::os::unix::net::UnixListener>,
+    acme: Option<(std::net::TcpListener, PathBuf)>,
+}
+
+impl Listeners {
+    pub fn bind(config: &Config) -> anyhow::Result<Self> {
+        let tcp = listener::listen_addresses(config)?
+            .into_iter()
+            .map(listener::bind)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let spartan = listener::spartan_addresses(config)?
+            .into_iter()
+            .map(listener::bind)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        #[cfg(unix)]
+        let unix = listener::UnixListenerConfig::from_config(config)?
+            .map(|unix_listener| unix_listener.bind())
+            .transpose()?;
+
+        let acme = acme::ChallengeListenerConfig::from_config(config)?
+            .map(|acme| anyhow::Ok((listener::bind(acme.addr)?, acme.dir)))
+            .transpose()?;
+
+        Ok(Listeners {
+            tcp,
+            spartan,
+            #[cfg(unix)]
+            unix,
+            acme,
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::config::read_and_parse_config;
+    use tokio::net::UnixStream;
+
+    #[test]
+    fn test_success_meta() {
+        let input = r#"
+server
+{
+    port 1965;
+    default_charset "utf-8";
+
+    vhost
+    {
+        hostname "localhost";
+        lang "en";
+
+        route { path "/"; respond_body "Hi"; }
+        route { path "/latin"; charset "iso-8859-1"; respond_body "Hi"; }
+    }
+}
+"#;
+        let config = read_and_parse_config(input).unwrap();
+        let routes = &config.server.vhosts[0].routes;
+
+        let cases = vec![
+            (0, "text/gemini", "text/gemini; charset=utf-8; lang=en"),
+            (0, "text/plain", "text/plain; charset=utf-8"),
+            (0, "image/png", "image/png"),
+            (1, "text/gemini", "text/gemini; charset=iso-8859-1; lang=en"),
+        ];
+
+        for (route, mime, expected) in cases {
+            assert_eq!(success_meta(mime, &routes[route]), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_per_certificate() {
+        let input = r#"
+server
+{
+    listen_unix "/nonexistent/gemini.sock";
+
+    vhost
+    {
+        hostname "localhost";
+
+        route { path "/hello"; respond_body "Hello {{ cert_cn }}"; cache_ttl 60s; }
+    }
+}
+"#;
+        let config = read_and_parse_config(input).unwrap();
+        let server = Server::from_config(config).unwrap();
+
+        let cert = |name: &str| ClientCert {
+            hash: format!("SHA256:{}", name.to_uppercase()),
+            common_name: Some(name.to_string()),
+        };
+        let (alice, bob) = (cert("alice"), cert("bob"));
+
+        let request = |cert: Option<ClientCert>| {
+            let mut target = Target::default();
+            let state = &server.state;
+            async move {
+                respond(
+                    state,
+                    "gemini://localhost/hello",
+                    cert.as_ref(),
+                    &mut target,
+                )
+                .await
+                .body
+            }
+        };
+
+        assert_eq!(request(Some(alice.clone())).await, "Hello alice");
+        assert_eq!(request(Some(bob)).await, "Hello bob");
+        assert_eq!(request(None).await, "Hello ");
+        assert_eq!(request(Some(alice.clone())).await, "Hello alice");
+        assert_eq!(server.state.cache.clear(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_clean_urls() {
+        let dir = std::env::temp_dir().join(format!("gemini-clean-urls-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("dir")).unwrap();
+        std::fs::write(dir.join("page.gmi"), "# Page").unwrap();
+        std::fs::write(dir.join("dir/index.gmi"), "# Dir").unwrap();
+
+        let header = |extension: &str, slash: &str, path: &'static str| {
+            let input = format!(
+                r#"server {{ vhost {{ hostname "localhost"; root "{}"; gmi_extension "{}"; trailing_slash "{}"; }} }}"#,
+                dir.display(),
+                extension,
+                slash
+            );
+            async move {
+                let config = read_and_parse_config(&input).unwrap();
+                let vhost = &config.server.vhosts[0];
+                let url = Url::parse(&format!("gemini://localhost{}?q", path)).unwrap();
+
+                respond_static(vhost, vhost, path, &url)
+                    .await
+                    .header_line()
+                    .to_string()
+            }
+        };
+
+        let cases = vec![
+            ("keep", "directories", "/page.gmi", "20 text/gemini"),
+            ("keep", "directories", "/page", "51 Not found"),
+            ("keep", "directories", "/dir", "31 gemini://localhost/dir/"),
+            (
+                "strip",
+                "directories",
+                "/page.gmi",
+                "31 gemini://localhost/page",
+            ),
+            ("strip", "directories", "/page", "20 text/gemini"),
+            (
+                "strip",
+                "directories",
+                "/page/",
+                "31 gemini://localhost/page",
+            ),
+            ("strip", "add", "/page", "31 gemini://localhost/page/"),
+            ("strip", "add", "/page/", "20 text/gemini"),
+            (
+                "add",
+                "directories",
+                "/page",
+                "31 gemini://localhost/page.gmi",
+            ),
+            ("keep", "strip", "/dir/", "31 gemini://localhost/dir"),
+            ("keep", "strip", "/dir", "20 text/gemini"),
+        ];
+
+        for (extension, slash, path, expected) in cases {
+            assert_eq!(
+                header(extension, slash, path).await,
+                expected,
+                "{extension} {slash} {path}"
+            );
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serve_in_process() {
+        let dir = std::env::temp_dir().join(format!("gemini-server-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("gemini.sock");
+
+        let input = format!(
+            r#"
+server
+{{
+    listen_unix "{}";
+
+    vhost
+    {{
+        hostname "localhost";
+        alternate_hostnames ["www.localhost", "127.0.0.1"];
+        favicon "🚀";
+
+        route {{ path "/index"; respond_body "Hello {{{{ query }}}}"; }}
+        route {{ path "/server-status"; status_page "gemtext"; }}
+        route {{ path_regex "^/old/(.*)$"; redirect "/new/$1"; }}
+    }}
+}}
+"#,
+            socket.display()
+        );
+        let config = read_and_parse_config(&input).unwrap();
+        let server = Server::from_config(config).unwrap();
+
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let serving = tokio::spawn(server.run(async {
+            let _ = stopped.await;
+        }));
+
+        let request = |req: &'static str| {
+            let socket = socket.clone();
+            async move {
+                // The listener is bound by the spawned task.
+                let mut stream = loop {
+                    match UnixStream::connect(&socket).await {
+                        Ok(stream) => break stream,
+                        Err(_) => tokio::task::yield_now().await,
+                    }
+                };
+                stream.write_all(req.as_bytes()).await.unwrap();
+
+                let mut resp = String::new();
+                stream.read_to_string(&mut resp).await.unwrap();
+                resp
+            }
+        };
+
+        assert_eq!(
+            request("gemini://localhost/index?there\r\n").await,
+            "20 text/gemini\r\nHello there"
+        );
+        assert_eq!(
+            request("gemini://localhost/favicon.txt\r\n").await,
+            "20 text/plain\r\n🚀\n"
+        );
+        assert_eq!(
+            request("gemini://www.localhost/index?there\r\n").await,
+            "31 gemini://localhost/index?there\r\n"
+        );
+        assert_eq!(
+            request("gemini://127.0.0.1:1965/index\r\n").await,
+            "31 gemini://localhost/index\r\n"
+        );
+        assert_eq!(
+            request("gemini://LOCALHOST/index?there\r\n").await,
+            "20 text/gemini\r\nHello there"
+        );
+        assert!(request("gemini://localhost/index#top\r\n")
+            .await
+            .starts_with("59 "));
+        assert!(request("gemini://user@localhost/index\r\n")
+            .await
+            .starts_with("59 "));
+        assert!(request("gemini://localhost/server-status\r\n")
+            .await
+            .starts_with("20 text/gemini"));
+        assert!(request("gemini://localhost/server-status.json\r\n")
+            .await
+            .starts_with("20 application/json\r\n{\"requests\":"));
+        assert_eq!(
+            request("gemini://localhost/old/a%20b\r\n").await,
+            "30 /new/a%20b\r\n"
+        );
+        assert_eq!(
+            request("gemini://localhost/old/%0D%0A20%20text/gemini%0D%0AForged\r\n").await,
+            "59 Bad request\r\n"
+        );
+        assert!(request("gemini://localhost/nope\r\n")
+            .await
+            .starts_with("51 "));
+        assert!(request("gemini://elsewhere/index\r\n")
+            .await
+            .starts_with("53 "));
+
+        stop.send(()).unwrap();
+        serving.await.unwrap().unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
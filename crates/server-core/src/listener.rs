@@ -0,0 +1,189 @@
+use crate::config::{Config, GetProperty};
+use crate::logging::with_connection_id;
+use crate::{handle_client_request, serve_requests, spartan, GlobalStateArc, TlsConnection};
+use anyhow::Context;
+use std::net::{Ipv6Addr, SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio_rustls::TlsAcceptor;
+
+/// Addresses from the `listen` property (repeated, a list or whitespace separated
+/// `address:port` pairs), or `[::]:port` when it is absent. A server only listening on a
+/// Unix socket or over Spartan has none.
+pub fn listen_addresses(config: &Config) -> anyhow::Result<Vec<SocketAddr>> {
+    let listen = parse_addresses(config, "listen")?;
+    if !listen.is_empty() {
+        return Ok(listen);
+    }
+
+    match config.get_property_number("port") {
+        Some(port) => Ok(vec![SocketAddr::from((Ipv6Addr::UNSPECIFIED, port as u16))]),
+        None if config.get_property_string("listen_unix").is_some() => Ok(vec![]),
+        None if !config.get_property_strings("listen_spartan").is_empty() => Ok(vec![]),
+        None => anyhow::bail!(
+            "The server needs a 'listen', 'port', 'listen_unix' or 'listen_spartan' property"
+        ),
+    }
+}
+
+/// Addresses from the `listen_spartan` property, in the format of `listen`. See
+/// [crate::spartan::serve_request].
+pub fn spartan_addresses(config: &Config) -> anyhow::Result<Vec<SocketAddr>> {
+    parse_addresses(config, "listen_spartan")
+}
+
+fn parse_addresses(config: &Config, property: &str) -> anyhow::Result<Vec<SocketAddr>> {
+    config
+        .get_property_strings(property)
+        .iter()
+        .flat_map(|listen| listen.split_whitespace())
+        .map(|addr| {
+            addr.parse()
+                .with_context(|| format!("Invalid {} address '{}'", property, addr))
+        })
+        .collect()
+}
+
+/// Binds before the runtime exists (and before detaching), so bind errors reach the terminal.
+pub fn bind(addr: SocketAddr) -> anyhow::Result<std::net::TcpListener> {
+    let listener =
+        std::net::TcpListener::bind(addr).with_context(|| format!("Failed to bind to {}", addr))?;
+    listener.set_nonblocking(true)?;
+
+    log::info!("Listening on: {}", listener.local_addr()?);
+
+    Ok(listener)
+}
+
+pub async fn accept_loop(
+    tcp_listener: TcpListener,
+    acceptor: TlsAcceptor,
+    global_state: GlobalStateArc,
+) {
+    loop {
+        let (socket, addr) = match tcp_listener.accept().await {
+            Ok((socket, addr)) => (socket, addr),
+            Err(e) => {
+                log::error!("Failed to accept connection; error = {:?}", e);
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let global_state = global_state.clone();
+
+        tokio::spawn(with_connection_id(async move {
+            let socket = TlsConnection {
+                socket,
+                addr,
+                acceptor,
+            };
+
+            if let Err(e) = handle_client_request(socket, global_state).await {
+                log::error!("failed to handle client request; error = {:?}", e);
+            }
+        }));
+    }
+}
+
+/// Spartan has no TLS, the sockets are served as they are accepted.
+pub async fn accept_spartan_loop(tcp_listener: TcpListener, global_state: GlobalStateArc) {
+    loop {
+        let (socket, addr) = match tcp_listener.accept().await {
+            Ok((socket, addr)) => (socket, addr),
+            Err(e) => {
+                log::error!("Failed to accept connection; error = {:?}", e);
+                continue;
+            }
+        };
+
+        let global_state = global_state.clone();
+
+        tokio::spawn(with_connection_id(async move {
+            log::info!("Accepted Spartan connection from {:?}", addr);
+
+            if let Err(e) = spartan::serve_request(socket, global_state).await {
+                log::error!("failed to handle client request; error = {:?}", e);
+            }
+        }));
+    }
+}
+
+/// `listen_unix "/run/gemini.sock";` with an optional octal `listen_unix_mode "660";`.
+///
+/// The socket speaks plain Gemini without TLS, it is meant to sit behind a frontend that
+/// terminates TLS (relayd, stunnel) or to be used for local testing.
+#[cfg(unix)]
+pub struct UnixListenerConfig {
+    pub path: PathBuf,
+    pub mode: Option<u32>,
+}
+
+#[cfg(unix)]
+impl UnixListenerConfig {
+    pub fn from_config(config: &Config) -> anyhow::Result<Option<Self>> {
+        let Some(path) = config.get_property_string("listen_unix") else {
+            return Ok(None);
+        };
+
+        let mode = config
+            .get_property_string("listen_unix_mode")
+            .map(|mode| {
+                u32::from_str_radix(mode, 8)
+                    .with_context(|| format!("Invalid octal listen_unix_mode '{}'", mode))
+            })
+            .transpose()?;
+
+        Ok(Some(UnixListenerConfig {
+            path: path.into(),
+            mode,
+        }))
+    }
+
+    pub fn bind(&self) -> anyhow::Result<std::os::unix::net::UnixListener> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        // A socket left behind by a previous run would make the bind fail.
+        if std::fs::symlink_metadata(&self.path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(&self.path)?;
+        }
+
+        let listener = std::os::unix::net::UnixListener::bind(&self.path)
+            .with_context(|| format!("Failed to bind to {}", self.path.display()))?;
+        listener.set_nonblocking(true)?;
+
+        if let Some(mode) = self.mode {
+            std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(mode))?;
+        }
+
+        log::info!("Listening on: {}", self.path.display());
+
+        Ok(listener)
+    }
+}
+
+#[cfg(unix)]
+pub async fn accept_unix_loop(listener: UnixListener, global_state: GlobalStateArc) {
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                log::error!("Failed to accept connection; error = {:?}", e);
+                continue;
+            }
+        };
+
+        let global_state = global_state.clone();
+
+        tokio::spawn(with_connection_id(async move {
+            log::info!("Accepted connection on Unix socket");
+
+            if let Err(e) = serve_requests(socket, global_state, None).await {
+                log::error!("failed to handle client request; error = {:?}", e);
+            }
+        }));
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A short ID for one accepted connection, added to every log line written while the
+/// connection is being served so interleaved connections can be told apart.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ConnectionId(u64);
+
+impl ConnectionId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+
+        ConnectionId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Display for ConnectionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "conn={}", self.0)
+    }
+}
+
+tokio::task_local! {
+    static CONNECTION_ID: ConnectionId;
+}
+
+/// Runs the connection's task with a new [ConnectionId].
+pub fn with_connection_id<F: Future>(f: F) -> impl Future<Output = F::Output> {
+    CONNECTION_ID.scope(ConnectionId::next(), f)
+}
+
+/// The ID of the connection being served by the current task, if any.
+pub fn connection_id() -> Option<ConnectionId> {
+    CONNECTION_ID.try_with(|id| *id).ok()
+}
+
+/// The default `env_logger` format with the connection ID after the target.
+pub fn init(level: log::LevelFilter) {
+    env_logger::builder()
+        .filter_level(level)
+        .format(|buf, record| {
+            let style = buf.default_level_style(record.level());
+            write!(
+                buf,
+                "[{} {style}{:<5}{style:#} {}",
+                buf.timestamp(),
+                record.level(),
+                record.target()
+            )?;
+
+            if let Some(id) = connection_id() {
+                write!(buf, " {}", id)?;
+            }
+
+            writeln!(buf, "] {}", record.args())
+        })
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_id() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let first = runtime.block_on(with_connection_id(async { connection_id() }));
+        let second = runtime.block_on(with_connection_id(async { connection_id() }));
+
+        assert!(first.is_some());
+        assert!(second.is_some());
+        assert_ne!(first, second);
+        assert_eq!(connection_id(), None);
+    }
+}
@@ -0,0 +1,432 @@
+use crate::config::{Config, GetProperty, Route};
+use crate::files::INDEX_FILE;
+use crate::routing::{normalize_path, RoutePattern};
+use anyhow::Context;
+use protocol::gemini_protocol::client;
+use protocol::gemini_protocol::parse_response_bytes;
+use protocol::gemtext::gemtext_body::Line;
+use protocol::gemtext::parse_gemtext;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// Used when a mirrored route has no `mirror_interval`.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Used when a mirrored route has no `mirror_max_files`.
+pub const DEFAULT_MAX_FILES: u32 = 1000;
+
+/// Larger responses are skipped.
+const MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Redirects followed for a single link.
+const MAX_REDIRECTS: usize = 5;
+
+/// A `path_prefix` route that serves a copy of another capsule:
+///
+/// ```text
+/// route { path_prefix "/mirror/"; root "/srv/gemini"; mirror "gemini://example.org/"; }
+/// ```
+///
+/// Every `mirror_interval` the pages below the mirrored URL are fetched, following gemtext
+/// links on the same host and below the same path, and stored where the route serves them
+/// from: `gemini://example.org/a/b.gmi` as `/srv/gemini/mirror/a/b.gmi`. At most
+/// `mirror_max_files` are fetched per run. Files that disappear upstream are kept.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Mirror {
+    pub url: Url,
+    pub dir: PathBuf,
+    pub interval: Duration,
+    pub max_files: u32,
+}
+
+impl Mirror {
+    pub fn for_route(route: &Route) -> anyhow::Result<Option<Self>> {
+        let Some(mirror) = route.get_property_string("mirror") else {
+            return Ok(None);
+        };
+
+        let url = Url::parse(mirror)
+            .ok()
+            .filter(|url| url.scheme() == "gemini" && url.host_str().is_some())
+            .with_context(|| format!("Invalid mirror URL '{}'", mirror))?;
+
+        let RoutePattern::Prefix(prefix) = &route.pattern else {
+            anyhow::bail!("The mirror of '{}' needs a path_prefix route", route.path);
+        };
+        let root = route
+            .get_property_string("root")
+            .with_context(|| format!("The mirror of '{}' needs a 'root'", route.path))?;
+
+        Ok(Some(Mirror {
+            url,
+            dir: Path::new(root).join(prefix.trim_start_matches('/')),
+            interval: route
+                .get_property_duration("mirror_interval")
+                .unwrap_or(DEFAULT_INTERVAL),
+            max_files: route
+                .get_property_number("mirror_max_files")
+                .unwrap_or(DEFAULT_MAX_FILES),
+        }))
+    }
+
+    /// The mirrors of every route of the config.
+    pub fn from_config(config: &Config) -> anyhow::Result<Vec<Self>> {
+        let mut mirrors = vec![];
+
+        for vhost in &config.server.vhosts {
+            for route in &vhost.routes {
+                mirrors.extend(Self::for_route(route)?);
+            }
+        }
+
+        Ok(mirrors)
+    }
+
+    /// The decoded path of `url` below the mirrored URL, `None` for URLs outside of it.
+    fn relative_path(&self, url: &Url) -> Option<String> {
+        if url.scheme() != self.url.scheme()
+            || url.host_str() != self.url.host_str()
+            || url.port() != self.url.port()
+            || url.query().is_some()
+        {
+            return None;
+        }
+
+        let base = normalize_path(self.url.path())?;
+        let base = base.trim_end_matches('/');
+        let path = normalize_path(url.path())?;
+
+        let relative = path.strip_prefix(base)?;
+        if !relative.is_empty() && !relative.starts_with('/') {
+            return None;
+        }
+
+        Some(relative.to_string())
+    }
+
+    /// Where the response for `url` is stored, directories as their [INDEX_FILE].
+    fn local_path(&self, url: &Url) -> Option<PathBuf> {
+        let relative = self.relative_path(url)?;
+
+        let mut path = self.dir.join(relative.trim_start_matches('/'));
+        if relative.is_empty() || relative.ends_with('/') {
+            path.push(INDEX_FILE);
+        }
+
+        Some(path)
+    }
+}
+
+/// Mirrored capsules are not expected to have certificates signed by a CA, like the
+/// client their certificates are not verified.
+#[derive(Debug)]
+struct NoCertificateVerification {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+pub(crate) fn client_config() -> anyhow::Result<Arc<ClientConfig>> {
+    let provider = crate::tls_store::crypto_provider();
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(NoCertificateVerification { provider }))
+        .with_no_client_auth();
+
+    Ok(Arc::new(config))
+}
+
+/// A response of the mirrored capsule.
+struct Fetched {
+    status: u8,
+    meta: String,
+    /// As it was received, gemtext isn't rewritten.
+    body: Vec<u8>,
+}
+
+/// A response that may be truncated is an error, the stored copy is kept instead.
+async fn fetch(config: &Arc<ClientConfig>, url: &Url) -> anyhow::Result<Fetched> {
+    let response = client::fetch_raw(url, config.clone(), MAX_RESPONSE_SIZE).await?;
+
+    let parsed = parse_response_bytes(url, &response)
+        .map_err(|e| anyhow::anyhow!("Invalid response: {}", e))?;
+    let end = response
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(response.len(), |i| i + 1);
+
+    Ok(Fetched {
+        status: parsed.status(),
+        meta: parsed.meta(),
+        body: response[end..].to_vec(),
+    })
+}
+
+/// The links of a gemtext page that stay within the mirror.
+fn links(mirror: &Mirror, url: &Url, body: &[u8]) -> Vec<Url> {
+    let Ok(text) = std::str::from_utf8(body) else {
+        return vec![];
+    };
+
+    let page = match parse_gemtext(url, text.to_string()) {
+        Ok(page) => page,
+        Err(e) => {
+            log::warn!("Not following the links of {}: {:?}", url, e);
+            return vec![];
+        }
+    };
+
+    page.0
+        .into_iter()
+        .filter_map(|line| match line {
+            Line::Link { mut url, .. } => {
+                url.set_fragment(None);
+                mirror.relative_path(&url).is_some().then_some(url)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Writes to a temporary file first, so a request never sees a half written page.
+async fn store(path: &Path, body: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let partial = path.with_file_name(format!(".{}.partial", name));
+
+    tokio::fs::write(&partial, body).await?;
+    tokio::fs::rename(&partial, path).await
+}
+
+/// Fetches and stores every page of the mirror once, returns how many were stored.
+async fn run_once(mirror: &Mirror, config: &Arc<ClientConfig>) -> usize {
+    let mut queue = VecDeque::from([(mirror.url.clone(), 0)]);
+    let mut seen = HashSet::from([mirror.url.clone()]);
+    let (mut fetches, mut stored) = (0, 0);
+
+    while let Some((url, redirects)) = queue.pop_front() {
+        if fetches == mirror.max_files {
+            log::warn!(
+                "Mirror of {} stopped at {} files",
+                mirror.url,
+                mirror.max_files
+            );
+            break;
+        }
+        fetches += 1;
+
+        let fetched = match fetch(config, &url).await {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                log::warn!("Failed to mirror {}; error = {:?}", url, e);
+                continue;
+            }
+        };
+
+        let next = match fetched.status {
+            20..=29 => {
+                let Some(path) = mirror.local_path(&url) else {
+                    continue;
+                };
+
+                if let Err(e) = store(&path, &fetched.body).await {
+                    log::error!("Failed to store {} at {:?}; error = {:?}", url, path, e);
+                    continue;
+                }
+                stored += 1;
+
+                if fetched.meta.starts_with("text/gemini") {
+                    links(mirror, &url, &fetched.body)
+                        .into_iter()
+                        .map(|link| (link, 0))
+                        .collect()
+                } else {
+                    vec![]
+                }
+            }
+            30..=39 if redirects < MAX_REDIRECTS => match url.join(&fetched.meta) {
+                Ok(target) if mirror.relative_path(&target).is_some() => {
+                    vec![(target, redirects + 1)]
+                }
+                _ => vec![],
+            },
+            status => {
+                log::warn!("Not mirroring {}: {} {}", url, status, fetched.meta);
+                vec![]
+            }
+        };
+
+        for (url, redirects) in next {
+            if seen.insert(url.clone()) {
+                queue.push_back((url, redirects));
+            }
+        }
+    }
+
+    stored
+}
+
+/// Refreshes the mirror every `interval`, starting right away.
+pub async fn mirror_loop(mirror: Mirror) {
+    let config = match client_config() {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!(
+                "Failed to set up the mirror of {}; error = {:?}",
+                mirror.url,
+                e
+            );
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(mirror.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let stored = run_once(&mirror, &config).await;
+        log::info!(
+            "Mirrored {} files of {} to {:?}",
+            stored,
+            mirror.url,
+            mirror.dir
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_and_parse_config;
+
+    #[test]
+    fn test_mirror() {
+        let input = r#"
+server
+{
+    port 1965;
+    root "/srv/gemini";
+
+    vhost
+    {
+        hostname "localhost";
+
+        route { path_prefix "/mirror/"; mirror "gemini://example.org/docs/"; }
+        route { path "/index"; respond_body "index"; }
+    }
+}
+"#;
+        let config = read_and_parse_config(input).unwrap();
+        let mirrors = Mirror::from_config(&config).unwrap();
+        assert_eq!(mirrors.len(), 1);
+
+        let mirror = &mirrors[0];
+        assert_eq!(mirror.dir, PathBuf::from("/srv/gemini/mirror/"));
+        assert_eq!(mirror.interval, DEFAULT_INTERVAL);
+
+        let url = |s: &str| Url::parse(s).unwrap();
+        let cases = vec![
+            (
+                "gemini://example.org/docs/",
+                Some("/srv/gemini/mirror/index.gmi"),
+            ),
+            (
+                "gemini://example.org/docs",
+                Some("/srv/gemini/mirror/index.gmi"),
+            ),
+            (
+                "gemini://example.org/docs/a/b.gmi",
+                Some("/srv/gemini/mirror/a/b.gmi"),
+            ),
+            (
+                "gemini://example.org/docs/a/",
+                Some("/srv/gemini/mirror/a/index.gmi"),
+            ),
+            ("gemini://example.org/docs/%2e%2e/secret", None),
+            ("gemini://example.org/docsearch", None),
+            ("gemini://example.org/docs/a?q", None),
+            ("gemini://example.org:1966/docs/a", None),
+            ("gemini://other.org/docs/a", None),
+            ("https://example.org/docs/a", None),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                mirror.local_path(&url(input)),
+                expected.map(PathBuf::from),
+                "{input}"
+            );
+        }
+
+        let page = b"# Docs\n=> a.gmi\n=> /docs/b/#top\n=> /elsewhere\n=> gemini://other.org/docs/";
+        assert_eq!(
+            links(mirror, &url("gemini://example.org/docs/"), page),
+            vec![
+                url("gemini://example.org/docs/a.gmi"),
+                url("gemini://example.org/docs/b/"),
+            ]
+        );
+
+        let input = r#"server { port 1965; vhost { hostname "localhost"; route { path "/m"; root "/srv"; mirror "gemini://example.org/"; } } }"#;
+        let config = read_and_parse_config(input).unwrap();
+        assert!(Mirror::from_config(&config).is_err());
+    }
+}
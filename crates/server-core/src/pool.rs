@@ -0,0 +1,103 @@
+use crate::config::{Config, GetProperty, Route};
+use crate::stats::Target;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Used when a limited route has no `queue_timeout`.
+pub const DEFAULT_QUEUE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The concurrency limit of a route, `max_concurrent 4;` turns it on. Requests beyond the
+/// limit wait in line for up to `queue_timeout`, after which they are turned away.
+#[derive(Debug)]
+pub struct Pool {
+    permits: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+impl Pool {
+    pub fn for_route(route: &Route) -> Option<Self> {
+        let limit = route
+            .get_property_number("max_concurrent")
+            .filter(|limit| *limit > 0)?;
+
+        Some(Pool {
+            permits: Arc::new(Semaphore::new(limit as usize)),
+            timeout: route
+                .get_property_duration("queue_timeout")
+                .unwrap_or(DEFAULT_QUEUE_TIMEOUT),
+        })
+    }
+
+    /// A slot to run in, held until the permit is dropped. `None` when the route stayed
+    /// saturated for the whole `queue_timeout`.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        let permit = self.permits.clone().acquire_owned();
+
+        tokio::time::timeout(self.timeout, permit).await.ok()?.ok()
+    }
+}
+
+/// The pools of every limited route, by the position of its vhost and route in the config.
+#[derive(Debug)]
+pub struct Pools {
+    vhosts: Vec<Vec<Option<Pool>>>,
+}
+
+impl Pools {
+    pub fn new(config: &Config) -> Self {
+        Pools {
+            vhosts: config
+                .server
+                .vhosts
+                .iter()
+                .map(|vhost| vhost.routes.iter().map(Pool::for_route).collect())
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, target: Target) -> Option<&Pool> {
+        self.vhosts.get(target.vhost?)?.get(target.route?)?.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_and_parse_config;
+
+    #[tokio::test]
+    async fn test_acquire() {
+        let input = r#"
+server
+{
+    port 1965;
+
+    vhost
+    {
+        hostname "localhost";
+
+        route { path "/a"; respond_body "a"; }
+        route { path "/b"; respond_body "b"; max_concurrent 2; queue_timeout 10ms; }
+    }
+}
+"#;
+        let config = read_and_parse_config(input).unwrap();
+        let pools = Pools::new(&config);
+        let target = |route| Target {
+            vhost: Some(0),
+            route: Some(route),
+        };
+
+        assert!(pools.get(target(0)).is_none());
+        assert!(pools.get(Target::default()).is_none());
+
+        let pool = pools.get(target(1)).unwrap();
+        let first = pool.acquire().await.unwrap();
+        let _second = pool.acquire().await.unwrap();
+        assert!(pool.acquire().await.is_none());
+
+        drop(first);
+        assert!(pool.acquire().await.is_some());
+    }
+}
@@ -0,0 +1,236 @@
+use crate::config::{Config, GetProperty, VHost};
+use crate::mirror::client_config;
+use crate::response::Response;
+use crate::router::{Middleware, Request};
+use anyhow::Context;
+use protocol::error::FetchError;
+use protocol::gemini_protocol::client;
+use protocol::url_util::to_ascii_host;
+use rustls::ClientConfig;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use url::Url;
+
+/// Mirrored requests waiting for the upstream at most. Further ones are dropped, so a slow
+/// upstream can't pile up connections on the server it mirrors.
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Longer mirrored responses are given up on.
+const MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Where a vhost sends the copies of its requests:
+///
+/// ```text
+/// vhost { hostname "example.org"; mirror_requests "gemini://staging.example.org:1966/"; mirror_requests_percent 10; }
+/// ```
+///
+/// The request is sent for the URL it was received for, so the upstream serves the same
+/// vhost.
+/// Without `mirror_requests_percent` every request is mirrored.
+#[derive(Debug)]
+struct Upstream {
+    host: String,
+    port: u16,
+    percent: u32,
+    /// The requests the vhost received so far, mirrored or not.
+    seen: AtomicU64,
+}
+
+impl Upstream {
+    fn for_vhost(vhost: &VHost) -> anyhow::Result<Option<Self>> {
+        let Some(upstream) = vhost.get_property_string("mirror_requests") else {
+            return Ok(None);
+        };
+
+        let url = Url::parse(upstream)
+            .ok()
+            .filter(|url| url.scheme() == "gemini" && url.host_str().is_some())
+            .and_then(|url| to_ascii_host(&url).ok())
+            .with_context(|| format!("Invalid mirror_requests URL '{}'", upstream))?;
+
+        let percent = vhost
+            .get_property_number("mirror_requests_percent")
+            .unwrap_or(100);
+        if !(1..=100).contains(&percent) {
+            anyhow::bail!(
+                "mirror_requests_percent of '{}' has to be between 1 and 100",
+                vhost.vhost
+            );
+        }
+
+        Ok(Some(Upstream {
+            host: url.host_str().unwrap_or_default().to_string(),
+            port: url.port().unwrap_or(1965),
+            percent,
+            seen: AtomicU64::new(0),
+        }))
+    }
+
+    /// Whether the next request is mirrored. The mirrored ones are spread evenly rather
+    /// than picked at random, `percent` of every hundred requests.
+    fn sample(&self) -> bool {
+        let n = self.seen.fetch_add(1, Ordering::Relaxed);
+        let percent = u64::from(self.percent);
+
+        (n + 1) * percent / 100 > n * percent / 100
+    }
+}
+
+/// Sends a copy of a share of the requests of a vhost to another server and discards its
+/// responses, to load-test a new backend with real traffic. Requests are mirrored in the
+/// background, they are answered as if there was no mirror.
+pub struct RequestMirror {
+    /// The upstream of every vhost of the config, by index.
+    upstreams: Vec<Option<Arc<Upstream>>>,
+    config: Arc<ClientConfig>,
+    in_flight: Arc<Semaphore>,
+}
+
+impl RequestMirror {
+    /// `None` when no vhost mirrors its requests.
+    pub fn from_config(config: &Config) -> anyhow::Result<Option<Self>> {
+        let upstreams = config
+            .server
+            .vhosts
+            .iter()
+            .map(|vhost| Ok(Upstream::for_vhost(vhost)?.map(Arc::new)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if upstreams.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+
+        Ok(Some(RequestMirror {
+            upstreams,
+            config: client_config()?,
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+        }))
+    }
+}
+
+impl Middleware for RequestMirror {
+    fn before(&self, req: &mut Request<'_>) -> Option<Response> {
+        let upstream = self.upstreams.get(req.target.vhost?)?.as_ref()?;
+        if !upstream.sample() {
+            return None;
+        }
+
+        let Ok(permit) = self.in_flight.clone().try_acquire_owned() else {
+            log::debug!("Not mirroring {:?}, the upstream is busy", req.line);
+            return None;
+        };
+
+        let upstream = upstream.clone();
+        let config = self.config.clone();
+        let url = req.url.clone();
+        let line = req.line.to_string();
+        tokio::spawn(async move {
+            let _permit = permit;
+
+            match send(&upstream, config, &url).await {
+                Ok(read) => log::debug!(
+                    "Mirrored {:?} to {}:{}, {} bytes back",
+                    line,
+                    upstream.host,
+                    upstream.port,
+                    read
+                ),
+                Err(e) => log::warn!(
+                    "Failed to mirror {:?} to {}:{}: {}",
+                    line,
+                    upstream.host,
+                    upstream.port,
+                    e
+                ),
+            }
+        });
+
+        None
+    }
+}
+
+/// Sends the request for `url` to the upstream and discards the response, returns how
+/// long it was. The response is thrown away, so one that may be truncated still counts.
+async fn send(
+    upstream: &Upstream,
+    config: Arc<ClientConfig>,
+    url: &Url,
+) -> Result<usize, FetchError> {
+    let result = client::fetch_raw_from(
+        &upstream.host,
+        upstream.port,
+        url,
+        config,
+        MAX_RESPONSE_SIZE,
+    )
+    .await;
+
+    match result {
+        Ok(response) | Err(FetchError::Truncated(response)) => Ok(response.len()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_and_parse_config;
+
+    #[test]
+    fn test_request_mirror() {
+        let input = r#"
+server
+{
+    port 1965;
+
+    vhost { hostname "localhost"; mirror_requests "gemini://staging.localhost:1966/"; mirror_requests_percent 10; }
+    vhost { hostname "example.org"; }
+}
+"#;
+        let config = read_and_parse_config(input).unwrap();
+        let mirror = RequestMirror::from_config(&config).unwrap().unwrap();
+        assert!(mirror.upstreams[1].is_none());
+
+        let upstream = mirror.upstreams[0].as_ref().unwrap();
+        assert_eq!(
+            (upstream.host.as_str(), upstream.port),
+            ("staging.localhost", 1966)
+        );
+
+        let sampled = (0..1000).filter(|_| upstream.sample()).count();
+        assert_eq!(sampled, 100);
+
+        let vhost = |props: &str| {
+            format!(r#"server {{ port 1965; vhost {{ hostname "localhost"; {props} }} }}"#)
+        };
+
+        let config = read_and_parse_config(&vhost("")).unwrap();
+        assert!(RequestMirror::from_config(&config).unwrap().is_none());
+
+        let config =
+            read_and_parse_config(&vhost(r#"mirror_requests "gemini://staging/";"#)).unwrap();
+        let mirror = RequestMirror::from_config(&config).unwrap().unwrap();
+        let upstream = mirror.upstreams[0].as_ref().unwrap();
+        assert!((0..10).all(|_| upstream.sample()));
+
+        let config =
+            read_and_parse_config(&vhost(r#"mirror_requests "gemini://bücher.example/";"#))
+                .unwrap();
+        let mirror = RequestMirror::from_config(&config).unwrap().unwrap();
+        let upstream = mirror.upstreams[0].as_ref().unwrap();
+        assert_eq!(
+            (upstream.host.as_str(), upstream.port),
+            ("xn--bcher-kva.example", 1965)
+        );
+
+        for props in [
+            r#"mirror_requests "https://staging/";"#,
+            r#"mirror_requests "gemini://staging/"; mirror_requests_percent 0;"#,
+            r#"mirror_requests "gemini://staging/"; mirror_requests_percent 101;"#,
+        ] {
+            let config = read_and_parse_config(&vhost(props)).unwrap();
+            assert!(RequestMirror::from_config(&config).is_err(), "{props}");
+        }
+    }
+}
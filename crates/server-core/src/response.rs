@@ -0,0 +1,101 @@
+use bytes::{Buf, Bytes};
+use protocol::gemini_protocol::response::Response as GeminiResponse;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// A response as it goes out on the wire. The header and the body stay separate buffers and
+/// are written with one vectored write, so a large body is never copied behind its header.
+/// Clones share the body.
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// The status, meta and the terminating `\r\n`.
+    pub header: String,
+    pub body: Bytes,
+}
+
+impl Response {
+    pub fn new(status: u8, meta: &str, body: impl Into<Bytes>) -> Self {
+        Response {
+            header: format!("{} {}\r\n", status, meta),
+            body: body.into(),
+        }
+    }
+
+    /// The header without its line ending, for logging.
+    pub fn header_line(&self) -> &str {
+        self.header.trim_end()
+    }
+
+    pub async fn write_to<W>(&self, writer: &mut W) -> std::io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut buf = self.header.as_bytes().chain(self.body.as_ref());
+
+        writer.write_all_buf(&mut buf).await
+    }
+}
+
+/// Splits a response rendered as one string (scripts) at the end of its header. Only the
+/// header is copied, the body keeps the buffer of the string.
+impl From<String> for Response {
+    fn from(response: String) -> Self {
+        let end = response.find("\r\n").map_or(response.len(), |i| i + 2);
+        let header = response[..end].to_string();
+
+        let mut body = response.into_bytes();
+        body.drain(..end);
+
+        Response {
+            header,
+            body: body.into(),
+        }
+    }
+}
+
+/// Replies built with the types of the protocol crate, a success has its gemtext rendered.
+impl From<GeminiResponse> for Response {
+    fn from(response: GeminiResponse) -> Self {
+        let body = match &response {
+            GeminiResponse::Success(ok) => ok.to_bytes(),
+            _ => vec![],
+        };
+
+        Response::new(response.status(), &response.meta(), body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GeminiResponse, Response};
+
+    #[tokio::test]
+    async fn test_write_to() {
+        let cases = vec![
+            (
+                Response::new(20, "text/gemini", "# Hi"),
+                "20 text/gemini\r\n# Hi",
+            ),
+            (Response::new(51, "Not found", ""), "51 Not found\r\n"),
+            (
+                Response::from("20 text/plain\r\nbody\r\nmore".to_string()),
+                "20 text/plain\r\nbody\r\nmore",
+            ),
+            (
+                Response::from("30 /elsewhere\r\n".to_string()),
+                "30 /elsewhere\r\n",
+            ),
+            (
+                Response::from(GeminiResponse::PermanentRedirect("/dir/".to_string())),
+                "31 /dir/\r\n",
+            ),
+            (Response::from(GeminiResponse::BadRequest(None)), "59 \r\n"),
+        ];
+
+        for (resp, expected) in cases {
+            let mut out = Vec::new();
+            resp.write_to(&mut out).await.unwrap();
+
+            assert_eq!(String::from_utf8(out).unwrap(), expected);
+        }
+    }
+}
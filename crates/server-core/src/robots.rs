@@ -0,0 +1,82 @@
+use crate::config::Robots;
+use protocol::robots::Group;
+
+pub use protocol::robots::ROBOTS_PATH;
+
+/// Renders the vhost's `robots { }` block as a robots.txt for the virtual agents of
+/// https://geminiprotocol.net/docs/companion/robots.gmi
+pub fn render(robots: &Robots) -> String {
+    let groups = robots
+        .agents
+        .iter()
+        .map(|agent| {
+            let name = match agent.agent.0.as_str() {
+                "all" => "*",
+                name => name,
+            };
+
+            Group {
+                agents: vec![name.to_string()],
+                disallow: agent.disallow.clone(),
+            }
+        })
+        .collect();
+
+    protocol::robots::Robots { groups }.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::error::Error;
+    use crate::config::read_and_parse_config;
+
+    fn config(robots: &str) -> String {
+        format!(
+            r#"
+server
+{{
+    vhost
+    {{
+        hostname "localhost";
+
+        robots
+        {{
+            {robots}
+        }}
+    }}
+}}
+    "#
+        )
+    }
+
+    #[test]
+    fn test_render() {
+        let input = config(
+            r#"
+            archiver { disallow "/private/  /drafts/"; }
+            all { disallow "/cgi-bin/"; }
+            indexer { allow "ignored"; }
+        "#,
+        );
+
+        let config = read_and_parse_config(&input).unwrap();
+        let robots = config.server.vhosts[0].robots.as_ref().unwrap();
+
+        assert_eq!(
+            super::render(robots),
+            "User-agent: archiver\nDisallow: /private/\nDisallow: /drafts/\n\n\
+             User-agent: *\nDisallow: /cgi-bin/\n\n\
+             User-agent: indexer\nDisallow:\n\n"
+        );
+    }
+
+    #[test]
+    fn test_unknown_agent() {
+        let input = config(r#"googlebot { disallow "/"; }"#);
+
+        assert!(matches!(
+            read_and_parse_config(&input),
+            Err(Error::InvalidBlockTag(_))
+        ));
+    }
+}
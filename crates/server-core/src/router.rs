@@ -0,0 +1,203 @@
+use crate::client_cert::ClientCert;
+use crate::config::{GetProperty, VHost};
+use crate::response::Response;
+use crate::routing::find_route;
+use crate::stats::{index_of, Target};
+use crate::GlobalState;
+use url::Url;
+
+/// A Gemini request on its way through the [Router], once its vhost is known.
+pub struct Request<'r> {
+    /// The request line without its line ending, also the key of cached responses.
+    pub line: &'r str,
+    pub url: &'r Url,
+    pub vhost: &'r VHost,
+    /// The normalized request path, as templates and scripts see it.
+    pub path: &'r str,
+    /// The path routes and static files are looked up by, which a rewrite replaces.
+    pub route_path: String,
+    pub client_cert: Option<&'r ClientCert>,
+    /// Where the request landed, counted once it has been answered.
+    pub target: Target,
+}
+
+/// A hook around every Gemini request, added with [crate::Server::with_middleware].
+///
+/// `before` runs in the order the middleware was added and can change the request or
+/// answer it right away, which skips the later middleware and the route. `after` runs in
+/// reverse order with the response, for every middleware whose `before` ran.
+pub trait Middleware: Send + Sync {
+    fn before(&self, _req: &mut Request<'_>) -> Option<Response> {
+        None
+    }
+
+    fn after(&self, _req: &Request<'_>, _resp: &mut Response) {}
+}
+
+/// Runs the middleware around the handlers of the routes.
+pub struct Router {
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router {
+            middleware: vec![Box::new(Rewrite)],
+        }
+    }
+}
+
+impl Router {
+    /// Runs after the built-in middleware and any added before.
+    pub fn push(&mut self, middleware: impl Middleware + 'static) {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    pub(crate) async fn handle(&self, state: &GlobalState, req: &mut Request<'_>) -> Response {
+        let mut ran = 0;
+        let mut early = None;
+
+        for middleware in &self.middleware {
+            ran += 1;
+
+            early = middleware.before(req);
+            if early.is_some() {
+                break;
+            }
+        }
+
+        let mut resp = match early {
+            Some(resp) => resp,
+            None => crate::dispatch(state, req).await,
+        };
+
+        for middleware in self.middleware[..ran].iter().rev() {
+            middleware.after(req, &mut resp);
+        }
+
+        resp
+    }
+}
+
+/// `rewrite "/posts/$1.gmi";` on a route serves the request as if it was for the rewritten
+/// path, by the route that matches it or else as a static file. Rewrites are resolved once,
+/// a rewritten path is not rewritten again.
+pub struct Rewrite;
+
+impl Middleware for Rewrite {
+    fn before(&self, req: &mut Request<'_>) -> Option<Response> {
+        let matched = find_route(req.vhost, &req.route_path)?;
+        req.target.route = index_of(&req.vhost.routes, matched.route);
+
+        let rewritten = matched.expand(matched.route.get_property_string("rewrite")?);
+
+        log::debug!("Rewrote {:?} to {:?}", req.route_path, rewritten);
+        req.route_path = rewritten;
+
+        None
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::config::read_and_parse_config;
+    use crate::Server;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Turns away requests without a client certificate below `/private/`.
+    struct RequireCert;
+
+    impl Middleware for RequireCert {
+        fn before(&self, req: &mut Request<'_>) -> Option<Response> {
+            (req.path.starts_with("/private/") && req.client_cert.is_none())
+                .then(|| Response::new(60, "Certificate required", ""))
+        }
+    }
+
+    /// Counts the responses it sees.
+    struct Count(Arc<AtomicUsize>);
+
+    impl Middleware for Count {
+        fn after(&self, _req: &Request<'_>, _resp: &mut Response) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Adds a header parameter to successful responses.
+    struct Tag;
+
+    impl Middleware for Tag {
+        fn after(&self, _req: &Request<'_>, resp: &mut Response) {
+            if resp.header.starts_with('2') {
+                resp.header = resp.header.replace("\r\n", "; tagged\r\n");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware() {
+        let dir = std::env::temp_dir().join(format!("gemini-rewrite-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("posts")).unwrap();
+        std::fs::write(dir.join("posts/first.gmi"), "# First").unwrap();
+
+        let input = format!(
+            r#"
+server
+{{
+    listen_unix "/nonexistent/gemini.sock";
+
+    vhost
+    {{
+        hostname "localhost";
+        root "{}";
+
+        route {{ path "/old/*"; rewrite "/new"; }}
+        route {{ path "/new"; respond_body "new"; }}
+        route {{ path "/gone"; rewrite "/nowhere"; }}
+        route {{ path "/private/page"; respond_body "secret"; }}
+        route {{ path_regex "^/p/([a-z]+)$"; rewrite "/posts/$1.gmi"; }}
+    }}
+}}
+"#,
+            dir.display()
+        );
+        let config = read_and_parse_config(&input).unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        let server = Server::from_config(config)
+            .unwrap()
+            .with_middleware(Count(count.clone()))
+            .with_middleware(RequireCert)
+            .with_middleware(Tag);
+
+        let request = |line: &'static str| {
+            let state = server.state.clone();
+            async move {
+                let mut target = Target::default();
+                let resp = crate::respond(&state, line, None, &mut target).await;
+                (resp.header_line().to_string(), target.route)
+            }
+        };
+
+        assert_eq!(
+            request("gemini://localhost/old/post").await,
+            ("20 text/gemini; tagged".to_string(), Some(1))
+        );
+        assert_eq!(
+            request("gemini://localhost/gone").await,
+            ("51 Not found".to_string(), Some(2))
+        );
+        assert_eq!(
+            request("gemini://localhost/private/page").await,
+            ("60 Certificate required".to_string(), Some(3))
+        );
+        assert_eq!(
+            request("gemini://localhost/p/first").await,
+            ("20 text/gemini; tagged".to_string(), Some(4))
+        );
+        assert_eq!(count.load(Ordering::Relaxed), 4);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
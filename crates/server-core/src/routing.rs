@@ -0,0 +1,455 @@
+use crate::config::{Route, VHost};
+use percent_encoding::percent_decode_str;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::{Captures, Regex};
+
+/// Encoded in a capture that is expanded into a URL, `/` is kept so a group can span
+/// several segments.
+const CAPTURE: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// A route path as written in the config, matched against the decoded request path.
+///
+/// Routes are selected by kind first and config order second: an exact match wins,
+/// then the longest matching prefix, then the first matching glob or regex.
+#[derive(Debug)]
+pub enum RoutePattern {
+    /// `path "/index";`
+    Exact(String),
+    /// `path_prefix "/docs/";`
+    Prefix(String),
+    /// `path "/blog/*.gmi";`
+    ///
+    /// `*` matches within a single path segment, `**` matches across segments
+    /// and `?` matches a single character other than `/`.
+    Glob(String),
+    /// `path_regex "^/user/(?<name>[^/]+)$";`
+    Regex(Regex),
+}
+
+impl PartialEq for RoutePattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (RoutePattern::Exact(a), RoutePattern::Exact(b)) => a == b,
+            (RoutePattern::Prefix(a), RoutePattern::Prefix(b)) => a == b,
+            (RoutePattern::Glob(a), RoutePattern::Glob(b)) => a == b,
+            (RoutePattern::Regex(a), RoutePattern::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for RoutePattern {}
+
+const GLOB_CHARS: &[char] = &['*', '?'];
+
+impl RoutePattern {
+    pub fn new(path: &str) -> Self {
+        if path.contains(GLOB_CHARS) {
+            RoutePattern::Glob(path.to_string())
+        } else {
+            RoutePattern::Exact(path.to_string())
+        }
+    }
+
+    pub fn new_regex(path: &str) -> Result<Self, regex::Error> {
+        Ok(RoutePattern::Regex(Regex::new(path)?))
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            RoutePattern::Exact(p) => p == path,
+            RoutePattern::Prefix(p) => path.starts_with(p.as_str()),
+            RoutePattern::Glob(p) => glob_match(p.as_bytes(), path.as_bytes()),
+            RoutePattern::Regex(r) => r.is_match(path),
+        }
+    }
+
+    /// Whether every path matched by `self` is also matched by `other`, assuming
+    /// `other` takes priority. Only catches the obvious cases.
+    fn is_shadowed_by(&self, other: &RoutePattern) -> bool {
+        match (self, other) {
+            (RoutePattern::Glob(glob), RoutePattern::Prefix(prefix)) => {
+                glob_literal_prefix(glob).starts_with(prefix.as_str())
+            }
+            _ => self == other,
+        }
+    }
+}
+
+/// The part of a glob before its first wildcard.
+fn glob_literal_prefix(glob: &str) -> &str {
+    let end = glob.find(GLOB_CHARS).unwrap_or(glob.len());
+
+    &glob[..end]
+}
+
+fn priority(pattern: &RoutePattern) -> u8 {
+    match pattern {
+        RoutePattern::Exact(_) => 0,
+        RoutePattern::Prefix(_) => 1,
+        RoutePattern::Glob(_) | RoutePattern::Regex(_) => 2,
+    }
+}
+
+pub(crate) fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| glob_match(rest, &path[i..])),
+        [b'*', rest @ ..] => {
+            let segment = path.iter().position(|&c| c == b'/').unwrap_or(path.len());
+
+            (0..=segment).any(|i| glob_match(rest, &path[i..]))
+        }
+        [b'?', rest @ ..] => match path {
+            [c, path @ ..] if *c != b'/' => glob_match(rest, path),
+            _ => false,
+        },
+        [c, rest @ ..] => match path {
+            [p, path @ ..] if p == c => glob_match(rest, path),
+            _ => false,
+        },
+    }
+}
+
+/// Decodes the request path and resolves `.`, `..` and empty segments, so routes see a
+/// single spelling of every path and `..` can't climb above `/`, even when encoded as
+/// `%2e%2e`. `None` when the decoded path isn't valid UTF-8 or contains a control
+/// character, e.g. a CR or LF that would end up in a response header.
+pub fn normalize_path(path: &str) -> Option<String> {
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    if decoded.contains(char::is_control) {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let trailing_slash = matches!(decoded.rsplit('/').next(), Some("" | "." | ".."));
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+
+    Some(normalized)
+}
+
+pub struct RouteMatch<'v, 'p> {
+    pub route: &'v Route,
+    /// Capture groups of a `path_regex` route.
+    pub captures: Option<Captures<'p>>,
+}
+
+impl RouteMatch<'_, '_> {
+    /// Substitutes `$1`, `$name` and `${name}` in `template` with the captured groups.
+    pub fn expand(&self, template: &str) -> String {
+        self.expand_with(template, str::to_string)
+    }
+
+    /// Like [RouteMatch::expand], with the captured groups percent-encoded, for a URL that
+    /// goes into a response header. Captures are decoded request input.
+    pub fn expand_url(&self, template: &str) -> String {
+        self.expand_with(template, |group| {
+            utf8_percent_encode(group, CAPTURE).to_string()
+        })
+    }
+
+    /// `$$` is a `$`, a reference to a group that didn't match is empty and a `$` that
+    /// isn't a reference is kept, like [Captures::expand].
+    fn expand_with(&self, template: &str, encode: impl Fn(&str) -> String) -> String {
+        let Some(captures) = &self.captures else {
+            return template.to_string();
+        };
+
+        let mut dst = String::new();
+        let mut rest = template;
+        while let Some(i) = rest.find('$') {
+            dst.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+
+            if let Some(after) = rest.strip_prefix('$') {
+                dst.push('$');
+                rest = after;
+                continue;
+            }
+
+            let (name, after) = match rest.strip_prefix('{') {
+                Some(braced) => match braced.split_once('}') {
+                    Some((name, after)) => (name, after),
+                    None => ("", rest),
+                },
+                None => {
+                    let end = rest
+                        .find(|c: char| c != '_' && !c.is_ascii_alphanumeric())
+                        .unwrap_or(rest.len());
+                    rest.split_at(end)
+                }
+            };
+            if name.is_empty() {
+                dst.push('$');
+                continue;
+            }
+
+            let group = match name.parse::<usize>() {
+                Ok(index) => captures.get(index),
+                Err(_) => captures.name(name),
+            };
+            if let Some(group) = group {
+                dst.push_str(&encode(group.as_str()));
+            }
+            rest = after;
+        }
+        dst.push_str(rest);
+
+        dst
+    }
+}
+
+/// Finds the route of the vhost that handles the decoded request path, see [RoutePattern].
+pub fn find_route<'v, 'p>(vhost: &'v VHost, path: &'p str) -> Option<RouteMatch<'v, 'p>> {
+    let routes = &vhost.routes;
+
+    let exact = routes
+        .iter()
+        .find(|route| matches!(&route.pattern, RoutePattern::Exact(p) if p == path));
+
+    // `max_by_key` returns the last maximum, so reverse to let the earlier route win ties.
+    let prefix = || {
+        routes
+            .iter()
+            .rev()
+            .filter_map(|route| match &route.pattern {
+                RoutePattern::Prefix(p) if path.starts_with(p.as_str()) => Some((p.len(), route)),
+                _ => None,
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, route)| route)
+    };
+
+    if let Some(route) = exact.or_else(prefix) {
+        return Some(RouteMatch {
+            route,
+            captures: None,
+        });
+    }
+
+    routes.iter().find_map(|route| match &route.pattern {
+        RoutePattern::Regex(r) => r.captures(path).map(|captures| RouteMatch {
+            route,
+            captures: Some(captures),
+        }),
+        RoutePattern::Glob(_) => route.pattern.matches(path).then_some(RouteMatch {
+            route,
+            captures: None,
+        }),
+        _ => None,
+    })
+}
+
+/// Pairs of `(shadowed, by)` routes, where `shadowed` can never be selected because
+/// `by` always wins first.
+pub fn shadowed_routes(vhost: &VHost) -> Vec<(&Route, &Route)> {
+    let mut shadowed = Vec::new();
+
+    for (idx, route) in vhost.routes.iter().enumerate() {
+        let by = vhost.routes.iter().enumerate().find(|(other_idx, other)| {
+            let wins = match priority(&other.pattern).cmp(&priority(&route.pattern)) {
+                std::cmp::Ordering::Less => true,
+                std::cmp::Ordering::Equal => *other_idx < idx,
+                std::cmp::Ordering::Greater => false,
+            };
+
+            wins && route.pattern.is_shadowed_by(&other.pattern)
+        });
+
+        if let Some((_, by)) = by {
+            shadowed.push((route, by));
+        }
+    }
+
+    shadowed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_path, RoutePattern};
+    use crate::config::{read_and_parse_config, GetProperty};
+
+    #[test]
+    fn test_exact() {
+        let pattern = RoutePattern::new("/index");
+
+        assert_eq!(pattern, RoutePattern::Exact("/index".to_string()));
+        assert!(pattern.matches("/index"));
+        assert!(!pattern.matches("/index.gmi"));
+        assert!(!pattern.matches("/"));
+    }
+
+    #[test]
+    fn test_glob() {
+        let cases = vec![
+            ("/blog/*.gmi", "/blog/hello.gmi", true),
+            ("/blog/*.gmi", "/blog/.gmi", true),
+            ("/blog/*.gmi", "/blog/2024/hello.gmi", false),
+            ("/blog/*.gmi", "/blog/hello.txt", false),
+            ("/blog/**", "/blog/2024/hello.gmi", true),
+            ("/blog/**.gmi", "/blog/2024/hello.gmi", true),
+            ("/blog/**", "/other/hello.gmi", false),
+            ("/file?.gmi", "/file1.gmi", true),
+            ("/file?.gmi", "/file.gmi", false),
+            ("/file?.gmi", "/file/.gmi", false),
+            ("/*/index.gmi", "/docs/index.gmi", true),
+            ("*", "/", false),
+        ];
+
+        for (pattern, path, expected) in cases {
+            assert_eq!(
+                RoutePattern::new(pattern).matches(path),
+                expected,
+                "{pattern} against {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        let cases = vec![
+            ("", Some("/")),
+            ("/", Some("/")),
+            ("/docs/index.gmi", Some("/docs/index.gmi")),
+            ("//docs///index.gmi", Some("/docs/index.gmi")),
+            ("/docs/./faq/../index.gmi", Some("/docs/index.gmi")),
+            ("/docs/", Some("/docs/")),
+            ("/docs/.", Some("/docs/")),
+            ("/docs/sub/..", Some("/docs/")),
+            ("/../../etc/passwd", Some("/etc/passwd")),
+            ("/%2e%2e/%2E%2E/etc/passwd", Some("/etc/passwd")),
+            ("/docs%2F..%2Fsecret", Some("/secret")),
+            ("/caf%C3%A9", Some("/café")),
+            ("/%FF", None),
+            ("/nul%00.gmi", None),
+            ("/old/%0D%0A20%20text/gemini", None),
+            ("/tab%09", None),
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!(normalize_path(path).as_deref(), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_regex_captures() {
+        let input = r#"
+server
+{
+    vhost
+    {
+        hostname "localhost";
+
+        route
+        {
+            path_regex "^/user/(?<name>[a-z]+)/(\d+)$";
+            redirect   "/people/$name/${2}.gmi";
+        }
+    }
+}
+    "#;
+
+        let config = read_and_parse_config(input).unwrap();
+        let vhost = &config.server.vhosts[0];
+
+        assert!(super::find_route(vhost, "/user/Bob/1").is_none());
+
+        let matched = super::find_route(vhost, "/user/bob/12").unwrap();
+        assert_eq!(
+            matched.expand("/people/$name/${2}.gmi"),
+            "/people/bob/12.gmi"
+        );
+        assert_eq!(matched.expand("$$1 $9 $ ${2"), "$1  $ ${2");
+    }
+
+    #[test]
+    fn test_expand_url() {
+        let input = r#"server { vhost { hostname "localhost"; route { path_regex "(?s)^/old/(.*)$"; redirect "/new/$1"; } } }"#;
+        let config = read_and_parse_config(input).unwrap();
+        let vhost = &config.server.vhosts[0];
+
+        // Controls are rejected before they reach a route, and encoded if one does anyway.
+        assert_eq!(normalize_path("/old/a%0D%0A20%20text/gemini"), None);
+
+        let cases = vec![
+            ("/old/a/b.gmi", "/new/a/b.gmi"),
+            ("/old/a b?c#d%", "/new/a%20b%3Fc%23d%25"),
+            ("/old/a\r\n20 text/gemini", "/new/a%0D%0A20%20text/gemini"),
+            ("/old/café", "/new/caf%C3%A9"),
+        ];
+
+        for (path, expected) in cases {
+            let matched = super::find_route(vhost, path).unwrap();
+            assert_eq!(matched.expand_url("/new/$1"), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_priority() {
+        let input = r#"
+server
+{
+    vhost
+    {
+        hostname "localhost";
+
+        route { path "/docs/*.gmi"; respond_body "glob"; }
+        route { path_prefix "/"; respond_body "root"; }
+        route { path_prefix "/docs/"; respond_body "docs"; }
+        route { path "/docs/index.gmi"; respond_body "exact"; }
+        route { path "/other/*.gmi"; respond_body "other"; }
+    }
+}
+    "#;
+
+        let config = read_and_parse_config(input).unwrap();
+        let vhost = &config.server.vhosts[0];
+
+        let cases = vec![
+            ("/docs/index.gmi", "exact"),
+            ("/docs/faq.gmi", "docs"),
+            ("/other/faq.gmi", "root"),
+            ("/", "root"),
+        ];
+
+        for (path, expected) in cases {
+            let matched = super::find_route(vhost, path).unwrap();
+
+            assert_eq!(
+                matched.route.get_property_string("respond_body"),
+                Some(expected),
+                "{path}"
+            );
+        }
+
+        let shadowed = super::shadowed_routes(vhost)
+            .into_iter()
+            .map(|(route, by)| (route.path.0.as_str(), by.path.0.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(shadowed, vec![("/docs/*.gmi", "/"), ("/other/*.gmi", "/")]);
+    }
+}
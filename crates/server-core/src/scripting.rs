@@ -0,0 +1,203 @@
+use crate::template::TemplateContext;
+use rhai::{Dynamic, Engine, Map, Scope};
+use std::fmt::Display;
+use std::sync::LazyLock;
+
+/// Scripts run on the connection's task, so a runaway script is cut off after this many
+/// operations instead of stalling the worker.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_expr_depths(64, 32);
+    engine
+});
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ScriptError {
+    Compile(String),
+    Runtime(String),
+    InvalidResponse(String),
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScriptError::Compile(e) => write!(f, "Failed to compile script: {}", e),
+            ScriptError::Runtime(e) => write!(f, "Script failed: {}", e),
+            ScriptError::InvalidResponse(e) => write!(f, "Invalid script response: {}", e),
+        }
+    }
+}
+
+/// Checks the script syntax without running it.
+pub fn check(source: &str) -> Result<(), ScriptError> {
+    ENGINE
+        .compile(source)
+        .map(|_| ())
+        .map_err(|e| ScriptError::Compile(e.to_string()))
+}
+
+/// Runs a `script` route and returns the full response.
+///
+/// The script sees a `request` map with `host`, `path`, `query`, `cert_cn`,
+/// `tls_client_hash` (`()` when absent) and `auth_type` (`"CERTIFICATE"` with a client
+/// certificate) and its last value is the response: either a string, sent as a `20` body
+/// with `meta`, or a map of `status` (defaults to `20`), `meta` and `body` (`2x` only).
+pub fn run(source: &str, ctx: &TemplateContext, meta: &str) -> Result<String, ScriptError> {
+    let ast = ENGINE
+        .compile(source)
+        .map_err(|e| ScriptError::Compile(e.to_string()))?;
+
+    let optional = |value: Option<&str>| value.map_or(Dynamic::UNIT, |v| v.into());
+
+    let mut request = Map::new();
+    request.insert("host".into(), ctx.host.into());
+    request.insert("path".into(), ctx.path.into());
+    request.insert("query".into(), optional(ctx.query));
+    request.insert("cert_cn".into(), optional(ctx.cert_cn));
+    request.insert("tls_client_hash".into(), optional(ctx.cert_hash));
+    request.insert(
+        "auth_type".into(),
+        optional(ctx.cert_hash.map(|_| "CERTIFICATE")),
+    );
+
+    let mut scope = Scope::new();
+    scope.push("request", request);
+
+    let value = ENGINE
+        .eval_ast_with_scope::<Dynamic>(&mut scope, &ast)
+        .map_err(|e| ScriptError::Runtime(e.to_string()))?;
+
+    response(value, meta)
+}
+
+fn response(value: Dynamic, default_meta: &str) -> Result<String, ScriptError> {
+    if value.is_string() {
+        return Ok(format!("20 {}\r\n{}", default_meta, value));
+    }
+
+    let Some(mut map) = value.clone().try_cast::<Map>() else {
+        return Err(ScriptError::InvalidResponse(format!(
+            "expected a string or map, got {}",
+            value.type_name()
+        )));
+    };
+
+    let mut field = |name: &str| map.remove(name).filter(|v| !v.is_unit());
+
+    let status = match field("status") {
+        Some(status) => status
+            .as_int()
+            .ok()
+            .filter(|s| (10..=69).contains(s))
+            .ok_or_else(|| ScriptError::InvalidResponse(format!("status {}", status)))?,
+        None => 20,
+    };
+    let success = (20..=29).contains(&status);
+
+    let string = |name: &str, value: Option<Dynamic>| match value {
+        Some(value) if value.is_string() => Ok(Some(value.to_string())),
+        Some(value) => Err(ScriptError::InvalidResponse(format!(
+            "{} must be a string, got {}",
+            name,
+            value.type_name()
+        ))),
+        None => Ok(None),
+    };
+
+    let meta = string("meta", field("meta"))?
+        .or_else(|| success.then(|| default_meta.to_string()))
+        .unwrap_or_default();
+    let body = string("body", field("body"))?;
+
+    if body.is_some() && !success {
+        return Err(ScriptError::InvalidResponse(format!(
+            "status {} can't have a body",
+            status
+        )));
+    }
+
+    Ok(format!(
+        "{} {}\r\n{}",
+        status,
+        meta,
+        body.unwrap_or_default()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let ctx = TemplateContext {
+            host: "localhost",
+            path: "/hello",
+            query: Some("world"),
+            cert_cn: None,
+            cert_hash: None,
+        };
+
+        let cases = vec![
+            (
+                r#"`# Hello ${request.query} from ${request.path}`"#,
+                Ok("20 text/gemini\r\n# Hello world from /hello".to_string()),
+            ),
+            (
+                r#"if request.cert_cn == () { #{ status: 60, meta: "Certificate required" } }"#,
+                Ok("60 Certificate required\r\n".to_string()),
+            ),
+            (
+                r#"`${request.auth_type}${request.tls_client_hash}`"#,
+                Ok("20 text/gemini\r\n".to_string()),
+            ),
+            (
+                r#"#{ meta: "text/plain", body: request.host }"#,
+                Ok("20 text/plain\r\nlocalhost".to_string()),
+            ),
+            (
+                r#"#{ status: 30, meta: "/other" }"#,
+                Ok("30 /other\r\n".to_string()),
+            ),
+            (
+                r#"#{ status: 100 }"#,
+                Err(ScriptError::InvalidResponse("status 100".to_string())),
+            ),
+            (
+                r#"#{ status: 51, body: "nope" }"#,
+                Err(ScriptError::InvalidResponse(
+                    "status 51 can't have a body".to_string(),
+                )),
+            ),
+            (
+                "42",
+                Err(ScriptError::InvalidResponse(
+                    "expected a string or map, got i64".to_string(),
+                )),
+            ),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(run(source, &ctx, "text/gemini"), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let ctx = TemplateContext::default();
+
+        assert!(matches!(check("let x = ;"), Err(ScriptError::Compile(_))));
+        assert_eq!(check("`${request.path}`"), Ok(()));
+        assert!(matches!(
+            run("loop {}", &ctx, "text/gemini"),
+            Err(ScriptError::Runtime(_))
+        ));
+        assert!(matches!(
+            run(r#"throw "oops""#, &ctx, "text/gemini"),
+            Err(ScriptError::Runtime(_))
+        ));
+    }
+}
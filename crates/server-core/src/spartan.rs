@@ -0,0 +1,137 @@
+use crate::response::Response;
+use crate::stats::Target;
+use crate::{respond, GlobalStateArc, MAX_REQUEST_SIZE};
+use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+use protocol::spartan::request::{Request, RequestLine};
+use protocol::spartan::response::status_from_gemini;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use url::{Position, Url};
+
+/// `listen_spartan "[::]:300";` serves the vhosts over Spartan as well, without TLS.
+///
+/// A Spartan request is answered by the routes of the Gemini URL it maps to, its content is
+/// the query. The content is held to the length of a Gemini request for that reason.
+pub(crate) async fn serve_request<S>(stream: S, global_state: GlobalStateArc) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    (&mut reader)
+        .take(MAX_REQUEST_SIZE as u64)
+        .read_line(&mut line)
+        .await?;
+
+    let mut target = Target::default();
+    let resp = match RequestLine::parse(&line) {
+        Ok(request) if request.content_length > MAX_REQUEST_SIZE => {
+            Response::new(4, "Content too large", "")
+        }
+        Ok(request) => {
+            let mut content = vec![0; request.content_length];
+            reader.read_exact(&mut content).await?;
+            let url = gemini_url(&request.with_content(content));
+
+            let resp = respond(&global_state, url.as_str(), None, &mut target).await;
+            global_state.stats.record(target, &resp);
+
+            to_spartan(&resp, &url)
+        }
+        Err(e) => Response::new(4, &e.to_string(), ""),
+    };
+
+    log::info!(
+        target: "access",
+        "{:?} {} -",
+        line.trim_end(),
+        resp.header_line()
+    );
+
+    let stream = reader.get_mut();
+    resp.write_to(stream).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+/// `gemini://<host><path>`, with the percent-encoded content as its query.
+fn gemini_url(request: &Request) -> Url {
+    let mut url = format!("gemini://{}{}", request.host, request.path);
+    if !request.content.is_empty() {
+        url.push('?');
+        url.extend(percent_encode(&request.content, NON_ALPHANUMERIC));
+    }
+
+    // Anything the request line can carry parses, an invalid host is refused by the router.
+    Url::parse(&url).unwrap_or_else(|_| Url::parse("gemini://invalid/").unwrap())
+}
+
+/// The response to a Gemini request as Spartan sends it. A redirect keeps the path of its
+/// target, Spartan only redirects on the same host.
+fn to_spartan(resp: &Response, url: &Url) -> Response {
+    let line = resp.header_line();
+    let (status, meta) = line.split_once(' ').unwrap_or((line, ""));
+    let status = status_from_gemini(status.parse().unwrap_or(40));
+
+    let meta = match status {
+        3 => url
+            .join(meta)
+            .map(|target| target[Position::BeforePath..].to_string())
+            .unwrap_or_else(|_| meta.to_string()),
+        _ => meta.to_string(),
+    };
+
+    Response {
+        header: format!("{} {}\r\n", status, meta),
+        body: resp.body.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gemini_url() {
+        let request = RequestLine::parse("example.org /guestbook 8\r\n")
+            .unwrap()
+            .with_content(b"Hi there".to_vec());
+        assert_eq!(
+            gemini_url(&request).as_str(),
+            "gemini://example.org/guestbook?Hi%20there"
+        );
+
+        let request = RequestLine::parse("example.org / 0\r\n")
+            .unwrap()
+            .with_content(vec![]);
+        assert_eq!(gemini_url(&request).as_str(), "gemini://example.org/");
+    }
+
+    #[test]
+    fn test_to_spartan() {
+        let url = Url::parse("gemini://example.org/dir/page").unwrap();
+
+        let cases = vec![
+            (
+                Response::new(20, "text/gemini", "# Hi"),
+                "2 text/gemini\r\n",
+            ),
+            (Response::new(31, "/new/", ""), "3 /new/\r\n"),
+            (Response::new(30, "other?q", ""), "3 /dir/other?q\r\n"),
+            (
+                Response::new(30, "gemini://example.org/elsewhere", ""),
+                "3 /elsewhere\r\n",
+            ),
+            (Response::new(51, "Not found", ""), "4 Not found\r\n"),
+            (Response::new(10, "Name?", ""), "4 Name?\r\n"),
+            (Response::new(42, "CGI failed", ""), "5 CGI failed\r\n"),
+        ];
+
+        for (resp, expected) in cases {
+            let spartan = to_spartan(&resp, &url);
+            assert_eq!(spartan.header, expected);
+            assert_eq!(spartan.body, resp.body);
+        }
+    }
+}
@@ -0,0 +1,433 @@
+use crate::config::{Config, GetProperty};
+use crate::response::Response;
+use crate::watch::Change;
+use protocol::gemtext::builder::GemTextBuilder;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Changed files listed on the status page, the most recent first.
+const RECENT_CHANGES: usize = 20;
+
+/// Request, byte and error (4x/5x) counts. Updates are relaxed atomic adds, so counting
+/// never makes connections wait on each other.
+#[derive(Debug, Default)]
+pub struct Counters {
+    requests: AtomicU64,
+    bytes: AtomicU64,
+    errors: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Snapshot {
+    pub requests: u64,
+    pub bytes: u64,
+    pub errors: u64,
+}
+
+impl Counters {
+    fn record(&self, resp: &Response) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(
+            (resp.header.len() + resp.body.len()) as u64,
+            Ordering::Relaxed,
+        );
+
+        if resp.header.starts_with(['4', '5']) {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Where a request ended up, filled in while it is handled so it can be counted once the
+/// response is known. Indices into the vhosts of the config and the routes of the vhost.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Target {
+    pub vhost: Option<usize>,
+    pub route: Option<usize>,
+}
+
+/// The position of `item` within `items`, by address.
+pub fn index_of<T>(items: &[T], item: &T) -> Option<usize> {
+    items.iter().position(|i| std::ptr::eq(i, item))
+}
+
+/// Counters for the whole server, every vhost and every route of the config.
+#[derive(Debug)]
+pub struct Stats {
+    total: Counters,
+    vhosts: Vec<(Counters, Vec<Counters>)>,
+    /// The server has `watch_files`, see [crate::watch::FileWatcher].
+    watching: bool,
+    file_changes: AtomicU64,
+    recent_changes: Mutex<VecDeque<(Instant, Change)>>,
+}
+
+impl Stats {
+    pub fn new(config: &Config) -> Self {
+        Stats {
+            total: Counters::default(),
+            vhosts: config
+                .server
+                .vhosts
+                .iter()
+                .map(|vhost| {
+                    let routes = vhost.routes.iter().map(|_| Counters::default()).collect();
+                    (Counters::default(), routes)
+                })
+                .collect(),
+            watching: config.get_property_duration("watch_files").is_some(),
+            file_changes: AtomicU64::new(0),
+            recent_changes: Mutex::default(),
+        }
+    }
+
+    pub fn record_change(&self, change: Change) {
+        self.file_changes.fetch_add(1, Ordering::Relaxed);
+
+        let mut recent = self.recent_changes.lock().unwrap();
+        recent.push_front((Instant::now(), change));
+        recent.truncate(RECENT_CHANGES);
+    }
+
+    /// The recently changed files with how many seconds ago they changed, `None` when the
+    /// server doesn't watch its files.
+    fn recent_changes(&self) -> Option<Vec<(u64, Change)>> {
+        let recent = self.recent_changes.lock().unwrap();
+        let changes = recent
+            .iter()
+            .map(|(at, change)| (at.elapsed().as_secs(), change.clone()))
+            .collect();
+
+        self.watching.then_some(changes)
+    }
+
+    pub fn record(&self, target: Target, resp: &Response) {
+        self.total.record(resp);
+
+        let Some((vhost, routes)) = target.vhost.and_then(|i| self.vhosts.get(i)) else {
+            return;
+        };
+        vhost.record(resp);
+
+        if let Some(route) = target.route.and_then(|i| routes.get(i)) {
+            route.record(resp);
+        }
+    }
+
+    pub fn total(&self) -> Snapshot {
+        self.total.snapshot()
+    }
+
+    /// The counters of a vhost and its routes, by their position in the config.
+    pub fn vhost(&self, idx: usize) -> Option<(Snapshot, Vec<Snapshot>)> {
+        let (vhost, routes) = self.vhosts.get(idx)?;
+
+        Some((
+            vhost.snapshot(),
+            routes.iter().map(Counters::snapshot).collect(),
+        ))
+    }
+
+    /// The `status_page "gemtext";` of a route.
+    pub fn render_gemtext(&self, config: &Config) -> String {
+        let line = |s: Snapshot| {
+            format!(
+                "{} requests, {} bytes, {} errors",
+                s.requests, s.bytes, s.errors
+            )
+        };
+        let section = |page: GemTextBuilder, depth, title: String| {
+            page.text("").heading(depth, title).text("")
+        };
+
+        let mut page = GemTextBuilder::new()
+            .heading(1, "Server status")
+            .text("")
+            .list_item(line(self.total()));
+
+        for (idx, vhost) in config.server.vhosts.iter().enumerate() {
+            let Some((counters, routes)) = self.vhost(idx) else {
+                continue;
+            };
+
+            page = section(page, 2, vhost.vhost.to_string()).list_item(line(counters));
+            for (route, counters) in vhost.routes.iter().zip(routes) {
+                page = section(page, 3, route.path.to_string()).list_item(line(counters));
+            }
+        }
+
+        if let Some(changes) = self.recent_changes() {
+            page = section(page, 2, "Changed files".to_string());
+            if changes.is_empty() {
+                page = page.list_item("None since the server started");
+            }
+            for (ago, change) in changes {
+                page = page.list_item(format!(
+                    "{} {}s ago: {}",
+                    change.kind,
+                    ago,
+                    change.path.display()
+                ));
+            }
+        }
+
+        page.build().to_string()
+    }
+
+    /// The `status_page "json";` of a route, and the `.json` variant of the others.
+    ///
+    /// ```json
+    /// {"requests": 4, "bytes": 70, "errors": 2, "vhosts": [
+    ///   {"vhost": "localhost", "requests": 3, ..., "routes": [{"route": "/a", ...}]}
+    /// ]}
+    /// ```
+    pub fn render_json(&self, config: &Config) -> String {
+        let counters = |s: Snapshot| {
+            format!(
+                "\"requests\":{},\"bytes\":{},\"errors\":{}",
+                s.requests, s.bytes, s.errors
+            )
+        };
+
+        let vhosts = config
+            .server
+            .vhosts
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, vhost)| {
+                let (vhost_counters, routes) = self.vhost(idx)?;
+                let routes = vhost
+                    .routes
+                    .iter()
+                    .zip(routes)
+                    .map(|(route, s)| {
+                        format!(
+                            "{{\"route\":{},{}}}",
+                            json_string(&route.path.0),
+                            counters(s)
+                        )
+                    })
+                    .collect::<Vec<_>>();
+
+                Some(format!(
+                    "{{\"vhost\":{},{},\"routes\":[{}]}}",
+                    json_string(&vhost.vhost.0),
+                    counters(vhost_counters),
+                    routes.join(",")
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        let changes = self.recent_changes().map(|changes| {
+            let changes = changes
+                .into_iter()
+                .map(|(ago, change)| {
+                    format!(
+                        "{{\"path\":{},\"change\":\"{}\",\"seconds_ago\":{}}}",
+                        json_string(&change.path.to_string_lossy()),
+                        change.kind,
+                        ago
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            format!(
+                ",\"file_changes\":{},\"recent_changes\":[{}]",
+                self.file_changes.load(Ordering::Relaxed),
+                changes.join(",")
+            )
+        });
+
+        format!(
+            "{{{},\"vhosts\":[{}]{}}}\n",
+            counters(self.total()),
+            vhosts.join(","),
+            changes.unwrap_or_default()
+        )
+    }
+
+    /// The `status_page "prometheus";` of a route, in the Prometheus text format.
+    pub fn render_prometheus(&self, config: &Config) -> String {
+        let mut out = String::new();
+
+        for name in ["requests", "bytes", "errors"] {
+            let value = |s: Snapshot| match name {
+                "requests" => s.requests,
+                "bytes" => s.bytes,
+                _ => s.errors,
+            };
+
+            let _ = writeln!(out, "# TYPE gemini_{}_total counter", name);
+            let _ = writeln!(out, "gemini_{}_total {}", name, value(self.total()));
+
+            let vhosts = config
+                .server
+                .vhosts
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, vhost)| Some((vhost, self.vhost(idx)?)))
+                .collect::<Vec<_>>();
+
+            let _ = writeln!(out, "# TYPE gemini_vhost_{}_total counter", name);
+            for (vhost, (counters, _)) in &vhosts {
+                let _ = writeln!(
+                    out,
+                    "gemini_vhost_{}_total{{vhost=\"{}\"}} {}",
+                    name,
+                    escape_label(&vhost.vhost.0),
+                    value(*counters)
+                );
+            }
+
+            let _ = writeln!(out, "# TYPE gemini_route_{}_total counter", name);
+            for (vhost, (_, routes)) in &vhosts {
+                for (route, counters) in vhost.routes.iter().zip(routes) {
+                    let _ = writeln!(
+                        out,
+                        "gemini_route_{}_total{{vhost=\"{}\",route=\"{}\"}} {}",
+                        name,
+                        escape_label(&vhost.vhost.0),
+                        escape_label(&route.path.0),
+                        value(*counters)
+                    );
+                }
+            }
+        }
+
+        if self.watching {
+            let _ = writeln!(out, "# TYPE gemini_file_changes_total counter");
+            let _ = writeln!(
+                out,
+                "gemini_file_changes_total {}",
+                self.file_changes.load(Ordering::Relaxed)
+            );
+        }
+
+        out
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn escape_label(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_and_parse_config;
+    use crate::watch::ChangeKind;
+
+    #[test]
+    fn test_record() {
+        let input = r#"
+server
+{
+    port 1965;
+
+    vhost
+    {
+        hostname "localhost";
+
+        route { path "/a"; respond_body "a"; }
+        route { path "/b"; respond_body "b"; }
+    }
+}
+"#;
+        let config = read_and_parse_config(input).unwrap();
+        let stats = Stats::new(&config);
+
+        let ok = Response::new(20, "text/gemini", "hello");
+        let not_found = Response::new(51, "Not found", "");
+        let route = |route| Target {
+            vhost: Some(0),
+            route: Some(route),
+        };
+
+        stats.record(route(0), &ok);
+        stats.record(route(0), &not_found);
+        stats.record(route(1), &ok);
+        stats.record(Target::default(), &not_found);
+
+        let snapshot = |requests, bytes, errors| Snapshot {
+            requests,
+            bytes,
+            errors,
+        };
+
+        assert_eq!(stats.total(), snapshot(4, 21 + 14 + 21 + 14, 2));
+
+        let (vhost, routes) = stats.vhost(0).unwrap();
+        assert_eq!(vhost, snapshot(3, 21 + 14 + 21, 1));
+        assert_eq!(routes, vec![snapshot(2, 35, 1), snapshot(1, 21, 0)]);
+
+        let metrics = stats.render_prometheus(&config);
+        assert!(metrics.contains("gemini_requests_total 4\n"));
+        assert!(metrics.contains("gemini_route_errors_total{vhost=\"localhost\",route=\"/a\"} 1\n"));
+
+        let json = stats.render_json(&config);
+        assert!(json.starts_with(
+            "{\"requests\":4,\"bytes\":70,\"errors\":2,\"vhosts\":[{\"vhost\":\"localhost\","
+        ));
+        assert!(json.contains("{\"route\":\"/b\",\"requests\":1,\"bytes\":21,\"errors\":0}"));
+        assert_eq!(json_string("a \"b\"\\\n"), "\"a \\\"b\\\"\\\\\\u000a\"");
+
+        let page = stats.render_gemtext(&config);
+        assert!(page.contains("### /b\n\n* 1 requests, 21 bytes, 0 errors\n"));
+        assert!(!page.contains("Changed files"));
+    }
+
+    #[test]
+    fn test_record_change() {
+        let input = r#"server { port 1965; watch_files 2s; vhost { hostname "localhost"; } }"#;
+        let config = read_and_parse_config(input).unwrap();
+        let stats = Stats::new(&config);
+
+        let page = stats.render_gemtext(&config);
+        assert!(page.ends_with("## Changed files\n\n* None since the server started\n"));
+
+        stats.record_change(Change {
+            path: "/srv/gemini/index.gmi".into(),
+            kind: ChangeKind::Modified,
+        });
+
+        let page = stats.render_gemtext(&config);
+        assert!(page.ends_with("* modified 0s ago: /srv/gemini/index.gmi\n"));
+
+        let json = stats.render_json(&config);
+        assert!(json.ends_with(
+            "\"file_changes\":1,\"recent_changes\":[{\"path\":\"/srv/gemini/index.gmi\",\"change\":\"modified\",\"seconds_ago\":0}]}\n"
+        ));
+
+        let metrics = stats.render_prometheus(&config);
+        assert!(metrics.ends_with("gemini_file_changes_total 1\n"));
+    }
+}
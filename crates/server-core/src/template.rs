@@ -0,0 +1,301 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const TAG_START: &str = "{{";
+const TAG_END: &str = "}}";
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Per request values available to templates.
+///
+/// `{{ path }}`, `{{ query }}`, `{{ host }}`, `{{ cert_cn }}`, `{{ tls_client_hash }}` and
+/// `{{ timestamp }}` expand to the matching value (or nothing when absent),
+/// `{{ include "file.gmi" }}` expands to the rendered contents of another template file,
+/// relative to the directory of the including one. The query is percent-decoded.
+///
+/// Values are inserted as text: line breaks become spaces, and a value that would start a
+/// line as a link, heading, list item, quote or preformat toggle is indented by a space, so
+/// a request can't add lines of its own to the page.
+#[derive(Debug, Default)]
+pub struct TemplateContext<'r> {
+    pub host: &'r str,
+    pub path: &'r str,
+    pub query: Option<&'r str>,
+    pub cert_cn: Option<&'r str>,
+    /// The [crate::client_cert::ClientCert] hash.
+    pub cert_hash: Option<&'r str>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum TemplateError {
+    UnterminatedTag(usize),
+    UnknownVariable(String),
+    InvalidInclude(String),
+    IncludeTooDeep(String),
+    UnreadableInclude(String, String),
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::UnterminatedTag(pos) => {
+                write!(f, "Unterminated tag at byte {}", pos)
+            }
+            TemplateError::UnknownVariable(v) => write!(f, "Unknown variable: {}", v),
+            TemplateError::InvalidInclude(i) => write!(f, "Invalid include: {}", i),
+            TemplateError::IncludeTooDeep(p) => write!(f, "Includes nested too deep at: {}", p),
+            TemplateError::UnreadableInclude(p, e) => {
+                write!(f, "Unable to read include '{}': {}", p, e)
+            }
+        }
+    }
+}
+
+enum Tag<'t> {
+    Variable(&'t str),
+    Include(&'t str),
+}
+
+fn tag(inner: &str) -> Result<Tag<'_>, TemplateError> {
+    let inner = inner.trim();
+
+    match inner.strip_prefix("include") {
+        Some(path) => {
+            let path = path.trim();
+            path.strip_prefix('"')
+                .and_then(|p| p.strip_suffix('"'))
+                .filter(|p| !p.is_empty())
+                .map(Tag::Include)
+                .ok_or_else(|| TemplateError::InvalidInclude(inner.to_string()))
+        }
+        None => Ok(Tag::Variable(inner)),
+    }
+}
+
+/// Splits the template into literal text and tags, calling `f` for each in order.
+fn walk<'t, F>(template: &'t str, mut f: F) -> Result<(), TemplateError>
+where
+    F: FnMut(&'t str, Option<Tag<'t>>) -> Result<(), TemplateError>,
+{
+    let mut rest = template;
+
+    while let Some(start) = rest.find(TAG_START) {
+        let after = &rest[start + TAG_START.len()..];
+        let end = after.find(TAG_END).ok_or(TemplateError::UnterminatedTag(
+            template.len() - rest.len() + start,
+        ))?;
+
+        f(&rest[..start], Some(tag(&after[..end])?))?;
+
+        rest = &after[end + TAG_END.len()..];
+    }
+
+    f(rest, None)
+}
+
+/// Checks the template syntax and variable names without rendering it.
+pub fn check(template: &str) -> Result<(), TemplateError> {
+    walk(template, |_, tag| match tag {
+        Some(Tag::Variable(v)) => variable(&TemplateContext::default(), v).map(|_| ()),
+        _ => Ok(()),
+    })
+}
+
+/// Renders `template`, which was read from a file in `dir`, or from the config for an
+/// empty `dir`.
+pub async fn render(
+    template: &str,
+    ctx: &TemplateContext<'_>,
+    dir: &Path,
+) -> Result<String, TemplateError> {
+    render_with_depth(template, ctx, dir, 0).await
+}
+
+type Rendered<'a> = Pin<Box<dyn Future<Output = Result<String, TemplateError>> + Send + 'a>>;
+
+fn render_with_depth<'a>(
+    template: &'a str,
+    ctx: &'a TemplateContext<'_>,
+    dir: &'a Path,
+    depth: usize,
+) -> Rendered<'a> {
+    Box::pin(async move {
+        let mut parts = vec![];
+        walk(template, |text, tag| {
+            parts.push((text, tag));
+            Ok(())
+        })?;
+
+        let mut out = String::with_capacity(template.len());
+        for (text, tag) in parts {
+            out.push_str(text);
+
+            match tag {
+                Some(Tag::Variable(v)) => {
+                    let line_start = out.is_empty() || out.ends_with('\n');
+                    out.push_str(&as_text(&variable(ctx, v)?, line_start));
+                }
+                Some(Tag::Include(path)) => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(TemplateError::IncludeTooDeep(path.to_string()));
+                    }
+
+                    let path = dir.join(path);
+                    let included = tokio::fs::read_to_string(&path).await.map_err(|e| {
+                        TemplateError::UnreadableInclude(path.display().to_string(), e.to_string())
+                    })?;
+                    let dir = path.parent().unwrap_or(Path::new(""));
+
+                    out.push_str(&render_with_depth(&included, ctx, dir, depth + 1).await?);
+                }
+                None => {}
+            }
+        }
+
+        Ok(out)
+    })
+}
+
+/// `value` as it is inserted, see [TemplateContext].
+fn as_text(value: &str, line_start: bool) -> String {
+    let value = value.replace(['\r', '\n'], " ");
+
+    if line_start && value.starts_with(['=', '#', '`', '*', '>']) {
+        format!(" {}", value)
+    } else {
+        value
+    }
+}
+
+fn variable(ctx: &TemplateContext, name: &str) -> Result<String, TemplateError> {
+    match name {
+        "host" => Ok(ctx.host.to_string()),
+        "path" => Ok(ctx.path.to_string()),
+        "query" => Ok(ctx.query.unwrap_or_default().to_string()),
+        "cert_cn" => Ok(ctx.cert_cn.unwrap_or_default().to_string()),
+        "tls_client_hash" => Ok(ctx.cert_hash.unwrap_or_default().to_string()),
+        "timestamp" => Ok(SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+            .to_string()),
+        v => Err(TemplateError::UnknownVariable(v.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_render() {
+        let ctx = TemplateContext {
+            host: "localhost",
+            path: "/search",
+            query: Some("gemini"),
+            cert_cn: None,
+            cert_hash: Some("SHA256:AB12"),
+        };
+
+        let cases = vec![
+            ("# No tags", Ok("# No tags".to_string())),
+            (
+                "# {{ path }}\nYou searched for {{query}}{{ cert_cn }}.",
+                Ok("# /search\nYou searched for gemini.".to_string()),
+            ),
+            (
+                "Welcome back, {{ tls_client_hash }}",
+                Ok("Welcome back, SHA256:AB12".to_string()),
+            ),
+            (
+                "=> gemini://{{ host }}/ Home",
+                Ok("=> gemini://localhost/ Home".to_string()),
+            ),
+            (
+                "{{ nope }}",
+                Err(TemplateError::UnknownVariable("nope".to_string())),
+            ),
+            ("ab {{ path", Err(TemplateError::UnterminatedTag(3))),
+            (
+                "{{ include }}",
+                Err(TemplateError::InvalidInclude("include".to_string())),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                render(input, &ctx, Path::new("")).await,
+                expected,
+                "{input}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_injection() {
+        let ctx = TemplateContext {
+            host: "localhost",
+            path: "/search",
+            query: Some("x\r\n=> gemini://evil/ Click"),
+            ..TemplateContext::default()
+        };
+        let rendered = render(
+            "# Results\nYou searched for {{ query }}",
+            &ctx,
+            Path::new(""),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            rendered,
+            "# Results\nYou searched for x  => gemini://evil/ Click"
+        );
+
+        for query in [
+            "=> gemini://evil/ Click",
+            "# Hacked",
+            "```",
+            "* item",
+            "> quote",
+        ] {
+            let ctx = TemplateContext {
+                query: Some(query),
+                ..TemplateContext::default()
+            };
+            let rendered = render("Results:\n{{ query }}", &ctx, Path::new(""))
+                .await
+                .unwrap();
+            assert_eq!(rendered, format!("Results:\n {}", query));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_include() {
+        let ctx = TemplateContext {
+            host: "localhost",
+            ..TemplateContext::default()
+        };
+
+        assert!(matches!(
+            render(r#"{{ include "does/not/exist.gmi" }}"#, &ctx, Path::new("")).await,
+            Err(TemplateError::UnreadableInclude(..))
+        ));
+        assert_eq!(check(r#"{{ include "does/not/exist.gmi" }}"#), Ok(()));
+
+        // Includes are relative to the including file, wherever the server runs from.
+        let dir = std::env::temp_dir().join(format!("gemini-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("parts")).unwrap();
+        std::fs::write(
+            dir.join("parts/header.gmi"),
+            r#"{{ include "title.gmi" }} on {{ host }}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("parts/title.gmi"), "# Title").unwrap();
+
+        let rendered = render(r#"{{ include "parts/header.gmi" }}"#, &ctx, &dir).await;
+        assert_eq!(rendered, Ok("# Title on localhost".to_string()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+use crate::config::{GetProperty, Route};
+
+/// Used when a route accepting uploads has no `max_upload_size`.
+pub const DEFAULT_MAX_UPLOAD_SIZE: u64 = 10 * 1024 * 1024;
+
+/// The parameters of a Titan upload, `titan://host/path;mime=text/plain;size=12`.
+///
+/// https://transjovian.org/titan/page/The%20Titan%20Specification
+#[derive(Debug, Eq, PartialEq)]
+pub struct Upload<'u> {
+    /// The request path without the parameters.
+    pub path: &'u str,
+    pub mime: &'u str,
+    pub size: u64,
+    pub token: Option<&'u str>,
+}
+
+impl<'u> Upload<'u> {
+    /// `None` without a valid `size`, the only required parameter. The MIME type defaults
+    /// to `text/gemini`.
+    pub fn from_path(path: &'u str) -> Option<Self> {
+        let mut params = path.split(';');
+        let path = params.next()?;
+
+        let mut mime = "text/gemini";
+        let mut size = None;
+        let mut token = None;
+
+        for param in params {
+            match param.split_once('=')? {
+                ("mime", value) => mime = value,
+                ("size", value) => size = Some(value.parse().ok()?),
+                ("token", value) => token = Some(value),
+                _ => {}
+            }
+        }
+
+        Some(Upload {
+            path,
+            mime,
+            size: size?,
+            token,
+        })
+    }
+}
+
+/// Checks the upload against the route's `upload_token`, `max_upload_size` and
+/// `allowed_upload_mime` before any of the body is read. `allowed_upload_mime` takes exact
+/// types and `type/*`.
+pub fn check(route: &Route, upload: &Upload) -> Result<(), String> {
+    if let Some(token) = route.get_property_string("upload_token")
+        && upload.token != Some(token)
+    {
+        return Err("Invalid upload token".to_string());
+    }
+
+    let max_size = route
+        .get_property_size("max_upload_size")
+        .unwrap_or(DEFAULT_MAX_UPLOAD_SIZE);
+
+    if upload.size > max_size {
+        return Err(format!("Upload too large, the limit is {} bytes", max_size));
+    }
+
+    let allowed = route.get_property_strings("allowed_upload_mime");
+    // Parameters like `; charset=utf-8` don't change the type.
+    let mime = upload.mime.split(';').next().unwrap_or_default().trim();
+    let mime_allowed = allowed.is_empty()
+        || allowed
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(family) => mime.split('/').next() == Some(family),
+                None => mime.eq_ignore_ascii_case(allowed),
+            });
+
+    if !mime_allowed {
+        return Err(format!("Uploads of type {} are not allowed", mime));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_and_parse_config;
+
+    #[test]
+    fn test_from_path() {
+        let cases = vec![
+            (
+                "/notes.txt;mime=text/plain;size=12;token=hunter2",
+                Some(Upload {
+                    path: "/notes.txt",
+                    mime: "text/plain",
+                    size: 12,
+                    token: Some("hunter2"),
+                }),
+            ),
+            (
+                "/index.gmi;size=0",
+                Some(Upload {
+                    path: "/index.gmi",
+                    mime: "text/gemini",
+                    size: 0,
+                    token: None,
+                }),
+            ),
+            ("/index.gmi;mime=text/plain", None),
+            ("/index.gmi;size=-1", None),
+            ("/index.gmi", None),
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!(Upload::from_path(path), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let input = r#"
+server
+{
+    vhost
+    {
+        hostname "localhost";
+
+        route
+        {
+            path "/uploads/*";
+            upload_directory "uploads";
+            max_upload_size 1KB;
+            allowed_upload_mime ["text/gemini", "image/*"];
+        }
+        route { path "/any/*"; upload_directory "uploads"; }
+        route { path "/token/*"; upload_directory "uploads"; upload_token "hunter2"; }
+    }
+}
+    "#;
+        let config = read_and_parse_config(input).unwrap();
+        let routes = &config.server.vhosts[0].routes;
+
+        let upload = |mime, size| Upload {
+            path: "/uploads/file",
+            mime,
+            size,
+            token: None,
+        };
+
+        assert_eq!(check(&routes[0], &upload("text/gemini", 1024)), Ok(()));
+        assert_eq!(check(&routes[0], &upload("image/png", 10)), Ok(()));
+        assert_eq!(
+            check(&routes[0], &upload("text/gemini; lang=en", 10)),
+            Ok(())
+        );
+        assert!(check(&routes[0], &upload("text/gemini", 1025)).is_err());
+        assert!(check(&routes[0], &upload("text/plain", 10)).is_err());
+        assert!(check(&routes[0], &upload("imagex/png", 10)).is_err());
+
+        assert_eq!(
+            check(&routes[1], &upload("text/plain", 1024 * 1024)),
+            Ok(())
+        );
+        assert!(check(
+            &routes[1],
+            &upload("text/plain", DEFAULT_MAX_UPLOAD_SIZE + 1)
+        )
+        .is_err());
+
+        let with_token = Upload {
+            token: Some("hunter2"),
+            ..upload("text/plain", 10)
+        };
+        assert_eq!(check(&routes[2], &with_token), Ok(()));
+        assert!(check(&routes[2], &upload("text/plain", 10)).is_err());
+    }
+}
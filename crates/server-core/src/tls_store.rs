@@ -0,0 +1,139 @@
+use crate::client_cert::AcceptAnyClientCert;
+use crate::config::{Config, GetProperty};
+use anyhow::Context;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, ResolvesServerCertUsingSni};
+use rustls::sign::CertifiedKey;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[cfg(not(any(feature = "aws-lc-rs", feature = "ring")))]
+compile_error!("Either the `aws-lc-rs` or the `ring` feature has to be enabled");
+
+/// The crypto of every TLS config, ring with the `ring` feature and aws-lc-rs otherwise.
+pub fn crypto_provider() -> Arc<CryptoProvider> {
+    #[cfg(feature = "ring")]
+    let provider = rustls::crypto::ring::default_provider();
+    #[cfg(not(feature = "ring"))]
+    let provider = rustls::crypto::aws_lc_rs::default_provider();
+
+    Arc::new(provider)
+}
+
+fn load_tls_files(
+    cert: PathBuf,
+    key: PathBuf,
+) -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    if !cert.exists() {
+        return Err(anyhow::anyhow!(
+            "Certificate file does not exist: {:?}, see `server cert generate`",
+            cert
+        ));
+    }
+
+    if !key.exists() {
+        return Err(anyhow::anyhow!(
+            "Private key file does not exist: {:?}",
+            key
+        ));
+    }
+
+    let certs = CertificateDer::pem_file_iter(&cert)
+        .with_context(|| format!("Failed to read certificate: {:?}", cert))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificate: {:?}", cert))?;
+    let key = PrivateKeyDer::from_pem_file(&key)
+        .with_context(|| format!("Failed to read private key: {:?}", key))?;
+
+    Ok((certs, key))
+}
+
+/// The certificate of a vhost by SNI. Clients send no SNI for an IP address, so those get
+/// the certificate of the vhost that lists the address in its `alternate_hostnames`.
+#[derive(Debug)]
+struct Resolver {
+    by_name: ResolvesServerCertUsingSni,
+    without_sni: Option<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        match client_hello.server_name() {
+            Some(_) => self.by_name.resolve(client_hello),
+            None => self.without_sni.clone(),
+        }
+    }
+}
+
+pub fn make_tls_config(config: &Config) -> anyhow::Result<Arc<rustls::ServerConfig>> {
+    let provider = crypto_provider();
+    let mut resolver = Resolver {
+        by_name: ResolvesServerCertUsingSni::new(),
+        without_sni: None,
+    };
+
+    for vhost in &config.server.vhosts {
+        let domain = &vhost.vhost;
+
+        let cert = vhost
+            .get_property_string("tls_cert")
+            .context(format!(
+                "The vhost '{}' is missing the 'tls_cert' property",
+                domain
+            ))?
+            .into();
+
+        let key = vhost
+            .get_property_string("tls_key")
+            .context(format!(
+                "The vhost '{}' is missing the 'tls_key' property",
+                domain
+            ))?
+            .into();
+
+        let (certs, key) = load_tls_files(cert, key).context(format!(
+            "Failed to create TLS config for vhost '{}'",
+            domain
+        ))?;
+
+        let key = CertifiedKey::from_der(certs, key, &provider)?;
+        for name in vhost.get_property_strings("alternate_hostnames") {
+            if name.parse::<IpAddr>().is_ok() {
+                resolver
+                    .without_sni
+                    .get_or_insert_with(|| Arc::new(key.clone()));
+            } else {
+                resolver.by_name.add(name, key.clone()).context(format!(
+                    "Invalid alternate hostname '{}' of vhost '{}'",
+                    name, domain
+                ))?;
+            }
+        }
+
+        resolver.by_name.add(&domain.0, key)?
+    }
+
+    let verifier = AcceptAnyClientCert::new(provider.signature_verification_algorithms);
+    let keylog = config.get_property_bool("tls_keylog") == Some(true);
+
+    let mut config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_client_cert_verifier(Arc::new(verifier))
+        .with_cert_resolver(Arc::new(resolver));
+
+    // `tls_keylog on;` writes the TLS secrets to SSLKEYLOGFILE, to decrypt captures while
+    // debugging. Anyone who can read the file can read the traffic.
+    if keylog {
+        match std::env::var_os("SSLKEYLOGFILE") {
+            Some(path) => log::warn!("tls_keylog is on, TLS secrets are written to {:?}", path),
+            None => log::warn!("tls_keylog is on, but SSLKEYLOGFILE is not set"),
+        }
+
+        config.key_log = Arc::new(rustls::KeyLogFile::new());
+    }
+
+    Ok(Arc::new(config))
+}
@@ -0,0 +1,284 @@
+use crate::cache::ResponseCache;
+use crate::config::{Config, GetProperty};
+use crate::files;
+use crate::stats::Target;
+use crate::GlobalStateArc;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Files scanned per check at most, the rest of a huge root isn't watched.
+const MAX_FILES: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChangeKind::Created => "created",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Removed => "removed",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Change {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+/// The modification time of every watched file.
+type Snapshot = BTreeMap<PathBuf, SystemTime>;
+
+/// `watch_files 2s;` on the server checks the served files for changes every 2 seconds:
+/// the `root` and `alias` directories, and the `respond_file` and `script` of routes.
+/// Changes are logged and listed on the status page.
+///
+/// A changed route file drops the cached responses of its routes. Any other change drops
+/// the whole cache, as templates can include any file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FileWatcher {
+    pub interval: Duration,
+    /// Every file below these is watched.
+    dirs: BTreeSet<PathBuf>,
+    /// The route files, with the routes that use them.
+    route_files: HashMap<PathBuf, Vec<Target>>,
+}
+
+impl FileWatcher {
+    pub fn from_config(config: &Config) -> anyhow::Result<Option<Self>> {
+        let Some(interval) = config.get_property_duration("watch_files") else {
+            return Ok(None);
+        };
+        if interval.is_zero() {
+            anyhow::bail!("watch_files needs an interval longer than zero");
+        }
+
+        let mut dirs = BTreeSet::new();
+        let mut route_files = HashMap::<_, Vec<_>>::new();
+
+        for (v, vhost) in config.server.vhosts.iter().enumerate() {
+            dirs.extend(vhost.get_property_string("root").map(PathBuf::from));
+            for (_, dir) in files::aliases(vhost).unwrap_or_default() {
+                dirs.insert(PathBuf::from(dir));
+            }
+
+            for (r, route) in vhost.routes.iter().enumerate() {
+                dirs.extend(route.get_property_string("root").map(PathBuf::from));
+
+                for name in ["respond_file", "script"] {
+                    if let Some(file) = route.get_property_string(name) {
+                        route_files
+                            .entry(PathBuf::from(file))
+                            .or_default()
+                            .push(Target {
+                                vhost: Some(v),
+                                route: Some(r),
+                            });
+                    }
+                }
+            }
+        }
+
+        Ok(Some(FileWatcher {
+            interval,
+            dirs,
+            route_files,
+        }))
+    }
+
+    fn scan(&self) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+
+        for file in self.route_files.keys() {
+            if let Ok(modified) = std::fs::metadata(file).and_then(|m| m.modified()) {
+                snapshot.insert(file.clone(), modified);
+            }
+        }
+
+        let mut dirs = self.dirs.iter().cloned().collect::<Vec<_>>();
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+
+            for entry in entries.flatten() {
+                if snapshot.len() >= MAX_FILES {
+                    return snapshot;
+                }
+
+                // Symbolic links to directories aren't followed, they may loop.
+                match entry.file_type() {
+                    Ok(kind) if kind.is_dir() => dirs.push(entry.path()),
+                    Ok(_) => {
+                        if let Ok(modified) = entry.metadata().and_then(|m| m.modified()) {
+                            snapshot.insert(entry.path(), modified);
+                        }
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+
+        snapshot
+    }
+
+    /// Drops the cached responses `change` makes stale, returns how many.
+    fn invalidate(&self, change: &Change, cache: &ResponseCache) -> usize {
+        match self.route_files.get(&change.path) {
+            Some(routes) => cache.remove_routes(routes),
+            None => cache.clear(),
+        }
+    }
+}
+
+fn diff(old: &Snapshot, new: &Snapshot) -> Vec<Change> {
+    let change = |path: &Path, kind| Change {
+        path: path.to_path_buf(),
+        kind,
+    };
+
+    let mut changes = vec![];
+    for (path, modified) in new {
+        match old.get(path) {
+            None => changes.push(change(path, ChangeKind::Created)),
+            Some(before) if before != modified => changes.push(change(path, ChangeKind::Modified)),
+            Some(_) => {}
+        }
+    }
+    for path in old.keys().filter(|path| !new.contains_key(*path)) {
+        changes.push(change(path, ChangeKind::Removed));
+    }
+
+    changes
+}
+
+/// Checks the files every interval, the first scan only notes what is there.
+pub async fn watch_loop(watcher: FileWatcher, state: GlobalStateArc) {
+    let scan = |watcher: FileWatcher| async move {
+        tokio::task::spawn_blocking(move || (watcher.scan(), watcher)).await
+    };
+
+    let Ok((mut snapshot, mut watcher)) = scan(watcher).await else {
+        log::error!("Failed to scan the watched files");
+        return;
+    };
+    log::info!("Watching {} files for changes", snapshot.len());
+    if snapshot.len() >= MAX_FILES {
+        log::warn!("Watching the first {} files only", MAX_FILES);
+    }
+
+    loop {
+        tokio::time::sleep(watcher.interval).await;
+
+        let Ok((new, scanned)) = scan(watcher).await else {
+            log::error!("Failed to scan the watched files");
+            return;
+        };
+        watcher = scanned;
+
+        for change in diff(&snapshot, &new) {
+            let dropped = watcher.invalidate(&change, &state.cache);
+            log::info!(
+                "{:?} was {}, dropped {} cached responses",
+                change.path,
+                change.kind,
+                dropped
+            );
+
+            state.stats.record_change(change);
+        }
+        snapshot = new;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CachePolicy;
+    use crate::config::read_and_parse_config;
+    use crate::response::Response;
+
+    #[test]
+    fn test_watch() {
+        let dir = std::env::temp_dir().join(format!("gemini-watch-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("posts")).unwrap();
+        std::fs::write(dir.join("index.gmi"), "# Index").unwrap();
+        std::fs::write(dir.join("posts/first.gmi"), "# First").unwrap();
+        std::fs::write(dir.join("page.gmi"), "# {{ host }}").unwrap();
+
+        let input = format!(
+            r#"
+server
+{{
+    port 1965;
+    watch_files 2s;
+
+    vhost
+    {{
+        hostname "localhost";
+        root "{dir}";
+
+        route {{ path "/page"; respond_file "{dir}/page.gmi"; cache_ttl 60s; }}
+        route {{ path "/other"; respond_body "other"; cache_ttl 60s; }}
+    }}
+}}
+"#,
+            dir = dir.display()
+        );
+        let config = read_and_parse_config(&input).unwrap();
+        let watcher = FileWatcher::from_config(&config).unwrap().unwrap();
+        assert_eq!(watcher.interval, Duration::from_secs(2));
+
+        let before = watcher.scan();
+        assert_eq!(before.len(), 3);
+
+        std::fs::remove_file(dir.join("posts/first.gmi")).unwrap();
+        std::fs::write(dir.join("posts/second.gmi"), "# Second").unwrap();
+        let mut after = watcher.scan();
+        // Modification times may not have moved on yet.
+        after.insert(dir.join("page.gmi"), SystemTime::UNIX_EPOCH);
+
+        let change = |path: &str, kind| Change {
+            path: dir.join(path),
+            kind,
+        };
+        assert_eq!(
+            diff(&before, &after),
+            vec![
+                change("page.gmi", ChangeKind::Modified),
+                change("posts/second.gmi", ChangeKind::Created),
+                change("posts/first.gmi", ChangeKind::Removed),
+            ]
+        );
+
+        let cache = ResponseCache::new(1024);
+        let policy = CachePolicy::for_route(&config.server.vhosts[0].routes[0]).unwrap();
+        let route = |route| Target {
+            vhost: Some(0),
+            route: Some(route),
+        };
+        let resp = Response::new(20, "text/gemini", "page");
+        cache.insert("gemini://localhost/page", &resp, policy, route(0));
+        cache.insert("gemini://localhost/other", &resp, policy, route(1));
+
+        let page = change("page.gmi", ChangeKind::Modified);
+        assert_eq!(watcher.invalidate(&page, &cache), 1);
+        assert!(cache.get("gemini://localhost/other").is_some());
+        let post = change("posts/second.gmi", ChangeKind::Created);
+        assert_eq!(watcher.invalidate(&post, &cache), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let input = r#"server { port 1965; watch_files 0; vhost { hostname "localhost"; } }"#;
+        let config = read_and_parse_config(input).unwrap();
+        assert!(FileWatcher::from_config(&config).is_err());
+    }
+}